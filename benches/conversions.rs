@@ -0,0 +1,114 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Cost of the FFI layer itself: conversions, result construction and callback invocation.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use sn_ffi_utils::dispatcher::{Dispatcher, DispatcherConfig};
+use sn_ffi_utils::test_utils::TestError;
+use sn_ffi_utils::{
+    base64_decode, base64_encode, call_result_cb, catch_unwind_cb, vec_from_raw_parts,
+    vec_into_raw_parts, FfiResult, IntoReprC, NativeResult, ReprC,
+};
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::ptr;
+use unwrap::unwrap;
+
+extern "C" fn noop_cb(_user_data: *mut c_void, _result: *const FfiResult) {}
+
+extern "C" fn noop_cb_1(_user_data: *mut c_void, _result: *const FfiResult, _value: u64) {}
+
+fn repr_c(c: &mut Criterion) {
+    let string = unwrap!(CString::new(
+        "a moderately long string passed across the FFI"
+    ));
+    let _ = c.bench_function("String::clone_from_repr_c", |b| {
+        b.iter(|| unsafe { unwrap!(String::clone_from_repr_c(black_box(string.as_ptr()))) })
+    });
+
+    let _ = c.bench_function("String::into_repr_c", |b| {
+        b.iter(|| unwrap!(black_box("a moderately long string".to_owned()).into_repr_c()))
+    });
+
+    let _ = c.bench_function("CString::new", |b| {
+        b.iter(|| unwrap!(CString::new(black_box("a moderately long string"))))
+    });
+
+    let _ = c.bench_function("NativeResult::into_repr_c", |b| {
+        b.iter(|| {
+            unwrap!(NativeResult {
+                error_code: -1,
+                description: Some(black_box(TestError::Test).to_string()),
+            }
+            .into_repr_c())
+        })
+    });
+
+    let _ = c.bench_function("NativeResult::into_repr_c spare capacity", |b| {
+        b.iter(|| {
+            let mut description = String::with_capacity(64);
+            description.push_str(black_box("Test Error"));
+            unwrap!(NativeResult {
+                error_code: -1,
+                description: Some(description),
+            }
+            .into_repr_c())
+        })
+    });
+}
+
+fn vec(c: &mut Criterion) {
+    let data = vec![7u8; 1024];
+    let _ = c.bench_function("vec_into_raw_parts round-trip 1 KiB", |b| {
+        b.iter(|| {
+            let (ptr, len) = vec_into_raw_parts(black_box(data.clone()));
+            unsafe { vec_from_raw_parts(ptr, len) }
+        })
+    });
+
+    let encoded = base64_encode(&data);
+    let _ = c.bench_function("base64_encode 1 KiB", |b| {
+        b.iter(|| base64_encode(black_box(&data)))
+    });
+    let _ = c.bench_function("base64_decode 1 KiB", |b| {
+        b.iter(|| unwrap!(base64_decode(black_box(&encoded))))
+    });
+}
+
+fn callbacks(c: &mut Criterion) {
+    let cb: extern "C" fn(_, _) = noop_cb;
+    let _ = c.bench_function("catch_unwind_cb success", |b| {
+        b.iter(|| catch_unwind_cb(ptr::null_mut::<c_void>(), cb, || Ok::<_, TestError>(())))
+    });
+    let _ = c.bench_function("catch_unwind_cb error", |b| {
+        b.iter(|| catch_unwind_cb(ptr::null_mut::<c_void>(), cb, || Err(TestError::Test)))
+    });
+    let _ = c.bench_function("call_result_cb! success", |b| {
+        b.iter(|| {
+            call_result_cb!(Ok::<_, TestError>(()), ptr::null_mut::<c_void>(), cb);
+        })
+    });
+
+    let dispatcher = Dispatcher::new(DispatcherConfig::default());
+    let cb_1: extern "C" fn(_, _, _) = noop_cb_1;
+    let _ = c.bench_function("Dispatcher::dispatch_cb", |b| {
+        b.iter(|| {
+            unwrap!(dispatcher.dispatch_cb(
+                ptr::null_mut::<c_void>(),
+                cb_1,
+                Ok::<_, TestError>(black_box(42u64))
+            ));
+            dispatcher.drain();
+        })
+    });
+}
+
+criterion_group!(benches, repr_c, vec, callbacks);
+criterion_main!(benches);
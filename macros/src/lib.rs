@@ -0,0 +1,243 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Procedural macros for `sn_ffi_utils`. Use them through the re-exports of that crate.
+
+#![warn(
+    missing_docs,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results
+)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Error, Expr, ExprPath, FnArg, GenericArgument, ItemFn, MetaNameValue, Pat,
+    Path, PathArguments, ReturnType, Type,
+};
+
+/// Turn a function returning `Result<T, E>` into an FFI function.
+///
+/// ```ignore
+/// #[ffi_fn]
+/// fn app_name(app: Handle, suffix: String) -> Result<String, AppError> {
+///     Ok(format!("{}{}", handles::get::<App>(app)?.name, suffix))
+/// }
+/// ```
+///
+/// expands to a `#[no_mangle] pub unsafe extern "C" fn app_name` which takes the arguments in
+/// their `ReprC` representation, followed by `user_data` and the callback `o_cb`:
+///
+/// ```ignore
+/// pub unsafe extern "C" fn app_name(
+///     app: <Handle as ReprC>::C,
+///     suffix: <String as ReprC>::C,
+///     user_data: *mut c_void,
+///     o_cb: extern "C" fn(
+///         user_data: *mut c_void,
+///         result: *const FfiResult,
+///         value: <String as ReprC>::C,
+///     ),
+/// )
+/// ```
+///
+/// The arguments are converted with `ReprC::clone_from_repr_c` and the function is run in
+/// `catch_unwind_cb`. The value it returns is converted with `IntoReprC` and passed to the
+/// callback, which has no `value` argument if `T` is `()`. Conversion failures are reported
+/// through the callback with errors built by `E::from(&str)`.
+///
+/// Attributes other than docs, such as `#[cfg]` or `#[deprecated]`, apply to the exported function.
+/// The generated code refers to `::sn_ffi_utils`; crates which depend on it under another name or
+/// re-export it pass the path with `#[ffi_fn(crate = path::to::sn_ffi_utils)]`.
+#[proc_macro_attribute]
+pub fn ffi_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let krate = match parse_crate_path(attr) {
+        Ok(krate) => krate,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
+    let item = parse_macro_input!(item as ItemFn);
+    match expand(item, &krate) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+// Parse the optional `crate = path` argument, defaulting to `::sn_ffi_utils`.
+fn parse_crate_path(attr: TokenStream) -> Result<Path, Error> {
+    if attr.is_empty() {
+        return Ok(syn::parse_quote!(::sn_ffi_utils));
+    }
+
+    let arg: MetaNameValue = syn::parse(attr)?;
+    if !arg.path.is_ident("crate") {
+        return Err(Error::new(
+            arg.path.span(),
+            "`ffi_fn` only takes a `crate = path` argument",
+        ));
+    }
+    match arg.value {
+        Expr::Path(ExprPath { path, .. }) => Ok(path),
+        value => Err(Error::new(value.span(), "expected a crate path")),
+    }
+}
+
+fn expand(item: ItemFn, krate: &Path) -> Result<TokenStream2, Error> {
+    let sig = &item.sig;
+    if sig.asyncness.is_some() || !sig.generics.params.is_empty() || sig.abi.is_some() {
+        return Err(Error::new(
+            sig.span(),
+            "`ffi_fn` requires a plain non-generic, non-async function",
+        ));
+    }
+
+    let name = &sig.ident;
+    // Docs and attributes such as `#[cfg]` or `#[deprecated]` belong to the exported function.
+    let attrs = &item.attrs;
+    let (value_ty, error_ty) = result_types(&sig.output)?;
+
+    let mut params = Vec::new();
+    let mut decode = Vec::new();
+    let mut arg_names = Vec::new();
+
+    for input in &sig.inputs {
+        let arg = match input {
+            FnArg::Typed(arg) => arg,
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new(receiver.span(), "`ffi_fn` can't take `self`"))
+            }
+        };
+        let ident = match &*arg.pat {
+            Pat::Ident(pat) => &pat.ident,
+            pat => {
+                return Err(Error::new(
+                    pat.span(),
+                    "`ffi_fn` arguments must be identifiers",
+                ))
+            }
+        };
+        let ty = &arg.ty;
+        let message = format!("Invalid argument `{}`", ident);
+
+        params.push(quote! { #ident: <#ty as #krate::ReprC>::C });
+        decode.push(quote_spanned! {ty.span()=>
+            let #ident = <#ty as #krate::ReprC>::clone_from_repr_c(#ident)
+                .map_err(|error| {
+                    #krate::__log::debug!("{}: {:?}", #message, error);
+                    <#error_ty>::from(#message)
+                })?;
+        });
+        arg_names.push(ident);
+    }
+
+    let unit = is_unit(value_ty);
+    let cb_ty = if unit {
+        quote! {
+            extern "C" fn(
+                user_data: *mut ::std::os::raw::c_void,
+                result: *const #krate::FfiResult,
+            )
+        }
+    } else {
+        quote! {
+            extern "C" fn(
+                user_data: *mut ::std::os::raw::c_void,
+                result: *const #krate::FfiResult,
+                value: <#value_ty as #krate::ReprC>::C,
+            )
+        }
+    };
+    let call_cb = if unit {
+        quote! {
+            let () = value;
+            o_cb(user_data.as_ptr(), #krate::FFI_RESULT_OK);
+        }
+    } else {
+        quote! {
+            let (value, _storage) = #krate::IntoReprC::into_repr_c(value).map_err(|error| {
+                #krate::__log::debug!("Invalid return value: {:?}", error);
+                <#error_ty>::from("Invalid return value")
+            })?;
+            o_cb(user_data.as_ptr(), #krate::FFI_RESULT_OK, value);
+        }
+    };
+
+    let inner = format_ident!("__{}_impl", name);
+    let mut inner_fn = item.clone();
+    inner_fn.sig.ident = inner.clone();
+    inner_fn
+        .attrs
+        .retain(|attr| !attr.path().is_ident("doc") && !attr.path().is_ident("deprecated"));
+    inner_fn.vis = syn::Visibility::Inherited;
+
+    Ok(quote! {
+        #(#attrs)*
+        #[no_mangle]
+        pub unsafe extern "C" fn #name(
+            #(#params,)*
+            user_data: *mut ::std::os::raw::c_void,
+            o_cb: #cb_ty,
+        ) {
+            #inner_fn
+
+            let user_data = #krate::OpaqueCtx::from_host_pointer(user_data);
+            #krate::catch_unwind_cb(user_data, o_cb, || -> ::std::result::Result<(), #error_ty> {
+                #(#decode)*
+                let value = #inner(#(#arg_names),*)?;
+                #call_cb
+                Ok(())
+            })
+        }
+    })
+}
+
+// Return `T` and `E` of a `Result<T, E>` return type.
+fn result_types(output: &ReturnType) -> Result<(&Type, &Type), Error> {
+    let error = || {
+        Error::new(
+            output.span(),
+            "`ffi_fn` functions must return `Result<T, E>`",
+        )
+    };
+
+    let ty = match output {
+        ReturnType::Type(_, ty) => ty,
+        ReturnType::Default => return Err(error()),
+    };
+    let segment = match &**ty {
+        Type::Path(path) => path.path.segments.last().ok_or_else(error)?,
+        _ => return Err(error()),
+    };
+    if segment.ident != "Result" {
+        return Err(error());
+    }
+
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => &args.args,
+        _ => return Err(error()),
+    };
+    let mut types = args.iter().filter_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    });
+    match (types.next(), types.next(), types.next()) {
+        (Some(value), Some(error), None) => Ok((value, error)),
+        _ => Err(error()),
+    }
+}
+
+fn is_unit(ty: &Type) -> bool {
+    matches!(ty, Type::Tuple(tuple) if tuple.elems.is_empty())
+}
@@ -0,0 +1,31 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Shared library exporting the `mock_*` functions of `sn_ffi_utils::mock`, for bindings to link
+//! against. Build it with:
+//!
+//! ```text
+//! cargo build -p sn_ffi_utils_mock --release
+//! ```
+//!
+//! which produces `libsn_ffi_utils_mock.so` (`.dylib` on macOS, `sn_ffi_utils_mock.dll` on
+//! Windows) in `target/release`.
+
+// For explanation of lint checks, run `rustc -W help`.
+#![warn(
+    missing_docs,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results
+)]
+
+pub use sn_ffi_utils::mock::*;
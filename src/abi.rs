@@ -0,0 +1,230 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! ABI versioning.
+//!
+//! A library computes the ABI version of its FFI surface at build time with
+//! `bindgen_utils::embed_abi_version` and exports it using `export_abi_version!`. Bindings
+//! generated from the same description embed the same number and compare it at load time, so a
+//! mismatched deployment fails with a clear error instead of corrupting memory.
+
+use crate::ErrorCode;
+use std::fmt::{self, Display};
+
+/// Error code returned when the ABI version expected by the caller doesn't match the library.
+pub const ERR_ABI_VERSION_MISMATCH: i32 = -9000;
+
+/// Version of the C types defined by this crate (`FfiResult` and the like). Bumped whenever one
+/// of them changes layout.
+pub const FFI_UTILS_ABI_VERSION: u64 = 1;
+
+/// ABI version mismatch between a binding and the loaded library.
+#[derive(Debug, Eq, PartialEq)]
+pub struct AbiVersionError {
+    /// ABI version the caller was generated against.
+    pub expected: u64,
+    /// ABI version of the loaded library.
+    pub actual: u64,
+}
+
+impl ErrorCode for AbiVersionError {
+    fn error_code(&self) -> i32 {
+        ERR_ABI_VERSION_MISMATCH
+    }
+}
+
+impl Display for AbiVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ABI version mismatch: expected {:#018x}, library has {:#018x}",
+            self.expected, self.actual
+        )
+    }
+}
+
+/// Check that the ABI version expected by a caller matches the actual one.
+pub fn check_abi_version(expected: u64, actual: u64) -> Result<(), AbiVersionError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(AbiVersionError { expected, actual })
+    }
+}
+
+/// Parse the ABI version embedded by `bindgen_utils::embed_abi_version`.
+///
+/// This is a `const fn` so the exporting macros can evaluate it at compile time, where a
+/// malformed version fails the build instead of silently becoming a version nobody matches.
+///
+/// # Panics
+///
+/// Panics if `version` is not a decimal `u64`.
+pub const fn parse_abi_version(version: &str) -> u64 {
+    let bytes = version.as_bytes();
+    if bytes.is_empty() {
+        panic!("malformed ABI version: empty");
+    }
+
+    let mut value: u64 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let digit = bytes[i];
+        if !digit.is_ascii_digit() {
+            panic!("malformed ABI version: not a decimal number");
+        }
+        value = match value.checked_mul(10) {
+            Some(value) => match value.checked_add((digit - b'0') as u64) {
+                Some(value) => value,
+                None => panic!("malformed ABI version: out of range"),
+            },
+            None => panic!("malformed ABI version: out of range"),
+        };
+        i += 1;
+    }
+
+    value
+}
+
+/// Export the ABI version of the library.
+///
+/// Defines three `#[no_mangle]` functions:
+///
+/// + `ffi_abi_version() -> u64` returning the version embedded at build time;
+/// + `ffi_check_abi_version(expected: u64) -> i32` returning 0 if `expected` matches, or
+///   `ERR_ABI_VERSION_MISMATCH` otherwise;
+/// + `ffi_utils_abi_version() -> u64` returning `FFI_UTILS_ABI_VERSION`.
+///
+/// By default the version is read from the `FFI_ABI_VERSION` variable set by
+/// `bindgen_utils::embed_abi_version` in the build script; a string literal may be passed
+/// instead.
+#[macro_export]
+macro_rules! export_abi_version {
+    () => {
+        $crate::export_abi_version!(env!("FFI_ABI_VERSION"));
+    };
+
+    ($version:expr) => {
+        /// Return the ABI version of this library.
+        #[no_mangle]
+        pub extern "C" fn ffi_abi_version() -> u64 {
+            const VERSION: u64 = $crate::abi::parse_abi_version($version);
+            VERSION
+        }
+
+        /// Check the ABI version expected by the caller against the one of this library.
+        #[no_mangle]
+        pub extern "C" fn ffi_check_abi_version(expected: u64) -> i32 {
+            $crate::ffi_result_code!($crate::abi::check_abi_version(expected, ffi_abi_version()))
+        }
+
+        /// Return the version of the C types defined by `sn_ffi_utils`.
+        #[no_mangle]
+        pub extern "C" fn ffi_utils_abi_version() -> u64 {
+            $crate::abi::FFI_UTILS_ABI_VERSION
+        }
+    };
+}
+
+/// Check the ABI version expected by the caller, typically in an exported init function. On
+/// mismatch, call the callback with an `ERR_ABI_VERSION_MISMATCH` error and return from the
+/// enclosing function.
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn app_init(
+///     abi_version: u64,
+///     user_data: *mut c_void,
+///     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+/// ) {
+///     require_abi!(abi_version, user_data, o_cb);
+///     // ...
+/// }
+/// ```
+///
+/// Like `export_abi_version!`, the version is read from `FFI_ABI_VERSION` by default, and may be
+/// given as a string literal instead.
+#[macro_export]
+macro_rules! require_abi {
+    ($expected:expr, $user_data:expr, $cb:expr) => {
+        $crate::require_abi!($expected, env!("FFI_ABI_VERSION"), $user_data, $cb)
+    };
+
+    ($expected:expr, $version:expr, $user_data:expr, $cb:expr) => {
+        if let Err(error) = $crate::abi::check_abi_version($expected, {
+            const ABI_VERSION: u64 = $crate::abi::parse_abi_version($version);
+            ABI_VERSION
+        }) {
+            $crate::call_result_cb!(Err::<(), _>(error), $user_data, $cb);
+            return;
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindgen_utils::{FfiDescription, FfiField, FfiFunction, FfiType};
+
+    #[test]
+    fn abi_version_tracks_signatures() {
+        let mut desc = FfiDescription::default();
+        desc.functions.push(FfiFunction {
+            name: "app_free".to_string(),
+            args: vec![FfiField::new("app", FfiType::VoidPtr)],
+            ret: FfiType::Void,
+        });
+
+        let v1 = desc.abi_version();
+        assert_eq!(v1, desc.clone().abi_version());
+
+        desc.functions[0].ret = FfiType::I32;
+        let v2 = desc.abi_version();
+        assert_ne!(v1, v2);
+
+        assert!(check_abi_version(v2, v2).is_ok());
+        assert_eq!(
+            check_abi_version(v1, v2).map_err(|e| e.error_code()),
+            Err(ERR_ABI_VERSION_MISMATCH)
+        );
+        assert_eq!(parse_abi_version(&v2.to_string()), v2);
+
+        // Renaming an argument doesn't change the ABI.
+        desc.functions[0].args[0].name = "handle".to_string();
+        assert_eq!(desc.abi_version(), v2);
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed ABI version")]
+    fn malformed_abi_version() {
+        let _ = parse_abi_version("garbage");
+    }
+
+    #[test]
+    fn require_abi_reports_mismatch() {
+        use crate::test_utils::call_0;
+        use crate::FfiResult;
+        use std::os::raw::c_void;
+
+        extern "C" fn init(
+            abi_version: u64,
+            user_data: *mut c_void,
+            o_cb: extern "C" fn(*mut c_void, *const FfiResult),
+        ) {
+            require_abi!(abi_version, "42", user_data, o_cb);
+            o_cb(user_data, crate::FFI_RESULT_OK);
+        }
+
+        assert_eq!(call_0(|ud, cb| init(42, ud, cb)), Ok(()));
+        assert_eq!(
+            call_0(|ud, cb| init(41, ud, cb)),
+            Err(ERR_ABI_VERSION_MISMATCH)
+        );
+    }
+}
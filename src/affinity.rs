@@ -0,0 +1,267 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Host-thread affinity of callbacks.
+//!
+//! Hosts such as JVMs or Swift apps may only accept callbacks on threads they know about, and
+//! a callback arriving on another thread usually crashes far from its cause. The host registers
+//! each thread it accepts callbacks on with `ffi_register_callback_thread` (exported with
+//! `export_callback_affinity!`), and declares with `ffi_set_callback_affinity` what happens to
+//! callbacks invoked on any other thread:
+//!
+//! + `AffinityPolicy::Assert` reports them as errors, and panics in debug builds. Coming from an
+//!   `extern "C"` function, the panic aborts the process with the offending thread in its
+//!   message;
+//! + `AffinityPolicy::Reroute` runs the callbacks invoked through `invoke` or `call_cb` on a
+//!   worker of the global dispatcher instead, and reports the others like `Assert`. On `wasm32`,
+//!   which has no dispatcher, it behaves like `Assert`.
+//!
+//! Under both policies, dispatcher workers are considered callback threads, so the host has to
+//! accept callbacks on them. Otherwise the callbacks queued with `Dispatcher::dispatch_cb` would
+//! never be delivered.
+//!
+//! The callbacks invoked through `call_result_cb!`, `call_cb_with_error`, `catch_unwind_cb` and
+//! the dispatcher are checked. Callbacks invoked directly aren't, unless preceded by `check`.
+
+use crate::callback::{cb_job, Callback};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::dispatcher;
+use crate::{ErrorCode, IntoReprC};
+use log::{error, warn};
+use std::cell::Cell;
+use std::fmt::{Debug, Display};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// What happens to callbacks invoked on a thread which isn't registered.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AffinityPolicy {
+    /// Callbacks may be invoked on any thread.
+    Off = 0,
+    /// Report the callbacks invoked on unregistered threads other than dispatcher workers,
+    /// panicking in debug builds.
+    Assert = 1,
+    /// Reroute the callbacks invoked on unregistered threads through the global dispatcher.
+    Reroute = 2,
+}
+
+impl AffinityPolicy {
+    /// Policy of the given FFI representation, as passed to `ffi_set_callback_affinity`.
+    pub fn from_u32(policy: u32) -> Option<Self> {
+        match policy {
+            0 => Some(AffinityPolicy::Off),
+            1 => Some(AffinityPolicy::Assert),
+            2 => Some(AffinityPolicy::Reroute),
+            _ => None,
+        }
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(AffinityPolicy::Off as u8);
+
+thread_local! {
+    static CALLBACK_THREAD: Cell<bool> = const { Cell::new(false) };
+    static DISPATCHER_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set the policy applied to callbacks invoked on unregistered threads.
+pub fn set_policy(policy: AffinityPolicy) {
+    POLICY.store(policy as u8, Ordering::Release);
+}
+
+/// Policy applied to callbacks invoked on unregistered threads.
+pub fn policy() -> AffinityPolicy {
+    AffinityPolicy::from_u32(u32::from(POLICY.load(Ordering::Acquire)))
+        .unwrap_or(AffinityPolicy::Off)
+}
+
+/// Accept callbacks on the current thread.
+pub fn register_current_thread() {
+    CALLBACK_THREAD.with(|registered| registered.set(true));
+}
+
+/// Stop accepting callbacks on the current thread, e.g. before it exits.
+pub fn unregister_current_thread() {
+    CALLBACK_THREAD.with(|registered| registered.set(false));
+}
+
+/// Return `true` if callbacks may be invoked on the current thread under the current policy.
+pub fn is_callback_thread() -> bool {
+    match policy() {
+        AffinityPolicy::Off => true,
+        AffinityPolicy::Assert | AffinityPolicy::Reroute => {
+            CALLBACK_THREAD.with(Cell::get) || DISPATCHER_WORKER.with(Cell::get)
+        }
+    }
+}
+
+// Flag the current thread as a worker of a dispatcher.
+pub(crate) fn mark_dispatcher_worker() {
+    DISPATCHER_WORKER.with(|worker| worker.set(true));
+}
+
+/// Report a callback about to be invoked on the current thread if it isn't allowed to: log an
+/// error, and panic in debug builds.
+pub fn check() {
+    if !is_callback_thread() {
+        violation()
+    }
+}
+
+fn violation() {
+    let thread = thread::current();
+    let message = format!(
+        "Callback invoked on thread {:?} ({}), which isn't registered with \
+         ffi_register_callback_thread",
+        thread.id(),
+        thread.name().unwrap_or("unnamed"),
+    );
+
+    error!("{}", message);
+    if cfg!(debug_assertions) {
+        panic!("{}", message);
+    }
+}
+
+/// Run `f`, which invokes a callback, on the current thread if it is allowed to. Otherwise
+/// queue it on the global dispatcher under `AffinityPolicy::Reroute`, or report it with `check`.
+pub fn invoke<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    if is_callback_thread() {
+        return f();
+    }
+    if policy() != AffinityPolicy::Reroute {
+        violation();
+        return f();
+    }
+    reroute(f)
+}
+
+// There is no dispatcher to reroute to on `wasm32`.
+#[cfg(target_arch = "wasm32")]
+fn reroute<F: FnOnce()>(f: F) {
+    violation();
+    f()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn reroute<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    // Keep hold of the job, to run it here if it can't be queued.
+    let job = Arc::new(Mutex::new(Some(f)));
+    let queued = Arc::clone(&job);
+    let take = |job: &Mutex<Option<F>>| job.lock().unwrap_or_else(PoisonError::into_inner).take();
+
+    if let Err(e) = dispatcher::global().dispatch(move || {
+        if let Some(f) = take(&queued) {
+            f()
+        }
+    }) {
+        warn!("Failed to reroute a callback: {}", e);
+        violation();
+        if let Some(f) = take(&job) {
+            f()
+        }
+    }
+}
+
+/// Invoke `cb` with `result`, like `Dispatcher::dispatch_cb`, on the current thread if it is
+/// allowed to or through `invoke` otherwise.
+pub fn call_cb<U, C, T, E>(user_data: U, cb: C, result: Result<T, E>)
+where
+    U: Into<*mut c_void>,
+    C: Callback<Args = T::C> + Send + 'static,
+    T: IntoReprC + Send + 'static,
+    T::Error: Debug,
+    E: Debug + Display + ErrorCode + From<&'static str> + Send + 'static,
+{
+    invoke(cb_job(user_data, cb, result))
+}
+
+/// Export the callback thread affinity functions of the library.
+///
+/// Defines three `#[no_mangle]` functions:
+///
+/// + `ffi_register_callback_thread()` accepting callbacks on the calling thread;
+/// + `ffi_unregister_callback_thread()` no longer accepting callbacks on the calling thread;
+/// + `ffi_set_callback_affinity(policy: u32) -> i32` setting the `AffinityPolicy` (0 for `Off`,
+///   1 for `Assert` and 2 for `Reroute`), returning 0 on success or `ERR_INVALID_ARG` for an
+///   unknown policy.
+#[macro_export]
+macro_rules! export_callback_affinity {
+    () => {
+        /// Accept callbacks on the calling thread.
+        #[no_mangle]
+        pub extern "C" fn ffi_register_callback_thread() {
+            $crate::affinity::register_current_thread()
+        }
+
+        /// Stop accepting callbacks on the calling thread.
+        #[no_mangle]
+        pub extern "C" fn ffi_unregister_callback_thread() {
+            $crate::affinity::unregister_current_thread()
+        }
+
+        /// Set the policy applied to callbacks invoked on unregistered threads.
+        #[no_mangle]
+        pub extern "C" fn ffi_set_callback_affinity(policy: u32) -> i32 {
+            match $crate::affinity::AffinityPolicy::from_u32(policy) {
+                Some(policy) => {
+                    $crate::affinity::set_policy(policy);
+                    0
+                }
+                None => $crate::ERR_INVALID_ARG,
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use unwrap::unwrap;
+
+    // The policy is global, so these tests only check the per-thread state; the policies are
+    // exercised by the `affinity` integration test.
+
+    #[test]
+    fn registration_is_per_thread() {
+        register_current_thread();
+        assert!(CALLBACK_THREAD.with(Cell::get));
+        assert!(!unwrap!(
+            thread::spawn(|| CALLBACK_THREAD.with(Cell::get)).join()
+        ));
+
+        unregister_current_thread();
+        assert!(!CALLBACK_THREAD.with(Cell::get));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn dispatcher_workers_are_flagged() {
+        let (tx, rx) = mpsc::channel();
+        let dispatcher = dispatcher::Dispatcher::new(Default::default());
+        unwrap!(dispatcher.dispatch(move || unwrap!(tx.send(DISPATCHER_WORKER.with(Cell::get)))));
+        assert!(unwrap!(rx.recv()));
+        assert!(!DISPATCHER_WORKER.with(Cell::get));
+    }
+
+    #[test]
+    fn policy_from_u32() {
+        assert_eq!(AffinityPolicy::from_u32(2), Some(AffinityPolicy::Reroute));
+        assert_eq!(AffinityPolicy::from_u32(3), None);
+    }
+}
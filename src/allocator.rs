@@ -0,0 +1,187 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Host-provided allocator for the buffers handed over to the host.
+//!
+//! By default, buffers transferred with `vec_into_raw_parts` are allocated by Rust and must be
+//! returned to Rust to be released. A host which would rather own that memory registers its
+//! allocation functions with `ffi_set_allocator` (exported with `export_allocator!`) before any
+//! other call; outgoing buffers are then allocated with `alloc`, and the host may release them
+//! with the matching `free` itself. Buffers returned to Rust with `vec_from_raw_parts` are
+//! released with `free` too.
+
+use std::alloc::{self, Layout};
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::OnceLock;
+
+/// Error code returned by `ffi_set_allocator` when an allocator is already registered, or a
+/// buffer has already been transferred.
+pub const ERR_ALLOCATOR_ALREADY_SET: i32 = -9015;
+
+/// Allocate `size` bytes aligned to `align`. Returns null on failure.
+pub type AllocFn = extern "C" fn(size: usize, align: usize) -> *mut c_void;
+
+/// Release memory returned by the matching `AllocFn`, with the same `size` and `align`.
+pub type FreeFn = extern "C" fn(ptr: *mut c_void, size: usize, align: usize);
+
+/// Pair of host allocation functions.
+#[derive(Clone, Copy, Debug)]
+pub struct HostAllocator {
+    /// Allocation function.
+    pub alloc: AllocFn,
+    /// Deallocation function.
+    pub free: FreeFn,
+}
+
+impl HostAllocator {
+    /// Move the elements of `v` into memory allocated with `alloc`, returning (pointer, size).
+    /// Empty buffers aren't allocated, and are represented by a dangling pointer.
+    pub fn transfer<T>(&self, mut v: Vec<T>) -> (*mut T, usize) {
+        let len = v.len();
+        let layout = layout::<T>(len);
+        if layout.size() == 0 {
+            v.truncate(0);
+            return (ptr::NonNull::dangling().as_ptr(), len);
+        }
+
+        let ptr = (self.alloc)(layout.size(), layout.align()) as *mut T;
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(v.as_ptr(), ptr, len);
+            // The elements now live in the host buffer.
+            v.set_len(0);
+        }
+        (ptr, len)
+    }
+
+    /// Move the elements of a buffer returned by `transfer` back into a `Vec`, releasing the
+    /// buffer with `free`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must have been returned by `transfer` on an allocator with the same
+    /// functions, and must not be used afterwards.
+    pub unsafe fn reclaim<T>(&self, ptr: *mut T, len: usize) -> Vec<T> {
+        let mut v = Vec::with_capacity(len);
+        ptr::copy_nonoverlapping(ptr, v.as_mut_ptr(), len);
+        v.set_len(len);
+
+        let layout = layout::<T>(len);
+        if layout.size() > 0 {
+            (self.free)(ptr as *mut c_void, layout.size(), layout.align());
+        }
+        v
+    }
+}
+
+fn layout<T>(len: usize) -> Layout {
+    // `Vec` guarantees that the layout of its buffer is valid.
+    Layout::array::<T>(len).unwrap_or_else(|_| unreachable!())
+}
+
+// Set on the first registration or transfer, whichever comes first: `None` once a buffer has
+// been transferred without a host allocator, whose buffers must keep being released by Rust.
+static ALLOCATOR: OnceLock<Option<HostAllocator>> = OnceLock::new();
+
+/// Register the host allocator. Fails, returning `allocator`, if one is already registered or if
+/// a buffer has already been transferred with `vec_into_raw_parts`.
+pub fn set_allocator(allocator: HostAllocator) -> Result<(), HostAllocator> {
+    ALLOCATOR.set(Some(allocator)).map_err(|_| allocator)
+}
+
+/// Return the registered host allocator, if any.
+pub fn allocator() -> Option<&'static HostAllocator> {
+    ALLOCATOR.get().and_then(Option::as_ref)
+}
+
+// Return the allocator for a buffer about to be transferred, preventing any later registration.
+pub(crate) fn allocator_for_transfer() -> Option<&'static HostAllocator> {
+    ALLOCATOR.get_or_init(|| None).as_ref()
+}
+
+/// Export the allocator registration function of the library.
+///
+/// Defines `ffi_set_allocator(alloc: AllocFn, free: FreeFn) -> i32`, returning 0 on success or
+/// `ERR_ALLOCATOR_ALREADY_SET`. See `allocator::set_allocator`.
+#[macro_export]
+macro_rules! export_allocator {
+    () => {
+        /// Register the functions allocating and releasing the buffers handed over to the
+        /// caller.
+        #[no_mangle]
+        pub extern "C" fn ffi_set_allocator(
+            alloc: $crate::allocator::AllocFn,
+            free: $crate::allocator::FreeFn,
+        ) -> i32 {
+            match $crate::allocator::set_allocator($crate::allocator::HostAllocator { alloc, free })
+            {
+                Ok(()) => 0,
+                Err(_) => $crate::allocator::ERR_ALLOCATOR_ALREADY_SET,
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static OUTSTANDING: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn host_alloc(size: usize, align: usize) -> *mut c_void {
+        let _ = OUTSTANDING.fetch_add(size, Ordering::SeqCst);
+        unsafe { alloc::alloc(Layout::from_size_align_unchecked(size, align)) as *mut c_void }
+    }
+
+    extern "C" fn host_free(ptr: *mut c_void, size: usize, align: usize) {
+        let _ = OUTSTANDING.fetch_sub(size, Ordering::SeqCst);
+        unsafe {
+            alloc::dealloc(
+                ptr as *mut u8,
+                Layout::from_size_align_unchecked(size, align),
+            )
+        }
+    }
+
+    #[test]
+    fn transfer_through_host_allocator() {
+        let allocator = HostAllocator {
+            alloc: host_alloc,
+            free: host_free,
+        };
+
+        let (ptr, len) = allocator.transfer(vec![String::from("a"), String::from("b")]);
+        assert_eq!(len, 2);
+        assert_eq!(OUTSTANDING.load(Ordering::SeqCst), 2 * size_of::<String>());
+        let v = unsafe { allocator.reclaim(ptr, len) };
+        assert_eq!(v, ["a", "b"]);
+        assert_eq!(OUTSTANDING.load(Ordering::SeqCst), 0);
+
+        let (ptr, len) = allocator.transfer(Vec::<u64>::new());
+        assert_eq!(OUTSTANDING.load(Ordering::SeqCst), 0);
+        assert!(unsafe { allocator.reclaim(ptr, len) }.is_empty());
+    }
+
+    #[test]
+    fn registration_after_transfer_fails() {
+        let (ptr, len) = crate::vec_into_raw_parts(vec![1u8, 2]);
+        let allocator = HostAllocator {
+            alloc: host_alloc,
+            free: host_free,
+        };
+        assert!(set_allocator(allocator).is_err());
+        assert!(self::allocator().is_none());
+        assert_eq!(unsafe { crate::vec_from_raw_parts(ptr, len) }, [1, 2]);
+    }
+}
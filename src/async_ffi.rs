@@ -0,0 +1,195 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Running futures on behalf of FFI functions and reporting their outcome through callbacks.
+//!
+//! The runtime is started on first use: a single-threaded tokio runtime driven by a background
+//! thread with the `tokio` feature, or the global async-std executor with the `async-std`
+//! feature.
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_fetch(
+//!     app: Handle,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, data: *const c_char),
+//! ) {
+//!     async_ffi::spawn_cb(user_data, o_cb, async move {
+//!         let app = handles::get::<App>(app)?;
+//!         app.fetch().await
+//!     })
+//! }
+//! ```
+
+use crate::callback::Callback;
+use crate::catch_unwind::{call_error_cb, trace_callback};
+use crate::{ffi_error, ErrorCode, IntoReprC, OpaqueCtx, FFI_RESULT_OK};
+use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Run `future` to completion in the background.
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    {
+        drop(runtime().spawn(future));
+    }
+    #[cfg(not(feature = "tokio"))]
+    {
+        drop(async_std::task::spawn(future));
+    }
+}
+
+#[cfg(feature = "tokio")]
+fn runtime() -> &'static tokio::runtime::Handle {
+    use std::sync::OnceLock;
+    use std::thread;
+
+    static RUNTIME: OnceLock<tokio::runtime::Handle> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        let runtime = unwrap::unwrap!(tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build());
+        let handle = runtime.handle().clone();
+        // Spawned futures make progress while the runtime is blocked on by its thread.
+        let _ = unwrap::unwrap!(thread::Builder::new()
+            .name("ffi-async".to_string())
+            .spawn(move || runtime.block_on(std::future::pending::<()>())));
+        handle
+    })
+}
+
+/// Run `future` in the background and call `cb` with its outcome: the value converted to its
+/// FFI representation on success, or the error converted through `NativeResult` on failure.
+///
+/// Panics in the future are caught and reported as errors, like with `catch_unwind_cb`.
+#[track_caller]
+pub fn spawn_cb<U, C, F, T, E>(user_data: U, cb: C, future: F)
+where
+    U: Into<*mut c_void>,
+    C: Callback<Args = T::C> + Copy + Send + 'static,
+    F: Future<Output = Result<T, E>> + Send + 'static,
+    T: IntoReprC,
+    T::Error: Debug,
+    E: Debug + Display + ErrorCode + From<&'static str>,
+{
+    let user_data = OpaqueCtx::from_host_pointer(user_data.into());
+    #[cfg(feature = "metrics")]
+    let timer = crate::metrics::Timer::start(crate::catch_unwind::function_name::<F>());
+
+    #[cfg(feature = "tracing")]
+    let (span, call_id) = crate::trace::call_span::<F>(panic::Location::caller()).into_parts();
+
+    let task = async move {
+        let result = match CatchUnwind(future).await {
+            Ok(result) => result,
+            Err(()) => Err(E::from("panic")),
+        };
+        #[cfg(feature = "tracing")]
+        let _call = crate::trace::CurrentCall::set(call_id);
+
+        let error = match result.map(IntoReprC::into_repr_c) {
+            Ok(Ok((repr_c, _storage))) => {
+                trace_callback(0);
+                #[cfg(feature = "metrics")]
+                timer.finish(0);
+                cb.call(user_data.as_ptr(), FFI_RESULT_OK, repr_c);
+                return;
+            }
+            Ok(Err(e)) => {
+                log::debug!(
+                    "Could not convert result into its FFI representation: {:?}",
+                    e
+                );
+                E::from("Could not convert result into its FFI representation")
+            }
+            Err(error) => error,
+        };
+
+        let (error_code, description) = ffi_error!(error);
+        #[cfg(feature = "metrics")]
+        timer.finish(error_code);
+        call_error_cb(user_data.as_ptr(), cb, error_code, description);
+    };
+
+    #[cfg(feature = "tracing")]
+    let task = tracing::Instrument::instrument(task, span);
+
+    spawn(task);
+}
+
+// Future resolving to `Err` if polling the inner future panics.
+struct CatchUnwind<F>(F);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, ()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // The inner future is never moved out of the pinned wrapper.
+        let inner = unsafe { self.map_unchecked_mut(|this| &mut this.0) };
+        match panic::catch_unwind(AssertUnwindSafe(|| inner.poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(_) => Poll::Ready(Err(())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_1, TestError};
+    use crate::FfiResult;
+    use unwrap::unwrap;
+
+    extern "C" fn answer(
+        fail: bool,
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, u32),
+    ) {
+        spawn_cb(user_data, o_cb, async move {
+            if fail {
+                Err(TestError::Test)
+            } else {
+                Ok(42u32)
+            }
+        })
+    }
+
+    extern "C" fn panicking(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, u32),
+    ) {
+        let explode = true;
+        spawn_cb(user_data, o_cb, async move {
+            if !explode {
+                return Ok::<_, TestError>(0u32);
+            }
+            panic!("boom")
+        })
+    }
+
+    #[test]
+    fn outcome_is_delivered_to_callback() {
+        let value: u32 = unsafe { unwrap!(call_1(|ud, cb| answer(false, ud, cb))) };
+        assert_eq!(value, 42);
+
+        let res: Result<u32, i32> = unsafe { call_1(|ud, cb| answer(true, ud, cb)) };
+        assert_eq!(res, Err(-1));
+
+        let res: Result<u32, i32> = unsafe { call_1(|ud, cb| panicking(ud, cb)) };
+        assert_eq!(res, Err(-2));
+    }
+}
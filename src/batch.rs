@@ -0,0 +1,360 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Coalescing of high-frequency events into batches delivered by a single callback.
+//!
+//! Events pushed to a `Batcher` accumulate in a buffer, each prefixed with its length as a
+//! little-endian `u32`. The buffer is handed to the callback, along with the number of events it
+//! contains, once it holds `max_events` events or `max_bytes` bytes, once `interval` has elapsed
+//! since its first event, when flushed explicitly, or when the last clone of the batcher is
+//! dropped:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn app_upload(
+//!     app: Handle,
+//!     user_data: *mut c_void,
+//!     o_progress: BatchCallback,
+//! ) -> i32 {
+//!     let progress = Batcher::new(user_data, o_progress, BatchPolicy::default());
+//!     ffi_result_code!(handles::get::<App>(app).map(|app| {
+//!         app.upload(move |sent| unwrap!(progress.push(&sent.to_le_bytes())))
+//!     }))
+//! }
+//! ```
+//!
+//! Rust hosts, and tests, can split a batch with `events`.
+
+use crate::payload::{self, PayloadTooLarge};
+use crate::serde_bridge::{Format, SerdeError};
+use crate::timers::{self, TimerHandle};
+use crate::{dispatcher, OpaqueCtx};
+use log::warn;
+use serde::Serialize;
+use std::mem;
+use std::os::raw::c_void;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Duration;
+
+/// Callback receiving a batch of `count` events, serialised in `data`. The pointer is valid for
+/// the duration of the call.
+pub type BatchCallback =
+    extern "C" fn(user_data: *mut c_void, data: *const u8, len: usize, count: u32);
+
+/// When a `Batcher` delivers its events.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BatchPolicy {
+    /// Deliver once this many events are pending.
+    pub max_events: usize,
+    /// Deliver once the pending events take this many bytes, length prefixes included.
+    pub max_bytes: usize,
+    /// Deliver this long after the first pending event was pushed, if set.
+    pub interval: Option<Duration>,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        Self {
+            max_events: 64,
+            max_bytes: 64 * 1024,
+            interval: Some(Duration::from_millis(100)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    buffer: Vec<u8>,
+    count: u32,
+    timer_armed: bool,
+    timer: Option<TimerHandle>,
+}
+
+struct Inner {
+    user_data: OpaqueCtx,
+    cb: BatchCallback,
+    policy: BatchPolicy,
+    pending: Mutex<Pending>,
+    // Held while delivering, so that batches are delivered in order. Set once the last
+    // `Batcher` is dropped, after which nothing is delivered.
+    closed: Mutex<bool>,
+}
+
+// `user_data` is only handed to the callback, which hosts expect to be called from any thread.
+unsafe impl Sync for Inner {}
+
+impl Inner {
+    fn pending(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn flush(&self) {
+        let closed = self.closed.lock().unwrap_or_else(PoisonError::into_inner);
+        if *closed {
+            return;
+        }
+        self.deliver()
+    }
+
+    // Deliver the last batch and stop any further delivery. Returns once any delivery in
+    // progress on another thread has completed.
+    fn close(&self) {
+        let mut closed = self.closed.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(timer) = self.pending().timer.take() {
+            let _ = timers::cancel(timer);
+        }
+        if !*closed {
+            self.deliver();
+            *closed = true;
+        }
+    }
+
+    // Must be called with `closed` locked.
+    fn deliver(&self) {
+        let (buffer, count) = {
+            let mut pending = self.pending();
+            (
+                mem::take(&mut pending.buffer),
+                mem::take(&mut pending.count),
+            )
+        };
+
+        if count > 0 {
+            (self.cb)(
+                self.user_data.as_ptr(),
+                buffer.as_ptr(),
+                buffer.len(),
+                count,
+            );
+        }
+    }
+}
+
+/// Accumulates events and delivers them in batches to a callback. Clones share the same
+/// buffer. Pending events are delivered when the last clone is dropped, on the dropping thread,
+/// and the callback is never invoked once that drop has returned.
+///
+/// The callback is invoked on the thread which triggered the delivery, or on the global
+/// dispatcher for interval deliveries, and must not push to the same batcher.
+#[derive(Clone)]
+pub struct Batcher {
+    inner: Arc<Owner>,
+}
+
+// Shared by the clones of a `Batcher`. Timers only hold a weak reference to the `Inner`, so
+// dropping the last clone closes it.
+struct Owner(Arc<Inner>);
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        self.0.close()
+    }
+}
+
+impl Batcher {
+    /// Create a batcher delivering to `cb` according to `policy`.
+    pub fn new<U: Into<*mut c_void>>(user_data: U, cb: BatchCallback, policy: BatchPolicy) -> Self {
+        Self {
+            inner: Arc::new(Owner(Arc::new(Inner {
+                user_data: OpaqueCtx::from_host_pointer(user_data.into()),
+                cb,
+                policy,
+                pending: Mutex::new(Pending::default()),
+                closed: Mutex::new(false),
+            }))),
+        }
+    }
+
+    /// Add an event, delivering the batch if it is full. Events longer than `u32::MAX` bytes
+    /// can't be prefixed with their length, and are rejected.
+    pub fn push(&self, event: &[u8]) -> Result<(), PayloadTooLarge> {
+        let inner = &self.inner.0;
+        let policy = &inner.policy;
+        payload::check_len(event.len(), u32::MAX as usize)?;
+
+        let (full, arm_timer) = {
+            let mut pending = inner.pending();
+            pending
+                .buffer
+                .extend_from_slice(&(event.len() as u32).to_le_bytes());
+            pending.buffer.extend_from_slice(event);
+            pending.count += 1;
+
+            let full = pending.count as usize >= policy.max_events
+                || pending.buffer.len() >= policy.max_bytes;
+            let arm_timer = !full && !pending.timer_armed && policy.interval.is_some();
+            pending.timer_armed |= arm_timer;
+            (full, arm_timer)
+        };
+
+        if full {
+            inner.flush();
+        } else if let (true, Some(interval)) = (arm_timer, policy.interval) {
+            let weak = Arc::downgrade(inner);
+            let scheduled = timers::schedule_fn(interval, move |_| {
+                if let Err(error) = dispatcher::global().dispatch(move || flush_weak(&weak)) {
+                    warn!("Failed to dispatch batch delivery: {}", error);
+                }
+            });
+            match scheduled {
+                Some(timer) => inner.pending().timer = Some(timer),
+                // Without a timer the events would wait for the batch to fill up: deliver them
+                // now.
+                None => flush_weak(&Arc::downgrade(inner)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Serialise `value` with the format `F` and add it as an event.
+    pub fn push_serialized<F: Format, T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.push(&F::encode(value)?)
+            .map_err(|e| SerdeError(e.to_string()))
+    }
+
+    /// Deliver the pending events now.
+    pub fn flush(&self) {
+        self.inner.0.flush()
+    }
+
+    /// Number of pending events.
+    pub fn len(&self) -> usize {
+        self.inner.0.pending().count as usize
+    }
+
+    /// Return `true` if no events are pending.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn flush_weak(inner: &Weak<Inner>) {
+    if let Some(inner) = inner.upgrade() {
+        {
+            let mut pending = inner.pending();
+            pending.timer_armed = false;
+            pending.timer = None;
+        }
+        inner.flush();
+    }
+}
+
+/// Split a batch delivered to a `BatchCallback` into its events. Stops at the first truncated
+/// event.
+pub fn events(batch: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = batch;
+    std::iter::from_fn(move || {
+        let (len, tail) = rest.split_at_checked(4)?;
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let (event, tail) = tail.split_at_checked(len)?;
+        rest = tail;
+        Some(event)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+    use std::slice;
+    use std::sync::mpsc::{self, Sender};
+    use unwrap::unwrap;
+
+    extern "C" fn on_batch(user_data: *mut c_void, data: *const u8, len: usize, count: u32) {
+        let tx = unsafe { &*(user_data as *const Sender<Vec<Vec<u8>>>) };
+        let events: Vec<_> = events(unsafe { slice::from_raw_parts(data, len) })
+            .map(<[u8]>::to_vec)
+            .collect();
+        assert_eq!(events.len(), count as usize);
+        unwrap!(tx.send(events));
+    }
+
+    #[test]
+    fn flush_on_size() {
+        let (tx, rx) = mpsc::channel::<Vec<Vec<u8>>>();
+        let policy = BatchPolicy {
+            max_events: 3,
+            max_bytes: 16,
+            interval: None,
+        };
+        let batcher = Batcher::new(ptr::from_ref(&tx) as *mut c_void, on_batch, policy);
+
+        unwrap!(batcher.push(&[1]));
+        unwrap!(batcher.push(&[2, 2]));
+        assert_eq!(batcher.len(), 2);
+        unwrap!(batcher.push(&[3]));
+        assert_eq!(unwrap!(rx.try_recv()), vec![vec![1], vec![2, 2], vec![3]]);
+
+        unwrap!(batcher.push(&[0; 12]));
+        assert_eq!(unwrap!(rx.try_recv()), vec![vec![0; 12]]);
+
+        unwrap!(batcher.push(&[4]));
+        drop(batcher);
+        assert_eq!(unwrap!(rx.try_recv()), vec![vec![4]]);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn flush_on_interval() {
+        let (tx, rx) = mpsc::channel::<Vec<Vec<u8>>>();
+        let policy = BatchPolicy {
+            interval: Some(Duration::from_millis(20)),
+            ..BatchPolicy::default()
+        };
+        let batcher = Batcher::new(ptr::from_ref(&tx) as *mut c_void, on_batch, policy);
+
+        unwrap!(batcher.push(&[1]));
+        unwrap!(batcher.push(&[2]));
+        assert_eq!(
+            unwrap!(rx.recv_timeout(Duration::from_secs(5))),
+            vec![vec![1], vec![2]]
+        );
+        assert!(batcher.is_empty());
+
+        // Dropping the batcher waits for a delivery in progress, which borrows `tx`.
+        drop(batcher);
+    }
+
+    #[test]
+    fn no_delivery_after_drop() {
+        let (tx, rx) = mpsc::channel::<Vec<Vec<u8>>>();
+        let policy = BatchPolicy {
+            interval: Some(Duration::from_millis(10)),
+            ..BatchPolicy::default()
+        };
+        let batcher = Batcher::new(ptr::from_ref(&tx) as *mut c_void, on_batch, policy);
+
+        unwrap!(batcher.push(&[1]));
+        drop(batcher);
+        assert_eq!(unwrap!(rx.try_recv()), vec![vec![1]]);
+
+        // The interval has elapsed without any further delivery.
+        std::thread::sleep(Duration::from_millis(50));
+        dispatcher::global().drain();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn oversized_event_is_rejected() {
+        let batcher = Batcher::new(ptr::null_mut(), on_batch, BatchPolicy::default());
+        let event = vec![0; u32::MAX as usize + 1];
+        assert_eq!(
+            batcher.push(&event),
+            Err(PayloadTooLarge {
+                len: event.len(),
+                limit: u32::MAX as usize
+            })
+        );
+        assert!(batcher.is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Android binding asset pipeline.
+//!
+//! Packages cross-compiled shared libraries into the `jniLibs/<abi>/` layout expected by Gradle
+//! and generates the Kotlin glue every app otherwise writes by hand.
+
+use super::desc::{FfiDescription, FfiField, FfiType};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Rust target triples supported on Android and their corresponding Android ABI names.
+pub const ANDROID_TARGETS: &[(&str, &str)] = &[
+    ("aarch64-linux-android", "arm64-v8a"),
+    ("armv7-linux-androideabi", "armeabi-v7a"),
+    ("i686-linux-android", "x86"),
+    ("x86_64-linux-android", "x86_64"),
+];
+
+/// Return the Android ABI name (e.g. `arm64-v8a`) for a Rust target triple.
+pub fn android_abi(target: &str) -> Option<&'static str> {
+    ANDROID_TARGETS
+        .iter()
+        .find(|(triple, _)| *triple == target)
+        .map(|(_, abi)| *abi)
+}
+
+/// Copy `lib<lib_name>.so` for every Android target built under `target_dir` into
+/// `jni_libs_dir/<abi>/`.
+///
+/// `target_dir` is the Cargo target directory and `profile` is `debug` or `release`. Targets
+/// which have not been built are skipped. Returns the ABIs that were packaged.
+pub fn copy_jni_libs<S: AsRef<Path>, T: AsRef<Path>>(
+    target_dir: S,
+    profile: &str,
+    lib_name: &str,
+    jni_libs_dir: T,
+) -> io::Result<Vec<&'static str>> {
+    let file_name = format!("lib{}.so", lib_name);
+    let mut packaged = Vec::new();
+
+    for (triple, abi) in ANDROID_TARGETS {
+        let source = target_dir
+            .as_ref()
+            .join(triple)
+            .join(profile)
+            .join(&file_name);
+        if !source.is_file() {
+            continue;
+        }
+
+        let target = jni_libs_dir.as_ref().join(abi);
+        fs::create_dir_all(&target)?;
+        let _ = fs::copy(&source, target.join(&file_name))?;
+        packaged.push(*abi);
+    }
+
+    Ok(packaged)
+}
+
+/// Generate a Kotlin object which loads the native library when first accessed.
+///
+/// Other generated classes can reference `<class_name>.init()` to make sure the library is
+/// loaded before calling any `external` function.
+pub fn generate_loader(package: &str, class_name: &str, lib_name: &str) -> String {
+    format!(
+        "// Automatically generated. Do not edit.\n\
+         package {package}\n\
+         \n\
+         object {class} {{\n    \
+             init {{\n        \
+                 System.loadLibrary(\"{lib}\")\n    \
+             }}\n\
+         \n    \
+             /** Forces the native library to be loaded. */\n    \
+             fun init() {{}}\n\
+         }}\n",
+        package = package,
+        class = class_name,
+        lib = lib_name
+    )
+}
+
+/// Generate Kotlin `typealias`es for the callbacks in the FFI description.
+///
+/// The `user_data` argument is dropped, as the JNI layer maps it to the callback object itself.
+/// A `usize` length argument directly following a pointer argument is folded into it, since
+/// on the Kotlin side both become a single array or object.
+pub fn generate_callback_typealiases(desc: &FfiDescription, package: &str) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "// Automatically generated. Do not edit.");
+    let _ = writeln!(out, "package {}\n", package);
+
+    for cb in &desc.callbacks {
+        let params = kotlin_params(&cb.args)
+            .into_iter()
+            .map(|(name, ty)| format!("{}: {}", kotlin_name(&name), ty))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(out, "typealias {} = ({}) -> Unit", cb.name, params);
+    }
+
+    out
+}
+
+/// Write the loader object and the callback typealiases into `source_dir`, laid out according
+/// to `package`.
+pub fn write_kotlin_sources<P: AsRef<Path>>(
+    desc: &FfiDescription,
+    package: &str,
+    class_name: &str,
+    lib_name: &str,
+    source_dir: P,
+) -> io::Result<()> {
+    let dir = package
+        .split('.')
+        .fold(source_dir.as_ref().to_path_buf(), |dir, part| {
+            dir.join(part)
+        });
+    fs::create_dir_all(&dir)?;
+
+    fs::write(
+        dir.join(format!("{}.kt", class_name)),
+        generate_loader(package, class_name, lib_name),
+    )?;
+    fs::write(
+        dir.join("Callbacks.kt"),
+        generate_callback_typealiases(desc, package),
+    )
+}
+
+fn kotlin_params(args: &[FfiField]) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut prev_is_ptr = false;
+
+    for arg in args {
+        let is_ptr = match arg.ty {
+            FfiType::Ptr(_) | FfiType::MutPtr(_) => true,
+            FfiType::Usize if prev_is_ptr => {
+                prev_is_ptr = false;
+                continue;
+            }
+            _ => false,
+        };
+        prev_is_ptr = is_ptr;
+
+        if arg.name == "user_data" {
+            continue;
+        }
+
+        params.push((arg.name.clone(), kotlin_type(&arg.ty)));
+    }
+
+    params
+}
+
+fn kotlin_type(ty: &FfiType) -> String {
+    match ty {
+        FfiType::Void => "Unit".to_string(),
+        FfiType::Bool => "Boolean".to_string(),
+        FfiType::U8 => "Byte".to_string(),
+        FfiType::I32 | FfiType::U32 => "Int".to_string(),
+        FfiType::I64 | FfiType::U64 | FfiType::Usize | FfiType::VoidPtr => "Long".to_string(),
+        FfiType::F32 => "Float".to_string(),
+        FfiType::F64 => "Double".to_string(),
+        FfiType::CString => "String".to_string(),
+        FfiType::Ptr(inner) | FfiType::MutPtr(inner) | FfiType::Array(inner, _) => match **inner {
+            FfiType::U8 => "ByteArray".to_string(),
+            FfiType::I32 | FfiType::U32 => "IntArray".to_string(),
+            FfiType::I64 | FfiType::U64 => "LongArray".to_string(),
+            FfiType::F32 => "FloatArray".to_string(),
+            FfiType::F64 => "DoubleArray".to_string(),
+            _ => kotlin_type(inner),
+        },
+        FfiType::Struct(name) | FfiType::Callback(name) => name.clone(),
+    }
+}
+
+// Convert `snake_case` argument names into Kotlin's `camelCase`.
+fn kotlin_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+
+    for c in name.chars() {
+        if c == '_' {
+            upper = !out.is_empty();
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindgen_utils::FfiCallback;
+
+    #[test]
+    fn callback_typealiases() {
+        let desc = FfiDescription {
+            callbacks: vec![FfiCallback {
+                name: "ContentCb".to_string(),
+                args: vec![
+                    FfiField::new("user_data", FfiType::VoidPtr),
+                    FfiField::new(
+                        "result",
+                        FfiType::Ptr(Box::new(FfiType::Struct("FfiResult".to_string()))),
+                    ),
+                    FfiField::new("content_ptr", FfiType::Ptr(Box::new(FfiType::U8))),
+                    FfiField::new("content_len", FfiType::Usize),
+                    FfiField::new("version", FfiType::U64),
+                ],
+            }],
+            ..Default::default()
+        };
+
+        let aliases = generate_callback_typealiases(&desc, "net.maidsafe.api");
+        assert!(aliases.contains("package net.maidsafe.api"));
+        assert!(aliases.contains(concat!(
+            "typealias ContentCb = ",
+            "(result: FfiResult, contentPtr: ByteArray, version: Long) -> Unit"
+        )));
+
+        assert_eq!(android_abi("aarch64-linux-android"), Some("arm64-v8a"));
+        assert_eq!(android_abi("x86_64-unknown-linux-gnu"), None);
+    }
+}
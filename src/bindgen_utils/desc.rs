@@ -0,0 +1,190 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Language-neutral description of an FFI surface, consumed by the binding emitters.
+
+use std::fmt::Write;
+
+/// Type of a value crossing the FFI boundary.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FfiType {
+    /// No value (only valid as a return type).
+    Void,
+    /// `bool`.
+    Bool,
+    /// `u8`.
+    U8,
+    /// `i32`.
+    I32,
+    /// `i64`.
+    I64,
+    /// `u32`.
+    U32,
+    /// `u64`.
+    U64,
+    /// `usize`.
+    Usize,
+    /// `f32`.
+    F32,
+    /// `f64`.
+    F64,
+    /// Nul-terminated UTF-8 string (`*const c_char`).
+    CString,
+    /// Opaque pointer (`*mut c_void`), e.g. `user_data` or a handle.
+    VoidPtr,
+    /// Const pointer to the given type.
+    Ptr(Box<FfiType>),
+    /// Mutable pointer to the given type.
+    MutPtr(Box<FfiType>),
+    /// Fixed-size array of the given type.
+    Array(Box<FfiType>, usize),
+    /// `#[repr(C)]` struct defined in the description (or `FfiResult`).
+    Struct(String),
+    /// Callback type defined in the description.
+    Callback(String),
+}
+
+impl FfiType {
+    /// Return the C spelling of the type, as used in headers and `cffi` declarations.
+    pub fn c_type(&self) -> String {
+        match self {
+            FfiType::Void => "void".to_string(),
+            FfiType::Bool => "bool".to_string(),
+            FfiType::U8 => "uint8_t".to_string(),
+            FfiType::I32 => "int32_t".to_string(),
+            FfiType::I64 => "int64_t".to_string(),
+            FfiType::U32 => "uint32_t".to_string(),
+            FfiType::U64 => "uint64_t".to_string(),
+            FfiType::Usize => "size_t".to_string(),
+            FfiType::F32 => "float".to_string(),
+            FfiType::F64 => "double".to_string(),
+            FfiType::CString => "const char*".to_string(),
+            FfiType::VoidPtr => "void*".to_string(),
+            FfiType::Ptr(inner) => format!("const {}*", inner.c_type()),
+            FfiType::MutPtr(inner) => format!("{}*", inner.c_type()),
+            // Arrays are only passed by pointer, so the element type is all C needs.
+            FfiType::Array(inner, _) => inner.c_type(),
+            FfiType::Struct(name) | FfiType::Callback(name) => name.clone(),
+        }
+    }
+}
+
+/// Named, typed value: a function argument or a struct field.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FfiField {
+    /// Name of the argument or field.
+    pub name: String,
+    /// Type of the argument or field.
+    pub ty: FfiType,
+}
+
+impl FfiField {
+    /// Construct a new field.
+    pub fn new<N: Into<String>>(name: N, ty: FfiType) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+        }
+    }
+}
+
+/// Description of a `#[repr(C)]` struct.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FfiStruct {
+    /// Struct name.
+    pub name: String,
+    /// Fields in declaration order.
+    pub fields: Vec<FfiField>,
+}
+
+/// Description of an `extern "C"` callback type.
+///
+/// Callbacks following our convention take `user_data: *mut c_void` and
+/// `result: *const FfiResult` first; those are listed explicitly in `args`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FfiCallback {
+    /// Name of the callback type.
+    pub name: String,
+    /// Callback arguments.
+    pub args: Vec<FfiField>,
+}
+
+/// Description of an exported `extern "C"` function.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FfiFunction {
+    /// Exported symbol name.
+    pub name: String,
+    /// Function arguments.
+    pub args: Vec<FfiField>,
+    /// Return type (`FfiType::Void` for none).
+    pub ret: FfiType,
+}
+
+/// Description of the whole FFI surface of a library.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FfiDescription {
+    /// Struct definitions, in dependency order.
+    pub structs: Vec<FfiStruct>,
+    /// Callback type definitions.
+    pub callbacks: Vec<FfiCallback>,
+    /// Exported functions.
+    pub functions: Vec<FfiFunction>,
+}
+
+impl FfiDescription {
+    /// Compute the ABI version of the described surface.
+    ///
+    /// This is a 64-bit FNV-1a hash of a canonical rendering of all structs, callbacks and
+    /// functions, so any change to a signature results in a different version. Argument names
+    /// don't affect the ABI and are left out, so renaming one keeps the version. Unlike
+    /// `std::hash::Hash`, the value is stable across compiler versions and platforms.
+    pub fn abi_version(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        self.canonical().bytes().fold(FNV_OFFSET, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    fn canonical(&self) -> String {
+        fn fields(out: &mut String, fields: &[FfiField], names: bool) {
+            for field in fields {
+                out.push_str(&field.ty.c_type());
+                if names {
+                    let _ = write!(out, " {}", field.name);
+                }
+                if let FfiType::Array(_, len) = field.ty {
+                    let _ = write!(out, "[{}]", len);
+                }
+                out.push(',');
+            }
+        }
+
+        let mut out = String::new();
+
+        for s in &self.structs {
+            let _ = write!(out, "struct {}{{", s.name);
+            fields(&mut out, &s.fields, true);
+            out.push_str("};");
+        }
+        for cb in &self.callbacks {
+            let _ = write!(out, "callback {}(", cb.name);
+            fields(&mut out, &cb.args, false);
+            out.push_str(");");
+        }
+        for f in &self.functions {
+            let _ = write!(out, "fn {} {}(", f.ret.c_type(), f.name);
+            fields(&mut out, &f.args, false);
+            out.push_str(");");
+        }
+
+        out
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Error code constants and exception hierarchies for the frontends, generated from the tables
+//! of `error_codes!`.
+//!
+//! Every generated hierarchy has a base exception carrying the code, one subclass per domain,
+//! and a `fromCode` factory picking the class of a code, so that bindings in every language
+//! group codes identically.
+
+use crate::error_codes::ErrorCodeDesc;
+use std::fmt::Write;
+
+/// Generate a C header fragment defining every code as `<prefix><NAME>`.
+pub fn generate_c_defines(prefix: &str, codes: &[ErrorCodeDesc]) -> String {
+    let mut out = String::new();
+    out.push_str("/* Automatically generated. Do not edit. */\n\n");
+
+    for code in codes {
+        let _ = writeln!(out, "/* {} */", comment_text(code.description));
+        let _ = writeln!(out, "#define {}{} ({})", prefix, code.name, code.code);
+    }
+
+    out
+}
+
+// Escape `description` for a `/* */` comment, which a `*/` in it would end early.
+fn comment_text(description: &str) -> String {
+    description.replace("*/", "*\\/")
+}
+
+/// Generate a Java class `class_name` in `package` holding every code as a constant.
+pub fn generate_java_constants(package: &str, class_name: &str, codes: &[ErrorCodeDesc]) -> String {
+    let mut out = String::new();
+    out.push_str("// Automatically generated. Do not edit.\n\n");
+    let _ = writeln!(out, "package {};\n", package);
+    let _ = writeln!(out, "public final class {} {{", class_name);
+    let _ = writeln!(out, "    private {}() {{}}", class_name);
+
+    for code in codes {
+        out.push('\n');
+        let _ = writeln!(out, "    /** {} */", comment_text(code.description));
+        let _ = writeln!(
+            out,
+            "    public static final int {} = {};",
+            code.name, code.code
+        );
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Generate a TypeScript enum `enum_name` with a member for every code.
+pub fn generate_ts_enum(enum_name: &str, codes: &[ErrorCodeDesc]) -> String {
+    let mut out = String::new();
+    out.push_str("// Automatically generated. Do not edit.\n\n");
+    let _ = writeln!(out, "export enum {} {{", enum_name);
+
+    for code in codes {
+        let _ = writeln!(out, "  /** {} */", comment_text(code.description));
+        let _ = writeln!(out, "  {} = {},", code.name, code.code);
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Generate a Java exception `base` in `package`, with the domains of `codes` as nested
+/// subclasses and a `fromCode(int, String)` factory.
+pub fn generate_java_exceptions(package: &str, base: &str, codes: &[ErrorCodeDesc]) -> String {
+    let mut out = String::new();
+    out.push_str("// Automatically generated. Do not edit.\n\n");
+    let _ = writeln!(out, "package {};\n", package);
+    let _ = writeln!(out, "public class {} extends RuntimeException {{", base);
+    out.push_str("    private final int code;\n\n");
+    let _ = writeln!(
+        out,
+        "    public {}(int code, String description) {{\n        \
+         super(description);\n        \
+         this.code = code;\n    \
+         }}\n",
+        base
+    );
+    out.push_str("    public int getCode() {\n        return code;\n    }\n\n");
+    let _ = writeln!(
+        out,
+        "    public static {} fromCode(int code, String description) {{",
+        base
+    );
+    out.push_str("        switch (code) {\n");
+    for code in codes.iter().filter(|code| !code.domain.is_empty()) {
+        let _ = writeln!(out, "            case {}: // {}", code.code, code.name);
+        let _ = writeln!(
+            out,
+            "                return new {}(code, description);",
+            code.domain
+        );
+    }
+    let _ = writeln!(
+        out,
+        "            default:\n                return new {}(code, description);",
+        base
+    );
+    out.push_str("        }\n    }\n");
+
+    for domain in domains(codes) {
+        let _ = writeln!(
+            out,
+            "\n    public static class {} extends {} {{\n        \
+             public {}(int code, String description) {{\n            \
+             super(code, description);\n        \
+             }}\n    \
+             }}",
+            domain, base, domain
+        );
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Generate C# exception classes in `namespace`: `base`, a subclass per domain of `codes`, and
+/// a `FromCode(int, string)` factory.
+pub fn generate_csharp_exceptions(namespace: &str, base: &str, codes: &[ErrorCodeDesc]) -> String {
+    let mut out = String::new();
+    out.push_str("// Automatically generated. Do not edit.\n\n");
+    out.push_str("using System;\n\n");
+    let _ = writeln!(out, "namespace {}\n{{", namespace);
+    let _ = writeln!(out, "    public class {} : Exception\n    {{", base);
+    out.push_str("        public int Code { get; }\n\n");
+    let _ = writeln!(
+        out,
+        "        public {}(int code, string description) : base(description)\n        \
+         {{\n            \
+         Code = code;\n        \
+         }}\n",
+        base
+    );
+    let _ = writeln!(
+        out,
+        "        public static {} FromCode(int code, string description)\n        {{",
+        base
+    );
+    out.push_str("            switch (code)\n            {\n");
+    for code in codes.iter().filter(|code| !code.domain.is_empty()) {
+        let _ = writeln!(out, "                case {}: // {}", code.code, code.name);
+        let _ = writeln!(
+            out,
+            "                    return new {}(code, description);",
+            code.domain
+        );
+    }
+    let _ = writeln!(
+        out,
+        "                default:\n                    return new {}(code, description);",
+        base
+    );
+    out.push_str("            }\n        }\n    }\n");
+
+    for domain in domains(codes) {
+        let _ = writeln!(
+            out,
+            "\n    public class {} : {}\n    {{\n        \
+             public {}(int code, string description) : base(code, description) {{ }}\n    \
+             }}",
+            domain, base, domain
+        );
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Generate TypeScript error classes: `base`, a subclass per domain of `codes`, and a
+/// `fromCode(number, string)` factory.
+pub fn generate_ts_exceptions(base: &str, codes: &[ErrorCodeDesc]) -> String {
+    let mut out = String::new();
+    out.push_str("// Automatically generated. Do not edit.\n\n");
+    let _ = writeln!(out, "export class {} extends Error {{", base);
+    out.push_str(
+        "  constructor(public readonly code: number, description: string) {\n    \
+         super(description);\n    \
+         this.name = new.target.name;\n    \
+         Object.setPrototypeOf(this, new.target.prototype);\n  \
+         }\n\n",
+    );
+    let _ = writeln!(
+        out,
+        "  static fromCode(code: number, description: string): {} {{",
+        base
+    );
+    out.push_str("    switch (code) {\n");
+    for code in codes.iter().filter(|code| !code.domain.is_empty()) {
+        let _ = writeln!(out, "      case {}: // {}", code.code, code.name);
+        let _ = writeln!(
+            out,
+            "        return new {}(code, description);",
+            code.domain
+        );
+    }
+    let _ = writeln!(
+        out,
+        "      default:\n        return new {}(code, description);",
+        base
+    );
+    out.push_str("    }\n  }\n}\n");
+
+    for domain in domains(codes) {
+        let _ = writeln!(out, "\nexport class {} extends {} {{}}", domain, base);
+    }
+
+    out
+}
+
+// Distinct domains of `codes`, in order of first appearance.
+fn domains(codes: &[ErrorCodeDesc]) -> Vec<&'static str> {
+    let mut domains: Vec<&'static str> = Vec::new();
+    for code in codes {
+        if !code.domain.is_empty() && !domains.contains(&code.domain) {
+            domains.push(code.domain);
+        }
+    }
+    domains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODES: &[ErrorCodeDesc] = &[
+        ErrorCodeDesc {
+            name: "NOT_REGISTERED",
+            code: -100,
+            description: "The app is not registered",
+            domain: "",
+        },
+        ErrorCodeDesc {
+            name: "NETWORK",
+            code: -200,
+            description: "Network failure",
+            domain: "NetworkError",
+        },
+        ErrorCodeDesc {
+            name: "TIMEOUT",
+            code: -201,
+            description: "Request timed out",
+            domain: "NetworkError",
+        },
+    ];
+
+    #[test]
+    fn emitters() {
+        let header = generate_c_defines("APP_ERR_", CODES);
+        assert!(header.contains("/* Network failure */\n#define APP_ERR_NETWORK (-200)\n"));
+
+        let java = generate_java_constants("net.maidsafe.app", "ErrorCodes", CODES);
+        assert!(java.contains("package net.maidsafe.app;"));
+        assert!(java.contains("    public static final int NOT_REGISTERED = -100;"));
+
+        let ts = generate_ts_enum("ErrorCode", CODES);
+        assert!(ts.contains("export enum ErrorCode {"));
+        assert!(ts.contains("  NETWORK = -200,"));
+    }
+
+    #[test]
+    fn comment_terminators_are_escaped() {
+        let codes = &[ErrorCodeDesc {
+            name: "GLOB",
+            code: -300,
+            description: "No match for */*.txt",
+            domain: "",
+        }];
+
+        let header = generate_c_defines("APP_ERR_", codes);
+        assert!(header.contains("/* No match for *\\/*.txt */\n"));
+
+        let java = generate_java_constants("net.maidsafe.app", "ErrorCodes", codes);
+        assert!(java.contains("    /** No match for *\\/*.txt */\n"));
+
+        let ts = generate_ts_enum("ErrorCode", codes);
+        assert!(ts.contains("  /** No match for *\\/*.txt */\n"));
+    }
+
+    #[test]
+    fn exception_hierarchies() {
+        let java = generate_java_exceptions("net.maidsafe.app", "AppException", CODES);
+        assert!(java.contains("public class AppException extends RuntimeException {"));
+        assert!(java.contains(
+            "            case -201: // TIMEOUT\n                \
+             return new NetworkError(code, description);"
+        ));
+        assert!(java.contains(
+            "            default:\n                return new AppException(code, description);"
+        ));
+        assert_eq!(
+            java.matches("public static class NetworkError extends AppException")
+                .count(),
+            1
+        );
+
+        let csharp = generate_csharp_exceptions("MaidSafe.App", "AppException", CODES);
+        assert!(csharp.contains("namespace MaidSafe.App\n{"));
+        assert!(csharp.contains("    public class NetworkError : AppException\n"));
+        assert!(csharp.contains("                case -200: // NETWORK\n"));
+
+        let ts = generate_ts_exceptions("AppError", CODES);
+        assert!(ts.contains("export class AppError extends Error {"));
+        assert!(ts.contains("static fromCode(code: number, description: string): AppError {"));
+        assert!(ts.contains("export class NetworkError extends AppError {}"));
+        assert!(!ts.contains("case -100"));
+    }
+}
@@ -9,11 +9,35 @@
 
 //! Utilities for binding generators.
 
+pub mod android;
+pub mod error_codes;
+pub mod python_gen;
+
+mod desc;
+
+pub use self::desc::{FfiCallback, FfiDescription, FfiField, FfiFunction, FfiStruct, FfiType};
+
 use std::fs;
 use std::io;
 use std::path::Path;
 use walkdir::WalkDir;
 
+/// Environment variable through which `embed_abi_version` passes the ABI version to the crate
+/// being built. Read it with `env!` (which `export_abi_version!` does by default).
+pub const ABI_VERSION_ENV: &str = "FFI_ABI_VERSION";
+
+/// Embed the ABI version of the given FFI surface into the crate being built.
+///
+/// Call this from `build.rs`. It computes `FfiDescription::abi_version` and instructs Cargo to
+/// expose it to the crate as the `FFI_ABI_VERSION` environment variable, which
+/// `export_abi_version!()` turns into an exported `ffi_abi_version` function. Returns the
+/// computed version so the build script can also pass it on to generated bindings.
+pub fn embed_abi_version(desc: &FfiDescription) -> u64 {
+    let version = desc.abi_version();
+    println!("cargo:rustc-env={}={}", ABI_VERSION_ENV, version);
+    version
+}
+
 /// Recursively copy all files with the given extension from the source to the target directories.
 pub fn copy_files<S: AsRef<Path>, T: AsRef<Path>>(
     source: S,
@@ -0,0 +1,295 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Python binding stub generator.
+//!
+//! Produces either a `ctypes` module (no third-party dependencies on the Python side) or the C
+//! declarations to feed into `cffi`'s `ffi.cdef()`.
+
+use super::desc::{FfiCallback, FfiDescription, FfiField, FfiType};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Definition of `FfiResult` which every generated module includes.
+const FFI_RESULT_CDEF: &str = "typedef struct FfiResult {\n    \
+                               int32_t error_code;\n    \
+                               const char* description;\n\
+                               } FfiResult;\n";
+
+/// Generate a `ctypes` Python module for the given FFI description.
+///
+/// The module exposes a `load(path=None)` function which opens the shared library (defaulting
+/// to `lib_name` resolved through `ctypes.util.find_library`) and annotates every exported
+/// function with its argument and return types.
+pub fn generate_ctypes(desc: &FfiDescription, lib_name: &str) -> String {
+    let mut out = String::new();
+
+    out.push_str("# Automatically generated. Do not edit.\n\n");
+    out.push_str("from ctypes import *\n");
+    out.push_str("import ctypes.util\n\n\n");
+
+    // Declare all structs up front and fill in the fields later, so that structs and callbacks
+    // may refer to each other regardless of declaration order.
+    out.push_str("class FfiResult(Structure):\n    pass\n\n\n");
+    for s in &desc.structs {
+        let _ = writeln!(out, "class {}(Structure):\n    pass\n\n", s.name);
+    }
+
+    // Callback types only refer to structs through `POINTER`, which works on the incomplete
+    // declarations above, but struct fields may refer to callback types, so those come first.
+    for cb in &desc.callbacks {
+        let _ = writeln!(out, "{} = {}\n", cb.name, cfunctype(cb));
+    }
+
+    out.push_str("FfiResult._fields_ = [\n");
+    out.push_str("    (\"error_code\", c_int32),\n");
+    out.push_str("    (\"description\", c_char_p),\n");
+    out.push_str("]\n\n");
+
+    for s in &desc.structs {
+        let _ = writeln!(out, "{}._fields_ = [", s.name);
+        for field in &s.fields {
+            let _ = writeln!(out, "    (\"{}\", {}),", field.name, ctype(&field.ty));
+        }
+        out.push_str("]\n\n");
+    }
+
+    out.push('\n');
+    out.push_str("def load(path=None):\n");
+    let _ = writeln!(
+        out,
+        "    lib = CDLL(path or ctypes.util.find_library(\"{}\"))",
+        lib_name
+    );
+    for f in &desc.functions {
+        let _ = writeln!(
+            out,
+            "    lib.{}.argtypes = [{}]",
+            f.name,
+            ctype_list(&f.args)
+        );
+        let _ = writeln!(out, "    lib.{}.restype = {}", f.name, ctype(&f.ret));
+    }
+    out.push_str("    return lib\n");
+
+    out
+}
+
+/// Generate C declarations for the given FFI description, suitable for passing to
+/// `cffi.FFI().cdef()`.
+pub fn generate_cffi_cdef(desc: &FfiDescription) -> String {
+    let mut out = String::new();
+
+    out.push_str(FFI_RESULT_CDEF);
+    out.push('\n');
+
+    // As in the `ctypes` module, structs are declared first so that callback types can point to
+    // them, and defined after the callback types which their fields may refer to.
+    for s in &desc.structs {
+        let _ = writeln!(out, "typedef struct {0} {0};", s.name);
+    }
+    if !desc.structs.is_empty() {
+        out.push('\n');
+    }
+
+    for cb in &desc.callbacks {
+        let _ = writeln!(
+            out,
+            "typedef void (*{})({});\n",
+            cb.name,
+            c_decl_list(&cb.args)
+        );
+    }
+
+    for s in &desc.structs {
+        let _ = writeln!(out, "struct {} {{", s.name);
+        for field in &s.fields {
+            let _ = writeln!(out, "    {};", c_decl(field));
+        }
+        out.push_str("};\n\n");
+    }
+
+    for f in &desc.functions {
+        let _ = writeln!(
+            out,
+            "{} {}({});",
+            f.ret.c_type(),
+            f.name,
+            c_decl_list(&f.args)
+        );
+    }
+
+    out
+}
+
+/// Generate a `ctypes` module and write it to `path`.
+pub fn write_ctypes<P: AsRef<Path>>(
+    desc: &FfiDescription,
+    lib_name: &str,
+    path: P,
+) -> io::Result<()> {
+    fs::write(path, generate_ctypes(desc, lib_name))
+}
+
+/// Generate `cffi` declarations and write them to `path`.
+pub fn write_cffi_cdef<P: AsRef<Path>>(desc: &FfiDescription, path: P) -> io::Result<()> {
+    fs::write(path, generate_cffi_cdef(desc))
+}
+
+fn ctype(ty: &FfiType) -> String {
+    match ty {
+        FfiType::Void => "None".to_string(),
+        FfiType::Bool => "c_bool".to_string(),
+        FfiType::U8 => "c_uint8".to_string(),
+        FfiType::I32 => "c_int32".to_string(),
+        FfiType::I64 => "c_int64".to_string(),
+        FfiType::U32 => "c_uint32".to_string(),
+        FfiType::U64 => "c_uint64".to_string(),
+        FfiType::Usize => "c_size_t".to_string(),
+        FfiType::F32 => "c_float".to_string(),
+        FfiType::F64 => "c_double".to_string(),
+        FfiType::CString => "c_char_p".to_string(),
+        FfiType::VoidPtr => "c_void_p".to_string(),
+        FfiType::Ptr(inner) | FfiType::MutPtr(inner) => match **inner {
+            // `POINTER(None)` is not valid; a pointer to void is just `c_void_p`.
+            FfiType::Void => "c_void_p".to_string(),
+            _ => format!("POINTER({})", ctype(inner)),
+        },
+        FfiType::Array(inner, len) => format!("({} * {})", ctype(inner), len),
+        FfiType::Struct(name) | FfiType::Callback(name) => name.clone(),
+    }
+}
+
+fn ctype_list(fields: &[FfiField]) -> String {
+    fields
+        .iter()
+        .map(|field| ctype(&field.ty))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn cfunctype(cb: &FfiCallback) -> String {
+    if cb.args.is_empty() {
+        "CFUNCTYPE(None)".to_string()
+    } else {
+        format!("CFUNCTYPE(None, {})", ctype_list(&cb.args))
+    }
+}
+
+fn c_decl(field: &FfiField) -> String {
+    match &field.ty {
+        FfiType::Array(inner, len) => format!("{} {}[{}]", inner.c_type(), field.name, len),
+        ty => format!("{} {}", ty.c_type(), field.name),
+    }
+}
+
+fn c_decl_list(fields: &[FfiField]) -> String {
+    if fields.is_empty() {
+        "void".to_string()
+    } else {
+        fields.iter().map(c_decl).collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindgen_utils::{FfiFunction, FfiStruct};
+    use unwrap::unwrap;
+
+    fn sample() -> FfiDescription {
+        FfiDescription {
+            structs: vec![FfiStruct {
+                name: "XorName".to_string(),
+                fields: vec![FfiField::new(
+                    "bytes",
+                    FfiType::Array(Box::new(FfiType::U8), 32),
+                )],
+            }],
+            callbacks: vec![FfiCallback {
+                name: "NameCb".to_string(),
+                args: vec![
+                    FfiField::new("user_data", FfiType::VoidPtr),
+                    FfiField::new(
+                        "result",
+                        FfiType::Ptr(Box::new(FfiType::Struct("FfiResult".to_string()))),
+                    ),
+                    FfiField::new(
+                        "name",
+                        FfiType::Ptr(Box::new(FfiType::Struct("XorName".to_string()))),
+                    ),
+                ],
+            }],
+            functions: vec![FfiFunction {
+                name: "random_name".to_string(),
+                args: vec![
+                    FfiField::new("user_data", FfiType::VoidPtr),
+                    FfiField::new("o_cb", FfiType::Callback("NameCb".to_string())),
+                ],
+                ret: FfiType::Void,
+            }],
+        }
+    }
+
+    #[test]
+    fn ctypes_module() {
+        let module = generate_ctypes(&sample(), "safe_app");
+
+        assert!(module.contains("class XorName(Structure):"));
+        assert!(module.contains("(\"bytes\", (c_uint8 * 32)),"));
+        assert!(module
+            .contains("NameCb = CFUNCTYPE(None, c_void_p, POINTER(FfiResult), POINTER(XorName))"));
+        assert!(module.contains("find_library(\"safe_app\")"));
+
+        // Callback types must be defined before any `_fields_` refer to them.
+        let cb_def = unwrap!(module.find("NameCb = CFUNCTYPE"));
+        let fields = unwrap!(module.find("XorName._fields_"));
+        assert!(cb_def < fields);
+        assert!(module.contains("lib.random_name.argtypes = [c_void_p, NameCb]"));
+        assert!(module.contains("lib.random_name.restype = None"));
+    }
+
+    #[test]
+    fn cffi_cdef() {
+        let cdef = generate_cffi_cdef(&sample());
+
+        assert!(cdef.contains("    uint8_t bytes[32];"));
+        assert!(cdef.contains(
+            "typedef void (*NameCb)(void* user_data, const FfiResult* result, \
+             const XorName* name);"
+        ));
+        assert!(cdef.contains("void random_name(void* user_data, NameCb o_cb);"));
+    }
+
+    #[test]
+    fn cffi_cdef_struct_with_callback_field() {
+        let mut desc = sample();
+        desc.structs.push(FfiStruct {
+            name: "Listener".to_string(),
+            fields: vec![
+                FfiField::new("user_data", FfiType::VoidPtr),
+                FfiField::new("on_name", FfiType::Callback("NameCb".to_string())),
+            ],
+        });
+        let cdef = generate_cffi_cdef(&desc);
+
+        assert!(cdef.contains("typedef struct Listener Listener;"));
+        assert!(cdef.contains("struct Listener {\n    void* user_data;\n    NameCb on_name;\n};"));
+
+        // Callback types must be defined before the struct fields referring to them, and after
+        // the declarations of the structs they point to.
+        let declaration = unwrap!(cdef.find("typedef struct XorName XorName;"));
+        let cb_def = unwrap!(cdef.find("typedef void (*NameCb)"));
+        let definition = unwrap!(cdef.find("struct Listener {"));
+        assert!(declaration < cb_def);
+        assert!(cb_def < definition);
+    }
+}
@@ -9,7 +9,10 @@
 
 //! Helpers to work with extern "C" callbacks.
 
+use crate::catch_unwind::call_error_cb;
 use crate::result::FfiResult;
+use crate::{affinity, ffi_error, ErrorCode, IntoReprC, OpaqueCtx, FFI_RESULT_OK};
+use std::fmt::{Debug, Display};
 use std::os::raw::c_void;
 use std::ptr;
 
@@ -158,3 +161,41 @@ impl<T0: CallbackArgs, T1: CallbackArgs, T2: CallbackArgs, T3: CallbackArgs> Cal
         )
     }
 }
+
+// Job invoking `cb` with `result`, as queued by `Dispatcher::dispatch_cb` or run by
+// `affinity::call_cb`.
+pub(crate) fn cb_job<U, C, T, E>(
+    user_data: U,
+    cb: C,
+    result: Result<T, E>,
+) -> impl FnOnce() + Send + 'static
+where
+    U: Into<*mut c_void>,
+    C: Callback<Args = T::C> + Send + 'static,
+    T: IntoReprC + Send + 'static,
+    T::Error: Debug,
+    E: Debug + Display + ErrorCode + From<&'static str> + Send + 'static,
+{
+    let user_data = OpaqueCtx::from_host_pointer(user_data.into());
+
+    move || {
+        let error = match result.map(IntoReprC::into_repr_c) {
+            Ok(Ok((repr_c, _storage))) => {
+                affinity::check();
+                cb.call(user_data.as_ptr(), FFI_RESULT_OK, repr_c);
+                return;
+            }
+            Ok(Err(e)) => {
+                log::debug!(
+                    "Could not convert result into its FFI representation: {:?}",
+                    e
+                );
+                E::from("Could not convert result into its FFI representation")
+            }
+            Err(error) => error,
+        };
+
+        let (error_code, description) = ffi_error!(error);
+        call_error_cb(user_data.as_ptr(), cb, error_code, description);
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Writing into buffers allocated by the caller, for consumers which can't take ownership of
+//! Rust allocations.
+//!
+//! The caller first passes a null buffer to learn the required length, then calls again with a
+//! buffer of at least that length:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_name(
+//!     app: Handle,
+//!     buf: *mut u8,
+//!     buf_len: usize,
+//!     o_required: *mut usize,
+//! ) -> i32 {
+//!     catch_unwind_out(o_required, || -> Result<_, AppError> {
+//!         let name = handles::get::<App>(app)?.name();
+//!         Ok(write_str_to_caller_buf(buf, buf_len, &name)?)
+//!     })
+//! }
+//! ```
+
+use crate::ErrorCode;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ptr;
+
+/// Error code returned when the caller's buffer is too small.
+pub const ERR_BUFFER_TOO_SMALL: i32 = -9016;
+/// Error code returned when a string to write as a NUL-terminated string contains a NUL byte.
+pub const ERR_INTERIOR_NUL: i32 = -9029;
+
+/// Error writing into a buffer which is too small. Nothing is written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BufferTooSmall {
+    /// Length the buffer needs.
+    pub required: usize,
+    /// Length of the buffer.
+    pub available: usize,
+}
+
+impl ErrorCode for BufferTooSmall {
+    fn error_code(&self) -> i32 {
+        ERR_BUFFER_TOO_SMALL
+    }
+}
+
+impl Display for BufferTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Buffer too small: {} bytes required, {} available",
+            self.required, self.available
+        )
+    }
+}
+
+impl Error for BufferTooSmall {}
+
+/// Error writing a NUL-terminated string into the caller's buffer. Nothing is written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WriteStrError {
+    /// The buffer is too small.
+    TooSmall(BufferTooSmall),
+    /// The string contains a NUL byte at this position, so the caller would only see the part
+    /// before it.
+    InteriorNul(usize),
+}
+
+impl From<BufferTooSmall> for WriteStrError {
+    fn from(error: BufferTooSmall) -> Self {
+        WriteStrError::TooSmall(error)
+    }
+}
+
+impl ErrorCode for WriteStrError {
+    fn error_code(&self) -> i32 {
+        match self {
+            WriteStrError::TooSmall(error) => error.error_code(),
+            WriteStrError::InteriorNul(_) => ERR_INTERIOR_NUL,
+        }
+    }
+}
+
+impl Display for WriteStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteStrError::TooSmall(error) => error.fmt(f),
+            WriteStrError::InteriorNul(position) => {
+                write!(f, "String contains a NUL byte at position {}", position)
+            }
+        }
+    }
+}
+
+impl Error for WriteStrError {}
+
+/// Copy `input` into the caller's buffer `out` of `out_len` bytes, returning the number of
+/// bytes required. If `out` is null, nothing is written and only the required length is
+/// returned.
+///
+/// # Safety
+///
+/// `out` must be null or valid for writes of `out_len` bytes.
+pub unsafe fn write_bytes_to_caller_buf(
+    out: *mut u8,
+    out_len: usize,
+    input: &[u8],
+) -> Result<usize, BufferTooSmall> {
+    write_parts(out, out_len, &[input])
+}
+
+/// Same as `write_bytes_to_caller_buf`, but writes `input` as a NUL-terminated string. The
+/// required length includes the terminator. Strings containing a NUL byte are rejected, as the
+/// caller would read them truncated.
+///
+/// # Safety
+///
+/// `out` must be null or valid for writes of `out_len` bytes.
+pub unsafe fn write_str_to_caller_buf(
+    out: *mut u8,
+    out_len: usize,
+    input: &str,
+) -> Result<usize, WriteStrError> {
+    if let Some(position) = input.bytes().position(|byte| byte == 0) {
+        return Err(WriteStrError::InteriorNul(position));
+    }
+    Ok(write_parts(out, out_len, &[input.as_bytes(), &[0]])?)
+}
+
+unsafe fn write_parts(
+    out: *mut u8,
+    out_len: usize,
+    parts: &[&[u8]],
+) -> Result<usize, BufferTooSmall> {
+    let required = parts.iter().map(|part| part.len()).sum();
+    if out.is_null() {
+        return Ok(required);
+    }
+    if out_len < required {
+        return Err(BufferTooSmall {
+            required,
+            available: out_len,
+        });
+    }
+
+    let mut offset = 0;
+    for part in parts {
+        ptr::copy_nonoverlapping(part.as_ptr(), out.add(offset), part.len());
+        offset += part.len();
+    }
+    Ok(required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use unwrap::unwrap;
+
+    #[test]
+    fn two_call_pattern() {
+        let required = unsafe { unwrap!(write_str_to_caller_buf(ptr::null_mut(), 0, "abc")) };
+        assert_eq!(required, 4);
+
+        let mut buf = vec![0xff; 3];
+        assert_eq!(
+            unsafe { write_str_to_caller_buf(buf.as_mut_ptr(), buf.len(), "abc") },
+            Err(WriteStrError::TooSmall(BufferTooSmall {
+                required: 4,
+                available: 3,
+            }))
+        );
+        assert_eq!(buf, [0xff; 3]);
+
+        let mut buf = vec![0xff; required];
+        assert_eq!(
+            unsafe { write_str_to_caller_buf(buf.as_mut_ptr(), buf.len(), "abc") },
+            Ok(4)
+        );
+        assert_eq!(unwrap!(CStr::from_bytes_with_nul(&buf)).to_str(), Ok("abc"));
+
+        let mut buf = [0; 8];
+        assert_eq!(
+            unsafe { write_bytes_to_caller_buf(buf.as_mut_ptr(), buf.len(), &[1, 2]) },
+            Ok(2)
+        );
+        assert_eq!(buf[..2], [1, 2]);
+    }
+
+    #[test]
+    fn interior_nul_is_rejected() {
+        let mut buf = [0xff; 8];
+        let error = unsafe { write_str_to_caller_buf(buf.as_mut_ptr(), buf.len(), "ab\0c") };
+        assert_eq!(error, Err(WriteStrError::InteriorNul(2)));
+        assert_eq!(unwrap!(error.err()).error_code(), ERR_INTERIOR_NUL);
+        assert_eq!(buf, [0xff; 8]);
+
+        let required = unsafe { write_str_to_caller_buf(ptr::null_mut(), 0, "\0") };
+        assert_eq!(required, Err(WriteStrError::InteriorNul(0)));
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Cancellation of long-running operations from the frontend.
+//!
+//! An FFI function starting an operation creates a `CancelHandle`, keeps a clone of it for the
+//! operation and returns its FFI handle to the caller. The caller can then abort the operation
+//! with `ffi_cancel` (exported with `export_cancel!`), and must eventually release the handle
+//! with `ffi_cancel_free`:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_upload(
+//!     app: Handle,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+//! ) -> Handle {
+//!     let cancel = CancelHandle::new();
+//!     let handle = cancel.to_ffi();
+//!     async_ffi::spawn_cb(user_data, o_cb, async move {
+//!         for chunk in chunks {
+//!             cancel.check()?;
+//!             upload(chunk).await?;
+//!         }
+//!         Ok(())
+//!     });
+//!     handle
+//! }
+//! ```
+
+use crate::handles::{self, Handle, HandleError};
+use crate::ErrorCode;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// Error code returned by operations which have been cancelled.
+pub const ERR_CANCELLED: i32 = -9003;
+
+/// Error returned by operations which have been cancelled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cancelled;
+
+impl ErrorCode for Cancelled {
+    fn error_code(&self) -> i32 {
+        ERR_CANCELLED
+    }
+}
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Operation cancelled")
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+    condvar: Condvar,
+}
+
+impl Inner {
+    fn wakers(&self) -> MutexGuard<'_, Vec<Waker>> {
+        self.wakers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Every flag created, so that shutting the library down can cancel the operations in flight.
+static LIVE: Mutex<Vec<Weak<Inner>>> = Mutex::new(Vec::new());
+
+fn live() -> MutexGuard<'static, Vec<Weak<Inner>>> {
+    LIVE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Shared cancellation flag. Clones refer to the same flag.
+#[derive(Clone)]
+pub struct CancelHandle {
+    inner: Arc<Inner>,
+}
+
+impl CancelHandle {
+    /// Create a handle which is not cancelled.
+    pub fn new() -> Self {
+        let inner = Arc::new(Inner::default());
+
+        let mut live = live();
+        live.retain(|inner| inner.strong_count() > 0);
+        live.push(Arc::downgrade(&inner));
+
+        Self { inner }
+    }
+
+    /// Register a clone of this handle in the global handle registry, returning the handle to
+    /// give to the frontend.
+    pub fn to_ffi(&self) -> Handle {
+        handles::register(self.clone())
+    }
+
+    /// Request cancellation, waking every task and thread waiting for it.
+    pub fn cancel(&self) {
+        // Taking the lock orders the flag with the waiters registering themselves.
+        let wakers = {
+            let mut wakers = self.inner.wakers();
+            self.inner.cancelled.store(true, Ordering::Release);
+            self.inner.condvar.notify_all();
+            std::mem::take(&mut *wakers)
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Return `true` if cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Return `Err(Cancelled)` if cancellation has been requested, to be used with `?` at the
+    /// points where the operation can be aborted.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Block the current thread until cancellation is requested or `timeout` elapses. Return
+    /// `true` if it was requested.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let wakers = self.inner.wakers();
+        let (_wakers, _) = self
+            .inner
+            .condvar
+            .wait_timeout_while(wakers, timeout, |_| !self.is_cancelled())
+            .unwrap_or_else(PoisonError::into_inner);
+        self.is_cancelled()
+    }
+
+    /// Return a future which completes once cancellation is requested.
+    pub fn cancelled(&self) -> WaitCancelled {
+        WaitCancelled {
+            handle: self.clone(),
+        }
+    }
+}
+
+impl Default for CancelHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by `CancelHandle::cancelled`.
+pub struct WaitCancelled {
+    handle: CancelHandle,
+}
+
+impl Future for WaitCancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let inner = &self.handle.inner;
+        let mut wakers = inner.wakers();
+        if inner.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Request cancellation of the operation behind the FFI `handle`.
+pub fn cancel(handle: Handle) -> Result<(), HandleError> {
+    handles::with(handle, CancelHandle::cancel)
+}
+
+/// Release the FFI `handle`. This does not cancel the operation.
+pub fn free(handle: Handle) -> Result<(), HandleError> {
+    handles::free::<CancelHandle>(handle)
+}
+
+/// Request cancellation of every operation whose `CancelHandle` is still alive and not
+/// cancelled yet. Returns the number of operations cancelled.
+pub fn cancel_all() -> usize {
+    let handles: Vec<_> = live()
+        .drain(..)
+        .filter_map(|inner| inner.upgrade())
+        .map(|inner| CancelHandle { inner })
+        .filter(|handle| !handle.is_cancelled())
+        .collect();
+
+    for handle in &handles {
+        handle.cancel();
+    }
+    handles.len()
+}
+
+/// Export the cancellation functions of the library.
+///
+/// Defines two `#[no_mangle]` functions:
+///
+/// + `ffi_cancel(handle: u64) -> i32` requesting cancellation of the operation behind `handle`;
+/// + `ffi_cancel_free(handle: u64) -> i32` releasing `handle`.
+///
+/// Both return 0 on success, or `ERR_INVALID_HANDLE` if the handle is not a live cancellation
+/// handle.
+#[macro_export]
+macro_rules! export_cancel {
+    () => {
+        /// Request cancellation of the operation behind `handle`.
+        #[no_mangle]
+        pub extern "C" fn ffi_cancel(handle: u64) -> i32 {
+            $crate::ffi_result_code!($crate::cancel::cancel(handle))
+        }
+
+        /// Release a cancellation handle.
+        #[no_mangle]
+        pub extern "C" fn ffi_cancel_free(handle: u64) -> i32 {
+            $crate::ffi_result_code!($crate::cancel::free(handle))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use unwrap::unwrap;
+
+    #[test]
+    fn cancel_through_ffi_handle() {
+        let cancel = CancelHandle::new();
+        let handle = cancel.to_ffi();
+        assert_eq!(cancel.check(), Ok(()));
+
+        let waiter = {
+            let cancel = cancel.clone();
+            thread::spawn(move || cancel.wait_timeout(Duration::from_secs(60)))
+        };
+
+        unwrap!(super::cancel(handle));
+        assert!(unwrap!(waiter.join()));
+        assert_eq!(
+            cancel.check().map_err(|e| e.error_code()),
+            Err(ERR_CANCELLED)
+        );
+
+        unwrap!(free(handle));
+        assert!(super::cancel(handle).is_err());
+        assert!(cancel.is_cancelled());
+    }
+
+    #[test]
+    fn wait_timeout_without_cancel() {
+        assert!(!CancelHandle::new().wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn cancelled_future() {
+        let cancel = CancelHandle::new();
+        let task = tokio::spawn(cancel.cancelled());
+        tokio::task::yield_now().await;
+        cancel.cancel();
+        unwrap!(task.await);
+        cancel.cancelled().await;
+    }
+}
@@ -8,12 +8,16 @@
 // Software.
 
 use super::callback::{Callback, CallbackArgs};
+#[cfg(any(test, feature = "testing"))]
+use super::test_utils::{fault, reentrancy};
 use super::{ErrorCode, FfiResult, NativeResult};
-use crate::ffi_result;
-use log::debug;
+use crate::{affinity, ffi_error_code, static_results};
+use log::{debug, error};
+use std::any::Any;
 use std::fmt::{Debug, Display};
 use std::os::raw::c_void;
 use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
 
 /// Catches panics and returns the result.
 pub fn catch_unwind_result<'a, F, T, E>(f: F) -> Result<T, E>
@@ -35,6 +39,7 @@ where
 }
 
 /// Catch panics. On error call the callback.
+#[track_caller]
 pub fn catch_unwind_cb<'a, U, C, F, E>(user_data: U, cb: C, f: F)
 where
     U: Into<*mut c_void>,
@@ -42,25 +47,241 @@ where
     F: FnOnce() -> Result<(), E>,
     E: Debug + Display + ErrorCode + From<&'a str>,
 {
-    if let Err(err) = catch_unwind_result(f) {
-        let (error_code, description) = ffi_result!(Err::<(), E>(err));
-        let res = NativeResult {
-            error_code,
-            description: Some(description),
-        }
-        .into_repr_c();
-
-        match res {
-            Ok(res) => cb.call(user_data.into(), &res, CallbackArgs::default()),
-            Err(_) => {
-                let res = FfiResult {
-                    error_code,
-                    description: b"Could not convert error description into CString\x00"
-                        as *const u8 as *const _,
-                };
-                cb.call(user_data.into(), &res, CallbackArgs::default());
+    #[cfg(any(test, feature = "testing"))]
+    let _reentrancy = reentrancy::enter(panic::Location::caller());
+    #[cfg(feature = "tracing")]
+    let _span = crate::trace::call_span::<F>(panic::Location::caller()).entered();
+    #[cfg(feature = "metrics")]
+    let timer = crate::metrics::Timer::start(function_name::<F>());
+
+    let user_data = user_data.into();
+    if let Some(_error_code) = report_injected_fault(user_data, cb) {
+        #[cfg(feature = "metrics")]
+        timer.finish(_error_code);
+    } else if let Err(err) = catch_unwind_result(f) {
+        let error_code = ffi_error_code!(err);
+        #[cfg(feature = "metrics")]
+        timer.finish(error_code);
+        call_display_error_cb(user_data, cb, error_code, &err)
+    } else {
+        #[cfg(feature = "metrics")]
+        timer.finish(0);
+    }
+}
+
+/// Consume a fault injected on the current thread with `test_utils::fault::fail_next`, if any,
+/// and report it to the callback. Returns the reported error code.
+///
+/// Faults can only be injected with the `testing` feature; without it this always returns `None`,
+/// so the hook compiles out of release builds.
+#[doc(hidden)]
+#[inline]
+pub fn report_injected_fault<C: Callback>(user_data: *mut c_void, cb: C) -> Option<i32> {
+    #[cfg(any(test, feature = "testing"))]
+    {
+        let error_code = fault::take()?;
+        fault::report(user_data, cb, error_code);
+        Some(error_code)
+    }
+    #[cfg(not(any(test, feature = "testing")))]
+    {
+        let _ = (user_data, cb);
+        None
+    }
+}
+
+/// Record the invocation of a callback reporting `error_code`, with the `tracing` feature.
+#[doc(hidden)]
+#[inline]
+pub fn trace_callback(_error_code: i32) {
+    #[cfg(feature = "tracing")]
+    crate::trace::invoking_callback(_error_code);
+}
+
+/// Call the callback with `error` and default values for its other arguments. If the
+/// description of `error` is the one registered for its code with `static_results`, the
+/// registered result is passed and nothing is allocated.
+pub fn call_cb_with_error<C, E>(user_data: *mut c_void, cb: C, error: &E)
+where
+    C: Callback,
+    E: Debug + Display + ErrorCode,
+{
+    call_display_error_cb(user_data, cb, ffi_error_code!(error), error)
+}
+
+fn call_display_error_cb<C, E>(user_data: *mut c_void, cb: C, error_code: i32, error: &E)
+where
+    C: Callback,
+    E: Display + ?Sized,
+{
+    match static_results::lookup(error_code, error) {
+        Some(result) => call_static_error_cb(user_data, cb, result),
+        None => call_allocated_error_cb(user_data, cb, error_code, error.to_string()),
+    }
+}
+
+/// Run the body of an `extern "C"` function, catching any panic so that it doesn't unwind across
+/// the FFI boundary, which aborts the process. A panic is logged along with the name of the
+/// function enclosing `f`, and `T::panic_fallback()` is returned in place of the result.
+///
+/// Prefer `shielded_extern!` for functions taking no generic parameters.
+pub fn shield<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+    T: PanicFallback,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        error!(
+            "panic in {}: {}",
+            function_name::<F>(),
+            panic_message(&*payload)
+        );
+        T::panic_fallback()
+    })
+}
+
+/// Value returned by `shield` in place of the result of a function which panicked.
+///
+/// Implemented for the types usually returned across the FFI: `()`, `false`, zero, null
+/// pointers and `None`. Other return types can implement it to pick their own fallback.
+pub trait PanicFallback {
+    /// Return the fallback value.
+    fn panic_fallback() -> Self;
+}
+
+macro_rules! impl_panic_fallback {
+    ($($ty:ty => $value:expr),* $(,)?) => {$(
+        impl PanicFallback for $ty {
+            fn panic_fallback() -> Self {
+                $value
             }
         }
+    )*};
+}
+
+impl_panic_fallback! {
+    () => (),
+    bool => false,
+    i8 => 0, i16 => 0, i32 => 0, i64 => 0, isize => 0,
+    u8 => 0, u16 => 0, u32 => 0, u64 => 0, usize => 0,
+    f32 => 0.0, f64 => 0.0,
+}
+
+impl<T> PanicFallback for *const T {
+    fn panic_fallback() -> Self {
+        ptr::null()
+    }
+}
+
+impl<T> PanicFallback for *mut T {
+    fn panic_fallback() -> Self {
+        ptr::null_mut()
+    }
+}
+
+impl<T> PanicFallback for Option<T> {
+    fn panic_fallback() -> Self {
+        None
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+/// Define `extern "C"` functions whose body runs under `shield`, so that a panic inside them is
+/// logged instead of aborting the process. Meant for callbacks passed to FFI functions, which
+/// otherwise need their own `catch_unwind`.
+///
+/// Functions returning a value yield its `PanicFallback` after a panic, e.g. a null pointer.
+/// Generic functions aren't supported; call `shield` from their body instead.
+///
+/// ```
+/// use sn_ffi_utils::{shielded_extern, FfiResult};
+/// use std::os::raw::c_void;
+///
+/// shielded_extern! {
+///     /// Called once the operation completes.
+///     pub fn on_done(_user_data: *mut c_void, result: *const FfiResult) {
+///         assert_eq!(unsafe { (*result).error_code }, 0);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! shielded_extern {
+    ($(
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    )*) => {$(
+        $(#[$attr])*
+        $vis extern "C" fn $name($($arg: $ty),*) $(-> $ret)? {
+            $crate::shield(move || $body)
+        }
+    )*};
+}
+
+/// Return the name of the function enclosing the closure or async block `F`.
+pub fn function_name<F>() -> &'static str {
+    let mut name = std::any::type_name::<F>();
+    while let Some(enclosing) = name.strip_suffix("::{{closure}}") {
+        name = enclosing;
+    }
+    name
+}
+
+/// Call the callback with an error and default values for its other arguments.
+pub(crate) fn call_error_cb<C: Callback>(
+    user_data: *mut c_void,
+    cb: C,
+    error_code: i32,
+    description: String,
+) {
+    match static_results::lookup(error_code, &description) {
+        Some(result) => call_static_error_cb(user_data, cb, result),
+        None => call_allocated_error_cb(user_data, cb, error_code, description),
+    }
+}
+
+fn call_static_error_cb<C: Callback>(user_data: *mut c_void, cb: C, result: &FfiResult) {
+    affinity::check();
+
+    trace_callback(result.error_code);
+
+    cb.call(user_data, result, CallbackArgs::default())
+}
+
+fn call_allocated_error_cb<C: Callback>(
+    user_data: *mut c_void,
+    cb: C,
+    error_code: i32,
+    description: String,
+) {
+    affinity::check();
+
+    trace_callback(error_code);
+
+    let res = NativeResult {
+        error_code,
+        description: Some(description),
+    }
+    .into_repr_c();
+
+    match res {
+        Ok(res) => cb.call(user_data, &res, CallbackArgs::default()),
+        Err(_) => {
+            let res = FfiResult {
+                error_code,
+                description: b"Could not convert error description into CString\x00" as *const u8
+                    as *const _,
+            };
+            cb.call(user_data, &res, CallbackArgs::default());
+        }
     }
 }
 
@@ -108,6 +329,29 @@ mod tests {
         assert!(did_unwind);
     }
 
+    shielded_extern! {
+        fn divide_100(divisor: u32) -> u32 {
+            100 / divisor
+        }
+    }
+
+    shielded_extern! {
+        fn element(values: *const u32, index: usize) -> *const u32 {
+            assert!(index < 2);
+            unsafe { values.add(index) }
+        }
+    }
+
+    #[test]
+    fn panic_inside_shielded_extern() {
+        assert_eq!(divide_100(4), 25);
+        assert_eq!(divide_100(0), 0);
+
+        let values = [1, 2];
+        assert_eq!(unsafe { *element(values.as_ptr(), 1) }, 2);
+        assert!(element(values.as_ptr(), 2).is_null());
+    }
+
     // Calls a callback on drop.
     struct DropProbe<F: FnOnce()>(Option<F>);
 
@@ -0,0 +1,320 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Polling alternative to callbacks.
+//!
+//! Hosts which cannot accept callbacks on arbitrary threads create a queue, pass its handle to
+//! FFI functions instead of a callback, and poll for completions from a thread of their choice
+//! with `ffi_poll` (exported with `export_completion_queue!`). Every completion carries the
+//! token given by the host when starting the operation:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn app_fetch(app: Handle, queue: Handle, token: u64) -> i32 {
+//!     ffi_result_code!(handles::get::<App>(app).map(|app| {
+//!         async_ffi::spawn(async move {
+//!             let _ = completion_queue::post(queue, token, app.fetch().await);
+//!         })
+//!     }))
+//! }
+//! ```
+
+use crate::handles::{self, Handle, HandleError};
+use crate::result::{FfiResult, NativeResult};
+use crate::{ffi_error, ErrorCode};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::ptr;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Returned by `ffi_poll` when no event arrived before the timeout.
+pub const POLL_EMPTY: i32 = 1;
+
+/// Completion of an operation, as returned by `CompletionQueue::poll`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Token given by the host when starting the operation.
+    pub token: u64,
+    /// Outcome of the operation.
+    pub result: NativeResult,
+    /// Data produced by the operation. Empty on error.
+    pub data: Vec<u8>,
+}
+
+/// FFI representation of an `Event`.
+///
+/// The pointers remain valid until the next poll of the same queue, or until the queue is freed.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FfiEvent {
+    /// Token given by the host when starting the operation.
+    pub token: u64,
+    /// Outcome of the operation.
+    pub result: *const FfiResult,
+    /// Data produced by the operation.
+    pub data: *const u8,
+    /// Length of `data`.
+    pub data_len: usize,
+}
+
+#[derive(Default)]
+struct State {
+    events: VecDeque<Event>,
+    // Event last returned by `ffi_poll`, borrowed by the host.
+    current: Option<(FfiResult, Vec<u8>)>,
+}
+
+// The `FfiResult` owns its description.
+unsafe impl Send for State {}
+
+/// Thread-safe queue of completed operations.
+#[derive(Default)]
+pub struct CompletionQueue {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+impl CompletionQueue {
+    /// Create an empty queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Post the outcome of the operation identified by `token`.
+    pub fn post<E>(&self, token: u64, result: Result<Vec<u8>, E>)
+    where
+        E: Debug + Display + ErrorCode,
+    {
+        let event = match result {
+            Ok(data) => Event {
+                token,
+                result: NativeResult {
+                    error_code: 0,
+                    description: None,
+                },
+                data,
+            },
+            Err(error) => {
+                let (error_code, description) = ffi_error!(error);
+                Event {
+                    token,
+                    result: NativeResult {
+                        error_code,
+                        description: Some(description),
+                    },
+                    data: Vec::new(),
+                }
+            }
+        };
+        self.post_event(event)
+    }
+
+    /// Post an already built event.
+    pub fn post_event(&self, event: Event) {
+        self.state().events.push_back(event);
+        self.condvar.notify_one();
+    }
+
+    /// Wait up to `timeout` for an event.
+    pub fn poll(&self, timeout: Duration) -> Option<Event> {
+        self.poll_with(timeout, |_, event| event)
+    }
+
+    /// Number of events waiting to be polled.
+    pub fn len(&self) -> usize {
+        self.state().events.len()
+    }
+
+    /// Return `true` if no event is waiting to be polled.
+    pub fn is_empty(&self) -> bool {
+        self.state().events.is_empty()
+    }
+
+    fn poll_with<F, R>(&self, timeout: Duration, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut State, Event) -> R,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state();
+
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Some(f(&mut state, event));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return None;
+            }
+
+            state = self
+                .condvar
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Create a queue in the global handle registry, returning its handle.
+pub fn new_queue() -> Handle {
+    handles::register(CompletionQueue::new())
+}
+
+/// Post the outcome of the operation identified by `token` to the queue behind `queue`.
+pub fn post<E>(queue: Handle, token: u64, result: Result<Vec<u8>, E>) -> Result<(), HandleError>
+where
+    E: Debug + Display + ErrorCode,
+{
+    handles::with(queue, |queue: &CompletionQueue| queue.post(token, result))
+}
+
+/// Release the queue behind `queue`. Events which were not polled are dropped.
+pub fn free(queue: Handle) -> Result<(), HandleError> {
+    handles::free::<CompletionQueue>(queue)
+}
+
+/// Wait up to `timeout_ms` milliseconds for an event on the queue behind `queue` and write it to
+/// `out_event`.
+///
+/// Return 0 if an event was written, `POLL_EMPTY` on timeout, or an error code if `queue` is not
+/// a live queue.
+///
+/// # Safety
+///
+/// `out_event` must be valid for writes.
+pub unsafe fn poll(queue: Handle, out_event: *mut FfiEvent, timeout_ms: u32) -> i32 {
+    let queue = match handles::get::<CompletionQueue>(queue) {
+        Ok(queue) => queue,
+        Err(error) => return error.error_code(),
+    };
+
+    let timeout = Duration::from_millis(u64::from(timeout_ms));
+    let polled = queue.poll_with(timeout, |state, event| {
+        let error_code = event.result.error_code;
+        let result = event.result.into_repr_c().unwrap_or_else(|_| FfiResult {
+            error_code,
+            description: ptr::null(),
+        });
+        let (result, data) = state.current.insert((result, event.data));
+
+        FfiEvent {
+            token: event.token,
+            result,
+            data: data.as_ptr(),
+            data_len: data.len(),
+        }
+    });
+
+    match polled {
+        Some(event) => {
+            ptr::write(out_event, event);
+            0
+        }
+        None => POLL_EMPTY,
+    }
+}
+
+/// Export the completion queue functions of the library.
+///
+/// Defines three `#[no_mangle]` functions:
+///
+/// + `ffi_queue_new() -> u64` creating a queue;
+/// + `ffi_queue_free(queue: u64) -> i32` releasing it;
+/// + `ffi_poll(queue: u64, out_event: *mut FfiEvent, timeout_ms: u32) -> i32` waiting for an
+///   event; see `completion_queue::poll`.
+#[macro_export]
+macro_rules! export_completion_queue {
+    () => {
+        /// Create a completion queue.
+        #[no_mangle]
+        pub extern "C" fn ffi_queue_new() -> u64 {
+            $crate::completion_queue::new_queue()
+        }
+
+        /// Release a completion queue.
+        #[no_mangle]
+        pub extern "C" fn ffi_queue_free(queue: u64) -> i32 {
+            $crate::ffi_result_code!($crate::completion_queue::free(queue))
+        }
+
+        /// Wait up to `timeout_ms` milliseconds for an event on `queue`.
+        #[no_mangle]
+        pub unsafe extern "C" fn ffi_poll(
+            queue: u64,
+            out_event: *mut $crate::completion_queue::FfiEvent,
+            timeout_ms: u32,
+        ) -> i32 {
+            $crate::completion_queue::poll(queue, out_event, timeout_ms)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handles::ERR_INVALID_HANDLE;
+    use crate::test_utils::TestError;
+    use crate::ReprC;
+    use std::mem::MaybeUninit;
+    use std::{slice, thread};
+    use unwrap::unwrap;
+
+    #[test]
+    fn poll_events_through_ffi() {
+        let queue = new_queue();
+        let mut event = MaybeUninit::<FfiEvent>::uninit();
+
+        assert_eq!(unsafe { poll(queue, event.as_mut_ptr(), 0) }, POLL_EMPTY);
+
+        let poster = thread::spawn(move || {
+            unwrap!(post::<TestError>(queue, 1, Ok(vec![1, 2, 3])));
+            unwrap!(post(queue, 2, Err::<Vec<u8>, _>(TestError::Test)));
+        });
+
+        assert_eq!(unsafe { poll(queue, event.as_mut_ptr(), 60_000) }, 0);
+        let ffi_event = unsafe { event.assume_init_ref() };
+        assert_eq!(ffi_event.token, 1);
+        assert_eq!(unsafe { (*ffi_event.result).error_code }, 0);
+        assert_eq!(
+            unsafe { slice::from_raw_parts(ffi_event.data, ffi_event.data_len) },
+            [1, 2, 3]
+        );
+
+        unwrap!(poster.join());
+        assert_eq!(unsafe { poll(queue, event.as_mut_ptr(), 0) }, 0);
+        let ffi_event = unsafe { event.assume_init_ref() };
+        let result = unsafe { unwrap!(NativeResult::clone_from_repr_c(ffi_event.result)) };
+        assert_eq!(ffi_event.token, 2);
+        assert_eq!(result.error_code, -1);
+        assert_eq!(ffi_event.data_len, 0);
+
+        unwrap!(free(queue));
+        assert_eq!(
+            unsafe { poll(queue, event.as_mut_ptr(), 0) },
+            ERR_INVALID_HANDLE
+        );
+    }
+
+    #[test]
+    fn poll_times_out() {
+        let queue = CompletionQueue::new();
+        assert_eq!(queue.poll(Duration::from_millis(10)), None);
+
+        queue.post::<TestError>(7, Ok(Vec::new()));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(unwrap!(queue.poll(Duration::from_secs(0))).token, 7);
+        assert!(queue.is_empty());
+    }
+}
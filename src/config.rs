@@ -0,0 +1,533 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Passing startup options across the FFI as a block of typed key-value entries.
+//!
+//! The caller fills an array of `FfiConfigEntry`, each holding an integer, a boolean or a
+//! string, and passes it as an `FfiConfig`. On the Rust side, `ffi_config!` declares the typed
+//! options struct, whose `ReprC` conversion rejects unknown keys, missing required keys and
+//! values of the wrong type with `ConfigError`:
+//!
+//! ```ignore
+//! ffi_config! {
+//!     /// Startup options of the app.
+//!     pub struct AppConfig {
+//!         /// Port to listen on. Required.
+//!         pub port: u16,
+//!         /// Verbosity of the logs.
+//!         pub log_level: String = "info".to_owned(),
+//!     }
+//! }
+//!
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_start(config: *const FfiConfig, ...) {
+//!     catch_unwind_cb(user_data, o_cb, || -> Result<_, AppError> {
+//!         let config = AppConfig::clone_from_repr_c(config)?;
+//!         ...
+//!     })
+//! }
+//! ```
+//!
+//! A null `FfiConfig` is treated as an empty one. Rust callers, typically tests, build
+//! configurations with `Config::new().with(key, value)` and convert them with `IntoReprC`.
+
+use crate::repr_c::{IntoReprC, ReprC};
+use crate::{ErrorCode, StringError};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt::{self, Display};
+use std::os::raw::c_char;
+use std::{ptr, slice};
+
+/// Error code returned for configurations which can't be converted.
+pub const ERR_INVALID_CONFIG: i32 = -9018;
+
+/// Kind of an entry holding an integer in `int_value`.
+pub const CONFIG_INT: u32 = 0;
+/// Kind of an entry holding a boolean in `int_value`, 0 being `false`.
+pub const CONFIG_BOOL: u32 = 1;
+/// Kind of an entry holding a NUL-terminated UTF-8 string in `string_value`.
+pub const CONFIG_STRING: u32 = 2;
+
+/// Configuration entry.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FfiConfigEntry {
+    /// NUL-terminated key.
+    pub key: *const c_char,
+    /// Kind of the value: `CONFIG_INT`, `CONFIG_BOOL` or `CONFIG_STRING`.
+    pub kind: u32,
+    /// Value of integer and boolean entries.
+    pub int_value: i64,
+    /// Value of string entries.
+    pub string_value: *const c_char,
+}
+
+/// Block of configuration entries.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FfiConfig {
+    /// Pointer to the entries.
+    pub entries: *const FfiConfigEntry,
+    /// Number of entries.
+    pub entries_len: usize,
+}
+
+/// Error converting a configuration.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ConfigError {
+    /// A key or string value isn't a valid string.
+    String(StringError),
+    /// An entry has an unknown kind.
+    InvalidKind {
+        /// Key of the entry.
+        key: String,
+        /// The unknown kind.
+        kind: u32,
+    },
+    /// The key appears more than once.
+    DuplicateKey(String),
+    /// The key isn't an option of the configuration.
+    UnknownKey(String),
+    /// The required key is missing.
+    MissingKey(&'static str),
+    /// The value of the key has the wrong type, or is out of range.
+    InvalidValue {
+        /// Key of the entry.
+        key: &'static str,
+        /// Name of the expected type.
+        expected: &'static str,
+    },
+}
+
+impl ErrorCode for ConfigError {
+    fn error_code(&self) -> i32 {
+        ERR_INVALID_CONFIG
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::String(error) => write!(f, "Invalid configuration string: {:?}", error),
+            ConfigError::InvalidKind { key, kind } => {
+                write!(f, "Configuration key {:?} has unknown kind {}", key, kind)
+            }
+            ConfigError::DuplicateKey(key) => write!(f, "Duplicate configuration key {:?}", key),
+            ConfigError::UnknownKey(key) => write!(f, "Unknown configuration key {:?}", key),
+            ConfigError::MissingKey(key) => write!(f, "Missing configuration key {:?}", key),
+            ConfigError::InvalidValue { key, expected } => {
+                write!(f, "Configuration key {:?} must be a {}", key, expected)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<StringError> for ConfigError {
+    fn from(error: StringError) -> Self {
+        ConfigError::String(error)
+    }
+}
+
+/// Value of a configuration entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigValue {
+    /// Integer value.
+    Int(i64),
+    /// Boolean value.
+    Bool(bool),
+    /// String value.
+    String(String),
+}
+
+macro_rules! impl_from_int {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for ConfigValue {
+                fn from(value: $ty) -> Self {
+                    ConfigValue::Int(i64::from(value))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_int!(i8, i16, i32, i64, u8, u16, u32);
+
+impl From<bool> for ConfigValue {
+    fn from(value: bool) -> Self {
+        ConfigValue::Bool(value)
+    }
+}
+
+impl From<String> for ConfigValue {
+    fn from(value: String) -> Self {
+        ConfigValue::String(value)
+    }
+}
+
+impl<'a> From<&'a str> for ConfigValue {
+    fn from(value: &'a str) -> Self {
+        ConfigValue::String(value.to_owned())
+    }
+}
+
+/// Type of a field of a configuration declared with `ffi_config!`.
+pub trait ConfigField: Sized {
+    /// Name of the type, used in `ConfigError::InvalidValue`.
+    const TYPE_NAME: &'static str;
+
+    /// Convert from the value of an entry, returning `None` if it has the wrong type or is out
+    /// of range.
+    fn from_value(value: ConfigValue) -> Option<Self>;
+}
+
+macro_rules! impl_config_field_int {
+    ($($ty:ty),*) => {
+        $(
+            impl ConfigField for $ty {
+                const TYPE_NAME: &'static str = stringify!($ty);
+
+                fn from_value(value: ConfigValue) -> Option<Self> {
+                    match value {
+                        ConfigValue::Int(value) => <$ty>::try_from(value).ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_config_field_int!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+impl ConfigField for bool {
+    const TYPE_NAME: &'static str = "bool";
+
+    fn from_value(value: ConfigValue) -> Option<Self> {
+        match value {
+            ConfigValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigField for String {
+    const TYPE_NAME: &'static str = "string";
+
+    fn from_value(value: ConfigValue) -> Option<Self> {
+        match value {
+            ConfigValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Untyped configuration, mapping keys to values.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Config {
+    values: BTreeMap<String, ConfigValue>,
+}
+
+impl Config {
+    /// Create an empty configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key` to `value`, replacing any previous value.
+    pub fn with<V: Into<ConfigValue>>(mut self, key: &str, value: V) -> Self {
+        let _ = self.values.insert(key.to_owned(), value.into());
+        self
+    }
+
+    /// Return the value of `key`, if set.
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.values.get(key)
+    }
+
+    /// Remove `key` and convert its value, returning `None` if it isn't set.
+    pub fn take<T: ConfigField>(&mut self, key: &'static str) -> Result<Option<T>, ConfigError> {
+        match self.values.remove(key) {
+            Some(value) => T::from_value(value)
+                .map(Some)
+                .ok_or(ConfigError::InvalidValue {
+                    key,
+                    expected: T::TYPE_NAME,
+                }),
+            None => Ok(None),
+        }
+    }
+
+    /// Fail with `ConfigError::UnknownKey` if any key is left, typically after taking the known
+    /// ones.
+    pub fn finish(self) -> Result<(), ConfigError> {
+        match self.values.into_iter().next() {
+            Some((key, _)) => Err(ConfigError::UnknownKey(key)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ReprC for Config {
+    type C = *const FfiConfig;
+    type Error = ConfigError;
+
+    unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
+        let mut config = Config::new();
+        if repr_c.is_null() || (*repr_c).entries_len == 0 {
+            return Ok(config);
+        }
+
+        for entry in slice::from_raw_parts((*repr_c).entries, (*repr_c).entries_len) {
+            let key = String::clone_from_repr_c(entry.key)?;
+            let value = match entry.kind {
+                CONFIG_INT => ConfigValue::Int(entry.int_value),
+                CONFIG_BOOL => ConfigValue::Bool(entry.int_value != 0),
+                CONFIG_STRING => {
+                    ConfigValue::String(String::clone_from_repr_c(entry.string_value)?)
+                }
+                kind => return Err(ConfigError::InvalidKind { key, kind }),
+            };
+            if config.values.contains_key(&key) {
+                return Err(ConfigError::DuplicateKey(key));
+            }
+            let _ = config.values.insert(key, value);
+        }
+
+        Ok(config)
+    }
+}
+
+/// Owner of the data an `FfiConfig` converted from a `Config` points to.
+pub struct ConfigStorage {
+    _strings: Vec<CString>,
+    _entries: Vec<FfiConfigEntry>,
+    _config: Box<FfiConfig>,
+}
+
+impl IntoReprC for Config {
+    type Storage = ConfigStorage;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let mut strings = Vec::new();
+        let mut entries = Vec::with_capacity(self.values.len());
+
+        for (key, value) in self.values {
+            let key = CString::new(key).map_err(StringError::from)?;
+            let mut entry = FfiConfigEntry {
+                key: key.as_ptr(),
+                kind: CONFIG_INT,
+                int_value: 0,
+                string_value: ptr::null(),
+            };
+            strings.push(key);
+
+            match value {
+                ConfigValue::Int(value) => entry.int_value = value,
+                ConfigValue::Bool(value) => {
+                    entry.kind = CONFIG_BOOL;
+                    entry.int_value = i64::from(value);
+                }
+                ConfigValue::String(value) => {
+                    let value = CString::new(value).map_err(StringError::from)?;
+                    entry.kind = CONFIG_STRING;
+                    entry.string_value = value.as_ptr();
+                    strings.push(value);
+                }
+            }
+            entries.push(entry);
+        }
+
+        let config = Box::new(FfiConfig {
+            entries: entries.as_ptr(),
+            entries_len: entries.len(),
+        });
+        Ok((
+            &*config,
+            ConfigStorage {
+                _strings: strings,
+                _entries: entries,
+                _config: config,
+            },
+        ))
+    }
+}
+
+/// Declare a configuration struct converted from an `FfiConfig`. Fields with a default value
+/// are optional; the others are required.
+///
+/// The struct implements `ReprC`, failing with `ConfigError` on unknown keys, missing required
+/// keys and values of the wrong type, and `TryFrom<Config>`. Field types must implement
+/// `ConfigField`.
+#[macro_export]
+macro_rules! ffi_config {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident : $ty:ty $(= $default:expr)?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl ::std::convert::TryFrom<$crate::config::Config> for $name {
+            type Error = $crate::config::ConfigError;
+
+            fn try_from(mut config: $crate::config::Config) -> Result<Self, Self::Error> {
+                let value = Self {
+                    $(
+                        $field: $crate::ffi_config!(@field config, $field, $ty $(, $default)?),
+                    )*
+                };
+                config.finish()?;
+                Ok(value)
+            }
+        }
+
+        impl $crate::ReprC for $name {
+            type C = *const $crate::config::FfiConfig;
+            type Error = $crate::config::ConfigError;
+
+            unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
+                let config = <$crate::config::Config as $crate::ReprC>::clone_from_repr_c(repr_c)?;
+                ::std::convert::TryFrom::try_from(config)
+            }
+        }
+    };
+
+    (@field $config:ident, $field:ident, $ty:ty) => {
+        $config
+            .take::<$ty>(stringify!($field))?
+            .ok_or($crate::config::ConfigError::MissingKey(stringify!($field)))?
+    };
+
+    (@field $config:ident, $field:ident, $ty:ty, $default:expr) => {
+        match $config.take::<$ty>(stringify!($field))? {
+            Some(value) => value,
+            None => $default,
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unwrap::unwrap;
+
+    ffi_config! {
+        /// Test configuration.
+        #[derive(Debug, PartialEq)]
+        struct AppConfig {
+            port: u16,
+            verbose: bool = false,
+            log_level: String = "info".to_owned(),
+        }
+    }
+
+    fn convert(config: Config) -> Result<AppConfig, ConfigError> {
+        let (repr_c, _storage) = unwrap!(config.into_repr_c());
+        unsafe { AppConfig::clone_from_repr_c(repr_c) }
+    }
+
+    #[test]
+    fn typed_config() {
+        assert_eq!(
+            convert(Config::new().with("port", 8080).with("log_level", "debug")),
+            Ok(AppConfig {
+                port: 8080,
+                verbose: false,
+                log_level: "debug".to_owned(),
+            })
+        );
+
+        assert_eq!(
+            convert(Config::new().with("port", 1).with("colour", true)),
+            Err(ConfigError::UnknownKey("colour".to_owned()))
+        );
+        assert_eq!(
+            convert(Config::new().with("verbose", true)),
+            Err(ConfigError::MissingKey("port"))
+        );
+        assert_eq!(
+            convert(Config::new().with("port", 1).with("verbose", 1)),
+            Err(ConfigError::InvalidValue {
+                key: "verbose",
+                expected: "bool",
+            })
+        );
+        assert_eq!(
+            convert(Config::new().with("port", 70_000)),
+            Err(ConfigError::InvalidValue {
+                key: "port",
+                expected: "u16",
+            })
+        );
+    }
+
+    #[test]
+    fn raw_entries() {
+        let key = unwrap!(CString::new("port"));
+        let entry = FfiConfigEntry {
+            key: key.as_ptr(),
+            kind: CONFIG_INT,
+            int_value: 80,
+            string_value: ptr::null(),
+        };
+
+        let entries = [entry, entry];
+        let config = FfiConfig {
+            entries: entries.as_ptr(),
+            entries_len: 1,
+        };
+        assert_eq!(
+            unsafe { Config::clone_from_repr_c(&config) },
+            Ok(Config::new().with("port", 80))
+        );
+
+        let duplicate = FfiConfig {
+            entries: entries.as_ptr(),
+            entries_len: 2,
+        };
+        assert_eq!(
+            unsafe { Config::clone_from_repr_c(&duplicate) },
+            Err(ConfigError::DuplicateKey("port".to_owned()))
+        );
+
+        let invalid = FfiConfigEntry { kind: 7, ..entry };
+        let config = FfiConfig {
+            entries: &invalid,
+            entries_len: 1,
+        };
+        assert_eq!(
+            unsafe { Config::clone_from_repr_c(&config) },
+            Err(ConfigError::InvalidKind {
+                key: "port".to_owned(),
+                kind: 7,
+            })
+        );
+
+        assert_eq!(
+            unsafe { Config::clone_from_repr_c(ptr::null()) },
+            Ok(Config::new())
+        );
+    }
+}
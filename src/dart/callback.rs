@@ -0,0 +1,74 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::{post, result_message, DartMessage, DartPort, IntoDart};
+use crate::{shield, FfiResult, NativeResult, ReprC};
+use log::error;
+use std::fmt::Debug;
+use std::os::raw::c_void;
+
+/// Transfer `port` into a `user_data` pointer for one of the `callback_*` functions.
+pub fn into_user_data(port: DartPort) -> *mut c_void {
+    Box::into_raw(Box::new(port)) as *mut c_void
+}
+
+unsafe fn complete<F>(user_data: *mut c_void, res: *const FfiResult, value: F)
+where
+    F: FnOnce() -> DartMessage,
+{
+    let port = *Box::from_raw(user_data as *mut DartPort);
+
+    let result = NativeResult::clone_from_repr_c(res).unwrap_or_else(|_| NativeResult {
+        error_code: (*res).error_code,
+        description: None,
+    });
+    let value = if result.error_code == 0 {
+        value()
+    } else {
+        DartMessage::Null
+    };
+
+    if let Err(e) = post(port, &result_message(result, value)) {
+        error!("Failed to post result to Dart port {}: {}", port, e);
+    }
+}
+
+/// Callback taking no value, posting `[error_code, description, null]` to the port behind
+/// `user_data`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `into_user_data`, and must not be used afterwards.
+pub unsafe extern "C" fn callback_0(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || complete(user_data, res, || DartMessage::Null))
+}
+
+/// Callback taking a value, posting `[error_code, description, value]` to the port behind
+/// `user_data`.
+///
+/// If the value can't be converted, the failure is logged and `value` is `null`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `into_user_data`, and must not be used afterwards.
+pub unsafe extern "C" fn callback_1<T>(user_data: *mut c_void, res: *const FfiResult, value: T::C)
+where
+    T: ReprC + IntoDart,
+    T::Error: Debug,
+{
+    shield(move || {
+        complete(user_data, res, || match T::clone_from_repr_c(value) {
+            Ok(value) => value.into_dart(),
+            Err(e) => {
+                error!("Invalid callback argument: {:?}", e);
+                DartMessage::Null
+            }
+        })
+    })
+}
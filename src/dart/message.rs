@@ -0,0 +1,284 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::DartError;
+use crate::NativeResult;
+use dart_sys::{
+    _Dart_CObject__bindgen_ty_1 as CObjectValue,
+    _Dart_CObject__bindgen_ty_1__bindgen_ty_3 as CObjectArray,
+    _Dart_CObject__bindgen_ty_1__bindgen_ty_4 as CObjectTypedData, Dart_CObject,
+    Dart_CObject_Type_Dart_CObject_kArray, Dart_CObject_Type_Dart_CObject_kBool,
+    Dart_CObject_Type_Dart_CObject_kDouble, Dart_CObject_Type_Dart_CObject_kInt64,
+    Dart_CObject_Type_Dart_CObject_kNull, Dart_CObject_Type_Dart_CObject_kString,
+    Dart_CObject_Type_Dart_CObject_kTypedData, Dart_TypedData_Type_Dart_TypedData_kUint8,
+};
+use std::ffi::CString;
+use std::ptr;
+
+/// Message posted to a Dart port.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DartMessage {
+    /// `null`.
+    Null,
+    /// `bool`.
+    Bool(bool),
+    /// `int`.
+    Int(i64),
+    /// `double`.
+    Double(f64),
+    /// `String`.
+    String(String),
+    /// `Uint8List`.
+    Bytes(Vec<u8>),
+    /// `List`.
+    Array(Vec<DartMessage>),
+}
+
+impl DartMessage {
+    /// Run `f` with the `Dart_CObject` representation of the message, which is only valid
+    /// during the call.
+    pub fn with_cobject<R, F>(&self, f: F) -> Result<R, DartError>
+    where
+        F: FnOnce(*mut Dart_CObject) -> R,
+    {
+        let mut storage = Storage::default();
+        let mut obj = storage.encode(self)?;
+        Ok(f(&mut obj))
+    }
+
+    /// Decode a message posted by `with_cobject`.
+    #[cfg(test)]
+    #[allow(non_upper_case_globals)]
+    pub(crate) unsafe fn from_cobject(obj: &Dart_CObject) -> Self {
+        use std::ffi::CStr;
+        use std::slice;
+
+        match obj.type_ {
+            Dart_CObject_Type_Dart_CObject_kBool => DartMessage::Bool(obj.value.as_bool),
+            Dart_CObject_Type_Dart_CObject_kInt64 => DartMessage::Int(obj.value.as_int64),
+            Dart_CObject_Type_Dart_CObject_kDouble => DartMessage::Double(obj.value.as_double),
+            Dart_CObject_Type_Dart_CObject_kString => DartMessage::String(
+                CStr::from_ptr(obj.value.as_string)
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            Dart_CObject_Type_Dart_CObject_kTypedData => {
+                let data = obj.value.as_typed_data;
+                DartMessage::Bytes(
+                    slice::from_raw_parts(data.values, data.length as usize).to_vec(),
+                )
+            }
+            Dart_CObject_Type_Dart_CObject_kArray => {
+                let array = obj.value.as_array;
+                if array.length == 0 {
+                    return DartMessage::Array(Vec::new());
+                }
+                DartMessage::Array(
+                    slice::from_raw_parts(array.values, array.length as usize)
+                        .iter()
+                        .map(|item| Self::from_cobject(&**item))
+                        .collect(),
+                )
+            }
+            _ => DartMessage::Null,
+        }
+    }
+}
+
+// Owns the memory referenced by an encoded message.
+#[derive(Default)]
+struct Storage {
+    strings: Vec<CString>,
+    arrays: Vec<Box<[Dart_CObject]>>,
+    pointers: Vec<Box<[*mut Dart_CObject]>>,
+}
+
+impl Storage {
+    fn encode(&mut self, message: &DartMessage) -> Result<Dart_CObject, DartError> {
+        let (type_, value) = match message {
+            DartMessage::Null => (
+                Dart_CObject_Type_Dart_CObject_kNull,
+                CObjectValue { as_int64: 0 },
+            ),
+            DartMessage::Bool(value) => (
+                Dart_CObject_Type_Dart_CObject_kBool,
+                CObjectValue { as_bool: *value },
+            ),
+            DartMessage::Int(value) => (
+                Dart_CObject_Type_Dart_CObject_kInt64,
+                CObjectValue { as_int64: *value },
+            ),
+            DartMessage::Double(value) => (
+                Dart_CObject_Type_Dart_CObject_kDouble,
+                CObjectValue { as_double: *value },
+            ),
+            DartMessage::String(value) => {
+                let value = CString::new(value.as_str()).map_err(|_| DartError::InteriorNul)?;
+                let ptr = value.as_ptr();
+                self.strings.push(value);
+                (
+                    Dart_CObject_Type_Dart_CObject_kString,
+                    CObjectValue { as_string: ptr },
+                )
+            }
+            DartMessage::Bytes(value) => (
+                Dart_CObject_Type_Dart_CObject_kTypedData,
+                CObjectValue {
+                    as_typed_data: CObjectTypedData {
+                        type_: Dart_TypedData_Type_Dart_TypedData_kUint8,
+                        length: value.len() as isize,
+                        values: value.as_ptr(),
+                    },
+                },
+            ),
+            DartMessage::Array(items) => {
+                let mut objects = items
+                    .iter()
+                    .map(|item| self.encode(item))
+                    .collect::<Result<Box<[_]>, _>>()?;
+                let mut pointers: Box<[*mut Dart_CObject]> =
+                    objects.iter_mut().map(ptr::from_mut).collect();
+                let values = if pointers.is_empty() {
+                    ptr::null_mut()
+                } else {
+                    pointers.as_mut_ptr()
+                };
+                // Moving the boxes doesn't move their contents.
+                self.arrays.push(objects);
+                self.pointers.push(pointers);
+                (
+                    Dart_CObject_Type_Dart_CObject_kArray,
+                    CObjectValue {
+                        as_array: CObjectArray {
+                            length: items.len() as isize,
+                            values,
+                        },
+                    },
+                )
+            }
+        };
+
+        Ok(Dart_CObject { type_, value })
+    }
+}
+
+/// Conversion of a native value into a `DartMessage`.
+pub trait IntoDart {
+    /// Convert `self` into a message.
+    fn into_dart(self) -> DartMessage;
+}
+
+impl IntoDart for DartMessage {
+    fn into_dart(self) -> DartMessage {
+        self
+    }
+}
+
+impl IntoDart for () {
+    fn into_dart(self) -> DartMessage {
+        DartMessage::Null
+    }
+}
+
+impl IntoDart for bool {
+    fn into_dart(self) -> DartMessage {
+        DartMessage::Bool(self)
+    }
+}
+
+macro_rules! impl_into_dart_int {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoDart for $ty {
+                #[allow(trivial_numeric_casts)]
+                fn into_dart(self) -> DartMessage {
+                    DartMessage::Int(self as i64)
+                }
+            }
+        )*
+    };
+}
+
+impl_into_dart_int!(i8, i16, i32, i64, u8, u16, u32, u64, isize, usize);
+
+impl IntoDart for f64 {
+    fn into_dart(self) -> DartMessage {
+        DartMessage::Double(self)
+    }
+}
+
+impl IntoDart for String {
+    fn into_dart(self) -> DartMessage {
+        DartMessage::String(self)
+    }
+}
+
+impl IntoDart for Vec<u8> {
+    fn into_dart(self) -> DartMessage {
+        DartMessage::Bytes(self)
+    }
+}
+
+impl<T: IntoDart> IntoDart for Option<T> {
+    fn into_dart(self) -> DartMessage {
+        self.map_or(DartMessage::Null, IntoDart::into_dart)
+    }
+}
+
+/// Encoded as `[error_code, description]`.
+impl IntoDart for NativeResult {
+    fn into_dart(self) -> DartMessage {
+        DartMessage::Array(vec![
+            DartMessage::Int(i64::from(self.error_code)),
+            self.description.into_dart(),
+        ])
+    }
+}
+
+/// Build the message reporting the outcome of a call: `[error_code, description, value]`.
+/// `value` is replaced by `null` if the call failed.
+pub fn result_message(result: NativeResult, value: DartMessage) -> DartMessage {
+    let value = if result.error_code == 0 {
+        value
+    } else {
+        DartMessage::Null
+    };
+
+    DartMessage::Array(vec![
+        DartMessage::Int(i64::from(result.error_code)),
+        result.description.into_dart(),
+        value,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unwrap::unwrap;
+
+    #[test]
+    fn round_trip() {
+        let message = DartMessage::Array(vec![
+            DartMessage::Null,
+            true.into_dart(),
+            42u32.into_dart(),
+            1.5.into_dart(),
+            "hello".to_owned().into_dart(),
+            vec![1u8, 2, 3].into_dart(),
+            DartMessage::Array(vec![]),
+            Some(-1i32).into_dart(),
+        ]);
+        let decoded =
+            unwrap!(message.with_cobject(|obj| unsafe { DartMessage::from_cobject(&*obj) }));
+        assert_eq!(decoded, message);
+
+        let message = DartMessage::String("a\0b".to_owned());
+        assert_eq!(message.with_cobject(|_| ()), Err(DartError::InteriorNul));
+    }
+}
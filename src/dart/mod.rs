@@ -0,0 +1,199 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Dart FFI / Flutter utilities.
+//!
+//! Dart isolates can't be called into from arbitrary threads, so instead of invoking a callback
+//! the result of a call is posted as a message to a `SendPort` of the isolate, through the Dart
+//! DL API. The Dart side creates a `ReceivePort`, passes its `sendPort.nativePort` to the call,
+//! and the native side passes the port as `user_data` together with one of the `callback_*`
+//! functions:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn fetch(app: *const App, port: i64) {
+//!     app_fetch(app, dart::into_user_data(port), dart::callback_1::<String>);
+//! }
+//! ```
+//!
+//! Every such message is a list `[error_code, description, value]`, where `description` is
+//! `null` on success, and `value` is `null` on failure.
+//!
+//! The DL API must be initialised once, before any message is posted, by calling the function
+//! exported by `export_dart!` with `NativeApi.initializeApiDLData`.
+
+mod callback;
+mod message;
+
+pub use self::callback::{callback_0, callback_1, into_user_data};
+pub use self::message::{result_message, DartMessage, IntoDart};
+
+use dart_sys::{Dart_InitializeApiDL, Dart_PostCObject_DL};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::os::raw::c_void;
+
+/// Native port of a Dart `SendPort` (`SendPort.nativePort`).
+pub type DartPort = i64;
+
+/// Error posting a message to Dart.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DartError {
+    /// The DL API hasn't been initialised.
+    NotInitialised,
+    /// `Dart_InitializeApiDL` failed with the given status.
+    Init(isize),
+    /// The message contains a string with an interior nul byte.
+    InteriorNul,
+    /// The message was rejected, e.g. because the port is closed.
+    Post,
+}
+
+impl Display for DartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DartError::NotInitialised => write!(f, "Dart DL API not initialised"),
+            DartError::Init(status) => {
+                write!(
+                    f,
+                    "Dart DL API initialisation failed with status {}",
+                    status
+                )
+            }
+            DartError::InteriorNul => write!(f, "String contains an interior nul byte"),
+            DartError::Post => write!(f, "Failed to post message to Dart port"),
+        }
+    }
+}
+
+impl Error for DartError {}
+
+/// Initialise the Dart DL API with the value of `NativeApi.initializeApiDLData`.
+///
+/// # Safety
+///
+/// `data` must be the value of `NativeApi.initializeApiDLData` of the running Dart VM.
+pub unsafe fn init(data: *mut c_void) -> Result<(), DartError> {
+    match Dart_InitializeApiDL(data) {
+        0 => Ok(()),
+        status => Err(DartError::Init(status)),
+    }
+}
+
+/// Post `message` to `port`. The message is copied, so it can be dropped afterwards.
+pub fn post(port: DartPort, message: &DartMessage) -> Result<(), DartError> {
+    let post = unsafe { Dart_PostCObject_DL }.ok_or(DartError::NotInitialised)?;
+    message.with_cobject(|obj| {
+        if unsafe { post(port, obj) } {
+            Ok(())
+        } else {
+            Err(DartError::Post)
+        }
+    })?
+}
+
+/// Generate the exported function initialising the Dart DL API:
+///
+/// ```ignore
+/// export_dart!();
+/// ```
+///
+/// This defines `ffi_dart_init(data: *mut c_void) -> i32`, to be called from Dart with
+/// `NativeApi.initializeApiDLData` before anything else. It returns 0 on success.
+#[macro_export]
+macro_rules! export_dart {
+    () => {
+        /// Initialise the Dart DL API. Returns 0 on success.
+        ///
+        /// # Safety
+        ///
+        /// `data` must be the value of `NativeApi.initializeApiDLData`.
+        #[no_mangle]
+        pub unsafe extern "C" fn ffi_dart_init(data: *mut std::os::raw::c_void) -> i32 {
+            match $crate::dart::init(data) {
+                Ok(()) => 0,
+                Err(e) => {
+                    log::error!("{}", e);
+                    -1
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntoReprC, NativeResult, FFI_RESULT_OK};
+    use dart_sys::{Dart_CObject, Dart_CObject_Type_Dart_CObject_kArray};
+    use std::ffi::CString;
+    use std::os::raw::c_char;
+    use std::sync::Mutex;
+    use unwrap::unwrap;
+
+    static POSTED: Mutex<Vec<(DartPort, DartMessage)>> = Mutex::new(Vec::new());
+
+    unsafe extern "C" fn fake_post(port: DartPort, message: *mut Dart_CObject) -> bool {
+        assert_eq!((*message).type_, Dart_CObject_Type_Dart_CObject_kArray);
+        unwrap!(POSTED.lock()).push((port, DartMessage::from_cobject(&*message)));
+        port != 0
+    }
+
+    // Single test, as it sets the global post function.
+    #[test]
+    fn post_messages() {
+        let message = DartMessage::Array(vec![DartMessage::Int(1)]);
+        assert_eq!(post(1, &message), Err(DartError::NotInitialised));
+
+        unsafe { Dart_PostCObject_DL = Some(fake_post) };
+
+        unwrap!(post(1, &message));
+        assert_eq!(post(0, &message), Err(DartError::Post));
+
+        let value = unwrap!(CString::new("hello"));
+        unsafe {
+            callback_1::<String>(
+                into_user_data(2),
+                FFI_RESULT_OK,
+                value.as_ptr() as *const c_char,
+            );
+
+            let (res, _storage) = unwrap!(IntoReprC::into_repr_c(NativeResult {
+                error_code: -3,
+                description: Some("failed".to_owned()),
+            }));
+            callback_0(into_user_data(3), res);
+        }
+
+        let posted = unwrap!(POSTED.lock());
+        assert_eq!(
+            *posted,
+            vec![
+                (1, message.clone()),
+                (0, message),
+                (
+                    2,
+                    DartMessage::Array(vec![
+                        DartMessage::Int(0),
+                        DartMessage::String(String::new()),
+                        DartMessage::String("hello".to_owned()),
+                    ])
+                ),
+                (
+                    3,
+                    DartMessage::Array(vec![
+                        DartMessage::Int(-3),
+                        DartMessage::String("failed".to_owned()),
+                        DartMessage::Null,
+                    ])
+                ),
+            ]
+        );
+    }
+}
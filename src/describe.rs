@@ -0,0 +1,254 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Runtime description of the FFI surface of a library.
+//!
+//! `describe_ffi_fn!` records the name, arguments and callback shapes of an exported function in
+//! a process-wide registry, when the library is loaded. Binding generators and hosts can then
+//! introspect the loaded library with `describe_api_json`, exported as `ffi_describe_api` by
+//! `export_api_description!`:
+//!
+//! ```ignore
+//! describe_ffi_fn! {
+//!     fn app_get_value(app: Handle, key: *const c_char)
+//!         => o_cb(result: *const FfiResult, value: u64);
+//! }
+//! ```
+//!
+//! The `user_data` argument and the callbacks themselves are implied. Functions with several
+//! callbacks list them all, separated by commas.
+//!
+//! Registration runs as a load-time constructor, as in the `inventory` crate, on Linux, Android,
+//! the BSDs, macOS, iOS and Windows. On other targets the registry stays empty. As with any
+//! constructor, the linker may discard descriptions in object files nothing else refers to, so
+//! they are best placed next to the functions they describe.
+
+use std::ffi::CString;
+use std::fmt::Write;
+use std::os::raw::c_char;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Argument of an FFI function or callback.
+#[derive(Debug)]
+pub struct FfiArgDesc {
+    /// Name of the argument.
+    pub name: &'static str,
+    /// Rust type of the argument.
+    pub ty: &'static str,
+}
+
+/// Callback of an FFI function, whose first argument is the implied `user_data`.
+#[derive(Debug)]
+pub struct FfiCallbackDesc {
+    /// Name of the callback argument.
+    pub name: &'static str,
+    /// Arguments following `user_data`.
+    pub args: &'static [FfiArgDesc],
+}
+
+/// FFI function recorded by `describe_ffi_fn!`.
+#[derive(Debug)]
+pub struct FfiFnDesc {
+    /// Name of the function.
+    pub name: &'static str,
+    /// Arguments preceding `user_data` and the callbacks.
+    pub args: &'static [FfiArgDesc],
+    /// Callbacks, following `user_data`.
+    pub callbacks: &'static [FfiCallbackDesc],
+}
+
+static REGISTRY: Mutex<Vec<&'static FfiFnDesc>> = Mutex::new(Vec::new());
+
+/// Add `desc` to the registry. Called by the constructors generated by `describe_ffi_fn!`.
+#[doc(hidden)]
+pub fn register(desc: &'static FfiFnDesc) {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(desc)
+}
+
+/// Descriptions of the registered functions, sorted by name.
+pub fn registered() -> Vec<&'static FfiFnDesc> {
+    let mut descs = REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    descs.sort_by_key(|desc| desc.name);
+    descs
+}
+
+/// Describe the registered functions as a JSON array of
+/// `{"name", "args": [{"name", "type"}], "callbacks": [{"name", "args"}]}` objects.
+pub fn describe_api_json() -> String {
+    let mut json = String::from("[");
+    for (i, desc) in registered().into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(json, r#"{{"name":"{}","args":"#, escape(desc.name));
+        write_args(&mut json, desc.args);
+        json.push_str(r#","callbacks":["#);
+        for (j, callback) in desc.callbacks.iter().enumerate() {
+            if j > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, r#"{{"name":"{}","args":"#, escape(callback.name));
+            write_args(&mut json, callback.args);
+            json.push('}');
+        }
+        json.push_str("]}");
+    }
+    json.push(']');
+    json
+}
+
+/// Same as `describe_api_json`, computed on the first call and cached as a NUL-terminated string
+/// for the lifetime of the process.
+pub fn describe_api_c_str() -> *const c_char {
+    static JSON: OnceLock<CString> = OnceLock::new();
+    JSON.get_or_init(|| CString::new(describe_api_json()).unwrap_or_default())
+        .as_ptr()
+}
+
+fn write_args(json: &mut String, args: &[FfiArgDesc]) {
+    json.push('[');
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            r#"{{"name":"{}","type":"{}"}}"#,
+            escape(arg.name),
+            escape(arg.ty)
+        );
+    }
+    json.push(']');
+}
+
+// Names and types only need quotes and backslashes escaped.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Record the description of an FFI function; see the module documentation.
+#[macro_export]
+macro_rules! describe_ffi_fn {
+    ($(
+        fn $name:ident($($arg:ident : $ty:ty),* $(,)?)
+            $(=> $($cb:ident($($cb_arg:ident : $cb_ty:ty),* $(,)?)),+ $(,)?)?;
+    )+) => {
+        $(
+            const _: () = {
+                static DESC: $crate::describe::FfiFnDesc = $crate::describe::FfiFnDesc {
+                    name: stringify!($name),
+                    args: &[$($crate::describe::FfiArgDesc {
+                        name: stringify!($arg),
+                        ty: stringify!($ty),
+                    }),*],
+                    callbacks: &[$($($crate::describe::FfiCallbackDesc {
+                        name: stringify!($cb),
+                        args: &[$($crate::describe::FfiArgDesc {
+                            name: stringify!($cb_arg),
+                            ty: stringify!($cb_ty),
+                        }),*],
+                    }),+)?],
+                };
+
+                $crate::on_load! {
+                    $crate::describe::register(&DESC)
+                }
+
+                // Check that the types exist.
+                let _: Option<fn($($ty,)* $($($($cb_ty,)*)+)?)> = None;
+            };
+        )+
+    };
+}
+
+/// Run a statement when the library is loaded, from a constructor placed in the platform's
+/// initialisation section. Used by `describe_ffi_fn!` and `export_free_fn!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! on_load {
+    ($body:expr) => {
+        const _: () = {
+            extern "C" fn on_load() {
+                $body
+            }
+
+            #[used]
+            #[cfg_attr(
+                any(
+                    target_os = "linux",
+                    target_os = "android",
+                    target_os = "freebsd",
+                    target_os = "netbsd",
+                    target_os = "openbsd",
+                    target_os = "dragonfly"
+                ),
+                link_section = ".init_array"
+            )]
+            #[cfg_attr(
+                any(target_os = "macos", target_os = "ios"),
+                link_section = "__DATA,__mod_init_func"
+            )]
+            #[cfg_attr(windows, link_section = ".CRT$XCU")]
+            static ON_LOAD: extern "C" fn() = on_load;
+        };
+    };
+}
+
+/// Export the description of the library's FFI surface.
+///
+/// Defines `ffi_describe_api() -> *const c_char`, returning `describe::describe_api_json` as a
+/// NUL-terminated string which remains valid for the lifetime of the process.
+#[macro_export]
+macro_rules! export_api_description {
+    () => {
+        /// Return the description of the FFI functions of this library, as JSON.
+        #[no_mangle]
+        pub extern "C" fn ffi_describe_api() -> *const ::std::os::raw::c_char {
+            $crate::describe::describe_api_c_str()
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handles::Handle;
+    use crate::FfiResult;
+    use unwrap::unwrap;
+
+    describe_ffi_fn! {
+        fn test_describe_get(app: Handle, key: *const c_char)
+            => o_cb(result: *const FfiResult, value: u64);
+        fn test_describe_stream(app: Handle)
+            => o_data(data: *const u8, len: usize), o_done(result: *const FfiResult);
+    }
+
+    #[test]
+    fn registered_descriptions() {
+        let descs = registered();
+        let get = unwrap!(descs.iter().find(|desc| desc.name == "test_describe_get"));
+        assert_eq!(get.args.len(), 2);
+        assert_eq!(get.args[1].ty, "*const c_char");
+        assert_eq!(get.callbacks[0].args[1].name, "value");
+
+        let json = describe_api_json();
+        assert!(json.contains(concat!(
+            r#"{"name":"test_describe_stream","args":[{"name":"app","type":"Handle"}],"#,
+            r#""callbacks":[{"name":"o_data","args":[{"name":"data","type":"*const u8"},"#,
+            r#"{"name":"len","type":"usize"}]},"#,
+            r#"{"name":"o_done","args":[{"name":"result","type":"*const FfiResult"}]}]}"#
+        )));
+    }
+}
@@ -0,0 +1,665 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Invocation of callbacks from a bounded pool of worker threads.
+//!
+//! Instead of invoking the frontend's callback on whichever internal thread produced the result,
+//! the invocation is queued on a `Dispatcher` and run by one of its workers. The queue is bounded,
+//! and its `Backpressure` policy decides what happens when the frontend can't keep up:
+//!
+//! ```ignore
+//! let dispatcher = Dispatcher::new(DispatcherConfig {
+//!     workers: 1,
+//!     capacity: 256,
+//!     backpressure: Backpressure::DropOldest,
+//! });
+//! dispatcher.dispatch_cb(user_data, o_cb, app.latest_event())?;
+//! ```
+//!
+//! Jobs queued with `dispatch_ordered` or `dispatch_cb_ordered` are keyed, typically by the
+//! handle of the object they report on: jobs with the same key run one at a time in the order
+//! they were queued, even with several workers, while jobs with different keys run in parallel.
+//! This keeps e.g. progress and completion callbacks for an operation in order:
+//!
+//! ```ignore
+//! dispatcher.dispatch_cb_ordered(handle, user_data, o_progress, Ok(progress))?;
+//! dispatcher.dispatch_cb_ordered(handle, user_data, o_done, result)?;
+//! ```
+//!
+//! A process-wide dispatcher is available through `global`, and can be configured once with
+//! `configure` before its first use.
+
+use crate::affinity;
+use crate::callback::{cb_job, Callback};
+use crate::handles::Handle;
+use crate::{pending, ErrorCode, IntoReprC};
+use log::{error, warn};
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::io;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// Error code returned when a job is rejected because the queue is full.
+pub const ERR_DISPATCH_QUEUE_FULL: i32 = -9005;
+/// Error code returned when a job is rejected because the dispatcher has been shut down.
+pub const ERR_DISPATCHER_SHUT_DOWN: i32 = -9006;
+
+/// What to do with a new job when the queue is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backpressure {
+    /// Block the dispatching thread until there is room in the queue.
+    Block,
+    /// Drop the oldest queued job to make room.
+    DropOldest,
+    /// Reject the new job with `DispatchError::Full`.
+    Error,
+}
+
+/// Configuration of a `Dispatcher`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DispatcherConfig {
+    /// Number of worker threads. With a single worker, jobs run in the order they were queued;
+    /// otherwise only jobs with the same ordering key do.
+    pub workers: usize,
+    /// Maximum number of queued jobs.
+    pub capacity: usize,
+    /// Policy applied when the queue is full.
+    pub backpressure: Backpressure,
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            capacity: 1024,
+            backpressure: Backpressure::Block,
+        }
+    }
+}
+
+/// Error returned when a job can't be queued.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DispatchError {
+    /// The queue is full and the policy is `Backpressure::Error`.
+    Full,
+    /// The dispatcher has been shut down.
+    ShutDown,
+}
+
+impl ErrorCode for DispatchError {
+    fn error_code(&self) -> i32 {
+        match self {
+            DispatchError::Full => ERR_DISPATCH_QUEUE_FULL,
+            DispatchError::ShutDown => ERR_DISPATCHER_SHUT_DOWN,
+        }
+    }
+}
+
+impl Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DispatchError::Full => write!(f, "Dispatch queue is full"),
+            DispatchError::ShutDown => write!(f, "Dispatcher has been shut down"),
+        }
+    }
+}
+
+impl Error for DispatchError {}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Queued {
+    key: Option<Handle>,
+    job: Job,
+}
+
+struct State {
+    jobs: VecDeque<Queued>,
+    // Keys of the running ordered jobs, whose successors have to wait.
+    busy: HashSet<Handle>,
+    running: usize,
+    dropped: u64,
+    shut_down: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    job_queued: Condvar,
+    job_taken: Condvar,
+    idle: Condvar,
+    capacity: usize,
+    backpressure: Backpressure,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Take the oldest job whose key isn't busy.
+    fn take_job(state: &mut State) -> Option<Queued> {
+        let busy = &state.busy;
+        let index = state
+            .jobs
+            .iter()
+            .position(|queued| queued.key.is_none_or(|key| !busy.contains(&key)))?;
+        let queued = state.jobs.remove(index)?;
+        if let Some(key) = queued.key {
+            let _ = state.busy.insert(key);
+        }
+        Some(queued)
+    }
+
+    fn run_worker(&self) {
+        loop {
+            let Queued { key, job } = {
+                let mut state = self.lock();
+                loop {
+                    if let Some(queued) = Self::take_job(&mut state) {
+                        state.running += 1;
+                        self.job_taken.notify_one();
+                        break queued;
+                    }
+                    if state.shut_down {
+                        return;
+                    }
+                    state = self
+                        .job_queued
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            };
+
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                error!("Dispatched job panicked");
+            }
+
+            let mut state = self.lock();
+            state.running -= 1;
+            if let Some(key) = key {
+                let _ = state.busy.remove(&key);
+                // The next job with this key may be waiting for an idle worker.
+                if !state.jobs.is_empty() {
+                    self.job_queued.notify_one();
+                }
+            }
+            if state.running == 0 && state.jobs.is_empty() {
+                self.idle.notify_all();
+            }
+            drop(state);
+            pending::notify_completed();
+        }
+    }
+}
+
+/// Bounded queue of jobs, typically callback invocations, run by a pool of worker threads.
+pub struct Dispatcher {
+    shared: Arc<Shared>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    // Set if no worker could be started, in which case jobs run on the dispatching thread.
+    inline: bool,
+}
+
+impl Dispatcher {
+    /// Start a dispatcher with the given configuration. At least one worker is started, and the
+    /// queue holds at least one job.
+    ///
+    /// If no worker thread can be spawned, jobs are run on the dispatching thread instead, as
+    /// soon as they are dispatched.
+    pub fn new(config: DispatcherConfig) -> Self {
+        Self::start(config, |name, run| {
+            thread::Builder::new().name(name).spawn(run)
+        })
+    }
+
+    fn start<S>(config: DispatcherConfig, mut spawn: S) -> Self
+    where
+        S: FnMut(String, Box<dyn FnOnce() + Send>) -> io::Result<JoinHandle<()>>,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                jobs: VecDeque::new(),
+                busy: HashSet::new(),
+                running: 0,
+                dropped: 0,
+                shut_down: false,
+            }),
+            job_queued: Condvar::new(),
+            job_taken: Condvar::new(),
+            idle: Condvar::new(),
+            capacity: config.capacity.max(1),
+            backpressure: config.backpressure,
+        });
+
+        let workers: Vec<_> = (0..config.workers.max(1))
+            .filter_map(|index| {
+                let shared = Arc::clone(&shared);
+                spawn(
+                    format!("ffi-dispatch-{}", index),
+                    Box::new(move || {
+                        affinity::mark_dispatcher_worker();
+                        shared.run_worker()
+                    }),
+                )
+                .map_err(|e| error!("Failed to start dispatcher worker: {}", e))
+                .ok()
+            })
+            .collect();
+
+        let inline = workers.is_empty();
+        if inline {
+            warn!("No dispatcher worker could be started, running jobs inline");
+        }
+
+        Self {
+            shared,
+            workers: Mutex::new(workers),
+            inline,
+        }
+    }
+
+    /// Queue `job`, applying the backpressure policy if the queue is full.
+    pub fn dispatch<F>(&self, job: F) -> Result<(), DispatchError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push(None, Box::new(job), true)
+    }
+
+    /// Queue `job` if there is room in the queue, failing with `DispatchError::Full` otherwise,
+    /// whatever the backpressure policy.
+    pub fn try_dispatch<F>(&self, job: F) -> Result<(), DispatchError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push(None, Box::new(job), false)
+    }
+
+    /// Queue `job` to run after every job previously queued with the same `key` has run.
+    pub fn dispatch_ordered<F>(&self, key: Handle, job: F) -> Result<(), DispatchError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push(Some(key), Box::new(job), true)
+    }
+
+    fn push(&self, key: Option<Handle>, job: Job, apply_policy: bool) -> Result<(), DispatchError> {
+        if self.inline {
+            return self.run_inline(job);
+        }
+
+        let mut state = self.shared.lock();
+
+        loop {
+            if state.shut_down {
+                return Err(DispatchError::ShutDown);
+            }
+            if state.jobs.len() < self.shared.capacity {
+                break;
+            }
+            if !apply_policy {
+                return Err(DispatchError::Full);
+            }
+
+            match self.shared.backpressure {
+                Backpressure::Block => {
+                    state = self
+                        .shared
+                        .job_taken
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                Backpressure::DropOldest => {
+                    let _ = state.jobs.pop_front();
+                    state.dropped += 1;
+                    warn!("Dispatch queue is full, dropping the oldest job");
+                }
+                Backpressure::Error => return Err(DispatchError::Full),
+            }
+        }
+
+        state.jobs.push_back(Queued { key, job });
+        self.shared.job_queued.notify_one();
+        Ok(())
+    }
+
+    fn run_inline(&self, job: Job) -> Result<(), DispatchError> {
+        {
+            let mut state = self.shared.lock();
+            if state.shut_down {
+                return Err(DispatchError::ShutDown);
+            }
+            state.running += 1;
+        }
+
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            error!("Dispatched job panicked");
+        }
+
+        let mut state = self.shared.lock();
+        state.running -= 1;
+        if state.running == 0 && state.jobs.is_empty() {
+            self.shared.idle.notify_all();
+        }
+        drop(state);
+        pending::notify_completed();
+        Ok(())
+    }
+
+    /// Queue the invocation of `cb` with `result`: the value converted to its FFI representation
+    /// on success, or the error converted through `NativeResult` on failure.
+    pub fn dispatch_cb<U, C, T, E>(
+        &self,
+        user_data: U,
+        cb: C,
+        result: Result<T, E>,
+    ) -> Result<(), DispatchError>
+    where
+        U: Into<*mut c_void>,
+        C: Callback<Args = T::C> + Send + 'static,
+        T: IntoReprC + Send + 'static,
+        T::Error: Debug,
+        E: Debug + Display + ErrorCode + From<&'static str> + Send + 'static,
+    {
+        self.dispatch(cb_job(user_data, cb, result))
+    }
+
+    /// Like `dispatch_cb`, but ordered with the other jobs queued with the same `key`.
+    pub fn dispatch_cb_ordered<U, C, T, E>(
+        &self,
+        key: Handle,
+        user_data: U,
+        cb: C,
+        result: Result<T, E>,
+    ) -> Result<(), DispatchError>
+    where
+        U: Into<*mut c_void>,
+        C: Callback<Args = T::C> + Send + 'static,
+        T: IntoReprC + Send + 'static,
+        T::Error: Debug,
+        E: Debug + Display + ErrorCode + From<&'static str> + Send + 'static,
+    {
+        self.dispatch_ordered(key, cb_job(user_data, cb, result))
+    }
+
+    /// Number of queued jobs, not counting the running ones.
+    pub fn len(&self) -> usize {
+        self.shared.lock().jobs.len()
+    }
+
+    /// Return `true` if no jobs are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of queued and running jobs.
+    pub fn pending(&self) -> usize {
+        let state = self.shared.lock();
+        state.jobs.len() + state.running
+    }
+
+    /// Number of jobs dropped by the `Backpressure::DropOldest` policy.
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    /// Block until every queued job has run. Must not be called from a dispatched job.
+    pub fn drain(&self) {
+        let mut state = self.shared.lock();
+        while state.running > 0 || !state.jobs.is_empty() {
+            state = self
+                .shared
+                .idle
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Stop accepting jobs, run the queued ones and stop the workers. Blocked dispatchers are
+    /// woken up with `DispatchError::ShutDown`.
+    pub fn shutdown(&self) {
+        {
+            let mut state = self.shared.lock();
+            state.shut_down = true;
+            self.shared.job_queued.notify_all();
+            self.shared.job_taken.notify_all();
+        }
+
+        let workers =
+            std::mem::take(&mut *self.workers.lock().unwrap_or_else(PoisonError::into_inner));
+        let current = thread::current().id();
+        for worker in workers {
+            // A job shutting down its own dispatcher can't wait for itself.
+            if worker.thread().id() != current {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+static GLOBAL: OnceLock<Dispatcher> = OnceLock::new();
+
+/// Configure the process-wide dispatcher. Fails, returning `config`, if it is already running.
+pub fn configure(config: DispatcherConfig) -> Result<(), DispatcherConfig> {
+    let mut config = Some(config);
+    let _ = GLOBAL.get_or_init(|| Dispatcher::new(config.take().unwrap_or_default()));
+    config.map_or(Ok(()), Err)
+}
+
+/// Process-wide dispatcher, started with the default configuration unless `configure` was
+/// called first.
+pub fn global() -> &'static Dispatcher {
+    GLOBAL.get_or_init(|| Dispatcher::new(DispatcherConfig::default()))
+}
+
+// Number of queued and running jobs of the process-wide dispatcher, if it was started.
+pub(crate) fn pending_global() -> usize {
+    GLOBAL.get().map_or(0, Dispatcher::pending)
+}
+
+// Drain the process-wide dispatcher, if it was started.
+pub(crate) fn drain_global() {
+    if let Some(dispatcher) = GLOBAL.get() {
+        dispatcher.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_1, TestError};
+    use std::sync::mpsc::{self, Sender};
+    use std::time::Duration;
+    use unwrap::unwrap;
+
+    fn config(capacity: usize, backpressure: Backpressure) -> DispatcherConfig {
+        DispatcherConfig {
+            workers: 1,
+            capacity,
+            backpressure,
+        }
+    }
+
+    // Occupy the single worker until the returned sender is used.
+    fn block_worker(dispatcher: &Dispatcher) -> Sender<()> {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        unwrap!(dispatcher.dispatch(move || {
+            unwrap!(started_tx.send(()));
+            let _ = release_rx.recv();
+        }));
+        unwrap!(started_rx.recv_timeout(Duration::from_secs(5)));
+        release_tx
+    }
+
+    #[test]
+    fn jobs_run_in_order() {
+        let dispatcher = Dispatcher::new(config(4, Backpressure::Block));
+        let (tx, rx) = mpsc::channel();
+
+        for i in 0..100 {
+            let tx = tx.clone();
+            unwrap!(dispatcher.dispatch(move || unwrap!(tx.send(i))));
+        }
+        dispatcher.drain();
+
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            (0..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn backpressure_policies() {
+        let dispatcher = Dispatcher::new(config(2, Backpressure::Error));
+        let release = block_worker(&dispatcher);
+        unwrap!(dispatcher.dispatch(|| ()));
+        unwrap!(dispatcher.dispatch(|| ()));
+        assert_eq!(dispatcher.dispatch(|| ()), Err(DispatchError::Full));
+        unwrap!(release.send(()));
+        dispatcher.drain();
+
+        let dispatcher = Dispatcher::new(config(2, Backpressure::DropOldest));
+        let (tx, rx) = mpsc::channel();
+        let release = block_worker(&dispatcher);
+        for i in 0..5 {
+            let tx = tx.clone();
+            unwrap!(dispatcher.dispatch(move || unwrap!(tx.send(i))));
+        }
+        assert_eq!(dispatcher.dropped(), 3);
+        unwrap!(release.send(()));
+        dispatcher.drain();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn try_dispatch_never_blocks() {
+        let dispatcher = Dispatcher::new(config(1, Backpressure::Block));
+        let release = block_worker(&dispatcher);
+        unwrap!(dispatcher.try_dispatch(|| ()));
+        assert_eq!(dispatcher.try_dispatch(|| ()), Err(DispatchError::Full));
+        unwrap!(release.send(()));
+        dispatcher.drain();
+    }
+
+    #[test]
+    fn ordered_jobs() {
+        let dispatcher = Dispatcher::new(DispatcherConfig {
+            workers: 4,
+            ..config(1024, Backpressure::Block)
+        });
+        let (tx, rx) = mpsc::channel();
+
+        for i in 0..100 {
+            for key in 1..=3 {
+                let tx = tx.clone();
+                unwrap!(dispatcher.dispatch_ordered(key, move || {
+                    if i % 7 == 0 {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    unwrap!(tx.send((key, i)));
+                }));
+            }
+        }
+        dispatcher.drain();
+
+        let delivered: Vec<(Handle, i32)> = rx.try_iter().collect();
+        for key in 1..=3 {
+            assert_eq!(
+                delivered
+                    .iter()
+                    .filter(|(k, _)| *k == key)
+                    .map(|(_, i)| *i)
+                    .collect::<Vec<_>>(),
+                (0..100).collect::<Vec<_>>()
+            );
+        }
+
+        // A blocked key doesn't hold back the others.
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        unwrap!(dispatcher.dispatch_ordered(1, move || {
+            unwrap!(started_tx.send(()));
+            let _ = release_rx.recv();
+        }));
+        unwrap!(started_rx.recv_timeout(Duration::from_secs(5)));
+
+        let (tx, rx) = mpsc::channel();
+        let tx2 = tx.clone();
+        unwrap!(dispatcher.dispatch_ordered(1, move || unwrap!(tx.send(1))));
+        unwrap!(dispatcher.dispatch_ordered(2, move || unwrap!(tx2.send(2))));
+        assert_eq!(unwrap!(rx.recv_timeout(Duration::from_secs(5))), 2);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        unwrap!(release_tx.send(()));
+        assert_eq!(unwrap!(rx.recv_timeout(Duration::from_secs(5))), 1);
+    }
+
+    #[test]
+    fn shutdown_runs_queued_jobs() {
+        let dispatcher = Dispatcher::new(config(8, Backpressure::Block));
+        let (tx, rx) = mpsc::channel();
+        let release = block_worker(&dispatcher);
+        unwrap!(dispatcher.dispatch(move || unwrap!(tx.send(()))));
+        unwrap!(release.send(()));
+
+        dispatcher.shutdown();
+        assert_eq!(rx.try_iter().count(), 1);
+        assert_eq!(dispatcher.dispatch(|| ()), Err(DispatchError::ShutDown));
+    }
+
+    #[test]
+    fn dispatch_callbacks() {
+        let value: u32 = unsafe {
+            unwrap!(call_1(|user_data, cb| {
+                unwrap!(global().dispatch_cb(user_data, cb, Ok::<_, TestError>(42u32)));
+            }))
+        };
+        assert_eq!(value, 42);
+
+        let res: Result<u32, i32> = unsafe {
+            call_1(|user_data, cb| {
+                unwrap!(global().dispatch_cb(
+                    user_data,
+                    cb,
+                    Err::<u32, _>(TestError::from("failed"))
+                ));
+            })
+        };
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn jobs_run_inline_without_workers() {
+        let dispatcher = Dispatcher::start(config(1, Backpressure::Error), |_, _| {
+            Err(io::Error::other("simulated spawn failure"))
+        });
+        let (tx, rx) = mpsc::channel();
+
+        for i in 0..3 {
+            let tx = tx.clone();
+            unwrap!(dispatcher.dispatch(move || unwrap!(tx.send(i))));
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(dispatcher.pending(), 0);
+
+        dispatcher.shutdown();
+        assert_eq!(dispatcher.dispatch(|| ()), Err(DispatchError::ShutDown));
+    }
+}
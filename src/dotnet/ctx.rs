@@ -0,0 +1,177 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use crate::callback::Callback;
+use crate::catch_unwind::call_error_cb;
+use crate::{ffi_error, ErrorCode, FfiResult, IntoReprC, FFI_RESULT_OK};
+use log::{debug, warn};
+use std::fmt::{Debug, Display};
+use std::os::raw::c_void;
+use std::sync::{PoisonError, RwLock};
+
+/// Function releasing a `GCHandle` given as an `IntPtr` (`GCHandle.FromIntPtr(ptr).Free()`).
+pub type GcHandleRelease = extern "C" fn(handle: *mut c_void);
+
+static RELEASE: RwLock<Option<GcHandleRelease>> = RwLock::new(None);
+
+/// Register the function releasing `GCHandle`s.
+pub fn set_gc_handle_release(release: GcHandleRelease) {
+    *RELEASE.write().unwrap_or_else(PoisonError::into_inner) = Some(release);
+}
+
+fn release(handle: *mut c_void) {
+    if handle.is_null() {
+        return;
+    }
+    match *RELEASE.read().unwrap_or_else(PoisonError::into_inner) {
+        Some(release) => release(handle),
+        None => warn!(
+            "Leaking GCHandle {:?}: no release function registered",
+            handle
+        ),
+    }
+}
+
+/// Owned `GCHandle`, released when dropped.
+#[derive(Debug)]
+pub struct GcHandle(*mut c_void);
+
+// `GCHandle`s may be used and released from any thread.
+unsafe impl Send for GcHandle {}
+unsafe impl Sync for GcHandle {}
+
+impl GcHandle {
+    /// Take ownership of a `GCHandle` passed as `user_data`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a `GCHandle` which is not owned by anything else.
+    pub unsafe fn from_raw(ptr: *mut c_void) -> Self {
+        GcHandle(ptr)
+    }
+
+    /// Return the handle, to pass it as `user_data` to managed callbacks.
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.0
+    }
+
+    /// Give up ownership of the handle without releasing it.
+    pub fn into_raw(self) -> *mut c_void {
+        let ptr = self.0;
+        std::mem::forget(self);
+        ptr
+    }
+}
+
+impl Drop for GcHandle {
+    fn drop(&mut self) {
+        release(self.0)
+    }
+}
+
+/// Final callback of an operation, owning the `GCHandle` passed as its `user_data`.
+///
+/// Invoking the callback consumes it and releases the handle once the callback returns. A
+/// callback dropped without being invoked (e.g. after an early return or a panic) releases the
+/// handle straight away, so the handle is released exactly once on every path.
+#[derive(Debug)]
+pub struct ReleasingCallback<C> {
+    cb: C,
+    handle: GcHandle,
+}
+
+impl<C: Callback> ReleasingCallback<C> {
+    /// Take ownership of the `GCHandle` passed as `user_data` to `cb`.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` must be a `GCHandle` which is not owned by anything else.
+    pub unsafe fn new(user_data: *mut c_void, cb: C) -> Self {
+        Self {
+            cb,
+            handle: GcHandle::from_raw(user_data),
+        }
+    }
+
+    /// Return the `GCHandle`, which stays valid until the callback is invoked or dropped.
+    pub fn user_data(&self) -> *mut c_void {
+        self.handle.as_ptr()
+    }
+
+    /// Invoke the callback, then release the handle.
+    pub fn call(self, result: *const FfiResult, args: C::Args) {
+        self.cb.call(self.handle.as_ptr(), result, args)
+    }
+
+    /// Invoke the callback with `result`: the value converted to its FFI representation on
+    /// success, or the error converted through `NativeResult` on failure. The handle is
+    /// released afterwards.
+    pub fn call_result<T, E>(self, result: Result<T, E>)
+    where
+        C: Callback<Args = T::C>,
+        T: IntoReprC,
+        T::Error: Debug,
+        E: Debug + Display + ErrorCode + From<&'static str>,
+    {
+        let error = match result.map(IntoReprC::into_repr_c) {
+            Ok(Ok((repr_c, _storage))) => return self.call(FFI_RESULT_OK, repr_c),
+            Ok(Err(e)) => {
+                debug!(
+                    "Could not convert result into its FFI representation: {:?}",
+                    e
+                );
+                E::from("Could not convert result into its FFI representation")
+            }
+            Err(error) => error,
+        };
+
+        let (error_code, description) = ffi_error!(error);
+        call_error_cb(self.handle.as_ptr(), self.cb, error_code, description);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+    static CALLED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn release_handle(handle: *mut c_void) {
+        let _ = RELEASED.fetch_add(handle as usize, Ordering::SeqCst);
+    }
+
+    extern "C" fn cb(user_data: *mut c_void, _result: *const FfiResult) {
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+        let _ = CALLED.fetch_add(user_data as usize, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn handle_released_exactly_once() {
+        set_gc_handle_release(release_handle);
+        let cb: extern "C" fn(_, _) = cb;
+
+        let callback = unsafe { ReleasingCallback::new(7 as *mut c_void, cb) };
+        callback.call_result(Err::<(), _>(TestError::Test));
+        assert_eq!(CALLED.load(Ordering::SeqCst), 7);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 7);
+
+        // Dropped without being invoked.
+        drop(unsafe { ReleasingCallback::new(4 as *mut c_void, cb) });
+        assert_eq!(CALLED.load(Ordering::SeqCst), 7);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 11);
+
+        let user_data = 7 as *mut c_void;
+        let handle = unsafe { GcHandle::from_raw(user_data) };
+        assert_eq!(handle.into_raw(), user_data);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 11);
+    }
+}
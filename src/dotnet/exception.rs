@@ -0,0 +1,121 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Mapping of error codes to .NET exception types.
+//!
+//! The mapping is defined on the native side, and `ExceptionMapping::to_csharp` turns it into
+//! a C# method for the binding, so both sides agree on which exception each error raises.
+
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+/// Exception type raised for error codes that are not covered by any mapping.
+pub const DEFAULT_EXCEPTION_TYPE: &str = "System.Exception";
+
+/// Mapping from ranges of error codes to the .NET exception types raised for them.
+#[derive(Clone, Debug)]
+pub struct ExceptionMapping {
+    ranges: Vec<(RangeInclusive<i32>, String)>,
+    default: String,
+}
+
+impl Default for ExceptionMapping {
+    fn default() -> Self {
+        Self::new(DEFAULT_EXCEPTION_TYPE)
+    }
+}
+
+impl ExceptionMapping {
+    /// Create a mapping which raises `default_type` for every error code.
+    pub fn new(default_type: &str) -> Self {
+        Self {
+            ranges: Vec::new(),
+            default: default_type.to_owned(),
+        }
+    }
+
+    /// Raise `exception_type` (e.g. `System.IO.IOException`) for error codes in `codes`. Ranges
+    /// are matched in the order they were added.
+    pub fn map(mut self, codes: RangeInclusive<i32>, exception_type: &str) -> Self {
+        self.ranges.push((codes, exception_type.to_owned()));
+        self
+    }
+
+    /// Return the exception type for the given error code.
+    pub fn type_for(&self, error_code: i32) -> &str {
+        self.ranges
+            .iter()
+            .find(|(codes, _)| codes.contains(&error_code))
+            .map(|(_, exception_type)| exception_type.as_str())
+            .unwrap_or(&self.default)
+    }
+
+    /// Generate a C# method `static Exception <method>(int code, string message)` creating the
+    /// exception for an error. Every exception type must have a `(string)` constructor.
+    pub fn to_csharp(&self, method: &str) -> String {
+        let mut out = format!(
+            "internal static System.Exception {}(int code, string message)\n{{\n",
+            method
+        );
+        for (codes, exception_type) in &self.ranges {
+            let _ = writeln!(
+                out,
+                "    if (code >= {} && code <= {}) return new {}(message);",
+                codes.start(),
+                codes.end(),
+                exception_type
+            );
+        }
+        let _ = writeln!(out, "    return new {}(message);\n}}", self.default);
+        out
+    }
+}
+
+fn mapping() -> &'static RwLock<ExceptionMapping> {
+    static MAPPING: OnceLock<RwLock<ExceptionMapping>> = OnceLock::new();
+    MAPPING.get_or_init(Default::default)
+}
+
+/// Replace the process-wide error code to exception type mapping.
+pub fn set_exception_mapping(new_mapping: ExceptionMapping) {
+    *mapping().write().unwrap_or_else(PoisonError::into_inner) = new_mapping;
+}
+
+/// Return the exception type the process-wide mapping raises for `error_code`.
+pub fn exception_type_for(error_code: i32) -> String {
+    mapping()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .type_for(error_code)
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csharp_mapping() {
+        let mapping = ExceptionMapping::default()
+            .map(-9002..=-9001, "System.ArgumentException")
+            .map(-99..=-1, "System.IO.IOException");
+
+        assert_eq!(mapping.type_for(-9001), "System.ArgumentException");
+        assert_eq!(mapping.type_for(-5), "System.IO.IOException");
+        assert_eq!(mapping.type_for(-100), DEFAULT_EXCEPTION_TYPE);
+
+        let csharp = mapping.to_csharp("ToException");
+        assert!(csharp.starts_with("internal static System.Exception ToException("));
+        assert!(csharp.contains(
+            "    if (code >= -99 && code <= -1) return new System.IO.IOException(message);\n"
+        ));
+        assert!(csharp.ends_with("    return new System.Exception(message);\n}\n"));
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! .NET interop utilities.
+//!
+//! C# bindings pass a `GCHandle` (`GCHandle.ToIntPtr`) as `user_data`, so that the managed
+//! state of a call (e.g. a `TaskCompletionSource`) stays alive until its callback is invoked.
+//! The binding registers, once, the function releasing such handles with
+//! `dotnet_set_gc_handle_release` (exported with `export_dotnet!`), and FFI functions wrap their
+//! final callback in `ReleasingCallback`, which releases the handle once it has been invoked, or
+//! when it is dropped without being invoked:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_fetch(
+//!     app: Handle,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, data: *const c_char),
+//! ) {
+//!     let o_cb = ReleasingCallback::new(user_data, o_cb);
+//!     async_ffi::spawn(async move { o_cb.call_result(fetch(app).await) })
+//! }
+//! ```
+
+mod ctx;
+mod exception;
+mod string;
+
+pub use self::ctx::{set_gc_handle_release, GcHandle, GcHandleRelease, ReleasingCallback};
+pub use self::exception::{
+    exception_type_for, set_exception_mapping, ExceptionMapping, DEFAULT_EXCEPTION_TYPE,
+};
+pub use self::string::{
+    string_into_utf16_raw, utf16_free, utf16_from_raw, utf16_from_raw_parts, utf16_len, Utf16Error,
+};
+
+/// Export the .NET interop functions of the library.
+///
+/// Defines `dotnet_set_gc_handle_release(release)`, which the C# binding calls once with a
+/// function freeing a `GCHandle` given as an `IntPtr`, and `dotnet_utf16_free(ptr)`, freeing
+/// strings returned by `string_into_utf16_raw`.
+#[macro_export]
+macro_rules! export_dotnet {
+    () => {
+        /// Register the function releasing the `GCHandle`s passed as `user_data`.
+        #[no_mangle]
+        pub extern "C" fn dotnet_set_gc_handle_release(release: $crate::dotnet::GcHandleRelease) {
+            $crate::dotnet::set_gc_handle_release(release)
+        }
+
+        /// Free a UTF-16 string returned by the library.
+        #[no_mangle]
+        pub unsafe extern "C" fn dotnet_utf16_free(ptr: *mut u16) {
+            $crate::dotnet::utf16_free(ptr)
+        }
+    };
+}
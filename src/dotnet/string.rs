@@ -0,0 +1,121 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! UTF-16 strings, as used by .NET (`UnmanagedType.LPWStr`).
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::slice;
+
+/// Error converting a UTF-16 string.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Utf16Error {
+    /// The string pointer is null.
+    Null,
+    /// The string contains an interior nul character, and can't be nul-terminated.
+    InteriorNul,
+    /// The string isn't valid UTF-16.
+    Invalid(String),
+}
+
+impl Display for Utf16Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Utf16Error::Null => write!(f, "String could not be constructed from C null pointer"),
+            Utf16Error::InteriorNul => write!(f, "String contains an interior nul character"),
+            Utf16Error::Invalid(e) => write!(f, "Invalid UTF-16: {}", e),
+        }
+    }
+}
+
+impl Error for Utf16Error {}
+
+/// Return the number of code units before the terminating nul of a UTF-16 string.
+///
+/// # Safety
+///
+/// `ptr` must point to a nul-terminated UTF-16 string.
+pub unsafe fn utf16_len(ptr: *const u16) -> usize {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+/// Convert a nul-terminated UTF-16 string into a `String`.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a nul-terminated UTF-16 string.
+pub unsafe fn utf16_from_raw(ptr: *const u16) -> Result<String, Utf16Error> {
+    if ptr.is_null() {
+        return Err(Utf16Error::Null);
+    }
+    utf16_from_raw_parts(ptr, utf16_len(ptr))
+}
+
+/// Convert `len` UTF-16 code units into a `String`.
+///
+/// # Safety
+///
+/// `ptr` must be valid for reads of `len` code units.
+pub unsafe fn utf16_from_raw_parts(ptr: *const u16, len: usize) -> Result<String, Utf16Error> {
+    if len == 0 {
+        return Ok(String::new());
+    }
+    String::from_utf16(slice::from_raw_parts(ptr, len))
+        .map_err(|e| Utf16Error::Invalid(e.to_string()))
+}
+
+/// Transfer `s` to the caller as a nul-terminated UTF-16 string, which must be freed with
+/// `utf16_free`.
+pub fn string_into_utf16_raw(s: &str) -> Result<*mut u16, Utf16Error> {
+    if s.contains('\0') {
+        return Err(Utf16Error::InteriorNul);
+    }
+    let units: Box<[u16]> = s.encode_utf16().chain(Some(0)).collect();
+    Ok(Box::into_raw(units) as *mut u16)
+}
+
+/// Free a string returned by `string_into_utf16_raw`.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been returned by `string_into_utf16_raw`, and must not be used
+/// afterwards.
+pub unsafe fn utf16_free(ptr: *mut u16) {
+    if !ptr.is_null() {
+        let len = utf16_len(ptr) + 1;
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unwrap::unwrap;
+
+    #[test]
+    fn utf16_roundtrip() {
+        let ptr = unwrap!(string_into_utf16_raw("grüße, 世界 🦀"));
+        unsafe {
+            assert_eq!(utf16_len(ptr), 12);
+            assert_eq!(unwrap!(utf16_from_raw(ptr)), "grüße, 世界 🦀");
+            utf16_free(ptr);
+
+            assert_eq!(utf16_from_raw(std::ptr::null()), Err(Utf16Error::Null));
+            assert!(matches!(
+                utf16_from_raw_parts([0xd800u16].as_ptr(), 1),
+                Err(Utf16Error::Invalid(_))
+            ));
+        }
+        assert_eq!(string_into_utf16_raw("a\0b"), Err(Utf16Error::InteriorNul));
+    }
+}
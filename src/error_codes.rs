@@ -0,0 +1,133 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Error code tables shared between the `ErrorCode` impl of an error type and the generated
+//! bindings.
+//!
+//! `error_codes!` maps the variants of an error type to named codes, implementing `ErrorCode`
+//! and `ErrorCodes` from the same table, so that the constants emitted for the frontends by
+//! `bindgen_utils::error_codes` can't drift from the codes actually returned:
+//!
+//! ```ignore
+//! error_codes! {
+//!     impl ErrorCode for AppError {
+//!         AppError::NotRegistered => NOT_REGISTERED = -100, "The app is not registered";
+//!         AppError::Network(..) => NETWORK = -200, "Network failure" in NetworkError;
+//!         AppError::Timeout => TIMEOUT = -201, "Request timed out" in NetworkError;
+//!     }
+//! }
+//!
+//! assert_eq!(AppError::NOT_REGISTERED, -100);
+//! ```
+//!
+//! Codes can be grouped into domains (`in NetworkError`), which become exception classes in the
+//! bindings generated by `bindgen_utils::error_codes`.
+
+/// Named error code, with a description for the generated documentation.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ErrorCodeDesc {
+    /// Name of the code, in `SCREAMING_SNAKE_CASE`.
+    pub name: &'static str,
+    /// The code.
+    pub code: i32,
+    /// Description of the error.
+    pub description: &'static str,
+    /// Domain of the error, or an empty string if it has none.
+    pub domain: &'static str,
+}
+
+/// Error types whose codes are described by a table, usually implemented with `error_codes!`.
+pub trait ErrorCodes {
+    /// Every code the type can return.
+    const ERROR_CODES: &'static [ErrorCodeDesc];
+}
+
+/// Implement `ErrorCode` and `ErrorCodes` for an error type from a table mapping patterns
+/// matching its values to named codes. Each code is also defined as an associated constant.
+#[macro_export]
+macro_rules! error_codes {
+    (
+        impl ErrorCode for $ty:ty {
+            $($pat:pat => $name:ident = $code:expr, $desc:literal $(in $domain:ident)?;)+
+        }
+    ) => {
+        impl $crate::ErrorCode for $ty {
+            fn error_code(&self) -> i32 {
+                match self {
+                    $($pat => $code,)+
+                }
+            }
+        }
+
+        impl $crate::error_codes::ErrorCodes for $ty {
+            const ERROR_CODES: &'static [$crate::error_codes::ErrorCodeDesc] = &[
+                $($crate::error_codes::ErrorCodeDesc {
+                    name: stringify!($name),
+                    code: $code,
+                    description: $desc,
+                    domain: $crate::error_codes!(@domain $($domain)?),
+                },)+
+            ];
+        }
+
+        #[allow(dead_code)]
+        impl $ty {
+            $(
+                #[doc = $desc]
+                pub const $name: i32 = $code;
+            )+
+        }
+    };
+
+    (@domain $domain:ident) => {
+        stringify!($domain)
+    };
+
+    (@domain) => {
+        ""
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorCode;
+
+    #[derive(Debug)]
+    enum AppError {
+        NotRegistered,
+        Network(#[allow(dead_code)] String),
+    }
+
+    error_codes! {
+        impl ErrorCode for AppError {
+            AppError::NotRegistered => NOT_REGISTERED = -100, "The app is not registered";
+            AppError::Network(..) => NETWORK = -200, "Network failure" in NetworkError;
+        }
+    }
+
+    #[test]
+    fn table_matches_impl() {
+        assert_eq!(
+            AppError::NotRegistered.error_code(),
+            AppError::NOT_REGISTERED
+        );
+        assert_eq!(AppError::Network(String::new()).error_code(), -200);
+        assert_eq!(AppError::ERROR_CODES[0].domain, "");
+        assert_eq!(
+            AppError::ERROR_CODES[1],
+            ErrorCodeDesc {
+                name: "NETWORK",
+                code: -200,
+                description: "Network failure",
+                domain: "NetworkError",
+            }
+        );
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Notification of native events to frontends.
+//!
+//! Frontends subscribe a callback to a topic, and native code emits payloads on that topic.
+//! Payloads are passed to the callbacks in their `ReprC` representation, which is only valid for
+//! the duration of the call:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn app_on_connection_state(
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, state: u32),
+//! ) -> SubscriptionHandle {
+//!     events::subscribe("connection_state", user_data, o_cb)
+//! }
+//!
+//! // Later, from native code:
+//! events::emit("connection_state", ConnectionState::Disconnected as u32);
+//! ```
+
+use crate::handles::HandleError;
+use crate::IntoReprC;
+use log::warn;
+use std::any::{self, Any};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+
+/// Handle to a subscription, used to unsubscribe. 0 is never a valid handle.
+pub type SubscriptionHandle = u64;
+
+// Returns `false` if the callback was not invoked, because the payload has another type or could
+// not be converted.
+type Deliver = dyn Fn(&dyn Any) -> bool + Send + Sync;
+
+struct Subscription {
+    handle: SubscriptionHandle,
+    deliver: Arc<Deliver>,
+}
+
+// Frontends are responsible for making their `user_data` usable from the emitting threads.
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+static TOPICS: Mutex<Option<HashMap<String, Vec<Subscription>>>> = Mutex::new(None);
+
+fn topics() -> MutexGuard<'static, Option<HashMap<String, Vec<Subscription>>>> {
+    TOPICS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Subscribe `cb` to the payloads of type `T` emitted on `topic`.
+///
+/// Payloads of other types emitted on the same topic are not delivered to `cb`.
+pub fn subscribe<T, U>(
+    topic: &str,
+    user_data: U,
+    cb: extern "C" fn(user_data: *mut c_void, payload: T::C),
+) -> SubscriptionHandle
+where
+    T: IntoReprC + Clone + 'static,
+    T::Error: std::fmt::Debug,
+    U: Into<*mut c_void>,
+{
+    let user_data = UserData(user_data.into());
+    let deliver = move |payload: &dyn Any| {
+        let payload = match payload.downcast_ref::<T>() {
+            Some(payload) => payload.clone(),
+            None => return false,
+        };
+        match payload.into_repr_c() {
+            Ok((repr_c, _storage)) => {
+                cb(user_data.0, repr_c);
+                true
+            }
+            Err(e) => {
+                warn!(
+                    "Could not convert {} payload into its FFI representation: {:?}",
+                    any::type_name::<T>(),
+                    e
+                );
+                false
+            }
+        }
+    };
+
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    topics()
+        .get_or_insert_with(HashMap::new)
+        .entry(topic.to_owned())
+        .or_default()
+        .push(Subscription {
+            handle,
+            deliver: Arc::new(deliver),
+        });
+    handle
+}
+
+/// Cancel a subscription. Its callback is not invoked anymore once this returns, except by
+/// emissions already in progress on other threads.
+pub fn unsubscribe(handle: SubscriptionHandle) -> Result<(), HandleError> {
+    let mut topics = topics();
+    let topics = topics.get_or_insert_with(HashMap::new);
+
+    let topic = topics
+        .iter_mut()
+        .find(|(_, subscriptions)| subscriptions.iter().any(|sub| sub.handle == handle))
+        .map(|(topic, subscriptions)| {
+            subscriptions.retain(|sub| sub.handle != handle);
+            topic.clone()
+        })
+        .ok_or(HandleError::Invalid(handle))?;
+
+    if topics.get(&topic).is_some_and(Vec::is_empty) {
+        let _ = topics.remove(&topic);
+    }
+    Ok(())
+}
+
+/// Number of subscriptions to `topic`.
+pub fn subscriber_count(topic: &str) -> usize {
+    topics()
+        .as_ref()
+        .and_then(|topics| topics.get(topic))
+        .map_or(0, Vec::len)
+}
+
+/// Deliver `payload` to the subscribers of `topic` on the current thread, returning the number
+/// of callbacks invoked. Subscriptions to payloads of another type, and those whose payload
+/// could not be converted, are not counted.
+///
+/// Callbacks are invoked without holding any lock, so they may subscribe and unsubscribe.
+pub fn emit<T: Any>(topic: &str, payload: T) -> usize {
+    let subscribers: Vec<_> = topics()
+        .as_ref()
+        .and_then(|topics| topics.get(topic))
+        .map(|subscriptions| {
+            subscriptions
+                .iter()
+                .map(|sub| Arc::clone(&sub.deliver))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    subscribers
+        .iter()
+        .filter(|deliver| deliver(&payload))
+        .count()
+}
+
+/// Same as `emit`, but the payload is delivered from a dedicated dispatcher thread, so emitters
+/// never run frontend code. Payloads are delivered in the order they were queued.
+pub fn emit_queued<T: Any + Send>(topic: &str, payload: T) {
+    let job: Job = Box::new({
+        let topic = topic.to_owned();
+        move || {
+            let _ = emit(&topic, payload);
+        }
+    });
+
+    if let Err(mpsc::SendError(job)) = dispatcher().send(job) {
+        warn!("Event dispatcher is gone, delivering on the current thread");
+        job();
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+fn dispatcher() -> Sender<Job> {
+    static DISPATCHER: OnceLock<Mutex<Sender<Job>>> = OnceLock::new();
+
+    DISPATCHER
+        .get_or_init(|| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let _ = thread::Builder::new()
+                .name("ffi-events".to_owned())
+                .spawn(move || {
+                    for job in rx {
+                        job();
+                    }
+                });
+            Mutex::new(tx)
+        })
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+    use unwrap::unwrap;
+
+    extern "C" fn on_u32(user_data: *mut c_void, value: u32) {
+        let tx = unsafe { &*(user_data as *const Sender<u32>) };
+        unwrap!(tx.clone().send(value));
+    }
+
+    fn channel() -> (Box<Sender<u32>>, Receiver<u32>) {
+        let (tx, rx) = mpsc::channel();
+        (Box::new(tx), rx)
+    }
+
+    #[test]
+    fn emit_to_subscribers() {
+        let (tx, rx) = channel();
+        let ud: *const Sender<u32> = &*tx;
+        let ud = ud as *mut c_void;
+
+        let handle = subscribe::<u32, _>("events::emit", ud, on_u32);
+        assert_eq!(subscriber_count("events::emit"), 1);
+
+        assert_eq!(emit("events::emit", 7u32), 1);
+        assert_eq!(unwrap!(rx.try_recv()), 7);
+
+        // Payloads of another type are not delivered, nor counted.
+        assert_eq!(emit("events::emit", 7u64), 0);
+        assert!(rx.try_recv().is_err());
+
+        unwrap!(unsubscribe(handle));
+        assert_eq!(emit("events::emit", 8u32), 0);
+        assert!(unsubscribe(handle).is_err());
+        assert_eq!(subscriber_count("events::emit"), 0);
+    }
+
+    #[test]
+    fn emit_queued_preserves_order() {
+        let (tx, rx) = channel();
+        let ud: *const Sender<u32> = &*tx;
+        let ud = ud as *mut c_void;
+        let handle = subscribe::<u32, _>("events::queued", ud, on_u32);
+
+        for i in 0..10u32 {
+            emit_queued("events::queued", i);
+        }
+        let received: Vec<_> = (0..10)
+            .map(|_| unwrap!(rx.recv_timeout(Duration::from_secs(60))))
+            .collect();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+
+        unwrap!(unsubscribe(handle));
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Passing `bitflags!` types across the FFI as plain integers.
+//!
+//! `gen_flags_converter!` implements `ReprC`, `IntoReprC` and `CallbackArgs` (plus `FromJava` and
+//! `ToJava` with the `java` feature) for a flags type, choosing how bits which don't correspond
+//! to any flag are handled when converting from the FFI representation:
+//!
+//! ```ignore
+//! bitflags! {
+//!     pub struct OpenOptions: u32 {
+//!         const CREATE = 0b01;
+//!         const APPEND = 0b10;
+//!     }
+//! }
+//!
+//! // Unknown bits are rejected with `UnknownFlags`.
+//! gen_flags_converter!(OpenOptions, u32, reject);
+//! // Unknown bits are silently dropped.
+//! gen_flags_converter!(LogOptions, u64, truncate);
+//! ```
+
+use crate::ErrorCode;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+/// Error code returned when flags contain unknown bits.
+pub const ERR_UNKNOWN_FLAGS: i32 = -9013;
+
+/// Error converting flags containing bits which don't correspond to any flag.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UnknownFlags {
+    /// Name of the flags type.
+    pub type_name: &'static str,
+    /// The unknown bits.
+    pub bits: u64,
+}
+
+impl ErrorCode for UnknownFlags {
+    fn error_code(&self) -> i32 {
+        ERR_UNKNOWN_FLAGS
+    }
+}
+
+impl Display for UnknownFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown bits {:#x} in {}", self.bits, self.type_name)
+    }
+}
+
+impl Error for UnknownFlags {}
+
+/// Generate the FFI conversions of a `bitflags!`-style type, represented as `$repr` (e.g. `u32`
+/// or `u64`). Unknown bits are either rejected with `UnknownFlags` (`reject`, the default) or
+/// masked off (`truncate`).
+#[macro_export]
+macro_rules! gen_flags_converter {
+    ($ty:ty, $repr:ident) => {
+        $crate::gen_flags_converter!($ty, $repr, reject);
+    };
+
+    ($ty:ty, $repr:ident, $unknown:ident) => {
+        impl $crate::ReprC for $ty {
+            type C = $repr;
+            type Error = $crate::flags::UnknownFlags;
+
+            unsafe fn clone_from_repr_c(bits: $repr) -> Result<Self, Self::Error> {
+                $crate::gen_flags_converter!(@from_bits $ty, bits, $unknown)
+            }
+        }
+
+        impl $crate::IntoReprC for $ty {
+            type Storage = ();
+
+            fn into_repr_c(self) -> Result<($repr, ()), Self::Error> {
+                Ok((self.bits(), ()))
+            }
+        }
+
+        impl $crate::callback::CallbackArgs for $ty {
+            fn default() -> Self {
+                <$ty>::empty()
+            }
+        }
+
+        $crate::__gen_flags_java_converter!($ty, $repr);
+    };
+
+    (@from_bits $ty:ty, $bits:ident, reject) => {
+        <$ty>::from_bits($bits).ok_or_else(|| $crate::flags::UnknownFlags {
+            type_name: stringify!($ty),
+            bits: u64::from($bits & !<$ty>::all().bits()),
+        })
+    };
+
+    (@from_bits $ty:ty, $bits:ident, truncate) => {
+        Ok(<$ty>::from_bits_truncate($bits))
+    };
+}
+
+#[cfg(feature = "java")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __gen_flags_java_converter {
+    ($ty:ty, u32) => {
+        $crate::__gen_flags_java_converter!(@impl $ty, u32, jni::sys::jint);
+    };
+
+    ($ty:ty, u64) => {
+        $crate::__gen_flags_java_converter!(@impl $ty, u64, jni::sys::jlong);
+    };
+
+    (@impl $ty:ty, $repr:ident, $java_type:ty) => {
+        impl $crate::java::FromJava<$java_type> for $ty {
+            fn from_java(_env: &jni::JNIEnv, input: $java_type) -> $crate::java::JniResult<Self> {
+                unsafe { <Self as $crate::ReprC>::clone_from_repr_c(input as $repr) }
+                    .map_err(|e| jni::errors::Error::from(e.to_string()))
+            }
+        }
+
+        impl<'a> $crate::java::ToJava<'a, $java_type> for $ty {
+            fn to_java(&self, _env: &jni::JNIEnv) -> $crate::java::JniResult<$java_type> {
+                Ok(self.bits() as $java_type)
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "java"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __gen_flags_java_converter {
+    ($ty:ty, $repr:ident) => {};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntoReprC, ReprC};
+    use unwrap::unwrap;
+
+    bitflags::bitflags! {
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        struct Strict: u32 {
+            const A = 0b001;
+            const B = 0b100;
+        }
+    }
+
+    bitflags::bitflags! {
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        struct Lenient: u64 {
+            const A = 0b001;
+        }
+    }
+
+    gen_flags_converter!(Strict, u32);
+    gen_flags_converter!(Lenient, u64, truncate);
+
+    #[test]
+    fn unknown_bits() {
+        let (bits, ()) = unwrap!((Strict::A | Strict::B).into_repr_c());
+        assert_eq!(bits, 0b101);
+        assert_eq!(
+            unsafe { Strict::clone_from_repr_c(bits) },
+            Ok(Strict::A | Strict::B)
+        );
+
+        let error = unsafe { unwrap!(Strict::clone_from_repr_c(0b111).err()) };
+        assert_eq!(error.bits, 0b010);
+        assert_eq!(error.error_code(), ERR_UNKNOWN_FLAGS);
+
+        assert_eq!(unsafe { Lenient::clone_from_repr_c(0b111) }, Ok(Lenient::A));
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Exported `*_free` functions for objects handed to frontends as raw pointers.
+//!
+//! Objects are transferred with `into_raw`, and released by functions defined with
+//! `export_free_fn!`, which are also recorded in a registry that bindings can check against:
+//!
+//! ```ignore
+//! export_free_fn! {
+//!     /// Free an `App` returned by `app_new`.
+//!     fn app_free(App);
+//! }
+//! ```
+//!
+//! In debug builds, every pointer returned by `into_raw` is tagged with the type of its object.
+//! Passing a pointer to the free function of another type, or freeing it twice, is then
+//! logged and the pointer left alone, instead of corrupting the heap.
+
+use log::error;
+#[cfg(debug_assertions)]
+use std::any::{self, TypeId};
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::sync::{Mutex, PoisonError};
+
+/// `*_free` function defined by `export_free_fn!`.
+#[derive(Debug)]
+pub struct FreeFnDesc {
+    /// Name of the function.
+    pub name: &'static str,
+    /// Type of the objects it frees.
+    pub ty: &'static str,
+}
+
+static REGISTRY: Mutex<Vec<&'static FreeFnDesc>> = Mutex::new(Vec::new());
+
+/// Add `desc` to the registry. Called by the constructors generated by `export_free_fn!`.
+#[doc(hidden)]
+pub fn register(desc: &'static FreeFnDesc) {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(desc)
+}
+
+/// Free functions defined by `export_free_fn!`, sorted by name.
+pub fn registered() -> Vec<&'static FreeFnDesc> {
+    let mut descs = REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    descs.sort_by_key(|desc| desc.name);
+    descs
+}
+
+/// Error releasing a pointer. Only detected in debug builds.
+#[derive(Debug, Eq, PartialEq)]
+pub enum FreeError {
+    /// The pointer wasn't returned by `into_raw`, or was freed already.
+    NotAllocated {
+        /// Free function which was called.
+        free_fn: &'static str,
+        /// Address of the pointer.
+        ptr: usize,
+    },
+    /// The pointer refers to an object of another type than the free function releases.
+    TypeMismatch {
+        /// Free function which was called.
+        free_fn: &'static str,
+        /// Type of the objects released by the free function.
+        expected: &'static str,
+        /// Type of the object behind the pointer.
+        actual: &'static str,
+    },
+}
+
+impl Display for FreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FreeError::NotAllocated { free_fn, ptr } => write!(
+                f,
+                "{} called on {:#x}, which is not allocated (double free?)",
+                free_fn, ptr
+            ),
+            FreeError::TypeMismatch {
+                free_fn,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} frees a {}, but was called on a {}",
+                free_fn, expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for FreeError {}
+
+// Type of every live object transferred by `into_raw`, by address.
+#[cfg(debug_assertions)]
+static LIVE: Mutex<Option<HashMap<usize, (TypeId, &'static str)>>> = Mutex::new(None);
+
+#[cfg(debug_assertions)]
+fn live<R>(f: impl FnOnce(&mut HashMap<usize, (TypeId, &'static str)>) -> R) -> R {
+    f(LIVE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(HashMap::new))
+}
+
+/// Box `value` and transfer it to the caller, to be released by the free function defined for
+/// `T` with `export_free_fn!`.
+pub fn into_raw<T: 'static>(value: T) -> *mut T {
+    let ptr = Box::into_raw(Box::new(value));
+
+    // Zero-sized objects all share the same address, so they can't be told apart.
+    #[cfg(debug_assertions)]
+    if size_of::<T>() > 0 {
+        let _ = live(|live| live.insert(ptr as usize, (TypeId::of::<T>(), any::type_name::<T>())));
+    }
+
+    ptr
+}
+
+/// Take back ownership of an object transferred with `into_raw`, on behalf of `free_fn`.
+///
+/// In debug builds, fails if `ptr` is not a live object of type `T`, in which case it is left
+/// alone. Release builds don't check anything.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `into_raw::<T>` and not been released yet, which is only
+/// verified in debug builds.
+pub unsafe fn from_raw<T: 'static>(
+    ptr: *mut T,
+    free_fn: &'static str,
+) -> Result<Box<T>, FreeError> {
+    #[cfg(debug_assertions)]
+    if size_of::<T>() > 0 {
+        live(|live| match live.get(&(ptr as usize)) {
+            Some((type_id, _)) if *type_id == TypeId::of::<T>() => {
+                let _ = live.remove(&(ptr as usize));
+                Ok(())
+            }
+            Some((_, actual)) => Err(FreeError::TypeMismatch {
+                free_fn,
+                expected: any::type_name::<T>(),
+                actual,
+            }),
+            None => Err(FreeError::NotAllocated {
+                free_fn,
+                ptr: ptr as usize,
+            }),
+        })?;
+    }
+    #[cfg(not(debug_assertions))]
+    let _ = free_fn;
+
+    Ok(Box::from_raw(ptr))
+}
+
+/// Release an object transferred with `into_raw`. Used by the functions defined with
+/// `export_free_fn!`.
+///
+/// Null pointers are ignored. Failures detected by `from_raw` are logged.
+///
+/// # Safety
+///
+/// See `from_raw`.
+pub unsafe fn release<T: 'static>(ptr: *mut T, free_fn: &'static str) {
+    if ptr.is_null() {
+        return;
+    }
+
+    if let Err(error) = from_raw(ptr, free_fn) {
+        error!("{}", error);
+    }
+}
+
+/// Define exported `*_free` functions, each taking a pointer returned by `into_raw` for the
+/// given type, and record them in the registry of free functions.
+#[macro_export]
+macro_rules! export_free_fn {
+    ($(
+        $(#[$attr:meta])*
+        fn $name:ident($ty:ty);
+    )+) => {
+        $(
+            $(#[$attr])*
+            ///
+            /// # Safety
+            ///
+            /// `ptr` must be null or an object returned by this library and not freed yet.
+            #[no_mangle]
+            pub unsafe extern "C" fn $name(ptr: *mut $ty) {
+                $crate::free::release::<$ty>(ptr, stringify!($name))
+            }
+
+            $crate::on_load! {
+                $crate::free::register(&$crate::free::FreeFnDesc {
+                    name: stringify!($name),
+                    ty: stringify!($ty),
+                })
+            }
+        )+
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unwrap::unwrap;
+
+    pub struct App(#[allow(dead_code)] u32);
+    pub struct Session(#[allow(dead_code)] u64);
+
+    export_free_fn! {
+        /// Free an `App`.
+        fn test_app_free(App);
+        /// Free a `Session`.
+        fn test_session_free(Session);
+    }
+
+    #[test]
+    fn free_fns_are_registered() {
+        let descs = registered();
+        let app = unwrap!(descs.iter().find(|desc| desc.name == "test_app_free"));
+        assert_eq!(app.ty, "App");
+        assert!(descs.iter().any(|desc| desc.name == "test_session_free"));
+    }
+
+    #[test]
+    fn release() {
+        let app = into_raw(App(1));
+        unsafe {
+            test_app_free(app);
+            test_app_free(std::ptr::null_mut());
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn mismatched_and_double_frees_are_detected() {
+        let app = into_raw(App(1));
+        let session = app as *mut Session;
+        assert_eq!(
+            unsafe { from_raw(session, "test_session_free") }.err(),
+            Some(FreeError::TypeMismatch {
+                free_fn: "test_session_free",
+                expected: any::type_name::<Session>(),
+                actual: any::type_name::<App>(),
+            })
+        );
+
+        // The mismatched call left the object alone.
+        assert!(unsafe { from_raw(app, "test_app_free") }.is_ok());
+        assert_eq!(
+            unsafe { from_raw(app, "test_app_free") }.err(),
+            Some(FreeError::NotAllocated {
+                free_fn: "test_app_free",
+                ptr: app as usize,
+            })
+        );
+    }
+}
@@ -0,0 +1,459 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Handles to operations whose outcome can be either awaited through a callback or polled.
+//!
+//! An FFI function starting an operation creates a future, completes its `Promise` once the
+//! operation finishes and returns the future's handle right away. The host then either attaches
+//! a callback with `ffi_future_then` or waits for the outcome with `ffi_future_poll` (exported
+//! with `export_future!`), and must eventually release the handle with `ffi_future_free`:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn app_fetch(app: Handle) -> Handle {
+//!     let (future, promise) = future::new_future();
+//!     match handles::get::<App>(app) {
+//!         Ok(app) => async_ffi::spawn(async move { promise.complete(app.fetch().await) }),
+//!         Err(error) => promise.complete(Err(error)),
+//!     }
+//!     future
+//! }
+//! ```
+
+use crate::cancel::Cancelled;
+use crate::handles::{self, Handle, HandleError};
+use crate::result::{FfiResult, NativeResult};
+use crate::{ffi_error, pending, ErrorCode, OpaqueCtx};
+use std::fmt::{self, Debug, Display};
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Returned by `ffi_future_poll` when the operation didn't complete before the timeout.
+pub const FUTURE_PENDING: i32 = 1;
+/// Error code returned when attaching a second callback to a future.
+pub const ERR_FUTURE_CALLBACK_SET: i32 = -9012;
+
+/// Callback invoked with the outcome of a future. The pointers are valid for the duration of the
+/// call.
+pub type FutureCallback = extern "C" fn(
+    user_data: *mut c_void,
+    result: *const FfiResult,
+    data: *const u8,
+    data_len: usize,
+);
+
+/// Error using a future.
+#[derive(Debug, Eq, PartialEq)]
+pub enum FutureError {
+    /// The handle doesn't refer to a live future.
+    Handle(HandleError),
+    /// A callback has already been attached to the future.
+    CallbackSet,
+}
+
+impl ErrorCode for FutureError {
+    fn error_code(&self) -> i32 {
+        match self {
+            FutureError::Handle(error) => error.error_code(),
+            FutureError::CallbackSet => ERR_FUTURE_CALLBACK_SET,
+        }
+    }
+}
+
+impl Display for FutureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FutureError::Handle(error) => write!(f, "{}", error),
+            FutureError::CallbackSet => write!(f, "A callback is already attached to the future"),
+        }
+    }
+}
+
+impl From<HandleError> for FutureError {
+    fn from(error: HandleError) -> Self {
+        FutureError::Handle(error)
+    }
+}
+
+/// FFI representation of the outcome of a future, as written by `ffi_future_poll`.
+///
+/// The pointers remain valid until the future is freed.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FfiFutureOutput {
+    /// Outcome of the operation.
+    pub result: *const FfiResult,
+    /// Data produced by the operation. Empty on error.
+    pub data: *const u8,
+    /// Length of `data`.
+    pub data_len: usize,
+}
+
+struct Output {
+    result: FfiResult,
+    data: Vec<u8>,
+}
+
+// The `FfiResult` owns its description.
+unsafe impl Send for Output {}
+unsafe impl Sync for Output {}
+
+impl Output {
+    fn call(&self, user_data: *mut c_void, cb: FutureCallback) {
+        cb(user_data, &self.result, self.data.as_ptr(), self.data.len())
+    }
+}
+
+// Number of callbacks attached to futures which haven't completed yet.
+static ATTACHED: AtomicUsize = AtomicUsize::new(0);
+
+enum State {
+    Pending(Option<(OpaqueCtx, FutureCallback)>),
+    Ready {
+        output: Arc<Output>,
+        callback_set: bool,
+    },
+}
+
+/// Outcome of an operation, delivered once to either a callback, pollers, or both.
+pub struct FfiFuture {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+impl FfiFuture {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(State::Pending(None)),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Return `true` if the operation has completed.
+    pub fn is_ready(&self) -> bool {
+        matches!(*self.state(), State::Ready { .. })
+    }
+
+    /// Attach `cb`, which is invoked with the outcome once the operation completes, or right
+    /// away on the current thread if it already has. Only one callback can be attached.
+    pub fn then(&self, user_data: *mut c_void, cb: FutureCallback) -> Result<(), FutureError> {
+        let mut state = self.state();
+        match &mut *state {
+            State::Pending(then @ None) => {
+                *then = Some((OpaqueCtx::from_host_pointer(user_data), cb));
+                let _ = ATTACHED.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            State::Ready {
+                output,
+                callback_set: callback_set @ false,
+            } => {
+                *callback_set = true;
+                let output = Arc::clone(output);
+                drop(state);
+                output.call(user_data, cb);
+                Ok(())
+            }
+            _ => Err(FutureError::CallbackSet),
+        }
+    }
+
+    /// Wait up to `timeout` for the operation to complete, and run `f` with its outcome.
+    fn wait<F, R>(&self, timeout: Duration, f: F) -> Option<R>
+    where
+        F: FnOnce(&Output) -> R,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state();
+
+        loop {
+            if let State::Ready { output, .. } = &*state {
+                return Some(f(output));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return None;
+            }
+
+            state = self
+                .condvar
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    fn complete(&self, result: NativeResult, data: Vec<u8>) {
+        let error_code = result.error_code;
+        let result = result.into_repr_c().unwrap_or_else(|_| FfiResult {
+            error_code,
+            description: ptr::null(),
+        });
+        let output = Arc::new(Output { result, data });
+
+        let then = {
+            let mut state = self.state();
+            let then = match &mut *state {
+                State::Pending(then) => then.take(),
+                State::Ready { .. } => return,
+            };
+            *state = State::Ready {
+                output: Arc::clone(&output),
+                callback_set: then.is_some(),
+            };
+            then
+        };
+        self.condvar.notify_all();
+
+        if let Some((user_data, cb)) = then {
+            output.call(user_data.as_ptr(), cb);
+            let _ = ATTACHED.fetch_sub(1, Ordering::Relaxed);
+            pending::notify_completed();
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Number of callbacks attached to futures which haven't completed yet.
+pub(crate) fn attached_callbacks() -> usize {
+    ATTACHED.load(Ordering::Relaxed)
+}
+
+/// Native side of an `FfiFuture`, used to complete it.
+///
+/// Dropping the promise without completing it completes the future with `Cancelled`.
+pub struct Promise {
+    future: Option<Arc<FfiFuture>>,
+}
+
+impl Promise {
+    /// Complete the future with the outcome of the operation.
+    pub fn complete<E>(mut self, result: Result<Vec<u8>, E>)
+    where
+        E: Debug + Display + ErrorCode,
+    {
+        if let Some(future) = self.future.take() {
+            complete(&future, result)
+        }
+    }
+}
+
+impl Drop for Promise {
+    fn drop(&mut self) {
+        if let Some(future) = self.future.take() {
+            complete(&future, Err::<Vec<u8>, _>(Cancelled))
+        }
+    }
+}
+
+fn complete<E>(future: &FfiFuture, result: Result<Vec<u8>, E>)
+where
+    E: Debug + Display + ErrorCode,
+{
+    match result {
+        Ok(data) => future.complete(
+            NativeResult {
+                error_code: 0,
+                description: None,
+            },
+            data,
+        ),
+        Err(error) => {
+            let (error_code, description) = ffi_error!(error);
+            future.complete(
+                NativeResult {
+                    error_code,
+                    description: Some(description),
+                },
+                Vec::new(),
+            )
+        }
+    }
+}
+
+/// Create a future in the global handle registry, returning its handle and the promise
+/// completing it. The promise remains usable after the handle is freed.
+pub fn new_future() -> (Handle, Promise) {
+    let future = Arc::new(FfiFuture::new());
+    let handle = handles::register_shared(Arc::clone(&future));
+    (
+        handle,
+        Promise {
+            future: Some(future),
+        },
+    )
+}
+
+/// Attach `cb` to the future behind `future`; see `FfiFuture::then`.
+pub fn then(future: Handle, user_data: *mut c_void, cb: FutureCallback) -> Result<(), FutureError> {
+    handles::get::<FfiFuture>(future)?.then(user_data, cb)
+}
+
+/// Release the future behind `future`. A callback which was attached is still invoked once the
+/// operation completes.
+pub fn free(future: Handle) -> Result<(), HandleError> {
+    handles::free::<FfiFuture>(future)
+}
+
+/// Wait up to `timeout_ms` milliseconds for the future behind `future` to complete and write its
+/// outcome to `out_output`.
+///
+/// Return 0 if the outcome was written, `FUTURE_PENDING` on timeout, or an error code if
+/// `future` is not a live future. Polling again after completion writes the same outcome.
+///
+/// # Safety
+///
+/// `out_output` must be valid for writes.
+pub unsafe fn poll(future: Handle, out_output: *mut FfiFutureOutput, timeout_ms: u32) -> i32 {
+    let future = match handles::get::<FfiFuture>(future) {
+        Ok(future) => future,
+        Err(error) => return error.error_code(),
+    };
+
+    let timeout = Duration::from_millis(u64::from(timeout_ms));
+    // The output is kept alive by the registered future until it is freed.
+    let polled = future.wait(timeout, |output| FfiFutureOutput {
+        result: &output.result,
+        data: output.data.as_ptr(),
+        data_len: output.data.len(),
+    });
+
+    match polled {
+        Some(output) => {
+            ptr::write(out_output, output);
+            0
+        }
+        None => FUTURE_PENDING,
+    }
+}
+
+/// Export the future functions of the library.
+///
+/// Defines three `#[no_mangle]` functions:
+///
+/// + `ffi_future_then(future: u64, user_data: *mut c_void, o_cb: FutureCallback) -> i32`
+///   attaching a callback; see `FfiFuture::then`;
+/// + `ffi_future_poll(future: u64, out_output: *mut FfiFutureOutput, timeout_ms: u32) -> i32`
+///   waiting for the outcome; see `future::poll`;
+/// + `ffi_future_free(future: u64) -> i32` releasing the future.
+#[macro_export]
+macro_rules! export_future {
+    () => {
+        /// Attach a callback invoked with the outcome of `future`.
+        #[no_mangle]
+        pub extern "C" fn ffi_future_then(
+            future: u64,
+            user_data: *mut std::os::raw::c_void,
+            o_cb: $crate::future::FutureCallback,
+        ) -> i32 {
+            $crate::ffi_result_code!($crate::future::then(future, user_data, o_cb))
+        }
+
+        /// Wait up to `timeout_ms` milliseconds for the outcome of `future`.
+        #[no_mangle]
+        pub unsafe extern "C" fn ffi_future_poll(
+            future: u64,
+            out_output: *mut $crate::future::FfiFutureOutput,
+            timeout_ms: u32,
+        ) -> i32 {
+            $crate::future::poll(future, out_output, timeout_ms)
+        }
+
+        /// Release a future.
+        #[no_mangle]
+        pub extern "C" fn ffi_future_free(future: u64) -> i32 {
+            $crate::ffi_result_code!($crate::future::free(future))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::ERR_CANCELLED;
+    use crate::handles::ERR_INVALID_HANDLE;
+    use crate::test_utils::TestError;
+    use std::mem::MaybeUninit;
+    use std::sync::mpsc::{self, Sender};
+    use std::{slice, thread};
+    use unwrap::unwrap;
+
+    extern "C" fn on_output(
+        user_data: *mut c_void,
+        result: *const FfiResult,
+        data: *const u8,
+        data_len: usize,
+    ) {
+        let tx = unsafe { &*(user_data as *const Sender<(i32, Vec<u8>)>) };
+        let data = unsafe { slice::from_raw_parts(data, data_len) }.to_vec();
+        unwrap!(tx.send((unsafe { (*result).error_code }, data)));
+    }
+
+    #[test]
+    fn poll_future() {
+        let (future, promise) = new_future();
+        let mut output = MaybeUninit::<FfiFutureOutput>::uninit();
+
+        assert_eq!(
+            unsafe { poll(future, output.as_mut_ptr(), 0) },
+            FUTURE_PENDING
+        );
+
+        let completer = thread::spawn(move || promise.complete::<TestError>(Ok(vec![1, 2, 3])));
+        assert_eq!(unsafe { poll(future, output.as_mut_ptr(), 60_000) }, 0);
+        let ffi_output = unsafe { output.assume_init_ref() };
+        assert_eq!(unsafe { (*ffi_output.result).error_code }, 0);
+        assert_eq!(
+            unsafe { slice::from_raw_parts(ffi_output.data, ffi_output.data_len) },
+            [1, 2, 3]
+        );
+        unwrap!(completer.join());
+
+        unwrap!(free(future));
+        assert_eq!(
+            unsafe { poll(future, output.as_mut_ptr(), 0) },
+            ERR_INVALID_HANDLE
+        );
+    }
+
+    #[test]
+    fn then_before_and_after_completion() {
+        let (tx, rx) = mpsc::channel::<(i32, Vec<u8>)>();
+        let user_data = ptr::from_ref(&tx) as *mut c_void;
+
+        let (future, promise) = new_future();
+        unwrap!(then(future, user_data, on_output));
+        assert_eq!(
+            then(future, user_data, on_output),
+            Err(FutureError::CallbackSet)
+        );
+        promise.complete(Err::<Vec<u8>, _>(TestError::Test));
+        assert_eq!(unwrap!(rx.recv()), (-1, Vec::new()));
+        unwrap!(free(future));
+
+        let (future, promise) = new_future();
+        promise.complete::<TestError>(Ok(vec![4]));
+        unwrap!(then(future, user_data, on_output));
+        assert_eq!(unwrap!(rx.recv()), (0, vec![4]));
+        unwrap!(free(future));
+
+        let (future, promise) = new_future();
+        drop(promise);
+        unwrap!(then(future, user_data, on_output));
+        assert_eq!(unwrap!(rx.recv()).0, ERR_CANCELLED);
+        unwrap!(free(future));
+    }
+}
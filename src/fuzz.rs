@@ -0,0 +1,368 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Fuzz targets for the FFI functions recorded by `describe_ffi_fn!`.
+//!
+//! `write_harnesses` generates one `cargo fuzz` target per described function. Each target
+//! decodes the fuzzer's input into argument values with `FuzzArg`, and calls the function with
+//! callbacks which ignore their arguments. It is meant to be run from a small program in the
+//! library's `fuzz` directory, which links the library so its descriptions get registered:
+//!
+//! ```ignore
+//! let names = fuzz::write_harnesses(
+//!     Path::new("fuzz/fuzz_targets"),
+//!     "my_lib",
+//!     "use my_lib::{FfiResult, Handle, Point};\nuse std::os::raw::c_char;",
+//!     &[("PointC", "Point")],
+//! )?;
+//! print!("{}", fuzz::manifest_bins(&names));
+//! ```
+//!
+//! A pointer to `u8` followed by a `usize` is taken to be a buffer and its length, and is given
+//! arbitrary bytes. Arguments whose C type is mapped to a native type (`PointC` to `Point`
+//! above) are decoded as an arbitrary native value and converted with `IntoReprC`, see
+//! `repr_arg`. Other arguments need a `FuzzArg` implementation for their type.
+
+use crate::catch_unwind::panic_message;
+use crate::describe::{self, FfiArgDesc, FfiFnDesc};
+use crate::IntoReprC;
+use std::ffi::CString;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+pub use arbitrary::{self, Arbitrary, Unstructured};
+
+/// Argument of an FFI function which can be built from fuzzer input.
+pub trait FuzzArg: Sized {
+    /// Value owning the data the argument points to, if any.
+    type Owned;
+
+    /// Decode the owned value from fuzzer input.
+    fn arbitrary(u: &mut Unstructured) -> arbitrary::Result<Self::Owned>;
+
+    /// Argument referring to `owned`.
+    fn get(owned: &Self::Owned) -> Self;
+}
+
+macro_rules! impl_fuzz_arg {
+    ($($ty:ty),*) => {$(
+        impl FuzzArg for $ty {
+            type Owned = Self;
+
+            fn arbitrary(u: &mut Unstructured) -> arbitrary::Result<Self> {
+                u.arbitrary()
+            }
+
+            fn get(owned: &Self) -> Self {
+                *owned
+            }
+        }
+    )*};
+}
+
+impl_fuzz_arg!(bool, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+impl FuzzArg for *const c_char {
+    type Owned = Option<CString>;
+
+    /// A null pointer, or a string of arbitrary bytes which need not be valid UTF-8.
+    fn arbitrary(u: &mut Unstructured) -> arbitrary::Result<Self::Owned> {
+        if u.ratio(1, 8)? {
+            return Ok(None);
+        }
+
+        let mut bytes: Vec<u8> = u.arbitrary()?;
+        if let Some(nul) = bytes.iter().position(|byte| *byte == 0) {
+            bytes.truncate(nul);
+        }
+        Ok(Some(CString::new(bytes).unwrap_or_default()))
+    }
+
+    fn get(owned: &Self::Owned) -> Self {
+        owned.as_ref().map_or(ptr::null(), |string| string.as_ptr())
+    }
+}
+
+/// Argument decoded by a fuzz target, along with the data it points to.
+pub struct Arg<T: FuzzArg>(T::Owned);
+
+impl<T: FuzzArg> Arg<T> {
+    /// The argument to pass to the FFI function.
+    pub fn get(&self) -> T {
+        T::get(&self.0)
+    }
+}
+
+/// Decode an argument of type `T` from fuzzer input.
+pub fn arg<T: FuzzArg>(u: &mut Unstructured) -> arbitrary::Result<Arg<T>> {
+    T::arbitrary(u).map(Arg)
+}
+
+/// Argument decoded as an arbitrary native value, passed in its FFI representation.
+pub struct ReprArg<T: IntoReprC> {
+    repr_c: T::C,
+    // Keeps the data `repr_c` points to alive.
+    _storage: T::Storage,
+}
+
+impl<T: IntoReprC> ReprArg<T>
+where
+    T::C: Copy,
+{
+    /// The argument to pass to the FFI function.
+    pub fn get(&self) -> T::C {
+        self.repr_c
+    }
+}
+
+/// Decode an arbitrary `T` from fuzzer input, and convert it to its FFI representation. Values
+/// which fail to convert are rejected as invalid input.
+pub fn repr_arg<'a, T>(u: &mut Unstructured<'a>) -> arbitrary::Result<ReprArg<T>>
+where
+    T: Arbitrary<'a> + IntoReprC,
+{
+    let (repr_c, storage) = T::arbitrary(u)?
+        .into_repr_c()
+        .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+    Ok(ReprArg {
+        repr_c,
+        _storage: storage,
+    })
+}
+
+/// Run the body of the fuzz target for the FFI function `name` on `data`. Inputs too short to
+/// decode every argument are skipped.
+///
+/// A panic is caught and raised again naming the function, so the fuzzer reports it as a crash
+/// of that entry point.
+pub fn run<F>(name: &str, data: &[u8], f: F)
+where
+    F: FnOnce(&mut Unstructured) -> arbitrary::Result<()>,
+{
+    let mut u = Unstructured::new(data);
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(&mut u))) {
+        panic!("`{}` panicked: {}", name, panic_message(&*payload));
+    }
+}
+
+/// Source of the `cargo fuzz` target for `desc`, a function of the crate `krate`. `prelude`
+/// is inserted after the target's own `use` declarations, and must bring the types named in
+/// the description into scope. `native_types` maps C argument types to the native types
+/// decoded with `repr_arg`, as `(C type, native type)` pairs.
+pub fn harness_source(
+    desc: &FfiFnDesc,
+    krate: &str,
+    prelude: &str,
+    native_types: &[(&str, &str)],
+) -> String {
+    format!(
+        "// Generated by `sn_ffi_utils::fuzz` from the description of `{}`.\n\n\
+         #![no_main]\n\n\
+         use libfuzzer_sys::fuzz_target;\n\
+         {}",
+        desc.name,
+        harness_body(desc, krate, prelude, native_types)
+    )
+}
+
+/// Source of the target generated by `harness_source`, without the crate attributes and the
+/// import of `fuzz_target!`, e.g. to embed it in a module.
+pub fn harness_body(
+    desc: &FfiFnDesc,
+    krate: &str,
+    prelude: &str,
+    native_types: &[(&str, &str)],
+) -> String {
+    let mut src = format!(
+        "use sn_ffi_utils::fuzz;\n\
+         use std::os::raw::c_void;\n\
+         use std::ptr;\n\
+         {}\n",
+        prelude
+    );
+
+    for callback in desc.callbacks {
+        let _ = write!(
+            src,
+            "\nextern \"C\" fn {}(_user_data: *mut c_void",
+            callback.name
+        );
+        for arg in callback.args {
+            let _ = write!(src, ", _{}: {}", arg.name, arg.ty);
+        }
+        src.push_str(") {}\n");
+    }
+
+    let _ = write!(
+        src,
+        "\nfuzz_target!(|input: &[u8]| {{\n    fuzz::run(\"{}\", input, |unstructured| {{\n",
+        desc.name
+    );
+
+    let mut call_args = Vec::new();
+    let mut args = desc.args.iter().peekable();
+    while let Some(arg) = args.next() {
+        if is_buffer(arg, args.peek().copied()) {
+            let _ = writeln!(
+                src,
+                "        let {}: Vec<u8> = unstructured.arbitrary()?;",
+                arg.name
+            );
+            call_args.push(format!("{}.as_ptr()", arg.name));
+            call_args.push(format!("{}.len()", arg.name));
+            let _ = args.next();
+        } else {
+            let decode = match native_types.iter().find(|(c_ty, _)| *c_ty == arg.ty) {
+                Some((_, native)) => format!("repr_arg::<{}>", native),
+                None => format!("arg::<{}>", arg.ty),
+            };
+            let _ = writeln!(
+                src,
+                "        let {} = fuzz::{}(unstructured)?;",
+                arg.name, decode
+            );
+            call_args.push(format!("{}.get()", arg.name));
+        }
+    }
+
+    if !desc.callbacks.is_empty() {
+        call_args.push("ptr::null_mut()".to_owned());
+        call_args.extend(desc.callbacks.iter().map(|cb| cb.name.to_owned()));
+    }
+
+    let _ = write!(
+        src,
+        "        unsafe {{ {}::{}({}) }};\n        Ok(())\n    }});\n}});\n",
+        krate,
+        desc.name,
+        call_args.join(", ")
+    );
+    src
+}
+
+fn is_buffer(arg: &FfiArgDesc, next: Option<&FfiArgDesc>) -> bool {
+    arg.ty == "*const u8" && next.is_some_and(|next| next.ty == "usize")
+}
+
+/// Write the fuzz target of every registered function to `dir`, as `<name>.rs`, and return
+/// their names. See `harness_source`.
+pub fn write_harnesses(
+    dir: &Path,
+    krate: &str,
+    prelude: &str,
+    native_types: &[(&str, &str)],
+) -> io::Result<Vec<String>> {
+    fs::create_dir_all(dir)?;
+
+    let mut names = Vec::new();
+    for desc in describe::registered() {
+        let path = dir.join(format!("{}.rs", desc.name));
+        fs::write(path, harness_source(desc, krate, prelude, native_types))?;
+        names.push(desc.name.to_owned());
+    }
+    Ok(names)
+}
+
+/// `[[bin]]` sections declaring the fuzz targets `names` in the `Cargo.toml` of a `cargo fuzz`
+/// project, whose targets live in `fuzz_targets`.
+pub fn manifest_bins(names: &[String]) -> String {
+    let mut manifest = String::new();
+    for name in names {
+        let _ = write!(
+            manifest,
+            "\n[[bin]]\nname = \"{0}\"\npath = \"fuzz_targets/{0}.rs\"\n\
+             test = false\ndoc = false\n",
+            name
+        );
+    }
+    manifest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::describe::FfiCallbackDesc;
+    use std::ffi::CStr;
+    use unwrap::unwrap;
+
+    static DESC: FfiFnDesc = FfiFnDesc {
+        name: "app_put",
+        args: &[
+            FfiArgDesc {
+                name: "app",
+                ty: "Handle",
+            },
+            FfiArgDesc {
+                name: "data",
+                ty: "*const u8",
+            },
+            FfiArgDesc {
+                name: "len",
+                ty: "usize",
+            },
+        ],
+        callbacks: &[FfiCallbackDesc {
+            name: "o_cb",
+            args: &[FfiArgDesc {
+                name: "result",
+                ty: "*const FfiResult",
+            }],
+        }],
+    };
+
+    #[test]
+    fn generated_harness() {
+        let src = harness_source(&DESC, "my_lib", "use my_lib::{FfiResult, Handle};", &[]);
+        assert!(src.starts_with(
+            "// Generated by `sn_ffi_utils::fuzz` from the description of `app_put`.\n\n\
+             #![no_main]\n\nuse libfuzzer_sys::fuzz_target;\nuse sn_ffi_utils::fuzz;\n"
+        ));
+        assert!(src.contains("use my_lib::{FfiResult, Handle};\n"));
+        assert!(src.contains(
+            "extern \"C\" fn o_cb(_user_data: *mut c_void, _result: *const FfiResult) {}"
+        ));
+        assert!(src.contains("fuzz::run(\"app_put\", input, |unstructured| {"));
+        assert!(src.contains("let app = fuzz::arg::<Handle>(unstructured)?;"));
+        assert!(src.contains("let data: Vec<u8> = unstructured.arbitrary()?;"));
+        assert!(src.contains(
+            "my_lib::app_put(app.get(), data.as_ptr(), data.len(), ptr::null_mut(), o_cb)"
+        ));
+
+        assert!(manifest_bins(&["app_put".to_owned()])
+            .contains("name = \"app_put\"\npath = \"fuzz_targets/app_put.rs\""));
+    }
+
+    #[test]
+    fn strings_are_null_or_nul_terminated() {
+        let data: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        let mut u = Unstructured::new(&data);
+        while !u.is_empty() {
+            let string = unwrap!(arg::<*const c_char>(&mut u));
+            let ptr = string.get();
+            if !ptr.is_null() {
+                let _ = unsafe { CStr::from_ptr(ptr) };
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "`app_put` panicked: bad input")]
+    fn panics_name_the_function() {
+        run("app_put", &[1, 2, 3], |u| {
+            let value = arg::<u8>(u)?;
+            assert!(value.get() == 0, "bad input");
+            Ok(())
+        });
+    }
+}
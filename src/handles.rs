@@ -0,0 +1,629 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Registry of native objects exposed to frontends as opaque integer handles.
+//!
+//! Instead of passing `Box::into_raw` pointers across the FFI, objects are registered and the
+//! frontend is given a `Handle`. Handles carry a generation, so using one after it was freed, or
+//! as an object of another type, yields an error code rather than undefined behaviour:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn app_free(app: Handle) -> i32 {
+//!     ffi_result_code!(handles::free::<App>(app))
+//! }
+//! ```
+//!
+//! Contexts tied to an object, such as the `user_data` of a pending callback or a subscription,
+//! can be attached to its handle with `attach_cb` or `attach`. They are released when the handle
+//! is freed, pending callbacks being invoked with `ERR_OBJECT_FREED`, so freeing an object with
+//! operations still in flight doesn't leave callbacks pointing at released memory.
+
+use crate::callback::Callback;
+use crate::catch_unwind::call_error_cb;
+use crate::ErrorCode;
+use std::any::{self, Any, TypeId};
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Error code returned for handles which don't refer to a live object.
+pub const ERR_INVALID_HANDLE: i32 = -9001;
+/// Error code returned for handles referring to an object of another type than expected.
+pub const ERR_HANDLE_TYPE_MISMATCH: i32 = -9002;
+/// Error code passed to callbacks attached to a handle which was freed before they completed.
+pub const ERR_OBJECT_FREED: i32 = -9019;
+
+/// Opaque handle to an object in a `HandleRegistry`. 0 is never a valid handle.
+pub type Handle = u64;
+
+/// Error using a handle.
+#[derive(Debug, Eq, PartialEq)]
+pub enum HandleError {
+    /// The handle was never registered, or has been freed.
+    Invalid(Handle),
+    /// The handle refers to an object of another type.
+    TypeMismatch {
+        /// The offending handle.
+        handle: Handle,
+        /// Name of the type which was expected.
+        expected: &'static str,
+        /// Name of the type of the object.
+        actual: &'static str,
+    },
+}
+
+impl ErrorCode for HandleError {
+    fn error_code(&self) -> i32 {
+        match self {
+            HandleError::Invalid(_) => ERR_INVALID_HANDLE,
+            HandleError::TypeMismatch { .. } => ERR_HANDLE_TYPE_MISMATCH,
+        }
+    }
+}
+
+impl Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::Invalid(handle) => write!(f, "Invalid handle {:#x}", handle),
+            HandleError::TypeMismatch {
+                handle,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Handle {:#x} refers to a {}, not a {}",
+                handle, actual, expected
+            ),
+        }
+    }
+}
+
+type Release = Box<dyn FnOnce() + Send>;
+
+struct Entry {
+    type_id: TypeId,
+    type_name: &'static str,
+    object: Arc<dyn Any + Send + Sync>,
+    scoped: Vec<(u64, Release)>,
+}
+
+impl Entry {
+    fn release_scoped(self) {
+        for (_, release) in self.scoped {
+            release();
+        }
+    }
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+struct Slots {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    next_scoped: u64,
+}
+
+/// Generational slot map of objects of any type, addressed by `Handle`s.
+///
+/// Most code should use the module-level functions, which operate on a global registry.
+pub struct HandleRegistry {
+    inner: Mutex<Slots>,
+}
+
+impl HandleRegistry {
+    /// Create an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Slots {
+                slots: Vec::new(),
+                free: Vec::new(),
+                next_scoped: 0,
+            }),
+        }
+    }
+
+    /// Register `object`, returning a new handle to it.
+    pub fn register<T: Send + Sync + 'static>(&self, object: T) -> Handle {
+        self.register_shared(Arc::new(object))
+    }
+
+    /// Register an object which may already be shared, returning a new handle to it. The
+    /// object is dropped once all of its handles are freed and all other `Arc`s are gone.
+    pub fn register_shared<T: Send + Sync + 'static>(&self, object: Arc<T>) -> Handle {
+        let entry = Entry {
+            type_id: TypeId::of::<T>(),
+            type_name: any::type_name::<T>(),
+            object,
+            scoped: Vec::new(),
+        };
+
+        let mut inner = self.lock();
+        let index = match inner.free.pop() {
+            Some(index) => index,
+            None => {
+                inner.slots.push(Slot {
+                    generation: 1,
+                    entry: None,
+                });
+                (inner.slots.len() - 1) as u32
+            }
+        };
+
+        let slot = &mut inner.slots[index as usize];
+        slot.entry = Some(entry);
+        let handle = handle(index, slot.generation);
+
+        #[cfg(feature = "memory-report")]
+        crate::memory::track_handle(self, handle, size_of::<T>());
+
+        handle
+    }
+
+    /// Return the object behind `handle`.
+    pub fn get<T: Send + Sync + 'static>(&self, handle: Handle) -> Result<Arc<T>, HandleError> {
+        let inner = self.lock();
+        let entry = lookup::<T>(&inner, handle)?;
+        Ok(downcast(Arc::clone(&entry.object)))
+    }
+
+    /// Run `f` with the object behind `handle`. The registry is not locked while `f` runs.
+    pub fn with<T, F, R>(&self, handle: Handle, f: F) -> Result<R, HandleError>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce(&T) -> R,
+    {
+        let object = self.get::<T>(handle)?;
+        Ok(f(&object))
+    }
+
+    /// Remove the object behind `handle` from the registry, invalidating the handle, and return
+    /// it. The object is dropped once the last `Arc` returned by `get` is gone.
+    ///
+    /// Contexts attached to the handle are released, after the registry is unlocked.
+    pub fn remove<T: Send + Sync + 'static>(&self, handle: Handle) -> Result<Arc<T>, HandleError> {
+        let entry = {
+            let mut inner = self.lock();
+            let _ = lookup::<T>(&inner, handle)?;
+
+            let index = index(handle);
+            let slot = &mut inner.slots[index as usize];
+            let entry = slot.entry.take();
+            slot.generation = match slot.generation.wrapping_add(1) {
+                0 => 1,
+                generation => generation,
+            };
+            inner.free.push(index);
+
+            #[cfg(feature = "memory-report")]
+            crate::memory::untrack_handle(self, handle);
+
+            entry.ok_or(HandleError::Invalid(handle))?
+        };
+
+        let object = Arc::clone(&entry.object);
+        entry.release_scoped();
+        Ok(downcast(object))
+    }
+
+    /// Free the object behind `handle`, invalidating the handle.
+    pub fn free<T: Send + Sync + 'static>(&self, handle: Handle) -> Result<(), HandleError> {
+        self.remove::<T>(handle).map(drop)
+    }
+
+    /// Remove every object from the registry, invalidating all handles, and return how many
+    /// were removed.
+    pub fn clear(&self) -> usize {
+        let entries: Vec<_> = {
+            let mut inner = self.lock();
+            let mut entries = Vec::new();
+            let mut free = Vec::new();
+            for (index, slot) in inner.slots.iter_mut().enumerate() {
+                if let Some(entry) = slot.entry.take() {
+                    #[cfg(feature = "memory-report")]
+                    crate::memory::untrack_handle(self, handle(index as u32, slot.generation));
+
+                    slot.generation = match slot.generation.wrapping_add(1) {
+                        0 => 1,
+                        generation => generation,
+                    };
+                    free.push(index as u32);
+                    entries.push(entry);
+                }
+            }
+            inner.free.extend(free);
+            entries
+        };
+
+        // Objects are dropped and contexts released without holding the lock, as they may use
+        // handles.
+        let count = entries.len();
+        for entry in entries {
+            entry.release_scoped();
+        }
+        count
+    }
+
+    /// Attach a context to `handle`, which `release` releases if the handle is freed before the
+    /// context is detached. The handle may refer to an object of any type.
+    pub fn attach<F>(&self, handle: Handle, release: F) -> Result<ScopedCtx<'_>, HandleError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut inner = self.lock();
+        let id = inner.next_scoped;
+        let entry = lookup_mut(&mut inner, handle)?;
+        entry.scoped.push((id, Box::new(release)));
+        inner.next_scoped += 1;
+
+        Ok(ScopedCtx {
+            registry: self,
+            handle,
+            id,
+        })
+    }
+
+    /// Attach a pending callback to `handle`. If the handle is freed before the context is
+    /// detached, `cb` is invoked with `ERR_OBJECT_FREED` and default values for its other
+    /// arguments.
+    pub fn attach_cb<C>(
+        &self,
+        handle: Handle,
+        user_data: *mut c_void,
+        cb: C,
+    ) -> Result<ScopedCtx<'_>, HandleError>
+    where
+        C: Callback + Send + 'static,
+    {
+        // Pointers aren't `Send`; the caller hands `user_data` over to whoever invokes `cb`.
+        let user_data = user_data as usize;
+        self.attach(handle, move || {
+            call_error_cb(
+                user_data as *mut c_void,
+                cb,
+                ERR_OBJECT_FREED,
+                format!("Object {:#x} freed before the operation completed", handle),
+            )
+        })
+    }
+
+    /// Number of live objects in the registry.
+    pub fn len(&self) -> usize {
+        let inner = self.lock();
+        inner.slots.len() - inner.free.len()
+    }
+
+    /// Return `true` if the registry holds no objects.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Slots> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for HandleRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn handle(index: u32, generation: u32) -> Handle {
+    (u64::from(generation) << 32) | u64::from(index)
+}
+
+fn index(handle: Handle) -> u32 {
+    handle as u32
+}
+
+fn generation(handle: Handle) -> u32 {
+    (handle >> 32) as u32
+}
+
+fn lookup<T: 'static>(inner: &Slots, handle: Handle) -> Result<&Entry, HandleError> {
+    let entry = inner
+        .slots
+        .get(index(handle) as usize)
+        .filter(|slot| slot.generation == generation(handle))
+        .and_then(|slot| slot.entry.as_ref())
+        .ok_or(HandleError::Invalid(handle))?;
+
+    if entry.type_id == TypeId::of::<T>() {
+        Ok(entry)
+    } else {
+        Err(HandleError::TypeMismatch {
+            handle,
+            expected: any::type_name::<T>(),
+            actual: entry.type_name,
+        })
+    }
+}
+
+fn lookup_mut(inner: &mut Slots, handle: Handle) -> Result<&mut Entry, HandleError> {
+    inner
+        .slots
+        .get_mut(index(handle) as usize)
+        .filter(|slot| slot.generation == generation(handle))
+        .and_then(|slot| slot.entry.as_mut())
+        .ok_or(HandleError::Invalid(handle))
+}
+
+/// Context attached to a handle with `HandleRegistry::attach` or `attach_cb`.
+///
+/// Detach it once the operation completes, before using the context: if the handle has been
+/// freed in the meantime, the context was already released and must not be used.
+#[must_use = "a context left attached is released when its handle is freed"]
+pub struct ScopedCtx<'a> {
+    registry: &'a HandleRegistry,
+    handle: Handle,
+    id: u64,
+}
+
+impl ScopedCtx<'_> {
+    /// Handle the context is attached to.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// Detach the context from its handle without releasing it. Returns `false` if the handle
+    /// was freed and the context released already.
+    pub fn detach(self) -> bool {
+        let mut inner = self.registry.lock();
+        let entry = match lookup_mut(&mut inner, self.handle) {
+            Ok(entry) => entry,
+            Err(_) => return false,
+        };
+
+        match entry.scoped.iter().position(|(id, _)| *id == self.id) {
+            Some(position) => {
+                // Dropping the release function doesn't run it.
+                let _ = entry.scoped.swap_remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn downcast<T: Send + Sync + 'static>(object: Arc<dyn Any + Send + Sync>) -> Arc<T> {
+    match object.downcast::<T>() {
+        Ok(object) => object,
+        // The type was checked by `lookup`.
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Registry of objects of a single type, which can be shared between several holders through
+/// distinct handles.
+///
+/// Each holder gets its own handle with `clone_handle` and gives it back with `release_handle`;
+/// the object is freed once the last handle to it is released.
+pub struct ObjectCache<T> {
+    registry: HandleRegistry,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Send + Sync + 'static> ObjectCache<T> {
+    /// Create an empty cache.
+    pub const fn new() -> Self {
+        Self {
+            registry: HandleRegistry::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Add `object` to the cache, returning the first handle to it.
+    pub fn insert(&self, object: T) -> Handle {
+        self.registry.register(object)
+    }
+
+    /// Return the object behind `handle`.
+    pub fn get(&self, handle: Handle) -> Result<Arc<T>, HandleError> {
+        self.registry.get(handle)
+    }
+
+    /// Run `f` with the object behind `handle`.
+    pub fn with<F, R>(&self, handle: Handle, f: F) -> Result<R, HandleError>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.registry.with(handle, f)
+    }
+
+    /// Return a new handle to the object behind `handle`, which must be released separately.
+    pub fn clone_handle(&self, handle: Handle) -> Result<Handle, HandleError> {
+        let object = self.registry.get::<T>(handle)?;
+        Ok(self.registry.register_shared(object))
+    }
+
+    /// Release `handle`, freeing the object if it was the last handle to it.
+    pub fn release_handle(&self, handle: Handle) -> Result<(), HandleError> {
+        self.registry.free::<T>(handle)
+    }
+
+    /// Number of live handles in the cache.
+    pub fn handle_count(&self) -> usize {
+        self.registry.len()
+    }
+}
+
+impl<T: Send + Sync + 'static> Default for ObjectCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static REGISTRY: HandleRegistry = HandleRegistry::new();
+
+/// Register `object` in the global registry, returning a new handle to it.
+pub fn register<T: Send + Sync + 'static>(object: T) -> Handle {
+    REGISTRY.register(object)
+}
+
+/// Register an object which may already be shared in the global registry, returning a new
+/// handle to it.
+pub fn register_shared<T: Send + Sync + 'static>(object: Arc<T>) -> Handle {
+    REGISTRY.register_shared(object)
+}
+
+/// Return the object behind `handle` in the global registry.
+pub fn get<T: Send + Sync + 'static>(handle: Handle) -> Result<Arc<T>, HandleError> {
+    REGISTRY.get(handle)
+}
+
+/// Run `f` with the object behind `handle` in the global registry.
+pub fn with<T, F, R>(handle: Handle, f: F) -> Result<R, HandleError>
+where
+    T: Send + Sync + 'static,
+    F: FnOnce(&T) -> R,
+{
+    REGISTRY.with(handle, f)
+}
+
+/// Remove the object behind `handle` from the global registry and return it.
+pub fn remove<T: Send + Sync + 'static>(handle: Handle) -> Result<Arc<T>, HandleError> {
+    REGISTRY.remove(handle)
+}
+
+/// Free the object behind `handle` in the global registry.
+pub fn free<T: Send + Sync + 'static>(handle: Handle) -> Result<(), HandleError> {
+    REGISTRY.free::<T>(handle)
+}
+
+/// Remove every object from the global registry, invalidating all handles.
+pub fn clear() -> usize {
+    REGISTRY.clear()
+}
+
+/// Attach a context to `handle` in the global registry. See `HandleRegistry::attach`.
+pub fn attach<F>(handle: Handle, release: F) -> Result<ScopedCtx<'static>, HandleError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    REGISTRY.attach(handle, release)
+}
+
+/// Attach a pending callback to `handle` in the global registry. See
+/// `HandleRegistry::attach_cb`.
+pub fn attach_cb<C>(
+    handle: Handle,
+    user_data: *mut c_void,
+    cb: C,
+) -> Result<ScopedCtx<'static>, HandleError>
+where
+    C: Callback + Send + 'static,
+{
+    REGISTRY.attach_cb(handle, user_data, cb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FfiResult;
+    use std::ptr;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use unwrap::unwrap;
+
+    #[test]
+    fn stale_and_mistyped_handles_are_rejected() {
+        let registry = HandleRegistry::new();
+        let a = registry.register(String::from("a"));
+        assert_eq!(unwrap!(registry.with(a, |s: &String| s.clone())), "a");
+
+        assert_eq!(
+            registry.get::<u32>(a).map_err(|e| e.error_code()),
+            Err(ERR_HANDLE_TYPE_MISMATCH)
+        );
+
+        assert!(registry.free::<String>(a).is_ok());
+        assert_eq!(registry.free::<String>(a), Err(HandleError::Invalid(a)));
+
+        // The slot is reused with a new generation.
+        let b = registry.register(1u32);
+        assert_ne!(a, b);
+        assert_eq!(
+            registry.get::<String>(a).err(),
+            Some(HandleError::Invalid(a))
+        );
+        assert_eq!(*unwrap!(registry.get::<u32>(b)), 1);
+        assert_eq!(registry.len(), 1);
+
+        assert_eq!(registry.get::<u32>(0).err(), Some(HandleError::Invalid(0)));
+    }
+
+    #[test]
+    fn object_freed_with_last_handle() {
+        let cache = ObjectCache::new();
+        let object = Arc::new(());
+        let first = cache.insert(Arc::clone(&object));
+        let second = unwrap!(cache.clone_handle(first));
+        assert_ne!(first, second);
+        assert_eq!(Arc::strong_count(&object), 2);
+
+        unwrap!(cache.release_handle(first));
+        assert!(cache.get(first).is_err());
+        assert_eq!(Arc::strong_count(&object), 2);
+
+        unwrap!(cache.release_handle(second));
+        assert_eq!(Arc::strong_count(&object), 1);
+        assert_eq!(cache.handle_count(), 0);
+    }
+
+    extern "C" fn record(user_data: *mut c_void, result: *const FfiResult) {
+        let error_code = unsafe { &*(user_data as *const AtomicI32) };
+        error_code.store(unsafe { (*result).error_code }, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn attached_callbacks_fail_when_handle_freed() {
+        let registry = HandleRegistry::new();
+        let error_code = AtomicI32::new(0);
+        let user_data = ptr::from_ref(&error_code) as *mut c_void;
+        let cb: extern "C" fn(_, _) = record;
+
+        // A context detached before the handle is freed is left alone.
+        let a = registry.register(String::from("a"));
+        let ctx = unwrap!(registry.attach_cb(a, user_data, cb));
+        assert_eq!(ctx.handle(), a);
+        assert!(ctx.detach());
+        unwrap!(registry.free::<String>(a));
+        assert_eq!(error_code.load(Ordering::SeqCst), 0);
+
+        let b = registry.register(String::from("b"));
+        let ctx = unwrap!(registry.attach_cb(b, user_data, cb));
+        unwrap!(registry.free::<String>(b));
+        assert_eq!(error_code.load(Ordering::SeqCst), ERR_OBJECT_FREED);
+        assert!(!ctx.detach());
+
+        assert!(registry.attach(b, || ()).is_err());
+    }
+
+    #[test]
+    fn clear_invalidates_all_handles() {
+        let registry = HandleRegistry::new();
+        let object = Arc::new(());
+        let a = registry.register_shared(Arc::clone(&object));
+        let b = registry.register(0u32);
+
+        assert_eq!(registry.clear(), 2);
+        assert!(registry.is_empty());
+        assert!(registry.get::<()>(a).is_err());
+        assert!(registry.get::<u32>(b).is_err());
+        assert_eq!(Arc::strong_count(&object), 1);
+
+        let c = registry.register(1u32);
+        assert_ne!(c, b);
+        assert_eq!(*unwrap!(registry.get::<u32>(c)), 1);
+    }
+}
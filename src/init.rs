@@ -0,0 +1,364 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Library lifecycle: global state created by an exported init function and torn down by an
+//! exported shutdown function.
+//!
+//! ```ignore
+//! static APP: Library<App> = Library::new();
+//!
+//! fn init_app() -> Result<App, AppError> {
+//!     App::connect()
+//! }
+//!
+//! export_lifecycle!(APP, init_app);
+//!
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_refresh(
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+//! ) {
+//!     catch_unwind_cb(user_data, o_cb, || -> Result<_, AppError> {
+//!         APP.get()?.refresh()?;
+//!         o_cb(user_data, FFI_RESULT_OK);
+//!         Ok(())
+//!     })
+//! }
+//! ```
+//!
+//! Shutting down waits for the callbacks queued on the global dispatcher, and frees every
+//! object in the handle registry of the library (`Library::handles`), leaving the registries of
+//! other libraries loaded in the same process alone.
+//!
+//! `Library::shutdown_graceful` (exported with `export_lifecycle!(APP, init_app, graceful)`)
+//! doesn't block the caller: it cancels the operations in flight, waits in the background for
+//! the pending callbacks to drain, for at most a timeout, and reports the outcome to a completion
+//! callback.
+
+use crate::handles::HandleRegistry;
+use crate::ErrorCode;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{cancel, dispatcher, pending, timers};
+#[cfg(not(target_arch = "wasm32"))]
+use log::warn;
+use std::error::Error;
+use std::fmt::{self, Display};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+/// Error code returned when the library is used before being initialised, or after being shut
+/// down.
+pub const ERR_NOT_INITIALISED: i32 = -9008;
+/// Error code returned when the library is initialised twice.
+pub const ERR_ALREADY_INITIALISED: i32 = -9009;
+/// Error code reported by a graceful shutdown when callbacks were still pending at its timeout.
+pub const ERR_SHUTDOWN_TIMED_OUT: i32 = -9020;
+/// Error code returned by `ffi_init` when the initialisation function panicked.
+pub const ERR_INIT_PANICKED: i32 = -9024;
+/// Error code returned by a graceful shutdown when its background thread couldn't be started.
+pub const ERR_SHUTDOWN_UNAVAILABLE: i32 = -9028;
+
+/// Error returned by `Library` operations.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LifecycleError {
+    /// The library hasn't been initialised, or has been shut down.
+    NotInitialised,
+    /// The library has already been initialised.
+    AlreadyInitialised,
+    /// Callbacks were still pending when a graceful shutdown timed out.
+    ShutdownTimedOut,
+    /// The initialisation function panicked.
+    InitPanicked,
+    /// The thread of a graceful shutdown couldn't be started. The library is left initialised.
+    ShutdownUnavailable,
+}
+
+impl ErrorCode for LifecycleError {
+    fn error_code(&self) -> i32 {
+        match self {
+            LifecycleError::NotInitialised => ERR_NOT_INITIALISED,
+            LifecycleError::AlreadyInitialised => ERR_ALREADY_INITIALISED,
+            LifecycleError::ShutdownTimedOut => ERR_SHUTDOWN_TIMED_OUT,
+            LifecycleError::InitPanicked => ERR_INIT_PANICKED,
+            LifecycleError::ShutdownUnavailable => ERR_SHUTDOWN_UNAVAILABLE,
+        }
+    }
+}
+
+impl Display for LifecycleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LifecycleError::NotInitialised => write!(f, "Library not initialised"),
+            LifecycleError::AlreadyInitialised => write!(f, "Library already initialised"),
+            LifecycleError::ShutdownTimedOut => {
+                write!(f, "Callbacks still pending when the shutdown timed out")
+            }
+            LifecycleError::InitPanicked => write!(f, "Library initialisation panicked"),
+            LifecycleError::ShutdownUnavailable => {
+                write!(f, "Could not start the shutdown thread")
+            }
+        }
+    }
+}
+
+impl Error for LifecycleError {}
+
+/// Global state of a library, available between its initialisation and its shutdown.
+pub struct Library<T> {
+    state: RwLock<Option<Arc<T>>>,
+    // Serialises initialisations.
+    init: Mutex<()>,
+    handles: HandleRegistry,
+}
+
+impl<T> Library<T> {
+    /// Create an uninitialised library.
+    pub const fn new() -> Self {
+        Self {
+            state: RwLock::new(None),
+            init: Mutex::new(()),
+            handles: HandleRegistry::new(),
+        }
+    }
+
+    /// Handle registry of the library, cleared when it is shut down.
+    pub fn handles(&self) -> &HandleRegistry {
+        &self.handles
+    }
+
+    /// Initialise the library with `state`.
+    pub fn init(&self, state: T) -> Result<(), LifecycleError> {
+        self.init_with(|| Ok(state))
+    }
+
+    /// Initialise the library with the state returned by `f`. Concurrent initialisations wait
+    /// for `f` to return, and fail with `AlreadyInitialised` if it succeeded.
+    ///
+    /// `f` runs without holding the state lock, so it may use the library, which is not
+    /// initialised yet.
+    pub fn init_with<F, E>(&self, f: F) -> Result<(), E>
+    where
+        F: FnOnce() -> Result<T, E>,
+        E: From<LifecycleError>,
+    {
+        let _init = self.init.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.is_initialised() {
+            return Err(E::from(LifecycleError::AlreadyInitialised));
+        }
+
+        let state = Arc::new(f()?);
+        *self.state.write().unwrap_or_else(PoisonError::into_inner) = Some(state);
+        Ok(())
+    }
+
+    /// Return `true` if the library is initialised.
+    pub fn is_initialised(&self) -> bool {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    /// Return the state of the library. It stays alive after a shutdown until the returned `Arc`
+    /// is dropped.
+    pub fn get(&self) -> Result<Arc<T>, LifecycleError> {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .ok_or(LifecycleError::NotInitialised)
+    }
+
+    /// Run `f` with the state of the library.
+    pub fn with<F, R>(&self, f: F) -> Result<R, LifecycleError>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.get().map(|state| f(&state))
+    }
+
+    /// Shut the library down: further calls fail with `NotInitialised`, pending timers are
+    /// cancelled, the callbacks queued on the global dispatcher are run and the handle registry
+    /// of the library is cleared. Returns the state, so that it can be torn down explicitly.
+    pub fn shutdown(&self) -> Result<Arc<T>, LifecycleError> {
+        let state = self
+            .state
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .ok_or(LifecycleError::NotInitialised)?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = timers::cancel_all();
+            dispatcher::drain_global();
+        }
+        let _ = self.handles.clear();
+
+        Ok(state)
+    }
+
+    /// Shut the library down without blocking the caller: further calls fail with
+    /// `NotInitialised`, pending timers and every live `CancelHandle` are cancelled, then a
+    /// background thread waits at most `timeout` for the pending callbacks to drain, clears the
+    /// handle registry of the library, drops the state and calls `done` with the outcome:
+    /// `ShutdownTimedOut` if callbacks were still pending.
+    ///
+    /// Fails with `ShutdownUnavailable`, leaving the library initialised, if the thread can't be
+    /// started.
+    ///
+    /// Must not be called from a dispatched job, which would be waited for. Not available on
+    /// `wasm32`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn shutdown_graceful<F>(
+        &'static self,
+        timeout: Duration,
+        done: F,
+    ) -> Result<(), LifecycleError>
+    where
+        F: FnOnce(Result<(), LifecycleError>) + Send + 'static,
+        T: Send + Sync + 'static,
+    {
+        // Spawn the thread before shutting down, so that a failure leaves the library usable.
+        // The thread exits without running anything if no job is sent.
+        let (tx, rx) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let _ = thread::Builder::new()
+            .name("ffi-shutdown".to_owned())
+            .spawn(move || {
+                if let Ok(job) = rx.recv() {
+                    job()
+                }
+            })
+            .map_err(|error| {
+                warn!("Failed to start the shutdown thread: {}", error);
+                LifecycleError::ShutdownUnavailable
+            })?;
+
+        let state = self
+            .state
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .ok_or(LifecycleError::NotInitialised)?;
+
+        let _ = timers::cancel_all();
+        let _ = cancel::cancel_all();
+
+        let drain = move || {
+            let drained = pending::wait_for_drain(timeout);
+            let _ = self.handles.clear();
+            drop(state);
+
+            if drained {
+                done(Ok(()))
+            } else {
+                warn!("{} callbacks still pending at shutdown", pending::count());
+                done(Err(LifecycleError::ShutdownTimedOut))
+            }
+        };
+        let _ = tx.send(Box::new(drain));
+
+        Ok(())
+    }
+}
+
+impl<T> Default for Library<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Generate the exported functions initialising and shutting down a `Library`:
+///
+/// ```ignore
+/// export_lifecycle!(APP, init_app);
+/// ```
+///
+/// This defines `ffi_init() -> i32`, which initialises the library with the state returned by
+/// `init_app` (a function returning `Result<T, E>`, where `E: ErrorCode + Display +
+/// From<LifecycleError>`), and `ffi_shutdown() -> i32`. Both return 0 on success, or an error
+/// code: `ERR_INIT_PANICKED` if `init_app` panicked.
+///
+/// With `graceful`, `ffi_shutdown` is defined with `Library::shutdown_graceful` instead:
+///
+/// ```ignore
+/// export_lifecycle!(APP, init_app, graceful);
+/// ```
+///
+/// `ffi_shutdown(timeout_ms: u64, user_data: *mut c_void, o_cb: extern "C" fn(user_data: *mut
+/// c_void, result: *const FfiResult))` returns immediately and calls `o_cb` once the pending
+/// callbacks have drained, or with `ERR_SHUTDOWN_TIMED_OUT` after `timeout_ms` milliseconds. A
+/// timeout too large to be represented waits for as long as callbacks are pending.
+#[macro_export]
+macro_rules! export_lifecycle {
+    ($library:path, $init:path, graceful) => {
+        $crate::export_lifecycle!(@init $library, $init);
+
+        /// Shut the library down, calling `o_cb` once the pending callbacks have drained or
+        /// `timeout_ms` milliseconds have elapsed.
+        #[no_mangle]
+        pub extern "C" fn ffi_shutdown(
+            timeout_ms: u64,
+            user_data: *mut std::os::raw::c_void,
+            o_cb: extern "C" fn(
+                user_data: *mut std::os::raw::c_void,
+                result: *const $crate::FfiResult,
+            ),
+        ) {
+            // Pointers aren't `Send`; the host hands `user_data` over to the shutdown thread.
+            let user_data = user_data as usize;
+            let done = move |result: Result<(), $crate::init::LifecycleError>| {
+                $crate::call_result_cb!(result, user_data as *mut std::os::raw::c_void, o_cb);
+            };
+            let timeout = std::time::Duration::from_millis(timeout_ms);
+            if let Err(e) = $library.shutdown_graceful(timeout, done) {
+                $crate::call_result_cb!(
+                    Err::<(), _>(e),
+                    user_data as *mut std::os::raw::c_void,
+                    o_cb
+                );
+            }
+        }
+    };
+    ($library:path, $init:path) => {
+        $crate::export_lifecycle!(@init $library, $init);
+
+        /// Shut the library down. Returns 0 on success, or an error code.
+        #[no_mangle]
+        pub extern "C" fn ffi_shutdown() -> i32 {
+            match $library.shutdown() {
+                Ok(_) => 0,
+                Err(e) => $crate::ErrorCode::error_code(&e),
+            }
+        }
+    };
+    (@init $library:path, $init:path) => {
+        /// Initialise the library. Returns 0 on success, or an error code.
+        #[no_mangle]
+        pub extern "C" fn ffi_init() -> i32 {
+            match std::panic::catch_unwind(|| $library.init_with($init)) {
+                Ok(Ok(())) => 0,
+                Ok(Err(e)) => {
+                    log::error!("{}", e);
+                    $crate::ErrorCode::error_code(&e)
+                }
+                Err(_) => {
+                    let e = $crate::init::LifecycleError::InitPanicked;
+                    log::error!("{}", e);
+                    $crate::ErrorCode::error_code(&e)
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,77 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Thread attachments which outlive a single `EnvGuard`.
+//!
+//! `EnvGuard::new` detaches native threads again as soon as the guard is dropped, so a thread
+//! invoking many callbacks pays for an attach/detach round trip every time. The constructors
+//! here attach the thread once and keep it attached until the thread exits.
+
+use super::{EnvGuard, JniResult};
+use jni::errors::Error as JniError;
+use jni::sys;
+use jni::JavaVM;
+use log::warn;
+use std::cell::RefCell;
+use std::mem;
+
+// Detaches the current thread when dropped, i.e. when the thread exits.
+struct ThreadAttachment(*mut sys::JavaVM);
+
+impl Drop for ThreadAttachment {
+    fn drop(&mut self) {
+        let res = unsafe {
+            match (**self.0).DetachCurrentThread {
+                Some(detach) => detach(self.0),
+                None => sys::JNI_ERR,
+            }
+        };
+        if res != sys::JNI_OK {
+            warn!("Error detaching current thread: {}", res);
+        }
+    }
+}
+
+thread_local! {
+    static ATTACHMENT: RefCell<Option<ThreadAttachment>> = const { RefCell::new(None) };
+}
+
+impl<'a> EnvGuard<'a> {
+    /// Same as `EnvGuard::new`, but a native thread stays attached after the guard is dropped
+    /// and is only detached when it exits.
+    pub fn new_cached(vm: Option<&'a JavaVM>) -> JniResult<Self> {
+        Self::new_attached(vm, |vm| {
+            mem::forget(vm.attach_current_thread()?);
+            Ok(())
+        })
+    }
+
+    /// Same as `new_cached`, but attaches native threads as daemon threads, which do not keep
+    /// the JVM from shutting down. Use this for long-running runtime threads.
+    pub fn new_daemon(vm: Option<&'a JavaVM>) -> JniResult<Self> {
+        Self::new_attached(vm, |vm| vm.attach_current_thread_as_daemon().map(|_| ()))
+    }
+
+    fn new_attached<F>(vm: Option<&'a JavaVM>, attach: F) -> JniResult<Self>
+    where
+        F: FnOnce(&JavaVM) -> JniResult<()>,
+    {
+        let vm = vm.ok_or_else(|| JniError::from("no JVM reference found"))?;
+        if let Ok(env) = vm.get_env() {
+            return Ok(EnvGuard::Auto(env));
+        }
+
+        attach(vm)?;
+        ATTACHMENT.with(|attachment| {
+            *attachment.borrow_mut() = Some(ThreadAttachment(vm.get_java_vm_pointer()));
+        });
+
+        Ok(EnvGuard::Auto(vm.get_env()?))
+    }
+}
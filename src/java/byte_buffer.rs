@@ -0,0 +1,80 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Zero-copy byte passing through direct `java.nio.ByteBuffer`s.
+//!
+//! Direct buffers refer to native memory instead of copying it into the Java heap, which avoids
+//! the two copies per crossing a `byte[]` costs. The flip side is that the JVM does not own the
+//! memory: Java code must not access a buffer created here once the Rust side has released it.
+
+use super::JniResult;
+use jni::objects::JByteBuffer;
+use jni::JNIEnv;
+
+/// Rust-owned bytes exposed to Java as a direct `ByteBuffer`.
+///
+/// The memory stays valid for as long as the guard is alive, so either hand the buffer to Java
+/// code that only uses it for the duration of a call, or keep the guard until Java signals that
+/// it is done with the buffer.
+pub struct DirectByteBuffer<'a> {
+    buffer: JByteBuffer<'a>,
+    // Boxed so that moving the guard does not move the bytes the buffer points to.
+    data: Box<[u8]>,
+}
+
+impl<'a> DirectByteBuffer<'a> {
+    /// Take ownership of `data` and wrap it into a direct `ByteBuffer`.
+    pub fn new(env: &JNIEnv<'a>, data: Vec<u8>) -> JniResult<Self> {
+        let mut data = data.into_boxed_slice();
+        let buffer = env.new_direct_byte_buffer(&mut data)?;
+        Ok(Self { buffer, data })
+    }
+
+    /// Return the Java `ByteBuffer`.
+    pub fn buffer(&self) -> JByteBuffer<'a> {
+        self.buffer
+    }
+
+    /// Return the bytes. Changes made by Java through the buffer are visible here.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Release the bytes back to Rust. Java must not access the buffer afterwards.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data.into_vec()
+    }
+}
+
+/// Wrap a borrowed slice into a direct `ByteBuffer`.
+///
+/// The buffer must not be used by Java after the borrow ends, so this is only suitable for
+/// synchronous calls into Java.
+pub fn slice_to_byte_buffer<'a>(
+    env: &JNIEnv<'a>,
+    data: &'a mut [u8],
+) -> JniResult<JByteBuffer<'a>> {
+    env.new_direct_byte_buffer(data)
+}
+
+/// View the contents of a direct `ByteBuffer` created by Java (`ByteBuffer.allocateDirect`) as a
+/// slice, without copying.
+///
+/// Returns an error if `buffer` is not a direct buffer.
+pub fn byte_buffer_as_slice<'b>(env: &'b JNIEnv, buffer: JByteBuffer) -> JniResult<&'b [u8]> {
+    Ok(env.get_direct_buffer_address(buffer)?)
+}
+
+/// Mutable version of `byte_buffer_as_slice`.
+pub fn byte_buffer_as_mut_slice<'b>(
+    env: &'b JNIEnv,
+    buffer: JByteBuffer,
+) -> JniResult<&'b mut [u8]> {
+    env.get_direct_buffer_address(buffer)
+}
@@ -0,0 +1,282 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Caching of Java classes and method IDs.
+//!
+//! `FindClass` resolves classes through the class loader of the calling Java method, so from a
+//! native thread attached with `EnvGuard::Manual` it only sees system classes (on Android it
+//! fails outright). Classes should therefore be looked up once from `JNI_OnLoad` through
+//! `init`, and served from the cache afterwards. Method IDs are cached alongside: they stay
+//! valid for as long as their class is loaded, which the cached global reference guarantees.
+//!
+//! Classes missing from the cache can still be resolved from native threads once the
+//! application class loader has been captured with `ClassCache::set_class_loader` (done by
+//! `jni_on_load!`): lookups then go through `ClassLoader.loadClass`, which works from any thread,
+//! including on Android.
+
+use super::JniResult;
+use jni::objects::{AutoLocal, GlobalRef, JClass, JMethodID, JObject, JStaticMethodID, JValue};
+use jni::sys::jmethodID;
+use jni::JNIEnv;
+use std::collections::HashMap;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+/// Cache of global references to Java classes, keyed by their binary name
+/// (e.g. `net/maidsafe/safe_app/FfiResult`).
+#[derive(Default)]
+pub struct ClassCache {
+    classes: RwLock<HashMap<String, GlobalRef>>,
+    loader: RwLock<Option<GlobalRef>>,
+}
+
+impl ClassCache {
+    /// Look up and cache the given classes. Call this from a thread that has the application
+    /// class loader, such as the one running `JNI_OnLoad`.
+    pub fn preload(&self, env: &JNIEnv, names: &[&str]) -> JniResult<()> {
+        for name in names {
+            let _ = self.load(env, name)?;
+        }
+        Ok(())
+    }
+
+    /// Return the cached class, if any.
+    pub fn get(&self, name: &str) -> Option<GlobalRef> {
+        self.classes
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+    }
+
+    /// Capture the class loader of `anchor`, an application class, and use it to resolve
+    /// classes missing from the cache. Call this from a thread that has the application class
+    /// loader.
+    pub fn set_class_loader(&self, env: &JNIEnv, anchor: &str) -> JniResult<()> {
+        let anchor = self.load(env, anchor)?;
+        let loader = env
+            .call_method(
+                anchor.as_obj(),
+                "getClassLoader",
+                "()Ljava/lang/ClassLoader;",
+                &[],
+            )?
+            .l()?;
+        let loader = env.new_global_ref(loader)?;
+
+        *self.loader.write().unwrap_or_else(PoisonError::into_inner) = Some(loader);
+        Ok(())
+    }
+
+    /// Return the cached class, looking it up and caching it on a miss.
+    ///
+    /// The lookup uses the class loader captured by `set_class_loader` if there is one, and
+    /// `FindClass` otherwise.
+    pub fn load(&self, env: &JNIEnv, name: &str) -> JniResult<GlobalRef> {
+        if let Some(class) = self.get(name) {
+            return Ok(class);
+        }
+
+        let loader = self
+            .loader
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let local = match loader {
+            Some(loader) => load_with_loader(env, &loader, name)?,
+            None => env.find_class(name)?.into(),
+        };
+        let class = env.new_global_ref(local)?;
+        env.delete_local_ref(local)?;
+
+        let _ = self
+            .classes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_owned(), class.clone());
+
+        Ok(class)
+    }
+
+    /// Drop all cached classes and the captured class loader.
+    pub fn clear(&self) {
+        *self.loader.write().unwrap_or_else(PoisonError::into_inner) = None;
+        self.classes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+fn load_with_loader<'a>(
+    env: &JNIEnv<'a>,
+    loader: &GlobalRef,
+    name: &str,
+) -> JniResult<JObject<'a>> {
+    // `ClassLoader.loadClass` expects the `java.lang.String` form of the name.
+    let binary_name = env.new_string(name.replace('/', "."))?;
+    let class = env
+        .call_method(
+            loader.as_obj(),
+            "loadClass",
+            "(Ljava/lang/String;)Ljava/lang/Class;",
+            &[JValue::from(JObject::from(binary_name))],
+        )
+        .and_then(JValue::l);
+    env.delete_local_ref(binary_name.into())?;
+
+    class
+}
+
+// Method IDs are plain pointers which JNI guarantees to be valid from any thread for as long
+// as the class is loaded.
+#[derive(Clone, Copy)]
+struct MethodId(jmethodID);
+unsafe impl Send for MethodId {}
+unsafe impl Sync for MethodId {}
+
+type MethodKey = (String, String, String);
+
+/// Cache of method IDs, keyed by class name, method name and signature.
+#[derive(Default)]
+pub struct MethodCache {
+    methods: RwLock<HashMap<MethodKey, MethodId>>,
+    static_methods: RwLock<HashMap<MethodKey, MethodId>>,
+}
+
+impl MethodCache {
+    /// Return the ID of an instance method, looking it up on a miss. The class is resolved
+    /// through `classes`.
+    pub fn method_id<'a>(
+        &self,
+        env: &JNIEnv<'a>,
+        classes: &ClassCache,
+        class: &str,
+        name: &str,
+        sig: &str,
+    ) -> JniResult<JMethodID<'a>> {
+        let id = lookup(&self.methods, class, name, sig, || {
+            let class = classes.load(env, class)?;
+            let id = env.get_method_id(JClass::from(class.as_obj()), name, sig)?;
+            Ok(id.into_inner())
+        })?;
+        Ok(JMethodID::from(id))
+    }
+
+    /// Return the ID of a static method, looking it up on a miss. The class is resolved
+    /// through `classes`.
+    pub fn static_method_id<'a>(
+        &self,
+        env: &JNIEnv<'a>,
+        classes: &ClassCache,
+        class: &str,
+        name: &str,
+        sig: &str,
+    ) -> JniResult<JStaticMethodID<'a>> {
+        let id = lookup(&self.static_methods, class, name, sig, || {
+            let class = classes.load(env, class)?;
+            let id = env.get_static_method_id(JClass::from(class.as_obj()), name, sig)?;
+            Ok(id.into_inner())
+        })?;
+        Ok(JStaticMethodID::from(id))
+    }
+
+    /// Drop all cached method IDs.
+    pub fn clear(&self) {
+        self.methods
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.static_methods
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+fn lookup<F>(
+    map: &RwLock<HashMap<MethodKey, MethodId>>,
+    class: &str,
+    name: &str,
+    sig: &str,
+    f: F,
+) -> JniResult<jmethodID>
+where
+    F: FnOnce() -> JniResult<jmethodID>,
+{
+    let key = (class.to_owned(), name.to_owned(), sig.to_owned());
+
+    if let Some(id) = map.read().unwrap_or_else(PoisonError::into_inner).get(&key) {
+        return Ok(id.0);
+    }
+
+    let id = f()?;
+    let _ = map
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(key, MethodId(id));
+
+    Ok(id)
+}
+
+/// Return the process-wide class cache.
+pub fn class_cache() -> &'static ClassCache {
+    static CACHE: OnceLock<ClassCache> = OnceLock::new();
+    CACHE.get_or_init(ClassCache::default)
+}
+
+/// Return the process-wide method ID cache.
+pub fn method_cache() -> &'static MethodCache {
+    static CACHE: OnceLock<MethodCache> = OnceLock::new();
+    CACHE.get_or_init(MethodCache::default)
+}
+
+/// Populate the process-wide class cache and capture the class loader of the first class for
+/// later lookups. Call this from `JNI_OnLoad`.
+pub fn init(env: &JNIEnv, classes: &[&str]) -> JniResult<()> {
+    let cache = class_cache();
+    cache.preload(env, classes)?;
+    if let Some(anchor) = classes.first() {
+        cache.set_class_loader(env, anchor)?;
+    }
+    Ok(())
+}
+
+/// Return a class from the process-wide cache, looking it up on a miss.
+pub fn find_class(env: &JNIEnv, name: &str) -> JniResult<GlobalRef> {
+    class_cache().load(env, name)
+}
+
+/// Return an instance method ID from the process-wide cache, looking it up on a miss.
+pub fn method_id<'a>(
+    env: &JNIEnv<'a>,
+    class: &str,
+    name: &str,
+    sig: &str,
+) -> JniResult<JMethodID<'a>> {
+    method_cache().method_id(env, class_cache(), class, name, sig)
+}
+
+/// Return a static method ID from the process-wide cache, looking it up on a miss.
+pub fn static_method_id<'a>(
+    env: &JNIEnv<'a>,
+    class: &str,
+    name: &str,
+    sig: &str,
+) -> JniResult<JStaticMethodID<'a>> {
+    method_cache().static_method_id(env, class_cache(), class, name, sig)
+}
+
+/// Class loader backed by the process-wide cache, suitable for passing to
+/// `object_array_to_java` and `gen_object_array_converter!`.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn load_class<'a>(env: &'a JNIEnv, name: &str) -> JniResult<AutoLocal<'a>> {
+    let class = find_class(env, name)?;
+    let local = env.new_local_ref::<JObject>(JObject::from(class.as_obj().into_inner()))?;
+    Ok(env.auto_local(local))
+}
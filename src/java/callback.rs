@@ -0,0 +1,201 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Invocation of Java callbacks from native callbacks.
+//!
+//! A typical native callback recovers the `CallbackCtx` from `user_data`, obtains a `JNIEnv`
+//! for the current thread, converts its arguments and calls the callback object's method:
+//!
+//! ```ignore
+//! extern "C" fn call_result_string(
+//!     user_data: *mut c_void,
+//!     res: *const FfiResult,
+//!     s: *const c_char,
+//! ) {
+//!     unsafe {
+//!         // Convert the arguments to Rust types implementing `ToJava` first. `None` is passed
+//!         // to Java as `null`.
+//!         let res = NativeResult::clone_from_repr_c(res).ok();
+//!         let s = String::clone_from_repr_c(s).ok();
+//!         call_java_cb_final!(
+//!             user_data, 0,
+//!             "net/maidsafe/safe_app/CallbackResultString",
+//!             "call",
+//!             "(Lnet/maidsafe/safe_app/FfiResult;Ljava/lang/String;)V",
+//!             res, s
+//!         );
+//!     }
+//! }
+//! ```
+
+use super::{cache, free_ctx, on_load, CallbackCtx, EnvGuard, JniResult};
+use jni::errors::Error as JniError;
+use jni::objects::{JObject, JValue};
+use jni::signature::TypeSignature;
+use jni::JNIEnv;
+use log::error;
+use std::os::raw::c_void;
+
+/// Call `method` with the signature `sig` on the callback object `cb`.
+///
+/// The method ID is looked up through `interface`, the name of the class or interface declaring
+/// the method, and cached.
+pub fn call_callback(
+    env: &JNIEnv,
+    cb: JObject,
+    interface: &str,
+    method: &str,
+    sig: &str,
+    args: &[JValue],
+) -> JniResult<()> {
+    let ret = TypeSignature::from_str(sig)?.ret;
+    let id = cache::method_id(env, interface, method, sig)?;
+    let _ = env.call_method_unchecked(cb, id, ret, args)?;
+    Ok(())
+}
+
+/// Run `f` with a `JNIEnv` for the current thread and the callback at `index` in the
+/// `CallbackCtx` behind `ctx`, leaving the context alive for further invocations.
+///
+/// The thread is attached through `EnvGuard` if needed. Errors, including exceptions thrown by
+/// the callback, are logged and cleared as there is no Java caller to report them to.
+///
+/// # Safety
+///
+/// `ctx` must have been returned by `CallbackCtx::into_raw` and not yet released.
+pub unsafe fn invoke_callback<F>(ctx: *mut c_void, index: usize, f: F)
+where
+    F: FnOnce(&JNIEnv, JObject) -> JniResult<()>,
+{
+    invoke_selected_callback(ctx, |ctx| ctx.callback(index), f)
+}
+
+/// Same as `invoke_callback`, but releases the context afterwards. Use this from the final
+/// callback of an operation.
+///
+/// # Safety
+///
+/// `ctx` must have been returned by `CallbackCtx::into_raw`, and must not be used afterwards.
+pub unsafe fn invoke_final_callback<F>(ctx: *mut c_void, index: usize, f: F)
+where
+    F: FnOnce(&JNIEnv, JObject) -> JniResult<()>,
+{
+    invoke_selected_final_callback(ctx, |ctx| ctx.callback(index), f)
+}
+
+/// Same as `invoke_callback`, but the callback is picked from the context by `select`.
+///
+/// # Safety
+///
+/// See `invoke_callback`.
+pub unsafe fn invoke_selected_callback<S, F>(ctx: *mut c_void, select: S, f: F)
+where
+    S: FnOnce(&CallbackCtx) -> Option<JObject>,
+    F: FnOnce(&JNIEnv, JObject) -> JniResult<()>,
+{
+    let _ = invoke(CallbackCtx::from_raw(ctx), select, f);
+}
+
+/// Same as `invoke_final_callback`, but the callback is picked from the context by `select`.
+///
+/// # Safety
+///
+/// See `invoke_final_callback`.
+pub unsafe fn invoke_selected_final_callback<S, F>(ctx: *mut c_void, select: S, f: F)
+where
+    S: FnOnce(&CallbackCtx) -> Option<JObject>,
+    F: FnOnce(&JNIEnv, JObject) -> JniResult<()>,
+{
+    if let Some(guard) = invoke(CallbackCtx::from_raw(ctx), select, f) {
+        free_ctx(guard.env(), ctx);
+    } else {
+        // Dropping the context deletes the references through its own `EnvGuard`.
+        drop(CallbackCtx::from_raw_owned(ctx));
+    }
+}
+
+fn invoke<S, F>(ctx: &CallbackCtx, select: S, f: F) -> Option<EnvGuard<'static>>
+where
+    S: FnOnce(&CallbackCtx) -> Option<JObject>,
+    F: FnOnce(&JNIEnv, JObject) -> JniResult<()>,
+{
+    let guard = match EnvGuard::new(on_load::vm()) {
+        Ok(guard) => guard,
+        Err(e) => {
+            error!("Failed to obtain JNIEnv for callback: {:?}", e);
+            return None;
+        }
+    };
+    let env = guard.env();
+
+    let res = select(ctx)
+        .ok_or_else(|| JniError::from("callback not found in context"))
+        .and_then(|cb| f(env, cb));
+
+    if let Err(e) = res {
+        error!("Java callback failed: {:?}", e);
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_describe();
+            let _ = env.exception_clear();
+        }
+    }
+
+    Some(guard)
+}
+
+/// Invoke a method of the Java callback at `index` in the `CallbackCtx` behind `user_data`,
+/// converting every argument with its `ToJava` impl.
+///
+/// For contexts declared with `gen_named_callback_ctx!`, the callback can be named instead:
+/// `call_java_cb!(FetchCtx::on_data(user_data), interface, method, sig, args...)`.
+///
+/// Must be used in an `unsafe` block; see `java::invoke_callback` for the safety requirements.
+#[macro_export]
+macro_rules! call_java_cb {
+    (
+        $ty:ident :: $field:ident ($ctx:expr),
+        $interface:expr, $method:expr, $sig:expr $(, $arg:expr)* $(,)*
+    ) => {
+        $ty::invoke($ctx, $ty::$field, |env, cb| {
+            let args = [$($crate::java::to_jvalue(&$arg, env)?),*];
+            $crate::java::call_callback(env, cb, $interface, $method, $sig, &args)
+        })
+    };
+
+    ($ctx:expr, $index:expr, $interface:expr, $method:expr, $sig:expr $(, $arg:expr)* $(,)*) => {
+        $crate::java::invoke_callback($ctx, $index, |env, cb| {
+            let args = [$($crate::java::to_jvalue(&$arg, env)?),*];
+            $crate::java::call_callback(env, cb, $interface, $method, $sig, &args)
+        })
+    };
+}
+
+/// Same as `call_java_cb!`, but releases the context afterwards.
+///
+/// Must be used in an `unsafe` block; see `java::invoke_final_callback` for the safety
+/// requirements.
+#[macro_export]
+macro_rules! call_java_cb_final {
+    (
+        $ty:ident :: $field:ident ($ctx:expr),
+        $interface:expr, $method:expr, $sig:expr $(, $arg:expr)* $(,)*
+    ) => {
+        $ty::invoke_final($ctx, $ty::$field, |env, cb| {
+            let args = [$($crate::java::to_jvalue(&$arg, env)?),*];
+            $crate::java::call_callback(env, cb, $interface, $method, $sig, &args)
+        })
+    };
+
+    ($ctx:expr, $index:expr, $interface:expr, $method:expr, $sig:expr $(, $arg:expr)* $(,)*) => {
+        $crate::java::invoke_final_callback($ctx, $index, |env, cb| {
+            let args = [$($crate::java::to_jvalue(&$arg, env)?),*];
+            $crate::java::call_callback(env, cb, $interface, $method, $sig, &args)
+        })
+    };
+}
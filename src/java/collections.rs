@@ -0,0 +1,243 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Conversions between Rust collections and `java.util` collections.
+//!
+//! `Vec<T>` converts into a `java.util.ArrayList` and from any `java.util.List`, and
+//! `HashMap<String, T>` converts into a `java.util.HashMap` and from any `java.util.Map`. The
+//! exceptions are vectors of `u8`, `i32`, `i64`, `f32` and `f64`, which convert to and from the
+//! corresponding primitive arrays (`byte[]`, `int[]`, ...).
+
+use super::{cache, FromJava, JniResult, ToJava};
+use jni::objects::{JClass, JObject, JValue};
+use jni::signature::{JavaType, Primitive};
+use jni::sys::jint;
+use jni::JNIEnv;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+const ARRAY_LIST: &str = "java/util/ArrayList";
+const HASH_MAP: &str = "java/util/HashMap";
+const ITERATOR: &str = "java/util/Iterator";
+const LIST: &str = "java/util/List";
+const MAP: &str = "java/util/Map";
+const MAP_ENTRY: &str = "java/util/Map$Entry";
+const SET: &str = "java/util/Set";
+
+impl<'a, T> ToJava<'a, JObject<'a>> for [T]
+where
+    T: ToJava<'a, JObject<'a>>,
+{
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        let class = cache::find_class(env, ARRAY_LIST)?;
+        let output = env.new_object(
+            JClass::from(class.as_obj()),
+            "(I)V",
+            &[(self.len() as jint).into()],
+        )?;
+
+        for item in self {
+            let item = item.to_java(env)?;
+            let _ = call(
+                env,
+                output,
+                LIST,
+                "add",
+                "(Ljava/lang/Object;)Z",
+                JavaType::Primitive(Primitive::Boolean),
+                &[item.into()],
+            )?;
+            env.delete_local_ref(item)?;
+        }
+
+        Ok(output)
+    }
+}
+
+impl<'a, T> ToJava<'a, JObject<'a>> for Vec<T>
+where
+    T: ToJava<'a, JObject<'a>>,
+{
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        self.as_slice().to_java(env)
+    }
+}
+
+impl<'a, T> FromJava<JObject<'a>> for Vec<T>
+where
+    T: for<'b> FromJava<JObject<'b>>,
+{
+    fn from_java(env: &JNIEnv, input: JObject) -> JniResult<Self> {
+        let len = call(
+            env,
+            input,
+            LIST,
+            "size",
+            "()I",
+            JavaType::Primitive(Primitive::Int),
+            &[],
+        )?
+        .i()?;
+        let mut output = Vec::with_capacity(len as usize);
+
+        for idx in 0..len {
+            let item = call(
+                env,
+                input,
+                LIST,
+                "get",
+                "(I)Ljava/lang/Object;",
+                object(),
+                &[idx.into()],
+            )?
+            .l()?;
+            output.push(T::from_java(env, item)?);
+            env.delete_local_ref(item)?;
+        }
+
+        Ok(output)
+    }
+}
+
+impl<'a, T, S> ToJava<'a, JObject<'a>> for HashMap<String, T, S>
+where
+    T: ToJava<'a, JObject<'a>>,
+    S: BuildHasher,
+{
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        let class = cache::find_class(env, HASH_MAP)?;
+        let output = env.new_object(
+            JClass::from(class.as_obj()),
+            "(I)V",
+            &[(self.len() as jint).into()],
+        )?;
+
+        for (key, value) in self {
+            let key = key.to_java(env)?;
+            let value = value.to_java(env)?;
+            let prev = call(
+                env,
+                output,
+                MAP,
+                "put",
+                "(Ljava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;",
+                object(),
+                &[key.into(), value.into()],
+            )?
+            .l()?;
+            env.delete_local_ref(prev)?;
+            env.delete_local_ref(value)?;
+            env.delete_local_ref(key)?;
+        }
+
+        Ok(output)
+    }
+}
+
+impl<'a, T, S> FromJava<JObject<'a>> for HashMap<String, T, S>
+where
+    T: for<'b> FromJava<JObject<'b>>,
+    S: BuildHasher + Default,
+{
+    fn from_java(env: &JNIEnv, input: JObject) -> JniResult<Self> {
+        let entries = call(
+            env,
+            input,
+            MAP,
+            "entrySet",
+            "()Ljava/util/Set;",
+            object(),
+            &[],
+        )?
+        .l()?;
+        let iter = call(
+            env,
+            entries,
+            SET,
+            "iterator",
+            "()Ljava/util/Iterator;",
+            object(),
+            &[],
+        )?
+        .l()?;
+        env.delete_local_ref(entries)?;
+
+        let mut output = HashMap::default();
+
+        while call(
+            env,
+            iter,
+            ITERATOR,
+            "hasNext",
+            "()Z",
+            JavaType::Primitive(Primitive::Boolean),
+            &[],
+        )?
+        .z()?
+        {
+            let entry = call(
+                env,
+                iter,
+                ITERATOR,
+                "next",
+                "()Ljava/lang/Object;",
+                object(),
+                &[],
+            )?
+            .l()?;
+            let key = call(
+                env,
+                entry,
+                MAP_ENTRY,
+                "getKey",
+                "()Ljava/lang/Object;",
+                object(),
+                &[],
+            )?
+            .l()?;
+            let value = call(
+                env,
+                entry,
+                MAP_ENTRY,
+                "getValue",
+                "()Ljava/lang/Object;",
+                object(),
+                &[],
+            )?
+            .l()?;
+
+            let _ = output.insert(String::from_java(env, key)?, T::from_java(env, value)?);
+
+            env.delete_local_ref(value)?;
+            env.delete_local_ref(key)?;
+            env.delete_local_ref(entry)?;
+        }
+
+        env.delete_local_ref(iter)?;
+        Ok(output)
+    }
+}
+
+fn object() -> JavaType {
+    JavaType::Object("java/lang/Object".to_owned())
+}
+
+// Call a method whose ID is looked up through `class` and cached.
+fn call<'a>(
+    env: &JNIEnv<'a>,
+    obj: JObject,
+    class: &str,
+    name: &str,
+    sig: &str,
+    ret: JavaType,
+    args: &[JValue],
+) -> JniResult<JValue<'a>> {
+    let id = cache::method_id(env, class, name, sig)?;
+    env.call_method_unchecked(obj, id, ret, args)
+}
@@ -0,0 +1,498 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Conversions between native Rust values and their Java counterparts.
+
+use super::{JniError, JniResult};
+use crate::payload::{self, PayloadTooLarge, JNI_MAX_LEN};
+use jni::objects::{JObject, JString, JValue};
+use jni::sys::{jboolean, jbyte, jbyteArray, jdouble, jfloat, jint, jlong, jobject, jsize};
+use jni::JNIEnv;
+
+/// Conversion from a Java value (e.g. `JString` or `jlong`) into a native Rust value.
+pub trait FromJava<T>: Sized {
+    /// Convert `input` into `Self`.
+    fn from_java(env: &JNIEnv, input: T) -> JniResult<Self>;
+}
+
+/// Conversion from a native Rust value into a Java value, whose local references live as long
+/// as `env`.
+pub trait ToJava<'a, T: Sized + 'a> {
+    /// Convert `self` into a Java value.
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<T>;
+}
+
+/// Value which can be passed as a Java method argument.
+pub trait IntoJValue<'a> {
+    /// Convert `self` into a `JValue`.
+    fn into_jvalue(self) -> JValue<'a>;
+}
+
+macro_rules! impl_into_jvalue {
+    ($($ty:ty),*) => {
+        $(
+            impl<'a> IntoJValue<'a> for $ty {
+                fn into_jvalue(self) -> JValue<'a> {
+                    self.into()
+                }
+            }
+        )*
+    };
+}
+
+impl_into_jvalue!(
+    bool,
+    jboolean,
+    jbyte,
+    jint,
+    jlong,
+    jfloat,
+    jdouble,
+    JObject<'a>
+);
+
+impl<'a> IntoJValue<'a> for JString<'a> {
+    fn into_jvalue(self) -> JValue<'a> {
+        JObject::from(self).into()
+    }
+}
+
+/// Value which can be extracted from a `JValue`, such as a field read with `JNIEnv::get_field`.
+pub trait FromJValue<'a>: Sized {
+    /// Extract `Self` from `value`, failing if it holds a different type.
+    fn from_jvalue(value: JValue<'a>) -> JniResult<Self>;
+}
+
+macro_rules! impl_from_jvalue {
+    ($($ty:ty => $getter:ident),*) => {
+        $(
+            impl<'a> FromJValue<'a> for $ty {
+                fn from_jvalue(value: JValue<'a>) -> JniResult<Self> {
+                    value.$getter()
+                }
+            }
+        )*
+    };
+}
+
+impl_from_jvalue!(
+    bool => z,
+    jbyte => b,
+    jint => i,
+    jlong => j,
+    jfloat => f,
+    jdouble => d,
+    JObject<'a> => l
+);
+
+impl<'a> FromJValue<'a> for jboolean {
+    fn from_jvalue(value: JValue<'a>) -> JniResult<Self> {
+        Ok(value.z()? as jboolean)
+    }
+}
+
+/// Convert `value` with its `ToJava` impl into a `JValue`, ready to be passed as a method
+/// argument.
+pub fn to_jvalue<'a, T, J>(value: &T, env: &'a JNIEnv) -> JniResult<JValue<'a>>
+where
+    T: ToJava<'a, J> + ?Sized,
+    J: IntoJValue<'a> + 'a,
+{
+    Ok(value.to_java(env)?.into_jvalue())
+}
+
+// Unsigned integers are reinterpreted bitwise as the signed Java type of the same width, so
+// e.g. `u64` values above `i64::MAX` arrive in Java as negative `long`s. Use
+// `Long.toUnsignedString`/`Long.compareUnsigned` on the Java side where this matters.
+crate::gen_primitive_type_converter!(u8, jbyte);
+crate::gen_primitive_type_converter!(i32, jint);
+crate::gen_primitive_type_converter!(u32, jint);
+crate::gen_primitive_type_converter!(i64, jlong);
+crate::gen_primitive_type_converter!(u64, jlong);
+crate::gen_primitive_type_converter!(usize, jlong);
+crate::gen_primitive_type_converter!(f32, jfloat);
+crate::gen_primitive_type_converter!(f64, jdouble);
+
+impl FromJava<jboolean> for bool {
+    fn from_java(_env: &JNIEnv, input: jboolean) -> JniResult<Self> {
+        Ok(input != 0)
+    }
+}
+
+impl<'a> ToJava<'a, jboolean> for bool {
+    fn to_java(&self, _env: &JNIEnv) -> JniResult<jboolean> {
+        Ok(*self as jboolean)
+    }
+}
+
+impl<'a> FromJava<JString<'a>> for String {
+    fn from_java(env: &JNIEnv, input: JString) -> JniResult<Self> {
+        Ok(env.get_string(input)?.into())
+    }
+}
+
+impl<'a> FromJava<JObject<'a>> for String {
+    fn from_java(env: &JNIEnv, input: JObject) -> JniResult<Self> {
+        Self::from_java(env, JString::from(input))
+    }
+}
+
+// Payloads are fitted within the configured limits, and never exceed `jsize`: larger ones can't
+// be created, and would otherwise fail inside the JVM.
+fn payload_error(error: PayloadTooLarge) -> JniError {
+    JniError::from(error.to_string())
+}
+
+// Strings convert into a plain `JObject` rather than `JString`, so that they compose with
+// `Option` and the collection converters like any other object. Wrap the result with
+// `JString::from` where the narrower type is needed.
+impl<'a> ToJava<'a, JObject<'a>> for str {
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        let s = payload::fit_value_str(self, JNI_MAX_LEN).map_err(payload_error)?;
+        Ok(env.new_string(s)?.into())
+    }
+}
+
+impl<'a> ToJava<'a, JObject<'a>> for String {
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        self.as_str().to_java(env)
+    }
+}
+
+impl<'a> FromJava<JObject<'a>> for Vec<u8> {
+    fn from_java(env: &JNIEnv, input: JObject) -> JniResult<Self> {
+        env.convert_byte_array(input.into_inner() as jbyteArray)
+    }
+}
+
+impl<'a> ToJava<'a, JObject<'a>> for [u8] {
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        let data = payload::fit_value_bytes(self, JNI_MAX_LEN).map_err(payload_error)?;
+        Ok(JObject::from(env.byte_array_from_slice(data)? as jobject))
+    }
+}
+
+impl<'a> ToJava<'a, JObject<'a>> for Vec<u8> {
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        self.as_slice().to_java(env)
+    }
+}
+
+// Numeric slices are copied with a single `Set<Type>ArrayRegion`/`Get<Type>ArrayRegion` call
+// rather than element by element.
+macro_rules! impl_primitive_array {
+    ($native_type:ty, $new:ident, $get_region:ident, $set_region:ident) => {
+        impl<'a> FromJava<JObject<'a>> for Vec<$native_type> {
+            fn from_java(env: &JNIEnv, input: JObject) -> JniResult<Self> {
+                let input = input.into_inner();
+                let mut output = vec![Default::default(); env.get_array_length(input)? as usize];
+                env.$get_region(input, 0, &mut output)?;
+                Ok(output)
+            }
+        }
+
+        impl<'a> ToJava<'a, JObject<'a>> for [$native_type] {
+            fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+                payload::check_len(self.len(), JNI_MAX_LEN).map_err(payload_error)?;
+                let output = env.$new(self.len() as jsize)?;
+                env.$set_region(output, 0, self)?;
+                Ok(JObject::from(output))
+            }
+        }
+
+        impl<'a> ToJava<'a, JObject<'a>> for Vec<$native_type> {
+            fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+                self.as_slice().to_java(env)
+            }
+        }
+    };
+}
+
+impl_primitive_array!(
+    i32,
+    new_int_array,
+    get_int_array_region,
+    set_int_array_region
+);
+impl_primitive_array!(
+    i64,
+    new_long_array,
+    get_long_array_region,
+    set_long_array_region
+);
+impl_primitive_array!(
+    f32,
+    new_float_array,
+    get_float_array_region,
+    set_float_array_region
+);
+impl_primitive_array!(
+    f64,
+    new_double_array,
+    get_double_array_region,
+    set_double_array_region
+);
+
+/// `null` converts into `None`.
+impl<'a, T, J> FromJava<J> for Option<T>
+where
+    T: FromJava<J>,
+    J: Copy + Into<JObject<'a>>,
+{
+    fn from_java(env: &JNIEnv, input: J) -> JniResult<Self> {
+        if input.into().is_null() {
+            Ok(None)
+        } else {
+            T::from_java(env, input).map(Some)
+        }
+    }
+}
+
+/// `None` converts into `null`.
+impl<'a, T, J> ToJava<'a, J> for Option<T>
+where
+    T: ToJava<'a, J>,
+    J: From<JObject<'a>> + 'a,
+{
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<J> {
+        match self {
+            Some(value) => value.to_java(env),
+            None => Ok(J::from(JObject::null())),
+        }
+    }
+}
+
+/// Generate `FromJava` and `ToJava` impls converting a Rust struct from and into a Java object
+/// with the same fields.
+///
+/// Field types are written as for `jni_sig!`. Java fields are named after the Rust fields unless
+/// renamed with `as`. The Java class must have a no-arg constructor, and the Rust field types
+/// must implement `FromJava`/`ToJava` for the corresponding JNI type (`jlong` for `long`,
+/// `JObject` for classes and arrays, etc.):
+///
+/// ```ignore
+/// gen_java_struct_converter!(File, "net/maidsafe/safe_app/File", {
+///     size: long,
+///     created_sec as "createdSec": long,
+///     user_metadata as "userMetadata": [byte],
+///     data_map_name as "dataMapName": [byte],
+/// });
+/// ```
+///
+/// The class is resolved through the class cache.
+#[macro_export]
+macro_rules! gen_java_struct_converter {
+    (
+        $native_type:ident,
+        $class:expr,
+        { $($field:ident $(as $java_name:literal)?: $java_ty:tt),* $(,)* }
+    ) => {
+        impl<'a> $crate::java::FromJava<jni::objects::JObject<'a>> for $native_type {
+            fn from_java(
+                env: &jni::JNIEnv,
+                input: jni::objects::JObject,
+            ) -> $crate::java::JniResult<Self> {
+                Ok($native_type {
+                    $(
+                        $field: {
+                            let value = env.get_field(
+                                input,
+                                $crate::gen_java_struct_converter!(@name $field $($java_name)?),
+                                $crate::jni_sig!($java_ty),
+                            )?;
+                            let value: $crate::gen_java_struct_converter!(@type $java_ty) =
+                                $crate::java::FromJValue::from_jvalue(value)?;
+                            let output = $crate::java::FromJava::from_java(env, value)?;
+                            $crate::gen_java_struct_converter!(@delete env, value, $java_ty);
+                            output
+                        },
+                    )*
+                })
+            }
+        }
+
+        impl<'a> $crate::java::ToJava<'a, jni::objects::JObject<'a>> for $native_type {
+            fn to_java(
+                &self,
+                env: &'a jni::JNIEnv,
+            ) -> $crate::java::JniResult<jni::objects::JObject<'a>> {
+                let class = $crate::java::cache::find_class(env, $class)?;
+                let output = env.new_object(
+                    jni::objects::JClass::from(class.as_obj()),
+                    "()V",
+                    &[],
+                )?;
+
+                $(
+                    let value: $crate::gen_java_struct_converter!(@type $java_ty) =
+                        $crate::java::ToJava::to_java(&self.$field, env)?;
+                    env.set_field(
+                        output,
+                        $crate::gen_java_struct_converter!(@name $field $($java_name)?),
+                        $crate::jni_sig!($java_ty),
+                        $crate::java::IntoJValue::into_jvalue(value),
+                    )?;
+                    $crate::gen_java_struct_converter!(@delete env, value, $java_ty);
+                )*
+
+                Ok(output)
+            }
+        }
+    };
+
+    (@name $field:ident) => { stringify!($field) };
+    (@name $field:ident $java_name:literal) => { $java_name };
+
+    (@type boolean) => { jni::sys::jboolean };
+    (@type byte) => { jni::sys::jbyte };
+    (@type char) => { jni::sys::jchar };
+    (@type short) => { jni::sys::jshort };
+    (@type int) => { jni::sys::jint };
+    (@type long) => { jni::sys::jlong };
+    (@type float) => { jni::sys::jfloat };
+    (@type double) => { jni::sys::jdouble };
+    (@type $object:tt) => { jni::objects::JObject };
+
+    (@delete $env:ident, $value:ident, boolean) => {};
+    (@delete $env:ident, $value:ident, byte) => {};
+    (@delete $env:ident, $value:ident, char) => {};
+    (@delete $env:ident, $value:ident, short) => {};
+    (@delete $env:ident, $value:ident, int) => {};
+    (@delete $env:ident, $value:ident, long) => {};
+    (@delete $env:ident, $value:ident, float) => {};
+    (@delete $env:ident, $value:ident, double) => {};
+    (@delete $env:ident, $value:ident, $object:tt) => {
+        $env.delete_local_ref($value)?;
+    };
+}
+
+/// Generate `FromJava` and `ToJava` impls converting a fieldless Rust enum from and into a Java
+/// enum.
+///
+/// Variants are matched by name, optionally renamed with `as`, or, with the `ordinal` form, by
+/// their position in the Java enum. Converting a Java value without a matching variant fails
+/// with an error naming the value.
+///
+/// ```ignore
+/// gen_java_enum_converter!(Permission, "net/maidsafe/safe_app/Permission", {
+///     Read as "READ",
+///     Insert as "INSERT",
+/// });
+///
+/// gen_java_enum_converter!(Kind, "net/maidsafe/safe_app/Kind", ordinal, { Public, Private });
+/// ```
+#[macro_export]
+macro_rules! gen_java_enum_converter {
+    (
+        $native_type:ident,
+        $class:literal,
+        { $($variant:ident $(as $java_name:literal)?),+ $(,)* }
+    ) => {
+        impl<'a> $crate::java::FromJava<jni::objects::JObject<'a>> for $native_type {
+            fn from_java(
+                env: &jni::JNIEnv,
+                input: jni::objects::JObject,
+            ) -> $crate::java::JniResult<Self> {
+                let name_obj = env.call_method(input, "name", "()Ljava/lang/String;", &[])?.l()?;
+                let name = <String as $crate::java::FromJava<jni::objects::JObject>>::from_java(
+                    env, name_obj,
+                );
+                env.delete_local_ref(name_obj)?;
+                let name = name?;
+
+                $(
+                    if name == $crate::gen_java_struct_converter!(@name $variant $($java_name)?) {
+                        return Ok($native_type::$variant);
+                    }
+                )+
+
+                Err(jni::errors::Error::from(format!(
+                    "unknown {} value: {}",
+                    stringify!($native_type),
+                    name
+                )))
+            }
+        }
+
+        impl<'a> $crate::java::ToJava<'a, jni::objects::JObject<'a>> for $native_type {
+            fn to_java(
+                &self,
+                env: &'a jni::JNIEnv,
+            ) -> $crate::java::JniResult<jni::objects::JObject<'a>> {
+                let class = $crate::java::cache::find_class(env, $class)?;
+                let name = match self {
+                    $(
+                        $native_type::$variant => {
+                            $crate::gen_java_struct_converter!(@name $variant $($java_name)?)
+                        }
+                    )+
+                };
+
+                env.get_static_field(
+                    jni::objects::JClass::from(class.as_obj().into_inner()),
+                    name,
+                    concat!("L", $class, ";"),
+                )?
+                .l()
+            }
+        }
+    };
+
+    ($native_type:ident, $class:literal, ordinal, { $($variant:ident),+ $(,)* }) => {
+        impl<'a> $crate::java::FromJava<jni::objects::JObject<'a>> for $native_type {
+            fn from_java(
+                env: &jni::JNIEnv,
+                input: jni::objects::JObject,
+            ) -> $crate::java::JniResult<Self> {
+                let ordinal = env.call_method(input, "ordinal", "()I", &[])?.i()?;
+
+                IntoIterator::into_iter([$($native_type::$variant),+])
+                    .nth(ordinal as usize)
+                    .ok_or_else(|| {
+                        jni::errors::Error::from(format!(
+                            "unknown {} ordinal: {}",
+                            stringify!($native_type),
+                            ordinal
+                        ))
+                    })
+            }
+        }
+
+        impl<'a> $crate::java::ToJava<'a, jni::objects::JObject<'a>> for $native_type {
+            fn to_java(
+                &self,
+                env: &'a jni::JNIEnv,
+            ) -> $crate::java::JniResult<jni::objects::JObject<'a>> {
+                let ordinal = [$(std::mem::discriminant(&$native_type::$variant)),+]
+                    .iter()
+                    .position(|d| *d == std::mem::discriminant(self))
+                    .ok_or_else(|| {
+                        jni::errors::Error::from(format!(
+                            "{} variant not listed in gen_java_enum_converter!",
+                            stringify!($native_type)
+                        ))
+                    })?;
+
+                let class = $crate::java::cache::find_class(env, $class)?;
+                let values = env
+                    .call_static_method(
+                        jni::objects::JClass::from(class.as_obj().into_inner()),
+                        "values",
+                        concat!("()[L", $class, ";"),
+                        &[],
+                    )?
+                    .l()?;
+                let value = env.get_object_array_element(values.into_inner(), ordinal as i32);
+                env.delete_local_ref(values)?;
+
+                value
+            }
+        }
+    };
+}
@@ -0,0 +1,290 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::debug::{self, RefKind};
+use super::{EnvGuard, JniResult};
+use jni::errors::Error as JniError;
+use jni::objects::JObject;
+use jni::sys::jobject;
+use jni::{JNIEnv, JavaVM};
+use log::warn;
+use std::os::raw::c_void;
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(debug_assertions)]
+static OUTSTANDING: AtomicUsize = AtomicUsize::new(0);
+
+/// `user_data` context holding global references to one or more Java callbacks.
+///
+/// Create it with `gen_callback_ctx!` (or `CallbackCtx::new` followed by `into_raw`), borrow it
+/// from intermediate callbacks with `from_raw`, and release it from the final callback with
+/// `free_ctx`. Unlike the contexts produced by `gen_ctx!`, the global references are deleted
+/// using the `JNIEnv` of the releasing thread, and contexts which are never released can be
+/// detected with `outstanding_contexts` in debug builds.
+pub struct CallbackCtx {
+    vm: JavaVM,
+    callbacks: Vec<jobject>,
+}
+
+// Global references are valid on every thread.
+unsafe impl Send for CallbackCtx {}
+unsafe impl Sync for CallbackCtx {}
+
+impl CallbackCtx {
+    /// Create global references to the given callback objects.
+    pub fn new(env: &JNIEnv, callbacks: &[JObject]) -> JniResult<Self> {
+        let mut ctx = Self {
+            vm: env.get_java_vm()?,
+            callbacks: Vec::with_capacity(callbacks.len()),
+        };
+
+        for cb in callbacks {
+            let global = unsafe { new_global_ref(env, cb.into_inner()) };
+            match global {
+                Ok(global) => {
+                    debug::ref_created(RefKind::CallbackCtx, 1);
+                    ctx.callbacks.push(global)
+                }
+                Err(e) => {
+                    ctx.release(env);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(ctx)
+    }
+
+    /// Number of callbacks in the context.
+    pub fn len(&self) -> usize {
+        self.callbacks.len()
+    }
+
+    /// Return `true` if the context holds no callbacks.
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+
+    /// Return the callback object at `index`.
+    pub fn callback(&self, index: usize) -> Option<JObject<'_>> {
+        self.callbacks.get(index).map(|cb| JObject::from(*cb))
+    }
+
+    /// Transfer the context into a `user_data` pointer.
+    pub fn into_raw(self) -> *mut c_void {
+        #[cfg(debug_assertions)]
+        let _ = OUTSTANDING.fetch_add(1, Ordering::Relaxed);
+
+        Box::into_raw(Box::new(self)) as *mut c_void
+    }
+
+    /// Borrow the context behind a `user_data` pointer without releasing it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `into_raw` and not yet passed to `from_raw_owned` or
+    /// `free_ctx`.
+    pub unsafe fn from_raw<'a>(ptr: *mut c_void) -> &'a Self {
+        &*(ptr as *const Self)
+    }
+
+    /// Take back ownership of the context behind a `user_data` pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `into_raw`, and must not be used afterwards.
+    pub unsafe fn from_raw_owned(ptr: *mut c_void) -> Self {
+        #[cfg(debug_assertions)]
+        let _ = OUTSTANDING.fetch_sub(1, Ordering::Relaxed);
+
+        *Box::from_raw(ptr as *mut Self)
+    }
+
+    /// Delete the global references using the given `JNIEnv`.
+    pub fn release(mut self, env: &JNIEnv) {
+        debug::ref_released(RefKind::CallbackCtx, self.callbacks.len());
+        for cb in self.callbacks.drain(..) {
+            unsafe { delete_global_ref(env, cb) };
+        }
+    }
+}
+
+impl Drop for CallbackCtx {
+    fn drop(&mut self) {
+        if self.callbacks.is_empty() {
+            return;
+        }
+
+        warn!("CallbackCtx dropped without being released");
+
+        let callbacks = std::mem::take(&mut self.callbacks);
+        debug::ref_released(RefKind::CallbackCtx, callbacks.len());
+        match EnvGuard::new(Some(&self.vm)) {
+            Ok(guard) => {
+                for cb in callbacks {
+                    unsafe { delete_global_ref(guard.env(), cb) };
+                }
+            }
+            Err(e) => warn!("Leaking {} global refs: {:?}", callbacks.len(), e),
+        }
+    }
+}
+
+/// Release a context created by `CallbackCtx::into_raw`. Call this from the final callback.
+///
+/// # Safety
+///
+/// `ctx` must have been returned by `CallbackCtx::into_raw`, and must not be used afterwards.
+pub unsafe fn free_ctx(env: &JNIEnv, ctx: *mut c_void) {
+    CallbackCtx::from_raw_owned(ctx).release(env)
+}
+
+/// Number of contexts created with `CallbackCtx::into_raw` which have not been released yet.
+///
+/// Only tracked in debug builds; always returns 0 in release builds.
+pub fn outstanding_contexts() -> usize {
+    #[cfg(debug_assertions)]
+    {
+        OUTSTANDING.load(Ordering::Relaxed)
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        0
+    }
+}
+
+unsafe fn new_global_ref(env: &JNIEnv, obj: jobject) -> JniResult<jobject> {
+    let raw_env = env.get_native_interface();
+    let new_global_ref = (**raw_env)
+        .NewGlobalRef
+        .ok_or_else(|| JniError::from("NewGlobalRef not available"))?;
+
+    let global = new_global_ref(raw_env, obj);
+    if global.is_null() && !obj.is_null() {
+        Err(JniError::from("NewGlobalRef failed"))
+    } else {
+        Ok(global)
+    }
+}
+
+unsafe fn delete_global_ref(env: &JNIEnv, obj: jobject) {
+    let raw_env = env.get_native_interface();
+    if let Some(delete_global_ref) = (**raw_env).DeleteGlobalRef {
+        delete_global_ref(raw_env, obj);
+    }
+}
+
+/// Generates a `user_data` context (`CallbackCtx`) containing references to one or several Java
+/// callbacks. Release it with `java::free_ctx` from the final callback.
+#[macro_export]
+macro_rules! gen_callback_ctx {
+    ($env:ident, $($cb:ident),+ ) => {
+        $crate::jni_unwrap!($crate::java::CallbackCtx::new(&$env, &[$($cb.into()),+])).into_raw()
+    };
+}
+
+/// Declares a `user_data` context type whose callbacks are accessed by name rather than by
+/// position:
+///
+/// ```ignore
+/// gen_named_callback_ctx! {
+///     /// Callbacks of `fetch`.
+///     pub struct FetchCtx { on_data, on_done }
+/// }
+///
+/// let ctx = jni_unwrap!(FetchCtx::new(&env, on_data, on_done)).into_raw();
+/// // ...
+/// call_java_cb_final!(FetchCtx::on_done(user_data), interface, "call", "()V");
+/// ```
+///
+/// The type wraps a `CallbackCtx`, so it is released like one, with `free_ctx` or
+/// `call_java_cb_final!`.
+#[macro_export]
+macro_rules! gen_named_callback_ctx {
+    ($(#[$attr:meta])* $vis:vis struct $name:ident { $($field:ident),+ $(,)* }) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        $vis struct $name($crate::java::CallbackCtx);
+
+        #[allow(dead_code)]
+        impl $name {
+            /// Create global references to the callback objects.
+            pub fn new(
+                env: &jni::JNIEnv,
+                $($field: jni::objects::JObject,)+
+            ) -> $crate::java::JniResult<Self> {
+                $crate::java::CallbackCtx::new(env, &[$($field),+]).map($name)
+            }
+
+            $crate::gen_named_callback_ctx!(@accessors 0usize; $($field),+);
+
+            /// Transfer the context into a `user_data` pointer.
+            pub fn into_raw(self) -> *mut std::os::raw::c_void {
+                self.0.into_raw()
+            }
+
+            /// Borrow the context behind a `user_data` pointer without releasing it.
+            ///
+            /// # Safety
+            ///
+            /// See `CallbackCtx::from_raw`.
+            pub unsafe fn from_raw<'a>(ptr: *mut std::os::raw::c_void) -> &'a Self {
+                Self::wrap($crate::java::CallbackCtx::from_raw(ptr))
+            }
+
+            /// Invoke the callback picked by `select` (e.g. `Self::on_data`); see
+            /// `java::invoke_callback`.
+            ///
+            /// # Safety
+            ///
+            /// See `java::invoke_callback`.
+            pub unsafe fn invoke<S, F>(ptr: *mut std::os::raw::c_void, select: S, f: F)
+            where
+                S: FnOnce(&Self) -> jni::objects::JObject,
+                F: FnOnce(&jni::JNIEnv, jni::objects::JObject) -> $crate::java::JniResult<()>,
+            {
+                $crate::java::invoke_selected_callback(ptr, |ctx| Some(select(Self::wrap(ctx))), f)
+            }
+
+            /// Same as `invoke`, but releases the context afterwards.
+            ///
+            /// # Safety
+            ///
+            /// See `java::invoke_final_callback`.
+            pub unsafe fn invoke_final<S, F>(ptr: *mut std::os::raw::c_void, select: S, f: F)
+            where
+                S: FnOnce(&Self) -> jni::objects::JObject,
+                F: FnOnce(&jni::JNIEnv, jni::objects::JObject) -> $crate::java::JniResult<()>,
+            {
+                $crate::java::invoke_selected_final_callback(
+                    ptr,
+                    |ctx| Some(select(Self::wrap(ctx))),
+                    f,
+                )
+            }
+
+            fn wrap(ctx: &$crate::java::CallbackCtx) -> &Self {
+                // Sound thanks to `#[repr(transparent)]`.
+                unsafe { &*(ctx as *const $crate::java::CallbackCtx as *const Self) }
+            }
+        }
+    };
+
+    (@accessors $idx:expr; $field:ident $(, $rest:ident)*) => {
+        /// Return the callback object.
+        pub fn $field(&self) -> jni::objects::JObject<'_> {
+            self.0.callback($idx).unwrap_or_else(jni::objects::JObject::null)
+        }
+
+        $crate::gen_named_callback_ctx!(@accessors $idx + 1; $($rest),*);
+    };
+
+    (@accessors $idx:expr;) => {};
+}
@@ -0,0 +1,84 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Tracking of JVM references held by the crate's callback contexts.
+//!
+//! In debug builds, every global reference created through `gen_ctx!`, `CallbackCtx` or
+//! `WeakCallback` is counted until it is released (by `convert_cb_from_java`, `free_multi_ctx`,
+//! `free_ctx` or dropping the owning context). Binding test suites can assert that
+//! `outstanding_refs().total()` returns to its initial value once all operations completed.
+//! Release builds do not track anything and always report zero.
+
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of outstanding global references, by the kind of context holding them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OutstandingRefs {
+    /// References created by `gen_ctx!`.
+    pub gen_ctx: usize,
+    /// References held by `CallbackCtx`s.
+    pub callback_ctx: usize,
+    /// Weak references held by `WeakCallback`s.
+    pub weak: usize,
+}
+
+impl OutstandingRefs {
+    /// Total number of outstanding references.
+    pub fn total(&self) -> usize {
+        self.gen_ctx + self.callback_ctx + self.weak
+    }
+}
+
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug)]
+pub enum RefKind {
+    GenCtx,
+    CallbackCtx,
+    Weak,
+}
+
+#[cfg(debug_assertions)]
+static COUNTERS: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Return the number of outstanding global references.
+pub fn outstanding_refs() -> OutstandingRefs {
+    #[cfg(debug_assertions)]
+    {
+        OutstandingRefs {
+            gen_ctx: COUNTERS[RefKind::GenCtx as usize].load(Ordering::Relaxed),
+            callback_ctx: COUNTERS[RefKind::CallbackCtx as usize].load(Ordering::Relaxed),
+            weak: COUNTERS[RefKind::Weak as usize].load(Ordering::Relaxed),
+        }
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        OutstandingRefs::default()
+    }
+}
+
+#[doc(hidden)]
+pub fn ref_created(kind: RefKind, count: usize) {
+    #[cfg(debug_assertions)]
+    let _ = COUNTERS[kind as usize].fetch_add(count, Ordering::Relaxed);
+    #[cfg(not(debug_assertions))]
+    let _ = (kind, count);
+}
+
+#[doc(hidden)]
+pub fn ref_released(kind: RefKind, count: usize) {
+    #[cfg(debug_assertions)]
+    let _ = COUNTERS[kind as usize].fetch_sub(count, Ordering::Relaxed);
+    #[cfg(not(debug_assertions))]
+    let _ = (kind, count);
+}
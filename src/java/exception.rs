@@ -0,0 +1,185 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::{cache, JniResult};
+use crate::{ffi_error, ErrorCode, NativeResult};
+use jni::errors::Error as JniError;
+use jni::objects::{JClass, JObject};
+use jni::JNIEnv;
+use std::fmt::{Debug, Display};
+use std::ops::RangeInclusive;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+/// Exception class thrown for error codes that are not covered by any mapping.
+pub const DEFAULT_EXCEPTION_CLASS: &str = "java/lang/RuntimeException";
+
+/// Mapping from ranges of error codes to the Java exception classes thrown for them.
+#[derive(Clone, Debug)]
+pub struct ExceptionMapping {
+    ranges: Vec<(RangeInclusive<i32>, String)>,
+    default: String,
+}
+
+impl Default for ExceptionMapping {
+    fn default() -> Self {
+        Self::new(DEFAULT_EXCEPTION_CLASS)
+    }
+}
+
+impl ExceptionMapping {
+    /// Create a mapping which throws `default_class` for every error code.
+    pub fn new(default_class: &str) -> Self {
+        Self {
+            ranges: Vec::new(),
+            default: default_class.to_owned(),
+        }
+    }
+
+    /// Throw `class` (e.g. `java/io/IOException`) for error codes in `codes`. Ranges are matched
+    /// in the order they were added.
+    pub fn map(mut self, codes: RangeInclusive<i32>, class: &str) -> Self {
+        self.ranges.push((codes, class.to_owned()));
+        self
+    }
+
+    /// Return the exception class for the given error code.
+    pub fn class_for(&self, error_code: i32) -> &str {
+        self.ranges
+            .iter()
+            .find(|(codes, _)| codes.contains(&error_code))
+            .map(|(_, class)| class.as_str())
+            .unwrap_or(&self.default)
+    }
+}
+
+fn mapping() -> &'static RwLock<ExceptionMapping> {
+    static MAPPING: OnceLock<RwLock<ExceptionMapping>> = OnceLock::new();
+    MAPPING.get_or_init(Default::default)
+}
+
+/// Replace the process-wide error code to exception class mapping.
+pub fn set_exception_mapping(new_mapping: ExceptionMapping) {
+    *mapping().write().unwrap_or_else(PoisonError::into_inner) = new_mapping;
+}
+
+/// Throw the Java exception corresponding to `result.error_code`, with the error description
+/// and code as its message.
+///
+/// The exception becomes pending in `env`; the native method should return immediately
+/// afterwards.
+pub fn throw_ffi_error(env: &JNIEnv, result: &NativeResult) -> JniResult<()> {
+    let (class_name, msg) = exception_parts(result);
+    let class = cache::find_class(env, &class_name)?;
+
+    env.throw_new(JClass::from(class.as_obj()), msg)
+}
+
+/// Create, without throwing it, the Java exception `throw_ffi_error` would throw.
+///
+/// Useful to fail a `CompletableFuture` or to hand the error to a callback.
+pub fn new_ffi_exception<'a>(env: &'a JNIEnv, result: &NativeResult) -> JniResult<JObject<'a>> {
+    let (class_name, msg) = exception_parts(result);
+    new_exception(env, &class_name, &msg)
+}
+
+pub(super) fn new_exception<'a>(
+    env: &'a JNIEnv,
+    class_name: &str,
+    msg: &str,
+) -> JniResult<JObject<'a>> {
+    let class = cache::find_class(env, class_name)?;
+    let msg = env.new_string(msg)?;
+    let exception = env.new_object(
+        JClass::from(class.as_obj()),
+        "(Ljava/lang/String;)V",
+        &[JObject::from(msg).into()],
+    )?;
+    env.delete_local_ref(msg.into())?;
+
+    Ok(exception)
+}
+
+fn exception_parts(result: &NativeResult) -> (String, String) {
+    let class_name = mapping()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .class_for(result.error_code)
+        .to_owned();
+    let msg = format!(
+        "{} (error code {})",
+        result.description.as_deref().unwrap_or_default(),
+        result.error_code
+    );
+
+    (class_name, msg)
+}
+
+/// Throw the Java exception corresponding to the given error.
+pub fn throw_error<E>(env: &JNIEnv, err: &E) -> JniResult<()>
+where
+    E: Debug + Display + ErrorCode,
+{
+    let (error_code, description) = ffi_error!(err);
+    throw_ffi_error(
+        env,
+        &NativeResult {
+            error_code,
+            description: Some(description),
+        },
+    )
+}
+
+/// Throw an exception carrying the text of a JNI error, using the default class of the
+/// exception mapping.
+///
+/// Nothing is thrown if a Java exception is already pending (e.g. because `err` was caused by
+/// it), so that the original exception reaches the caller.
+pub fn throw_jni_error(env: &JNIEnv, err: &JniError) -> JniResult<()> {
+    if env.exception_check()? {
+        return Ok(());
+    }
+
+    let class_name = mapping()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .default
+        .clone();
+    let class = cache::find_class(env, &class_name)?;
+
+    env.throw_new(JClass::from(class.as_obj()), err.to_string())
+}
+
+/// Unwraps the results like `jni_unwrap!`, but throws a Java exception describing the error
+/// (see `java::throw_jni_error`) before returning, so that the Java caller sees the failure.
+///
+/// Native methods with a return value have to provide the value to return on error:
+///
+/// ```ignore
+/// let value = jni_unwrap_or_throw!(env, String::from_java(&env, name), ptr::null_mut());
+/// ```
+#[macro_export]
+macro_rules! jni_unwrap_or_throw {
+    ($env:expr, $res:expr) => {
+        $crate::jni_unwrap_or_throw!($env, $res, ())
+    };
+
+    ($env:expr, $res:expr, $ret:expr) => {{
+        let res: Result<_, jni::errors::Error> = $res;
+        match res {
+            Ok(val) => val,
+            Err(e) => {
+                log::error!("{:?}", e);
+                if let Err(e) = $crate::java::throw_jni_error(&$env, &e) {
+                    log::error!("Failed to throw exception: {:?}", e);
+                }
+                return $ret;
+            }
+        }
+    }};
+}
@@ -0,0 +1,57 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::JniResult;
+use jni::objects::JObject;
+use jni::JNIEnv;
+
+// Pops the frame with a `null` result unless `pop` has been called, so that the frame is
+// popped on early returns and panics alike.
+struct FrameGuard<'e, 'a: 'e> {
+    env: &'e JNIEnv<'a>,
+    popped: bool,
+}
+
+impl<'e, 'a> FrameGuard<'e, 'a> {
+    fn pop(mut self, result: JObject<'a>) -> JniResult<JObject<'a>> {
+        self.popped = true;
+        self.env.pop_local_frame(result)
+    }
+}
+
+impl<'e, 'a> Drop for FrameGuard<'e, 'a> {
+    fn drop(&mut self) {
+        if !self.popped {
+            let _ = self.env.pop_local_frame(JObject::null());
+        }
+    }
+}
+
+/// Run `f` inside a new local reference frame with room for at least `capacity` references.
+///
+/// All local references created by `f` are freed when it returns, except for the returned
+/// object, which is moved into the enclosing frame (return `JObject::null()` if there is
+/// nothing to keep). Unlike `JNIEnv::with_local_frame`, the frame is also popped if `f` panics.
+///
+/// Use this around loop bodies which create local references, as JVMs only guarantee room for
+/// 16 of them per native call and some abort after 512.
+pub fn with_local_frame<'e, 'a, F>(
+    env: &'e JNIEnv<'a>,
+    capacity: i32,
+    f: F,
+) -> JniResult<JObject<'a>>
+where
+    F: FnOnce(&'e JNIEnv<'a>) -> JniResult<JObject<'a>>,
+{
+    env.push_local_frame(capacity)?;
+    let guard = FrameGuard { env, popped: false };
+
+    let result = f(env)?;
+    guard.pop(result)
+}
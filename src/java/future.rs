@@ -0,0 +1,129 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Bridging of the callback convention to `java.util.concurrent.CompletableFuture`.
+//!
+//! Instead of taking a callback object, a native method can return a future which the final
+//! native callback completes, giving Kotlin and Java consumers an awaitable API:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "system" fn Java_net_maidsafe_Client_fetch(env: JNIEnv, _class: JClass) -> jobject {
+//!     let (future, user_data) = jni_unwrap_or_throw!(env, new_future(&env), ptr::null_mut());
+//!     unsafe { fetch(user_data, fetch_cb) };
+//!     future.into_inner()
+//! }
+//!
+//! extern "C" fn fetch_cb(user_data: *mut c_void, result: *const FfiResult, len: u64) {
+//!     unsafe { complete_future(user_data, result, |env| len.to_java(env)) };
+//! }
+//! ```
+
+use super::exception::{new_exception, new_ffi_exception, DEFAULT_EXCEPTION_CLASS};
+use super::{cache, on_load, EnvGuard, JniResult};
+use crate::{FfiResult, NativeResult, ReprC};
+use jni::objects::{GlobalRef, JClass, JObject};
+use jni::JNIEnv;
+use log::error;
+use std::os::raw::c_void;
+
+const COMPLETABLE_FUTURE: &str = "java/util/concurrent/CompletableFuture";
+
+// `user_data` context holding the future to be completed by the final callback.
+struct FutureCtx {
+    future: GlobalRef,
+}
+
+/// Create a new `CompletableFuture`. Returns the future, to be returned to the Java caller, and
+/// the `user_data` pointer to pass to the native function, to be released by
+/// `complete_future`.
+pub fn new_future<'a>(env: &'a JNIEnv) -> JniResult<(JObject<'a>, *mut c_void)> {
+    let class = cache::find_class(env, COMPLETABLE_FUTURE)?;
+    let future = env.new_object(JClass::from(class.as_obj()), "()V", &[])?;
+    let ctx = FutureCtx {
+        future: env.new_global_ref(future)?,
+    };
+
+    Ok((future, Box::into_raw(Box::new(ctx)) as *mut c_void))
+}
+
+/// Complete the future behind `ctx`, a pointer returned by `new_future`, and release it.
+///
+/// If `result` holds an error, the future is completed exceptionally with the exception
+/// `throw_ffi_error` would throw. Otherwise it is completed with the object returned by `f`
+/// (`null` for `Void` futures), or exceptionally if `f` fails.
+///
+/// # Safety
+///
+/// `ctx` must have been returned by `new_future`, and must not be used afterwards. `result`
+/// must point to a valid `FfiResult`.
+pub unsafe fn complete_future<F>(ctx: *mut c_void, result: *const FfiResult, f: F)
+where
+    F: for<'a> FnOnce(&'a JNIEnv) -> JniResult<JObject<'a>>,
+{
+    let ctx = Box::from_raw(ctx as *mut FutureCtx);
+
+    let guard = match EnvGuard::new(on_load::vm()) {
+        Ok(guard) => guard,
+        Err(e) => {
+            error!("Failed to obtain JNIEnv for future: {:?}", e);
+            return;
+        }
+    };
+    let env = guard.env();
+
+    if let Err(e) = complete(env, ctx.future.as_obj(), result, f) {
+        error!("Failed to complete future: {:?}", e);
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_describe();
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+unsafe fn complete<F>(
+    env: &JNIEnv,
+    future: JObject,
+    result: *const FfiResult,
+    f: F,
+) -> JniResult<()>
+where
+    F: for<'a> FnOnce(&'a JNIEnv) -> JniResult<JObject<'a>>,
+{
+    let result = NativeResult::clone_from_repr_c(result).unwrap_or_else(|e| NativeResult {
+        error_code: -1,
+        description: Some(format!("Invalid FfiResult: {:?}", e)),
+    });
+
+    let exception = if result.error_code != 0 {
+        new_ffi_exception(env, &result)?
+    } else {
+        match f(env) {
+            Ok(value) => {
+                let _ =
+                    env.call_method(future, "complete", "(Ljava/lang/Object;)Z", &[value.into()])?;
+                return Ok(());
+            }
+            Err(e) => {
+                if env.exception_check()? {
+                    env.exception_clear()?;
+                }
+                new_exception(env, DEFAULT_EXCEPTION_CLASS, &e.to_string())?
+            }
+        }
+    };
+
+    let _ = env.call_method(
+        future,
+        "completeExceptionally",
+        "(Ljava/lang/Throwable;)Z",
+        &[exception.into()],
+    )?;
+    Ok(())
+}
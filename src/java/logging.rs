@@ -0,0 +1,182 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Forwarding of Rust log records to the Java side.
+//!
+//! Installs a `log::Log` implementation which writes to `android.util.Log` on Android and to
+//! `java.util.logging` elsewhere, so that frontend developers can see the crate's diagnostics
+//! (e.g. the `ffi_error_code!` output) in their usual tooling. The library must have been
+//! loaded through `jni_on_load!`, which provides the `JavaVM`.
+
+use super::{cache, on_load, with_local_frame, EnvGuard, JniResult};
+use jni::objects::{JClass, JObject};
+use jni::signature::{JavaType, Primitive};
+use jni::JNIEnv;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+
+thread_local! {
+    // Set while a record is being forwarded, so that records logged by the JNI layer itself
+    // are dropped instead of recursing.
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Logger forwarding records to Java.
+pub struct JavaLogger {
+    tag: String,
+    level: LevelFilter,
+}
+
+impl JavaLogger {
+    /// Create a logger which forwards records up to `level`, tagged with `tag` (the Android log
+    /// tag, or the `java.util.logging.Logger` name).
+    pub fn new(tag: &str, level: LevelFilter) -> Self {
+        Self {
+            tag: tag.to_owned(),
+            level,
+        }
+    }
+
+    fn forward(&self, record: &Record) -> JniResult<()> {
+        // Threads logging from outside the JVM are attached as daemons, so that they don't keep it
+        // from exiting.
+        let guard = EnvGuard::new_daemon(on_load::vm())?;
+        let env = guard.env();
+        let msg = format!("{}: {}", record.target(), record.args());
+
+        let res = with_local_frame(env, 8, |env| {
+            write(env, &self.tag, record.level(), &msg)?;
+            Ok(JObject::null())
+        });
+        if res.is_err() && env.exception_check().unwrap_or(false) {
+            let _ = env.exception_clear();
+        }
+
+        res.map(|_| ())
+    }
+}
+
+impl Log for JavaLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) || FORWARDING.with(Cell::get) {
+            return;
+        }
+
+        FORWARDING.with(|f| f.set(true));
+        // Errors can't be reported without going through this logger again, so they are dropped.
+        // Panics are caught as well, as logging may happen inside `extern "C"` functions.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| self.forward(record)));
+        FORWARDING.with(|f| f.set(false));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install a `JavaLogger` as the global logger.
+pub fn init(tag: &str, level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(Box::leak(Box::new(JavaLogger::new(tag, level))))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(target_os = "android")]
+fn write(env: &JNIEnv, tag: &str, level: Level, msg: &str) -> JniResult<()> {
+    const LOG: &str = "android/util/Log";
+
+    // Priority constants of `android.util.Log`.
+    let priority: jni::sys::jint = match level {
+        Level::Error => 6,
+        Level::Warn => 5,
+        Level::Info => 4,
+        Level::Debug => 3,
+        Level::Trace => 2,
+    };
+
+    let class = cache::find_class(env, LOG)?;
+    let id = cache::static_method_id(
+        env,
+        LOG,
+        "println",
+        "(ILjava/lang/String;Ljava/lang/String;)I",
+    )?;
+    let tag = env.new_string(tag)?;
+    let msg = env.new_string(msg)?;
+
+    let _ = env.call_static_method_unchecked(
+        JClass::from(class.as_obj().into_inner()),
+        id,
+        JavaType::Primitive(Primitive::Int),
+        &[
+            priority.into(),
+            JObject::from(tag).into(),
+            JObject::from(msg).into(),
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "android"))]
+fn write(env: &JNIEnv, tag: &str, level: Level, msg: &str) -> JniResult<()> {
+    const LEVEL: &str = "java/util/logging/Level";
+    const LOGGER: &str = "java/util/logging/Logger";
+
+    let level_name = match level {
+        Level::Error => "SEVERE",
+        Level::Warn => "WARNING",
+        Level::Info => "INFO",
+        Level::Debug => "FINE",
+        Level::Trace => "FINEST",
+    };
+
+    let level_class = cache::find_class(env, LEVEL)?;
+    let level = env
+        .get_static_field(
+            JClass::from(level_class.as_obj().into_inner()),
+            level_name,
+            "Ljava/util/logging/Level;",
+        )?
+        .l()?;
+
+    let logger_class = cache::find_class(env, LOGGER)?;
+    let get_logger = cache::static_method_id(
+        env,
+        LOGGER,
+        "getLogger",
+        "(Ljava/lang/String;)Ljava/util/logging/Logger;",
+    )?;
+    let tag = env.new_string(tag)?;
+    let logger = env
+        .call_static_method_unchecked(
+            JClass::from(logger_class.as_obj().into_inner()),
+            get_logger,
+            JavaType::Object(LOGGER.to_owned()),
+            &[JObject::from(tag).into()],
+        )?
+        .l()?;
+
+    let log = cache::method_id(
+        env,
+        LOGGER,
+        "log",
+        "(Ljava/util/logging/Level;Ljava/lang/String;)V",
+    )?;
+    let msg = env.new_string(msg)?;
+    let _ = env.call_method_unchecked(
+        logger,
+        log,
+        JavaType::Primitive(Primitive::Void),
+        &[level.into(), JObject::from(msg).into()],
+    )?;
+    Ok(())
+}
@@ -9,6 +9,49 @@
 
 //! Java/JNI utilities.
 
+pub mod cache;
+pub mod debug;
+pub mod logging;
+
+mod attach;
+mod byte_buffer;
+mod callback;
+mod collections;
+mod convert;
+mod ctx;
+mod exception;
+mod frame;
+mod future;
+mod mutf8;
+mod on_load;
+mod result;
+mod sig;
+mod weak;
+
+pub use self::byte_buffer::{
+    byte_buffer_as_mut_slice, byte_buffer_as_slice, slice_to_byte_buffer, DirectByteBuffer,
+};
+pub use self::callback::{
+    call_callback, invoke_callback, invoke_final_callback, invoke_selected_callback,
+    invoke_selected_final_callback,
+};
+pub use self::convert::{to_jvalue, FromJValue, FromJava, IntoJValue, ToJava};
+pub use self::ctx::{free_ctx, outstanding_contexts, CallbackCtx};
+pub use self::exception::{
+    new_ffi_exception, set_exception_mapping, throw_error, throw_ffi_error, throw_jni_error,
+    ExceptionMapping, DEFAULT_EXCEPTION_CLASS,
+};
+pub use self::frame::with_local_frame;
+pub use self::future::{complete_future, new_future};
+pub use self::mutf8::{from_modified_utf8, to_modified_utf8};
+pub use self::on_load::{catch_on_load, on_load, vm, NativeMethod};
+pub use self::result::{
+    set_ffi_exception_class, set_ffi_result_class, FfiException, DEFAULT_FFI_EXCEPTION_CLASS,
+    DEFAULT_FFI_RESULT_CLASS,
+};
+pub use self::sig::is_valid_signature;
+pub use self::weak::{invoke_weak_callback, WeakCallback};
+
 use jni::errors::Error as JniError;
 use jni::objects::{AutoLocal, GlobalRef, JObject};
 use jni::sys::{jobject, jsize};
@@ -22,6 +65,9 @@ pub type JniResult<T> = Result<T, JniError>;
 /// of a Java thread, we just reuse it (`Auto`). If we are in the context of a
 /// native thread, then we will attach it to JVM by calling `attach_current_thread`
 /// and it will be automatically detached when it goes out of scope (`Manual`).
+///
+/// Threads which call into Java repeatedly should use `EnvGuard::new_cached` or
+/// `EnvGuard::new_daemon` instead, which keep the thread attached until it exits.
 pub enum EnvGuard<'a> {
     /// Automatically obtained `JNIEnv`. We do not need to detach it.
     Auto(JNIEnv<'a>),
@@ -43,10 +89,10 @@ impl<'a> EnvGuard<'a> {
     }
 
     /// Return `JNIEnv` that we obtained.
-    pub fn env(&self) -> &JNIEnv {
+    pub fn env(&self) -> &JNIEnv<'a> {
         match self {
-            EnvGuard::Auto(env) => &env,
-            EnvGuard::Manual(guard) => &*guard,
+            EnvGuard::Auto(env) => env,
+            EnvGuard::Manual(guard) => guard,
         }
     }
 }
@@ -70,11 +116,17 @@ macro_rules! jni_unwrap {
 }
 
 /// Generates a `user_data` context containing a reference to a single or several Java callbacks.
+///
+/// Single-callback contexts are released by dropping the result of `convert_cb_from_java`, and
+/// multi-callback ones with `free_multi_ctx`. Prefer `gen_callback_ctx!`, whose contexts are
+/// freed with `free_ctx` once the final callback has been invoked, or `gen_named_callback_ctx!`
+/// when there are several callbacks.
 #[macro_export]
 macro_rules! gen_ctx {
     ($env:ident, $cb:ident) => {
         {
             let ctx = $crate::jni_unwrap!($env.new_global_ref($cb));
+            $crate::java::debug::ref_created($crate::java::debug::RefKind::GenCtx, 1);
             let ptr = *ctx.as_obj() as *mut c_void;
             mem::forget(ctx);
             ptr
@@ -89,6 +141,7 @@ macro_rules! gen_ctx {
                     Some($crate::jni_unwrap!($env.new_global_ref($cb_rest))),
                 )+
             ];
+            $crate::java::debug::ref_created($crate::java::debug::RefKind::GenCtx, ctx.len());
             let ctx = Box::into_raw(Box::new(ctx)) as *mut c_void;
             ctx
         }
@@ -100,12 +153,14 @@ macro_rules! gen_ctx {
 macro_rules! gen_primitive_type_converter {
     ($native_type:ty, $java_type:ty) => {
         impl FromJava<$java_type> for $native_type {
+            #[allow(trivial_numeric_casts)]
             fn from_java(_env: &JNIEnv, input: $java_type) -> JniResult<Self> {
                 Ok(input as Self)
             }
         }
 
         impl<'a> ToJava<'a, $java_type> for $native_type {
+            #[allow(trivial_numeric_casts)]
             fn to_java(&self, _env: &JNIEnv) -> JniResult<$java_type> {
                 Ok(*self as $java_type)
             }
@@ -115,8 +170,18 @@ macro_rules! gen_primitive_type_converter {
 
 /// Generate a `ToJava` impl that converts a slice of structures (`&[Foo]`) into a Java object array
 /// (`Foo[]`).
+///
+/// If no class loader is given, the class is resolved through `java::cache::load_class`.
 #[macro_export]
 macro_rules! gen_object_array_converter {
+    ($native_type:ident, $java_ty_name:expr) => {
+        $crate::gen_object_array_converter!(
+            $crate::java::cache::load_class,
+            $native_type,
+            $java_ty_name
+        );
+    };
+
     ($class_loader:expr, $native_type:ident, $java_ty_name:expr) => {
         impl<'a, 'b> ToJava<'a, JObject<'a>> for &'b [$native_type] {
             fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
@@ -176,7 +241,8 @@ pub unsafe fn object_array_to_java<'a, T, U: Into<JObject<'a>> + 'a>(
     let output = env.new_object_array(list.len() as jsize, &cls, JObject::null())?;
 
     for (idx, entry) in list.iter().enumerate() {
-        let jentry = transform_fn(entry, env)?.into();
+        // Local references created by the conversion are freed for every entry.
+        let jentry = with_local_frame(env, 16, |env| Ok(transform_fn(entry, env)?.into()))?;
         env.set_object_array_element(output, idx as i32, jentry)?;
         env.delete_local_ref(jentry)?;
     }
@@ -187,5 +253,20 @@ pub unsafe fn object_array_to_java<'a, T, U: Into<JObject<'a>> + 'a>(
 /// Converts `user_data` back into a Java callback object
 #[allow(clippy::missing_safety_doc)]
 pub unsafe fn convert_cb_from_java(env: &JNIEnv, ctx: *mut c_void) -> JniResult<GlobalRef> {
-    Ok(GlobalRef::from_raw(env.get_java_vm()?, ctx as jobject))
+    let cb = GlobalRef::from_raw(env.get_java_vm()?, ctx as jobject);
+    debug::ref_released(debug::RefKind::GenCtx, 1);
+    Ok(cb)
+}
+
+/// Releases a context created by the multi-callback form of `gen_ctx!` with `N` callbacks.
+///
+/// # Safety
+///
+/// `ctx` must have been returned by `gen_ctx!` with exactly `N` callbacks, and must not be used
+/// afterwards.
+pub unsafe fn free_multi_ctx<const N: usize>(ctx: *mut c_void) {
+    drop(Box::from_raw(ctx as *mut [Option<GlobalRef>; N]));
+    // Callbacks taken out of the array have been dropped by their user in the meantime, or are
+    // dropped by them now, without going through the accounting. Count all of them here.
+    debug::ref_released(debug::RefKind::GenCtx, N);
 }
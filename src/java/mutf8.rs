@@ -0,0 +1,112 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Conversions to and from JNI's modified UTF-8.
+//!
+//! JNI functions such as `NewStringUTF` and `GetStringUTFChars` do not use standard UTF-8:
+//! `U+0000` is encoded as the two bytes `C0 80`, and characters outside the Basic Multilingual
+//! Plane (e.g. emoji) are encoded as a surrogate pair of three-byte sequences. Passing a C
+//! string straight through therefore mangles such characters. `FromJava`/`ToJava` for strings
+//! already convert correctly; these helpers are for code handling the raw bytes.
+
+use super::{JniResult, ToJava};
+use crate::StringError;
+use jni::errors::Error as JniError;
+use jni::objects::JObject;
+use jni::JNIEnv;
+use std::ffi::CStr;
+
+/// Encode `s` into modified UTF-8, without a terminating NUL.
+pub fn to_modified_utf8(s: &str) -> Vec<u8> {
+    let mut output = Vec::with_capacity(s.len());
+
+    for unit in s.encode_utf16() {
+        match unit {
+            0x01..=0x7F => output.push(unit as u8),
+            0x00 | 0x80..=0x7FF => {
+                output.push(0xC0 | (unit >> 6) as u8);
+                output.push(0x80 | (unit & 0x3F) as u8);
+            }
+            _ => {
+                output.push(0xE0 | (unit >> 12) as u8);
+                output.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                output.push(0x80 | (unit & 0x3F) as u8);
+            }
+        }
+    }
+
+    output
+}
+
+/// Decode modified UTF-8 bytes, without a terminating NUL, into a `String`.
+pub fn from_modified_utf8(bytes: &[u8]) -> Result<String, StringError> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let b0 = u16::from(bytes[i]);
+        let (unit, len) = match bytes[i] {
+            0x01..=0x7F => (b0, 1),
+            0xC0..=0xDF => (((b0 & 0x1F) << 6) | continuation(bytes, i + 1)?, 2),
+            0xE0..=0xEF => (
+                ((b0 & 0x0F) << 12)
+                    | (continuation(bytes, i + 1)? << 6)
+                    | continuation(bytes, i + 2)?,
+                3,
+            ),
+            _ => return Err(invalid(i)),
+        };
+        units.push(unit);
+        i += len;
+    }
+
+    String::from_utf16(&units).map_err(|e| StringError::Utf8(e.to_string()))
+}
+
+fn continuation(bytes: &[u8], i: usize) -> Result<u16, StringError> {
+    match bytes.get(i) {
+        Some(b) if b & 0xC0 == 0x80 => Ok(u16::from(b & 0x3F)),
+        _ => Err(invalid(i)),
+    }
+}
+
+fn invalid(i: usize) -> StringError {
+    StringError::Utf8(format!("invalid modified UTF-8 sequence at byte {}", i))
+}
+
+/// Converts a standard UTF-8 C string, such as a string received from a native callback.
+impl<'a> ToJava<'a, JObject<'a>> for CStr {
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        self.to_str()
+            .map_err(|e| JniError::from(e.to_string()))?
+            .to_java(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unwrap::unwrap;
+
+    #[test]
+    fn round_trip() {
+        let s = "a\u{0}é€😀";
+        let encoded = to_modified_utf8(s);
+        assert_eq!(
+            encoded,
+            [0x61, 0xC0, 0x80, 0xC3, 0xA9, 0xE2, 0x82, 0xAC, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80]
+        );
+        assert!(!encoded.contains(&0));
+        assert_eq!(unwrap!(from_modified_utf8(&encoded)), s);
+
+        // Standard UTF-8 encoding of the emoji.
+        assert!(from_modified_utf8(&[0xF0, 0x9F, 0x98, 0x80]).is_err());
+        assert!(from_modified_utf8(&[0xC3]).is_err());
+    }
+}
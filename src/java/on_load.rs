@@ -0,0 +1,176 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::{cache, JniResult};
+use crate::catch_unwind::panic_message;
+use jni::errors::Error as JniError;
+use jni::objects::JClass;
+use jni::sys::{self, jint, JNINativeMethod, JNI_ERR, JNI_VERSION_1_6};
+use jni::{JNIEnv, JavaVM};
+use log::error;
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::OnceLock;
+
+static JVM: OnceLock<JavaVM> = OnceLock::new();
+
+/// Return the `JavaVM` stored by `jni_on_load!`, if the library has been loaded by a JVM.
+///
+/// The result can be passed straight to `EnvGuard::new`.
+pub fn vm() -> Option<&'static JavaVM> {
+    JVM.get()
+}
+
+/// Native method to register with `RegisterNatives` during `JNI_OnLoad`.
+pub struct NativeMethod {
+    /// Name of the Java `native` method.
+    pub name: &'static str,
+    /// JNI signature of the method, e.g. `(J)V`.
+    pub sig: &'static str,
+    /// Pointer to the `extern "system"` implementation.
+    pub fn_ptr: *mut c_void,
+}
+
+// The function pointer is never written through, so the table can live in a `static`.
+unsafe impl Send for NativeMethod {}
+unsafe impl Sync for NativeMethod {}
+
+/// Implementation of `JNI_OnLoad` used by `jni_on_load!`.
+///
+/// Stores the `JavaVM` for `vm()`, populates the class cache with `classes` (capturing the
+/// class loader of the first one, which should be an application class) and registers
+/// `natives` (pairs of class name and its native methods). Returns the required JNI version, or
+/// `JNI_ERR` on failure after logging the reason.
+///
+/// # Safety
+///
+/// `vm` must be the pointer passed by the JVM to `JNI_OnLoad`.
+pub unsafe fn on_load(
+    vm: *mut sys::JavaVM,
+    classes: &[&str],
+    natives: &[(&str, &[NativeMethod])],
+) -> jint {
+    let res = (|| -> JniResult<()> {
+        let _ = JVM.set(JavaVM::from_raw(vm)?);
+        let vm = JVM
+            .get()
+            .ok_or_else(|| JniError::from("no JVM reference found"))?;
+        let env = vm.get_env()?;
+
+        cache::init(&env, classes)?;
+        for (class, methods) in natives {
+            register_natives(&env, class, methods)?;
+        }
+
+        Ok(())
+    })();
+
+    match res {
+        Ok(()) => JNI_VERSION_1_6,
+        Err(e) => {
+            error!("JNI_OnLoad failed: {:?}", e);
+            JNI_ERR
+        }
+    }
+}
+
+/// Run the body of `JNI_OnLoad`, returning `JNI_ERR` instead of unwinding into the JVM if it
+/// panics.
+pub fn catch_on_load<F: FnOnce() -> jint>(f: F) -> jint {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        error!("JNI_OnLoad panicked: {}", panic_message(&*payload));
+        JNI_ERR
+    })
+}
+
+fn register_natives(env: &JNIEnv, class: &str, methods: &[NativeMethod]) -> JniResult<()> {
+    let class: JClass = JClass::from(cache::find_class(env, class)?.as_obj().into_inner());
+
+    // Keep the C strings alive until `RegisterNatives` returns.
+    let names = methods
+        .iter()
+        .map(|m| CString::new(m.name).map_err(|e| JniError::from(e.to_string())))
+        .collect::<JniResult<Vec<_>>>()?;
+    let sigs = methods
+        .iter()
+        .map(|m| CString::new(m.sig).map_err(|e| JniError::from(e.to_string())))
+        .collect::<JniResult<Vec<_>>>()?;
+
+    let raw_methods: Vec<JNINativeMethod> = methods
+        .iter()
+        .zip(names.iter().zip(&sigs))
+        .map(|(m, (name, sig))| JNINativeMethod {
+            name: name.as_ptr() as *mut _,
+            signature: sig.as_ptr() as *mut _,
+            fnPtr: m.fn_ptr,
+        })
+        .collect();
+
+    let raw_env = env.get_native_interface();
+    let res = unsafe {
+        let register = (**raw_env)
+            .RegisterNatives
+            .ok_or_else(|| JniError::from("RegisterNatives not available"))?;
+        register(
+            raw_env,
+            class.into_inner(),
+            raw_methods.as_ptr(),
+            raw_methods.len() as jint,
+        )
+    };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(JniError::from(format!(
+            "RegisterNatives failed with code {}",
+            res
+        )))
+    }
+}
+
+/// Define `JNI_OnLoad` for the library.
+///
+/// The generated function stores the `JavaVM` (available afterwards through `java::vm()`),
+/// preloads the listed classes into the class cache and, optionally, registers native methods.
+/// The first class should belong to the application: its class loader is used to resolve
+/// classes missing from the cache, which makes lookups from native threads work on Android.
+/// A panic while doing so is logged and reported to the JVM as `JNI_ERR`.
+///
+/// ```ignore
+/// jni_on_load!(
+///     ["net/maidsafe/safe_app/FfiResult", "net/maidsafe/safe_app/File"],
+///     [("net/maidsafe/safe_app/NativeBindings", NATIVE_METHODS)]
+/// );
+/// ```
+#[macro_export]
+macro_rules! jni_on_load {
+    ([$($class:expr),* $(,)*]) => {
+        $crate::jni_on_load!([$($class),*], []);
+    };
+
+    ([$($class:expr),* $(,)*], [$(($native_class:expr, $methods:expr)),* $(,)*]) => {
+        /// Called by the JVM when the library is loaded.
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub unsafe extern "system" fn JNI_OnLoad(
+            vm: *mut jni::sys::JavaVM,
+            _reserved: *mut std::os::raw::c_void,
+        ) -> jni::sys::jint {
+            $crate::java::catch_on_load(|| {
+                $crate::java::on_load(
+                    vm,
+                    &[$($class),*],
+                    &[$(($native_class, &$methods[..])),*],
+                )
+            })
+        }
+    };
+}
@@ -0,0 +1,157 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Conversions of `NativeResult` into Java objects.
+//!
+//! A result is converted either into a plain result object (`FfiResult`, via `ToJava` for
+//! `NativeResult`), e.g. to pass it to a callback, or into an exception object (via
+//! `FfiException`), e.g. to throw it or to fail a future. The choice is made at each call site.
+
+use super::{cache, JniResult, ToJava};
+use crate::{ErrorCode, NativeResult};
+use jni::objects::{JClass, JObject};
+use jni::sys::jsize;
+use jni::JNIEnv;
+use std::error::Error;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+/// Default name of the Java class `NativeResult` is converted into.
+pub const DEFAULT_FFI_RESULT_CLASS: &str = "net/maidsafe/safe_app/FfiResult";
+
+/// Default name of the Java class `FfiException` is converted into.
+pub const DEFAULT_FFI_EXCEPTION_CLASS: &str = "net/maidsafe/safe_app/FfiException";
+
+fn ffi_result_class() -> &'static RwLock<String> {
+    static CLASS: OnceLock<RwLock<String>> = OnceLock::new();
+    CLASS.get_or_init(|| RwLock::new(DEFAULT_FFI_RESULT_CLASS.to_owned()))
+}
+
+/// Set the name of the Java class `NativeResult` is converted into. The class must have a no-arg
+/// constructor and the fields `int errorCode` and `String description`.
+pub fn set_ffi_result_class(name: &str) {
+    *ffi_result_class()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = name.to_owned();
+}
+
+impl<'a> ToJava<'a, JObject<'a>> for NativeResult {
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        let class_name = ffi_result_class()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let class = cache::find_class(env, &class_name)?;
+
+        let output = env.new_object(JClass::from(class.as_obj()), "()V", &[])?;
+        env.set_field(output, "errorCode", "I", self.error_code.into())?;
+
+        if let Some(ref description) = self.description {
+            let description = description.to_java(env)?;
+            env.set_field(
+                output,
+                "description",
+                "Ljava/lang/String;",
+                description.into(),
+            )?;
+            env.delete_local_ref(description)?;
+        }
+
+        Ok(output)
+    }
+}
+
+fn ffi_exception_class() -> &'static RwLock<String> {
+    static CLASS: OnceLock<RwLock<String>> = OnceLock::new();
+    CLASS.get_or_init(|| RwLock::new(DEFAULT_FFI_EXCEPTION_CLASS.to_owned()))
+}
+
+/// Set the name of the Java class `FfiException` is converted into. The class must have a
+/// constructor taking the error code, the description and the causes
+/// (`(ILjava/lang/String;[Ljava/lang/String;)V`).
+pub fn set_ffi_exception_class(name: &str) {
+    *ffi_exception_class()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = name.to_owned();
+}
+
+/// Error converted into a Java exception object, which can then be thrown or passed around.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FfiException {
+    /// Error code.
+    pub error_code: i32,
+    /// Error description.
+    pub description: String,
+    /// Descriptions of the underlying errors, outermost first.
+    pub causes: Vec<String>,
+}
+
+impl FfiException {
+    /// Build an exception from an error and the chain of its sources.
+    pub fn from_error<E: Error + ErrorCode>(err: &E) -> Self {
+        let mut causes = Vec::new();
+        let mut source = err.source();
+        while let Some(err) = source {
+            causes.push(err.to_string());
+            source = err.source();
+        }
+
+        Self {
+            error_code: err.error_code(),
+            description: err.to_string(),
+            causes,
+        }
+    }
+}
+
+impl<'r> From<&'r NativeResult> for FfiException {
+    fn from(result: &'r NativeResult) -> Self {
+        Self {
+            error_code: result.error_code,
+            description: result.description.clone().unwrap_or_default(),
+            causes: Vec::new(),
+        }
+    }
+}
+
+impl<'a> ToJava<'a, JObject<'a>> for FfiException {
+    fn to_java(&self, env: &'a JNIEnv) -> JniResult<JObject<'a>> {
+        let class_name = ffi_exception_class()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let class = cache::find_class(env, &class_name)?;
+        let string_class = cache::find_class(env, "java/lang/String")?;
+
+        let causes = env.new_object_array(
+            self.causes.len() as jsize,
+            JClass::from(string_class.as_obj()),
+            JObject::null(),
+        )?;
+        for (idx, cause) in self.causes.iter().enumerate() {
+            let cause = cause.to_java(env)?;
+            env.set_object_array_element(causes, idx as jsize, cause)?;
+            env.delete_local_ref(cause)?;
+        }
+
+        let description = self.description.to_java(env)?;
+        let output = env.new_object(
+            JClass::from(class.as_obj()),
+            "(ILjava/lang/String;[Ljava/lang/String;)V",
+            &[
+                self.error_code.into(),
+                description.into(),
+                JObject::from(causes).into(),
+            ],
+        )?;
+        env.delete_local_ref(description)?;
+        env.delete_local_ref(causes.into())?;
+
+        Ok(output)
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! JNI type signatures.
+
+/// Return `true` if `sig` is a valid JNI method signature (e.g. `(J[BLjava/lang/String;)V`) or
+/// field type signature (e.g. `[I`).
+///
+/// This is a `const fn`, which lets `jni_sig!` reject malformed signatures at compile time.
+pub const fn is_valid_signature(sig: &str) -> bool {
+    let bytes = sig.as_bytes();
+    if bytes.is_empty() {
+        return false;
+    }
+
+    let end = if bytes[0] == b'(' {
+        let mut i = 1;
+        while i < bytes.len() && bytes[i] != b')' {
+            match parse_type(bytes, i, false) {
+                Some(next) => i = next,
+                None => return false,
+            }
+        }
+        if i >= bytes.len() {
+            return false;
+        }
+        parse_type(bytes, i + 1, true)
+    } else {
+        parse_type(bytes, 0, false)
+    };
+
+    match end {
+        Some(end) => end == bytes.len(),
+        None => false,
+    }
+}
+
+// Parse the type starting at `i` and return the index following it.
+const fn parse_type(bytes: &[u8], mut i: usize, allow_void: bool) -> Option<usize> {
+    while i < bytes.len() && bytes[i] == b'[' {
+        i += 1;
+    }
+    if i >= bytes.len() {
+        return None;
+    }
+
+    match bytes[i] {
+        b'Z' | b'B' | b'C' | b'S' | b'I' | b'J' | b'F' | b'D' => Some(i + 1),
+        b'V' if allow_void && (i == 0 || bytes[i - 1] != b'[') => Some(i + 1),
+        b'L' => {
+            let start = i + 1;
+            i = start;
+            while i < bytes.len() && bytes[i] != b';' {
+                let c = bytes[i];
+                let valid = c.is_ascii_alphanumeric()
+                    || c == b'_'
+                    || c == b'$'
+                    || (c == b'/' && i > start && bytes[i - 1] != b'/');
+                if !valid {
+                    return None;
+                }
+                i += 1;
+            }
+            if i >= bytes.len() || i == start || bytes[i - 1] == b'/' {
+                None
+            } else {
+                Some(i + 1)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Build a JNI signature string from Java-like type names, checked at compile time.
+///
+/// Primitive types are written as in Java (`boolean`, `byte`, `char`, `short`, `int`, `long`,
+/// `float`, `double`, `void`), classes as string literals with their binary name, and arrays
+/// by wrapping the element type in brackets. `String` and `Object` are shorthands for the
+/// `java.lang` classes.
+///
+/// ```
+/// # use sn_ffi_utils::jni_sig;
+/// assert_eq!(jni_sig!((long, [byte], String) -> void), "(J[BLjava/lang/String;)V");
+/// assert_eq!(
+///     jni_sig!(() -> "net/maidsafe/safe_app/FfiResult"),
+///     "()Lnet/maidsafe/safe_app/FfiResult;"
+/// );
+/// assert_eq!(jni_sig!([[int]]), "[[I");
+/// ```
+///
+/// Unknown type names and malformed class names fail to compile.
+#[macro_export]
+macro_rules! jni_sig {
+    (($($arg:tt),* $(,)*) -> $ret:tt) => {{
+        const SIG: &str = concat!(
+            "(",
+            $($crate::jni_sig!(@ty $arg),)*
+            ")",
+            $crate::jni_sig!(@ty $ret)
+        );
+        const _: () = assert!($crate::java::is_valid_signature(SIG), "invalid JNI signature");
+        SIG
+    }};
+
+    (@ty boolean) => { "Z" };
+    (@ty byte) => { "B" };
+    (@ty char) => { "C" };
+    (@ty short) => { "S" };
+    (@ty int) => { "I" };
+    (@ty long) => { "J" };
+    (@ty float) => { "F" };
+    (@ty double) => { "D" };
+    (@ty void) => { "V" };
+    (@ty String) => { "Ljava/lang/String;" };
+    (@ty Object) => { "Ljava/lang/Object;" };
+    (@ty [$elem:tt]) => { concat!("[", $crate::jni_sig!(@ty $elem)) };
+    (@ty $class:literal) => { concat!("L", $class, ";") };
+    (@ty $other:tt) => {
+        compile_error!(concat!("unknown JNI type: ", stringify!($other)))
+    };
+
+    ($ty:tt) => {{
+        const SIG: &str = $crate::jni_sig!(@ty $ty);
+        const _: () = assert!($crate::java::is_valid_signature(SIG), "invalid JNI signature");
+        SIG
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        assert!(is_valid_signature("(J[BLjava/lang/String;)V"));
+        assert!(is_valid_signature("()Z"));
+        assert!(is_valid_signature("[[Ljava/util/Map$Entry;"));
+        assert!(!is_valid_signature("(J)"));
+        assert!(!is_valid_signature("(V)V"));
+        assert!(!is_valid_signature("[V"));
+        assert!(!is_valid_signature("(Ljava.lang.String;)V"));
+        assert!(!is_valid_signature("(Ljava/lang/String)V"));
+        assert!(!is_valid_signature("L;"));
+        assert!(!is_valid_signature("IJ"));
+    }
+}
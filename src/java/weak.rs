@@ -0,0 +1,167 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Weak references to Java callbacks.
+//!
+//! A `CallbackCtx` holds strong global references, which keep the callback and everything it
+//! references (e.g. an Android `Activity`) alive until the context is released. Long-lived
+//! subscriptions should hold the listener through a `WeakCallback` instead, and drop the
+//! subscription once the listener has been garbage collected.
+
+use super::debug::{self, RefKind};
+use super::{on_load, EnvGuard, JniResult};
+use jni::errors::Error as JniError;
+use jni::objects::JObject;
+use jni::sys::{jobject, jweak};
+use jni::{JNIEnv, JavaVM};
+use log::{error, warn};
+use std::os::raw::c_void;
+
+/// `user_data` context holding a weak global reference to a Java callback.
+pub struct WeakCallback {
+    vm: JavaVM,
+    weak: jweak,
+}
+
+// Weak global references are valid on every thread.
+unsafe impl Send for WeakCallback {}
+unsafe impl Sync for WeakCallback {}
+
+impl WeakCallback {
+    /// Create a weak global reference to `callback`.
+    pub fn new(env: &JNIEnv, callback: JObject) -> JniResult<Self> {
+        let raw_env = env.get_native_interface();
+        let weak = unsafe {
+            let new_weak_global_ref = (**raw_env)
+                .NewWeakGlobalRef
+                .ok_or_else(|| JniError::from("NewWeakGlobalRef not available"))?;
+            new_weak_global_ref(raw_env, callback.into_inner())
+        };
+
+        if weak.is_null() && !callback.is_null() {
+            return Err(JniError::from("NewWeakGlobalRef failed"));
+        }
+
+        let vm = env.get_java_vm()?;
+        debug::ref_created(RefKind::Weak, 1);
+        Ok(Self { vm, weak })
+    }
+
+    /// Return a local reference to the callback, or `None` if it has been garbage collected.
+    pub fn upgrade<'a>(&self, env: &JNIEnv<'a>) -> JniResult<Option<JObject<'a>>> {
+        let raw_env = env.get_native_interface();
+        let local: jobject = unsafe {
+            let new_local_ref = (**raw_env)
+                .NewLocalRef
+                .ok_or_else(|| JniError::from("NewLocalRef not available"))?;
+            new_local_ref(raw_env, self.weak)
+        };
+
+        Ok(if local.is_null() {
+            None
+        } else {
+            Some(JObject::from(local))
+        })
+    }
+
+    /// Transfer the reference into a `user_data` pointer.
+    pub fn into_raw(self) -> *mut c_void {
+        Box::into_raw(Box::new(self)) as *mut c_void
+    }
+
+    /// Borrow the reference behind a `user_data` pointer without releasing it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `into_raw` and not yet passed to `from_raw_owned`.
+    pub unsafe fn from_raw<'a>(ptr: *mut c_void) -> &'a Self {
+        &*(ptr as *const Self)
+    }
+
+    /// Take back ownership of the reference behind a `user_data` pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `into_raw`, and must not be used afterwards.
+    pub unsafe fn from_raw_owned(ptr: *mut c_void) -> Self {
+        *Box::from_raw(ptr as *mut Self)
+    }
+
+    /// Delete the weak reference using the given `JNIEnv`.
+    pub fn release(mut self, env: &JNIEnv) {
+        debug::ref_released(RefKind::Weak, 1);
+        unsafe { delete_weak_global_ref(env, self.weak) };
+        self.weak = std::ptr::null_mut();
+    }
+}
+
+impl Drop for WeakCallback {
+    fn drop(&mut self) {
+        if self.weak.is_null() {
+            return;
+        }
+
+        debug::ref_released(RefKind::Weak, 1);
+        match EnvGuard::new(Some(&self.vm)) {
+            Ok(guard) => unsafe { delete_weak_global_ref(guard.env(), self.weak) },
+            Err(e) => warn!("Leaking weak global ref: {:?}", e),
+        }
+    }
+}
+
+unsafe fn delete_weak_global_ref(env: &JNIEnv, weak: jweak) {
+    let raw_env = env.get_native_interface();
+    if let Some(delete_weak_global_ref) = (**raw_env).DeleteWeakGlobalRef {
+        delete_weak_global_ref(raw_env, weak);
+    }
+}
+
+/// Run `f` with a `JNIEnv` for the current thread and the callback behind `ctx`, a pointer
+/// returned by `WeakCallback::into_raw`.
+///
+/// Returns `false` if the callback has been garbage collected, in which case the subscription
+/// should be cancelled and the context released with `WeakCallback::from_raw_owned`. Errors
+/// are logged like in `invoke_callback`.
+///
+/// # Safety
+///
+/// `ctx` must have been returned by `WeakCallback::into_raw` and not yet released.
+pub unsafe fn invoke_weak_callback<F>(ctx: *mut c_void, f: F) -> bool
+where
+    F: FnOnce(&JNIEnv, JObject) -> JniResult<()>,
+{
+    let guard = match EnvGuard::new(on_load::vm()) {
+        Ok(guard) => guard,
+        Err(e) => {
+            error!("Failed to obtain JNIEnv for callback: {:?}", e);
+            return true;
+        }
+    };
+    let env = guard.env();
+
+    let cb = match WeakCallback::from_raw(ctx).upgrade(env) {
+        Ok(Some(cb)) => cb,
+        Ok(None) => return false,
+        Err(e) => {
+            error!("Failed to upgrade weak callback: {:?}", e);
+            return true;
+        }
+    };
+
+    if let Err(e) = f(env, cb) {
+        error!("Java callback failed: {:?}", e);
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_describe();
+            let _ = env.exception_clear();
+        }
+    }
+    let _ = env.delete_local_ref(cb);
+
+    true
+}
@@ -0,0 +1,162 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Passing complex values across the FFI as JSON strings.
+//!
+//! For rarely called APIs with complex-shaped inputs or outputs, a JSON string is often simpler
+//! for the bindings than a `#[repr(C)]` twin of every type. `json_cb` delivers any `Serialize`
+//! value to a string callback, and `parse_json_arg` reads a `Deserialize` value from a string
+//! argument:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_search(
+//!     app: Handle,
+//!     query: *const c_char,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, json: *const c_char),
+//! ) {
+//!     let result = parse_json_arg::<Query>(query)
+//!         .map_err(AppError::from)
+//!         .and_then(|query| Ok(handles::get::<App>(app)?.search(&query)?));
+//!     json_cb(user_data, o_cb, result);
+//! }
+//! ```
+//!
+//! `JsonString<T>` implements `ReprC` and `IntoReprC` as well, for use with `decode_args!`,
+//! `Dispatcher::dispatch_cb` and the like.
+
+use crate::callback::Callback;
+use crate::catch_unwind::call_error_cb;
+use crate::serde_bridge::{SerdeError, ERR_SERDE};
+use crate::{ffi_error, ErrorCode, FfiResult, IntoReprC, ReprC, FFI_RESULT_OK};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::ffi::{CStr, CString};
+use std::fmt::{Debug, Display};
+use std::os::raw::{c_char, c_void};
+
+/// Serialise `value` into a NUL-terminated JSON string.
+pub fn to_json_cstring<T: Serialize + ?Sized>(value: &T) -> Result<CString, SerdeError> {
+    let json = serde_json::to_vec(value).map_err(|e| SerdeError(e.to_string()))?;
+    CString::new(json).map_err(|e| SerdeError(e.to_string()))
+}
+
+/// Deserialise a value from the NUL-terminated JSON string `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a NUL-terminated string.
+pub unsafe fn parse_json_arg<T: DeserializeOwned>(ptr: *const c_char) -> Result<T, SerdeError> {
+    if ptr.is_null() {
+        return Err(SerdeError(
+            "JSON could not be parsed from C null pointer".to_owned(),
+        ));
+    }
+    serde_json::from_slice(CStr::from_ptr(ptr).to_bytes()).map_err(|e| SerdeError(e.to_string()))
+}
+
+/// Call the string callback `cb` with `result`: the value serialised into JSON on success, or
+/// the error converted through `NativeResult` on failure. Values which can't be serialised are
+/// reported with `ERR_SERDE`.
+pub fn json_cb<U, T, E>(
+    user_data: U,
+    cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, json: *const c_char),
+    result: Result<T, E>,
+) where
+    U: Into<*mut c_void>,
+    T: Serialize,
+    E: Debug + Display + ErrorCode,
+{
+    let user_data = user_data.into();
+    let (error_code, description) = match result {
+        Ok(value) => match to_json_cstring(&value) {
+            Ok(json) => return cb.call(user_data, FFI_RESULT_OK, json.as_ptr()),
+            Err(error) => (
+                ERR_SERDE,
+                format!("Could not serialise into JSON: {}", error.0),
+            ),
+        },
+        Err(error) => ffi_error!(error),
+    };
+    call_error_cb(user_data, cb, error_code, description)
+}
+
+/// Value passed across the FFI as a JSON string.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct JsonString<T>(pub T);
+
+impl<T: DeserializeOwned> ReprC for JsonString<T> {
+    type C = *const c_char;
+    type Error = SerdeError;
+
+    unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
+        parse_json_arg(repr_c).map(JsonString)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> IntoReprC for JsonString<T> {
+    type Storage = CString;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = to_json_cstring(&self.0)?;
+        Ok((storage.as_ptr(), storage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_string, TestError};
+    use serde_derive::{Deserialize, Serialize};
+    use std::ptr;
+    use unwrap::unwrap;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Query {
+        terms: Vec<String>,
+        limit: u32,
+    }
+
+    #[test]
+    fn json_callbacks() {
+        let query = Query {
+            terms: vec!["a".to_owned()],
+            limit: 10,
+        };
+        let json = unsafe {
+            unwrap!(call_string(|ud, cb| json_cb(
+                ud,
+                cb,
+                Ok::<_, TestError>(&query)
+            )))
+        };
+        assert_eq!(json, r#"{"terms":["a"],"limit":10}"#);
+
+        let res =
+            unsafe { call_string(|ud, cb| json_cb(ud, cb, Err::<Query, _>(TestError::Test))) };
+        assert_eq!(res, Err(-1));
+    }
+
+    #[test]
+    fn json_args() {
+        let json = unwrap!(CString::new(r#"{"terms":[],"limit":1}"#));
+        let query: Query = unsafe { unwrap!(parse_json_arg(json.as_ptr())) };
+        assert_eq!(query.limit, 1);
+
+        let invalid = unwrap!(CString::new(r#"{"terms":[]}"#));
+        let error = unwrap!(unsafe { parse_json_arg::<Query>(invalid.as_ptr()) }.err());
+        assert_eq!(error.error_code(), ERR_SERDE);
+        assert!(unsafe { parse_json_arg::<Query>(ptr::null()) }.is_err());
+
+        let (repr_c, _storage) = unwrap!(JsonString(query).into_repr_c());
+        let query = unsafe { unwrap!(JsonString::<Query>::clone_from_repr_c(repr_c)) };
+        assert_eq!(query.0.terms, Vec::<String>::new());
+    }
+}
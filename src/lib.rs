@@ -8,6 +8,14 @@
 // Software.
 
 //! FFI utilities.
+//!
+//! The conversion core (`ReprC`, `FfiResult`, strings and vectors) only needs `alloc`, and builds
+//! with `no_std` when the default `std` feature is disabled. Everything else, including the test
+//! utilities, the language bindings and the code generators, requires `std`.
+//!
+//! The modules which spawn threads or measure time (`batch`, `completion_queue`, `dispatcher`,
+//! `future`, `pending`, `timers` and `mock`) aren't available on `wasm32`, where neither is
+//! supported.
 
 #![doc(
     html_logo_url = "https://raw.githubusercontent.com/maidsafe/QA/master/Images/maidsafe_logo.png",
@@ -26,40 +34,149 @@
 )]
 // This crate makes liberal use of unsafe code to work with FFI.
 #![allow(unsafe_code)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod abi;
+#[cfg(feature = "std")]
+pub mod affinity;
+#[cfg(feature = "std")]
+pub mod allocator;
+#[cfg(all(feature = "std", any(feature = "tokio", feature = "async-std")))]
+pub mod async_ffi;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod batch;
+#[cfg(feature = "std")]
 pub mod bindgen_utils;
+#[cfg(feature = "std")]
 pub mod callback;
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod completion_queue;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "dart")]
+pub mod dart;
+#[cfg(feature = "std")]
+pub mod describe;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod dispatcher;
+#[cfg(feature = "dotnet")]
+pub mod dotnet;
+#[cfg(feature = "std")]
+pub mod error_codes;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
+pub mod flags;
+#[cfg(feature = "std")]
+pub mod free;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod future;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(feature = "std")]
+pub mod handles;
+#[cfg(feature = "std")]
+pub mod init;
 #[cfg(feature = "java")]
 pub mod java;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod logging;
+#[cfg(feature = "memory-report")]
+pub mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(all(feature = "mock", not(target_arch = "wasm32")))]
+pub mod mock;
+#[cfg(feature = "napi")]
+pub mod napi;
+#[cfg(feature = "std")]
+pub mod payload;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod pending;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod query;
+#[cfg(feature = "std")]
+pub mod reentrancy;
 pub mod result;
+#[cfg(feature = "std")]
+pub mod serde_bridge;
+#[cfg(all(unix, feature = "shmem"))]
+pub mod shmem;
+#[cfg(feature = "std")]
+pub mod static_results;
 pub mod string;
+#[cfg(feature = "std")]
 pub mod test_utils;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod timers;
+#[cfg(all(feature = "std", feature = "tracing"))]
+pub mod trace;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+#[cfg(feature = "std")]
 mod b64;
+#[cfg(feature = "std")]
+mod caller_buf;
+#[cfg(feature = "std")]
 mod catch_unwind;
 mod macros;
+#[cfg(feature = "std")]
+mod opaque_ctx;
+#[cfg(feature = "std")]
+mod option;
+#[cfg(feature = "std")]
+mod out_param;
 mod repr_c;
+#[cfg(feature = "std")]
+mod typed_ctx;
 mod vec;
 
+#[cfg(feature = "std")]
 pub use self::b64::{base64_decode, base64_encode};
-pub use self::catch_unwind::{catch_unwind_cb, catch_unwind_result};
-pub use self::repr_c::ReprC;
+#[cfg(feature = "std")]
+pub use self::caller_buf::{
+    write_bytes_to_caller_buf, write_str_to_caller_buf, BufferTooSmall, WriteStrError,
+    ERR_BUFFER_TOO_SMALL, ERR_INTERIOR_NUL,
+};
+#[cfg(feature = "std")]
+pub use self::catch_unwind::{
+    call_cb_with_error, catch_unwind_cb, catch_unwind_result, report_injected_fault, shield,
+    trace_callback, PanicFallback,
+};
+#[cfg(feature = "std")]
+pub use self::opaque_ctx::OpaqueCtx;
+#[cfg(feature = "std")]
+pub use self::option::{FfiOption, FFI_OPTION_NONE, FFI_OPTION_SOME};
+#[cfg(feature = "std")]
+pub use self::out_param::{
+    catch_unwind_out, catch_unwind_status, get_out, last_error, write_out_param, NullOutParam,
+    ERR_NULL_OUT_PARAM,
+};
+pub use self::repr_c::{decode_arg, IntoReprC, InvalidArg, ReprC, ERR_INVALID_ARG};
 pub use self::result::{FfiResult, NativeResult, FFI_RESULT_OK};
-pub use self::string::StringError;
+pub use self::string::{validate_utf8, InvalidUtf8, StringError};
+#[cfg(feature = "std")]
+pub use self::typed_ctx::{
+    outstanding_owned_contexts, ContextError, CtxOwned, TypedCtx, ERR_INVALID_CONTEXT,
+};
 pub use self::vec::{vec_clone_from_raw_parts, vec_from_raw_parts, vec_into_raw_parts, SafePtr};
+#[cfg(feature = "std")]
+pub use sn_ffi_utils_macros::ffi_fn;
 
-use std::os::raw::c_void;
-
-/// Type that holds opaque user data handed into FFI functions.
-#[derive(Clone, Copy)]
-pub struct OpaqueCtx(pub *mut c_void);
-unsafe impl Send for OpaqueCtx {}
-
-impl Into<*mut c_void> for OpaqueCtx {
-    fn into(self) -> *mut c_void {
-        self.0
-    }
-}
+// Used by the code `#[ffi_fn]` generates, so that callers don't need to depend on `log`.
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub use log as __log;
 
 /// Trait for types that can be converted to integer error code.
 pub trait ErrorCode {
@@ -0,0 +1,220 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Forwarding of log records to a frontend callback.
+//!
+//! Frontends register a callback with `ffi_set_logger` (exported with `export_logger!`) to see
+//! the library's diagnostics, including the `**ERRNO**` lines of `ffi_error_code!`, in their own
+//! logging. Levels are passed as integers: 1 for errors down to 5 for trace records, and 0 as
+//! `max_level` disables logging.
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::cell::Cell;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+/// Error code returned by `ffi_set_logger` when another logger is already installed.
+pub const ERR_LOGGER_ALREADY_SET: i32 = -9004;
+
+/// Callback receiving log records. The strings are only valid for the duration of the call.
+pub type LogCallback =
+    extern "C" fn(level: i32, target: *const c_char, msg: *const c_char, user_data: *mut c_void);
+
+#[derive(Clone, Copy)]
+struct Sink {
+    cb: LogCallback,
+    user_data: *mut c_void,
+}
+
+// The frontend is responsible for making its `user_data` usable from any thread.
+unsafe impl Send for Sink {}
+unsafe impl Sync for Sink {}
+
+thread_local! {
+    // Set while a record is being forwarded, so that records logged by the callback itself
+    // are dropped instead of recursing.
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+struct FfiLogger {
+    sink: RwLock<Option<Sink>>,
+}
+
+static LOGGER: FfiLogger = FfiLogger {
+    sink: RwLock::new(None),
+};
+
+impl Log for FfiLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) || FORWARDING.with(Cell::get) {
+            return;
+        }
+        let sink = match *self.sink.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(sink) => sink,
+            None => return,
+        };
+
+        FORWARDING.with(|f| f.set(true));
+        // Formatting the record runs arbitrary `Display` impls, which must not unwind into the
+        // code that logged it nor leave forwarding disabled for the thread.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let target = to_c_string(record.target());
+            let msg = to_c_string(&record.args().to_string());
+            (sink.cb)(
+                record.level() as i32,
+                target.as_ptr(),
+                msg.as_ptr(),
+                sink.user_data,
+            );
+        }));
+        FORWARDING.with(|f| f.set(false));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the forwarding logger as the global logger if needed, and register `cb` to receive
+/// records up to `max_level`. Passing `None` unregisters the current callback.
+///
+/// Fails, on every call, if another logger (e.g. `java::logging`) was already installed the
+/// first time.
+pub fn set_logger(
+    cb: Option<LogCallback>,
+    user_data: *mut c_void,
+    max_level: i32,
+) -> Result<(), &'static SetLoggerError> {
+    static INSTALLED: OnceLock<Result<(), SetLoggerError>> = OnceLock::new();
+
+    if let Err(error) = INSTALLED.get_or_init(|| log::set_logger(&LOGGER)) {
+        return Err(error);
+    }
+
+    *LOGGER.sink.write().unwrap_or_else(PoisonError::into_inner) =
+        cb.map(|cb| Sink { cb, user_data });
+    log::set_max_level(match cb {
+        Some(_) => level_filter(max_level),
+        None => LevelFilter::Off,
+    });
+    Ok(())
+}
+
+/// Convert an integer level, as passed by frontends, into a `LevelFilter`. Values above 5 are
+/// clamped to `Trace`.
+pub fn level_filter(level: i32) -> LevelFilter {
+    match level {
+        i32::MIN..=0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Convert a `Level` into the integer passed to `LogCallback`.
+pub fn level_code(level: Level) -> i32 {
+    level as i32
+}
+
+fn to_c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "\\0")).unwrap_or_default()
+}
+
+/// Export the logger registration function of the library.
+///
+/// Defines `ffi_set_logger(cb, user_data: *mut c_void, max_level: i32) -> i32`, returning 0 on
+/// success or `ERR_LOGGER_ALREADY_SET`. See `logging::set_logger`.
+#[macro_export]
+macro_rules! export_logger {
+    () => {
+        /// Register `cb` to receive log records up to `max_level`, or unregister the current
+        /// callback if `cb` is null.
+        #[no_mangle]
+        pub extern "C" fn ffi_set_logger(
+            cb: Option<$crate::logging::LogCallback>,
+            user_data: *mut std::os::raw::c_void,
+            max_level: i32,
+        ) -> i32 {
+            match $crate::logging::set_logger(cb, user_data, max_level) {
+                Ok(()) => 0,
+                Err(_) => $crate::logging::ERR_LOGGER_ALREADY_SET,
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestError;
+    use crate::ReprC;
+    use std::ptr;
+    use std::sync::Mutex;
+    use unwrap::unwrap;
+
+    static RECORDS: Mutex<Vec<(i32, String, String)>> = Mutex::new(Vec::new());
+
+    extern "C" fn record(
+        level: i32,
+        target: *const c_char,
+        msg: *const c_char,
+        _user_data: *mut c_void,
+    ) {
+        let target = unsafe { unwrap!(String::clone_from_repr_c(target)) };
+        let msg = unsafe { unwrap!(String::clone_from_repr_c(msg)) };
+        unwrap!(RECORDS.lock()).push((level, target, msg));
+    }
+
+    fn records(target: &str) -> Vec<(i32, String)> {
+        unwrap!(RECORDS.lock())
+            .iter()
+            .filter(|(_, t, _)| t.starts_with(target))
+            .map(|(level, _, msg)| (*level, msg.clone()))
+            .collect()
+    }
+
+    struct PanickingDisplay;
+
+    impl std::fmt::Display for PanickingDisplay {
+        fn fmt(&self, _f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            panic!("simulated panic")
+        }
+    }
+
+    #[test]
+    fn forward_records() {
+        unwrap!(set_logger(Some(record), ptr::null_mut(), 4));
+
+        log::info!(target: "logging_test", "hello");
+        log::trace!(target: "logging_test", "dropped");
+        log::info!(target: "logging_test", "{}", PanickingDisplay);
+        log::info!(target: "logging_test", "after panic");
+        let _ = crate::ffi_error_code!(TestError::Test);
+
+        unwrap!(set_logger(None, ptr::null_mut(), 5));
+        log::error!(target: "logging_test", "after unregistering");
+
+        assert_eq!(
+            records("logging_test"),
+            vec![
+                (level_code(Level::Info), "hello".to_owned()),
+                (level_code(Level::Info), "after panic".to_owned())
+            ]
+        );
+        assert!(records(module_path!())
+            .iter()
+            .any(|(level, msg)| *level == 4 && msg.starts_with("**ERRNO: -1**")));
+    }
+}
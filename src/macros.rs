@@ -63,41 +63,38 @@ macro_rules! ffi_error_code {
         use $crate::ErrorCode;
 
         let err = &$err;
-        let err_str = format!("{:?}", err);
         let err_code = err.error_code();
 
-        log::debug!("**ERRNO: {}** {}", err_code, err_str);
+        // Formatted by `debug!` only if enabled.
+        log::debug!("**ERRNO: {}** {:?}", err_code, err);
         err_code
     }};
 }
 
 /// Convert a result into an `FfiResult` and call a callback.
 ///
-/// The error must implement `Debug + Display`.
+/// The error must implement `Debug + Display`. Errors injected with `test_utils::fault::fail_next`
+/// (with the `testing` feature) take precedence over the result. Success is reported with
+/// `FFI_RESULT_OK`, and errors registered with `static_results` with their static result, so
+/// neither allocates.
 #[macro_export]
 macro_rules! call_result_cb {
     ($result:expr, $user_data:expr, $cb:expr) => {
         #[cfg_attr(feature = "cargo-clippy", allow(useless_attribute))]
         #[allow(unused)]
         use $crate::callback::{Callback, CallbackArgs};
-        use $crate::result::{FfiResult, NativeResult};
-
-        let (error_code, description) = $crate::ffi_result!($result);
-        let res = NativeResult {
-            error_code,
-            description: Some(description),
-        }
-        .into_repr_c();
-
-        match res {
-            Ok(res) => $cb.call($user_data.into(), &res, CallbackArgs::default()),
-            Err(_) => {
-                let res = FfiResult {
-                    error_code,
-                    description: b"Could not convert error description into CString\x00"
-                        as *const u8 as *const _,
-                };
-                $cb.call($user_data.into(), &res, CallbackArgs::default());
+
+        let result = $result;
+        let user_data: *mut std::os::raw::c_void = $user_data.into();
+        let cb = $cb;
+        if $crate::report_injected_fault(user_data, cb).is_none() {
+            match result {
+                Ok(_) => {
+                    $crate::affinity::check();
+                    $crate::trace_callback(0);
+                    cb.call(user_data, $crate::FFI_RESULT_OK, CallbackArgs::default())
+                }
+                Err(error) => $crate::call_cb_with_error(user_data, cb, &error),
             }
         }
     };
@@ -120,9 +117,48 @@ macro_rules! try_cb {
     };
 }
 
-#[cfg(test)]
+/// Convert the arguments of an FFI function from their FFI representation with
+/// `ReprC::clone_from_repr_c`, shadowing them with the converted values. Should be called
+/// within `catch_unwind_cb`, and returns early with an `InvalidArg` error naming the first
+/// argument which failed to convert, so the error type must implement `From<InvalidArg>`.
+///
+/// Arguments are given as `name: Type` pairs, where `name` is the raw argument, or as
+/// `name: Type = expr` to convert another expression. As the conversions trust the raw
+/// arguments to be valid, the macro must be invoked in an unsafe context, typically the body of
+/// an `unsafe extern "C" fn`:
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub unsafe extern "C" fn app_find(name: *const c_char, raw_id: u64, ...) {
+///     catch_unwind_cb(user_data, o_cb, || -> Result<_, AppError> {
+///         decode_args!(name: String, id: u64 = raw_id);
+///         ...
+///     })
+/// }
+/// ```
+#[macro_export]
+macro_rules! decode_args {
+    ($($name:ident : $ty:ty $(= $repr_c:expr)?),* $(,)?) => {
+        $(
+            let $name = $crate::decode_args!(@decode $name, $ty $(, $repr_c)?)?;
+        )*
+    };
+    (@decode $name:ident, $ty:ty) => {
+        $crate::decode_arg::<$ty>(stringify!($name), $name)
+    };
+    (@decode $name:ident, $ty:ty, $repr_c:expr) => {
+        $crate::decode_arg::<$ty>(stringify!($name), $repr_c)
+    };
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use crate::test_utils::TestError;
+    use crate::{catch_unwind_cb, ErrorCode, FfiResult, InvalidArg, StringError, ERR_INVALID_ARG};
+    use std::ffi::{CStr, CString};
+    use std::fmt::{self, Display};
+    use std::os::raw::{c_char, c_void};
+    use std::ptr;
 
     #[test]
     fn error_code_and_desc() {
@@ -142,4 +178,125 @@ mod tests {
             assert_eq!(desc, "howdy".to_string());
         }
     }
+
+    #[test]
+    fn call_result_cb_evaluates_arguments_once() {
+        extern "C" fn cb(user_data: *mut c_void, result: *const FfiResult) {
+            unsafe { *(user_data as *mut i32) = (*result).error_code };
+        }
+
+        let mut error_code = 1;
+        let error_code_ptr = ptr::from_mut(&mut error_code) as *mut c_void;
+        let mut evaluations = 0;
+        let mut user_data = || {
+            evaluations += 1;
+            error_code_ptr
+        };
+        let mut callbacks = 0;
+        let mut o_cb = || -> extern "C" fn(*mut c_void, *const FfiResult) {
+            callbacks += 1;
+            cb
+        };
+
+        {
+            call_result_cb!(Err::<(), _>(TestError::Test), user_data(), o_cb());
+        }
+        assert_eq!(unsafe { *(error_code_ptr as *const i32) }, -1);
+        {
+            call_result_cb!(Ok::<_, TestError>(()), user_data(), o_cb());
+        }
+        assert_eq!((evaluations, callbacks), (2, 2));
+        assert_eq!(error_code, 0);
+    }
+
+    #[test]
+    fn call_result_cb_reports_success_with_empty_description() {
+        extern "C" fn cb(user_data: *mut c_void, result: *const FfiResult) {
+            let description = unsafe { CStr::from_ptr((*result).description) };
+            unsafe { *(user_data as *mut bool) = description.to_bytes().is_empty() };
+        }
+
+        let o_cb: extern "C" fn(*mut c_void, *const FfiResult) = cb;
+        let mut empty = false;
+        call_result_cb!(
+            Ok::<_, TestError>(()),
+            ptr::from_mut(&mut empty) as *mut c_void,
+            o_cb
+        );
+        assert!(empty);
+    }
+
+    #[test]
+    fn decode_args_names_failing_argument() {
+        #[derive(Debug)]
+        enum Error {
+            Arg(InvalidArg),
+            Panic,
+        }
+
+        impl From<InvalidArg> for Error {
+            fn from(err: InvalidArg) -> Self {
+                Error::Arg(err)
+            }
+        }
+
+        impl<'a> From<&'a str> for Error {
+            fn from(_: &'a str) -> Self {
+                Error::Panic
+            }
+        }
+
+        impl ErrorCode for Error {
+            fn error_code(&self) -> i32 {
+                match self {
+                    Error::Arg(err) => err.error_code(),
+                    Error::Panic => -1,
+                }
+            }
+        }
+
+        impl Display for Error {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    Error::Arg(err) => write!(f, "{}", err),
+                    Error::Panic => write!(f, "panic"),
+                }
+            }
+        }
+
+        extern "C" fn cb(user_data: *mut c_void, result: *const FfiResult) {
+            unsafe {
+                let out = &mut *(user_data as *mut (i32, String));
+                out.0 = (*result).error_code;
+                out.1 = unwrap::unwrap!(CStr::from_ptr((*result).description).to_str()).to_owned();
+            }
+        }
+
+        unsafe fn call(name: *const c_char, raw_id: u64) -> (i32, String) {
+            let mut out = (0, String::new());
+            let user_data = ptr::from_mut(&mut out).cast::<c_void>();
+            let cb: extern "C" fn(_, _) = cb;
+
+            catch_unwind_cb(user_data, cb, || -> Result<(), Error> {
+                decode_args!(name: String, id: u64 = raw_id);
+                assert_eq!(name, "foo");
+                assert_eq!(id, 7);
+                Ok(())
+            });
+            out
+        }
+
+        let name = unwrap::unwrap!(CString::new("foo"));
+        assert_eq!(unsafe { call(name.as_ptr(), 7) }, (0, String::new()));
+
+        let (error_code, description) = unsafe { call(ptr::null(), 7) };
+        assert_eq!(error_code, ERR_INVALID_ARG);
+        assert_eq!(
+            description,
+            format!(
+                "Invalid argument `name`: {}",
+                StringError::Null("String could not be constructed from C null pointer".to_owned())
+            )
+        );
+    }
 }
@@ -0,0 +1,260 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Accounting of memory transferred to frontends.
+//!
+//! With the `memory-report` feature, memory handed over by `vec_into_raw_parts`, the
+//! descriptions of `FfiResult`s and the objects of handle registries are recorded until they are
+//! given back, so binding developers can check that they call every free function they should.
+//! The report is exported with `export_memory_report!`.
+//!
+//! Memory given back through the crate's functions (e.g. `vec_from_raw_parts`) is accounted for
+//! out of the box. To also account for memory released through other routes, install
+//! `TrackingAllocator` as the global allocator of the library:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: TrackingAllocator = TrackingAllocator::new(std::alloc::System);
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Kind of memory transferred to a frontend.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Category {
+    /// Buffers transferred with `vec_into_raw_parts`.
+    Vec,
+    /// C strings transferred with `CString::into_raw`.
+    CString,
+    /// Objects registered in a handle registry.
+    Handle,
+}
+
+/// Outstanding transfers of one category.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FfiMemoryStats {
+    /// Number of transfers which have not been given back.
+    pub count: usize,
+    /// Total size of these transfers, in bytes.
+    pub bytes: usize,
+}
+
+/// Outstanding transfers by category.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FfiMemoryReport {
+    /// Buffers transferred with `vec_into_raw_parts`.
+    pub vecs: FfiMemoryStats,
+    /// C strings transferred with `CString::into_raw`.
+    pub c_strings: FfiMemoryStats,
+    /// Objects registered in a handle registry.
+    pub handles: FfiMemoryStats,
+}
+
+impl FfiMemoryReport {
+    fn stats_mut(&mut self, category: Category) -> &mut FfiMemoryStats {
+        match category {
+            Category::Vec => &mut self.vecs,
+            Category::CString => &mut self.c_strings,
+            Category::Handle => &mut self.handles,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Transfers {
+    // Transferred allocations by address.
+    pointers: HashMap<usize, (Category, usize)>,
+    // Registered objects by registry address and handle.
+    handles: HashMap<(usize, u64), usize>,
+}
+
+static TRANSFERS: Mutex<Option<Transfers>> = Mutex::new(None);
+// Number of entries in `Transfers::pointers`, so the allocator can skip the lookup.
+static TRACKED_POINTERS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Set while `TRANSFERS` is being updated, whose own deallocations must not be looked up.
+    static UPDATING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn update<F: FnOnce(&mut Transfers)>(f: F) {
+    // The bookkeeping outlives the calls checked by `assert_no_leaks`, so it's not a leak.
+    #[cfg(feature = "leak-check")]
+    let f = |transfers: &mut Transfers| crate::test_utils::leak::untracked(|| f(transfers));
+
+    UPDATING.with(|updating| updating.set(true));
+    {
+        let mut transfers = lock();
+        let transfers = transfers.get_or_insert_with(Transfers::default);
+        f(transfers);
+        TRACKED_POINTERS.store(transfers.pointers.len(), Ordering::Release);
+    }
+    UPDATING.with(|updating| updating.set(false));
+}
+
+fn lock() -> MutexGuard<'static, Option<Transfers>> {
+    TRANSFERS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Record the transfer of `bytes` bytes at `ptr`. Empty transfers are not recorded, as they
+/// don't own an allocation.
+pub fn track<T>(category: Category, ptr: *const T, bytes: usize) {
+    if bytes == 0 {
+        return;
+    }
+    update(|transfers| {
+        let _ = transfers.pointers.insert(ptr as usize, (category, bytes));
+    })
+}
+
+/// Record that the memory at `ptr` has been given back.
+pub fn untrack<T>(ptr: *const T) {
+    if TRACKED_POINTERS.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    update(|transfers| {
+        let _ = transfers.pointers.remove(&(ptr as usize));
+    })
+}
+
+pub(crate) fn track_handle<R>(registry: &R, handle: u64, bytes: usize) {
+    let key = handle_key(registry, handle);
+    update(|transfers| {
+        let _ = transfers.handles.insert(key, bytes);
+    })
+}
+
+pub(crate) fn untrack_handle<R>(registry: &R, handle: u64) {
+    let key = handle_key(registry, handle);
+    update(|transfers| {
+        let _ = transfers.handles.remove(&key);
+    })
+}
+
+fn handle_key<R>(registry: &R, handle: u64) -> (usize, u64) {
+    let registry: *const R = registry;
+    (registry as usize, handle)
+}
+
+/// Return the outstanding transfers by category.
+pub fn report() -> FfiMemoryReport {
+    let mut report = FfiMemoryReport::default();
+    if let Some(transfers) = &*lock() {
+        for (category, bytes) in transfers.pointers.values() {
+            let stats = report.stats_mut(*category);
+            stats.count += 1;
+            stats.bytes += bytes;
+        }
+        for bytes in transfers.handles.values() {
+            report.handles.count += 1;
+            report.handles.bytes += bytes;
+        }
+    }
+    report
+}
+
+/// Allocator wrapper recording that transferred memory was given back whenever it is freed,
+/// whichever way that happens.
+pub struct TrackingAllocator<A> {
+    inner: A,
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Wrap the `inner` allocator.
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !UPDATING.with(Cell::get) {
+            untrack(ptr);
+        }
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !UPDATING.with(Cell::get) {
+            untrack(ptr);
+        }
+        self.inner.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Export the memory report of the library.
+///
+/// Defines `ffi_memory_report() -> FfiMemoryReport` returning the outstanding transfers by
+/// category.
+#[macro_export]
+macro_rules! export_memory_report {
+    () => {
+        /// Return the memory transferred to the frontend which has not been given back yet.
+        #[no_mangle]
+        pub extern "C" fn ffi_memory_report() -> $crate::memory::FfiMemoryReport {
+            $crate::memory::report()
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::System;
+
+    // Other tests transfer memory concurrently, so only the transfers of the test are checked.
+    fn is_tracked<T>(ptr: *const T) -> bool {
+        lock()
+            .as_ref()
+            .is_some_and(|transfers| transfers.pointers.contains_key(&(ptr as usize)))
+    }
+
+    #[test]
+    fn given_back_through_allocator() {
+        let allocator = TrackingAllocator::new(System);
+        let layout = unwrap::unwrap!(Layout::from_size_align(16, 8));
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            track(Category::Vec, ptr, 16);
+            assert!(is_tracked(ptr));
+            assert!(report().vecs.bytes >= 16);
+
+            allocator.dealloc(ptr, layout);
+            assert!(!is_tracked(ptr));
+        }
+    }
+
+    #[test]
+    fn handles_are_reported() {
+        let registry = crate::handles::HandleRegistry::new();
+        let handle = registry.register(0u64);
+        assert!(report().handles.bytes >= 8);
+
+        unwrap::unwrap!(registry.free::<u64>(handle));
+        let key = handle_key(&registry, handle);
+        assert!(lock()
+            .as_ref()
+            .is_some_and(|transfers| !transfers.handles.contains_key(&key)));
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Per-function call metrics: call counts, error counts by code and latency histograms.
+//!
+//! With the `metrics` feature, every call through `catch_unwind_cb` or `async_ffi::spawn_cb` is
+//! recorded under the name of the enclosing FFI function. For async calls, the latency covers
+//! the whole operation, up to the callback invocation. Other entry points can be recorded with
+//! `record`.
+//!
+//! The metrics are available through `snapshot`, or as JSON to the frontend through the
+//! function exported by `export_metrics!`.
+
+use crate::{FfiResult, FFI_RESULT_OK};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Upper bounds, in microseconds, of the latency histogram buckets. A last bucket counts the
+/// calls slower than the last bound.
+pub const LATENCY_BUCKETS_US: [u64; 7] = [10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// Latency distribution of the calls to a function.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct LatencyHistogram {
+    /// Number of calls in each bucket of `LATENCY_BUCKETS_US`, plus the overflow bucket.
+    pub counts: [u64; LATENCY_BUCKETS_US.len() + 1],
+    /// Total latency, in microseconds.
+    pub total_us: u64,
+    /// Highest latency, in microseconds.
+    pub max_us: u64,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| us <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.counts[bucket] += 1;
+        self.total_us = self.total_us.saturating_add(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    /// Mean latency, or `None` if no calls were recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        let count: u64 = self.counts.iter().sum();
+        self.total_us.checked_div(count).map(Duration::from_micros)
+    }
+}
+
+/// Metrics of the calls to a function.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct CallMetrics {
+    /// Number of calls.
+    pub calls: u64,
+    /// Number of failed calls, by error code.
+    pub errors: BTreeMap<i32, u64>,
+    /// Latency distribution.
+    pub latency: LatencyHistogram,
+}
+
+static METRICS: Mutex<BTreeMap<&'static str, CallMetrics>> = Mutex::new(BTreeMap::new());
+
+fn lock() -> MutexGuard<'static, BTreeMap<&'static str, CallMetrics>> {
+    METRICS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Record a call to `function` which took `latency` and returned `error_code` (0 on success).
+pub fn record(function: &'static str, latency: Duration, error_code: i32) {
+    let mut metrics = lock();
+    let entry = metrics.entry(function).or_default();
+    entry.calls += 1;
+    if error_code != 0 {
+        *entry.errors.entry(error_code).or_insert(0) += 1;
+    }
+    entry.latency.record(latency);
+}
+
+/// Return the metrics recorded so far, by function name.
+pub fn snapshot() -> BTreeMap<&'static str, CallMetrics> {
+    lock().clone()
+}
+
+/// Forget the metrics recorded so far.
+pub fn reset() {
+    lock().clear()
+}
+
+/// Return the metrics recorded so far as JSON:
+/// `{"latency_buckets_us": [...], "functions": {"name": {"calls": ..., "errors": {...},
+/// "latency": {...}}}}`.
+pub fn to_json() -> String {
+    #[derive(Serialize)]
+    struct Report {
+        latency_buckets_us: [u64; LATENCY_BUCKETS_US.len()],
+        functions: BTreeMap<&'static str, CallMetrics>,
+    }
+
+    let report = Report {
+        latency_buckets_us: LATENCY_BUCKETS_US,
+        functions: snapshot(),
+    };
+    serde_json::to_string(&report).unwrap_or_else(|e| {
+        log::error!("Failed to serialise metrics: {}", e);
+        String::from("{}")
+    })
+}
+
+// Measures a call, from its start to `finish`.
+pub(crate) struct Timer {
+    function: &'static str,
+    start: Instant,
+}
+
+impl Timer {
+    pub(crate) fn start(function: &'static str) -> Self {
+        Self {
+            function,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn finish(self, error_code: i32) {
+        record(self.function, self.start.elapsed(), error_code)
+    }
+}
+
+/// Call `o_cb` with the metrics as JSON (see `to_json`).
+pub fn metrics_json(
+    user_data: *mut c_void,
+    o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, json: *const c_char),
+) {
+    // Serialised JSON never contains nul bytes.
+    let json = CString::new(to_json()).unwrap_or_default();
+    o_cb(user_data, FFI_RESULT_OK, json.as_ptr());
+}
+
+/// Generate the exported function reporting the call metrics:
+///
+/// ```ignore
+/// export_metrics!();
+/// ```
+///
+/// This defines `ffi_metrics_json(user_data, o_cb)`, which calls `o_cb` with the metrics as a
+/// JSON string.
+#[macro_export]
+macro_rules! export_metrics {
+    () => {
+        /// Call `o_cb` with the FFI call metrics as a JSON string.
+        #[no_mangle]
+        pub extern "C" fn ffi_metrics_json(
+            user_data: *mut std::os::raw::c_void,
+            o_cb: extern "C" fn(
+                user_data: *mut std::os::raw::c_void,
+                result: *const $crate::FfiResult,
+                json: *const std::os::raw::c_char,
+            ),
+        ) {
+            $crate::metrics::metrics_json(user_data, o_cb)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_0, call_1, TestError};
+    use crate::{catch_unwind_cb, OpaqueCtx};
+
+    extern "C" fn ffi_check(
+        fail: bool,
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+    ) {
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        catch_unwind_cb(user_data, o_cb, || -> Result<_, TestError> {
+            if fail {
+                return Err(TestError::from("failed"));
+            }
+            o_cb(user_data.as_ptr(), FFI_RESULT_OK);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn calls_are_recorded() {
+        for fail in [false, false, true] {
+            let _ = call_0(|ud, cb| ffi_check(fail, ud, cb));
+        }
+
+        let metrics = snapshot();
+        let check = &metrics["sn_ffi_utils::metrics::tests::ffi_check"];
+        assert_eq!(check.calls, 3);
+        assert_eq!(check.errors.values().sum::<u64>(), 1);
+        assert_eq!(check.latency.counts.iter().sum::<u64>(), 3);
+        assert!(check.latency.mean().is_some());
+
+        let json: String = unsafe { unwrap::unwrap!(call_1(metrics_json)) };
+        assert!(json.starts_with("{\"latency_buckets_us\":[10,100,"));
+        assert!(json.contains("\"sn_ffi_utils::metrics::tests::ffi_check\":{\"calls\":3,"));
+    }
+}
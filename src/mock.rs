@@ -0,0 +1,377 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Mock native library, for developing and testing bindings before the real one is available.
+//!
+//! With the `mock` feature, the crate exports a few `mock_*` functions covering each calling
+//! convention it supports: asynchronous callbacks with strings and byte vectors, errors with
+//! descriptions, objects behind handles with synchronous getters, and streams of values followed
+//! by a completion callback. Callbacks of the asynchronous functions arrive on a worker of the
+//! global dispatcher, as they would from a real library.
+//!
+//! Each call is recorded, so that binding tests can check which functions were reached with
+//! `mock_call_count`, and start afresh with `mock_reset`.
+//!
+//! The `sn_ffi_utils_mock` crate of this repository builds the functions into a shared library
+//! for bindings to link against:
+//!
+//! ```text
+//! cargo build -p sn_ffi_utils_mock --release
+//! ```
+
+use crate::dispatcher::{self, DispatchError};
+use crate::handles::{self, Handle, HandleError};
+use crate::{
+    catch_unwind_cb, catch_unwind_out, catch_unwind_status, decode_arg, ffi_getter,
+    vec_clone_from_raw_parts, ErrorCode, FfiResult, InvalidArg, OpaqueCtx, FFI_RESULT_OK,
+};
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fmt::{self, Display};
+use std::os::raw::{c_char, c_void};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Error reported by the mock functions.
+#[derive(Debug)]
+pub enum MockError {
+    /// An argument failed to convert.
+    Arg(InvalidArg),
+    /// A handle isn't valid.
+    Handle(HandleError),
+    /// The callback couldn't be queued.
+    Dispatch(DispatchError),
+    /// Error requested with `mock_fail`.
+    Requested {
+        /// Error code.
+        code: i32,
+        /// Description.
+        description: String,
+    },
+    /// Any other error.
+    Other(String),
+}
+
+/// Error code of `MockError::Other`.
+pub const ERR_MOCK: i32 = -1;
+
+impl ErrorCode for MockError {
+    fn error_code(&self) -> i32 {
+        match self {
+            MockError::Arg(e) => e.error_code(),
+            MockError::Handle(e) => e.error_code(),
+            MockError::Dispatch(e) => e.error_code(),
+            MockError::Requested { code, .. } => *code,
+            MockError::Other(_) => ERR_MOCK,
+        }
+    }
+}
+
+impl Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MockError::Arg(e) => write!(f, "{}", e),
+            MockError::Handle(e) => write!(f, "{}", e),
+            MockError::Dispatch(e) => write!(f, "{}", e),
+            MockError::Requested { description, .. } => write!(f, "{}", description),
+            MockError::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<InvalidArg> for MockError {
+    fn from(e: InvalidArg) -> Self {
+        MockError::Arg(e)
+    }
+}
+
+impl From<HandleError> for MockError {
+    fn from(e: HandleError) -> Self {
+        MockError::Handle(e)
+    }
+}
+
+impl From<DispatchError> for MockError {
+    fn from(e: DispatchError) -> Self {
+        MockError::Dispatch(e)
+    }
+}
+
+impl<'a> From<&'a str> for MockError {
+    fn from(s: &'a str) -> Self {
+        MockError::Other(s.to_owned())
+    }
+}
+
+static CALLS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+fn record(name: &'static str) {
+    *CALLS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(name)
+        .or_insert(0) += 1;
+}
+
+/// Number of calls to the mock function `name` since the last `mock_reset`. Unknown or invalid
+/// names have no calls.
+///
+/// # Safety
+///
+/// `name` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mock_call_count(name: *const c_char) -> u64 {
+    if name.is_null() {
+        return 0;
+    }
+    let name = CStr::from_ptr(name).to_string_lossy();
+    CALLS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&*name)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Forget the recorded calls.
+#[no_mangle]
+pub extern "C" fn mock_reset() {
+    CALLS.lock().unwrap_or_else(PoisonError::into_inner).clear();
+}
+
+/// Call `o_cb` with `input`, from a dispatcher worker.
+///
+/// # Safety
+///
+/// `input` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mock_echo(
+    input: *const c_char,
+    user_data: *mut c_void,
+    o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, output: *const c_char),
+) {
+    record("mock_echo");
+    catch_unwind_cb(user_data, o_cb, || -> Result<_, MockError> {
+        let input = decode_arg::<String>("input", input)?;
+        dispatcher::global().dispatch_cb(user_data, o_cb, Ok::<_, MockError>(input))?;
+        Ok(())
+    })
+}
+
+/// Call `o_cb` with the `len` bytes at `data` in reverse order, from a dispatcher worker.
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mock_reverse(
+    data: *const u8,
+    len: usize,
+    user_data: *mut c_void,
+    o_cb: extern "C" fn(
+        user_data: *mut c_void,
+        result: *const FfiResult,
+        data: *const u8,
+        len: usize,
+    ),
+) {
+    record("mock_reverse");
+    catch_unwind_cb(user_data, o_cb, || -> Result<_, MockError> {
+        if data.is_null() && len > 0 {
+            return Err(MockError::from("Null data"));
+        }
+        let mut reversed = if len == 0 {
+            Vec::new()
+        } else {
+            vec_clone_from_raw_parts(data, len)
+        };
+        reversed.reverse();
+
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        dispatcher::global().dispatch(move || {
+            o_cb(
+                user_data.as_ptr(),
+                FFI_RESULT_OK,
+                reversed.as_ptr(),
+                reversed.len(),
+            )
+        })?;
+        Ok(())
+    })
+}
+
+/// Call `o_cb` with `error_code` and `description`.
+///
+/// # Safety
+///
+/// `description` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mock_fail(
+    error_code: i32,
+    description: *const c_char,
+    user_data: *mut c_void,
+    o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+) {
+    record("mock_fail");
+    catch_unwind_cb(user_data, o_cb, || -> Result<(), MockError> {
+        let description = if description.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(description).to_string_lossy().into_owned()
+        };
+        Err(MockError::Requested {
+            code: error_code,
+            description,
+        })
+    })
+}
+
+/// Counter living in the global handle registry.
+pub struct Counter(AtomicU64);
+
+/// Create a counter starting at `start`, writing its handle to `o_handle`. Returns 0 on success,
+/// or an error code.
+///
+/// # Safety
+///
+/// `o_handle` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mock_counter_new(start: u64, o_handle: *mut Handle) -> i32 {
+    record("mock_counter_new");
+    catch_unwind_out(o_handle, || -> Result<_, MockError> {
+        Ok(handles::register(Counter(AtomicU64::new(start))))
+    })
+}
+
+/// Add `amount` to the counter behind `handle`, and call `o_cb` with its new value from a
+/// dispatcher worker.
+#[no_mangle]
+pub extern "C" fn mock_counter_add(
+    handle: Handle,
+    amount: u64,
+    user_data: *mut c_void,
+    o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, value: u64),
+) {
+    record("mock_counter_add");
+    catch_unwind_cb(user_data, o_cb, || -> Result<_, MockError> {
+        let value = handles::with(handle, |counter: &Counter| {
+            counter
+                .0
+                .fetch_add(amount, Ordering::SeqCst)
+                .wrapping_add(amount)
+        })?;
+        dispatcher::global().dispatch_cb(user_data, o_cb, Ok::<_, MockError>(value))?;
+        Ok(())
+    })
+}
+
+ffi_getter! {
+    /// Write the value of the counter behind `handle` to `o_out`. Returns 0 on success, or an
+    /// error code.
+    pub fn mock_counter_value(counter: Counter) -> Result<u64, MockError> {
+        record("mock_counter_value");
+        Ok(counter.0.load(Ordering::SeqCst))
+    }
+}
+
+/// Release the counter behind `handle`. Returns 0 on success, or an error code.
+#[no_mangle]
+pub extern "C" fn mock_counter_free(handle: Handle) -> i32 {
+    record("mock_counter_free");
+    catch_unwind_status(|| -> Result<_, MockError> { Ok(handles::free::<Counter>(handle)?) })
+}
+
+/// Call `o_data` with each number from 1 to `count`, then `o_done`, from a dispatcher worker.
+#[no_mangle]
+pub extern "C" fn mock_count_up(
+    count: u32,
+    user_data: *mut c_void,
+    o_data: extern "C" fn(user_data: *mut c_void, value: u32),
+    o_done: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+) {
+    record("mock_count_up");
+    catch_unwind_cb(user_data, o_done, || -> Result<_, MockError> {
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        dispatcher::global().dispatch(move || {
+            for value in 1..=count {
+                o_data(user_data.as_ptr(), value);
+            }
+            o_done(user_data.as_ptr(), FFI_RESULT_OK)
+        })?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_0, call_1, call_stream, call_vec_u8, sync_call_1};
+    use crate::{ERR_INVALID_ARG, ERR_NULL_OUT_PARAM};
+    use std::ffi::CString;
+    use std::ptr;
+    use unwrap::unwrap;
+
+    fn calls(name: &str) -> u64 {
+        let name = unwrap!(CString::new(name));
+        unsafe { mock_call_count(name.as_ptr()) }
+    }
+
+    #[test]
+    fn mock_surface() {
+        let input = unwrap!(CString::new("hello"));
+        let output: String = unsafe { unwrap!(call_1(|ud, cb| mock_echo(input.as_ptr(), ud, cb))) };
+        assert_eq!(output, "hello");
+        let result: Result<String, _> = unsafe { call_1(|ud, cb| mock_echo(ptr::null(), ud, cb)) };
+        assert_eq!(result, Err(ERR_INVALID_ARG));
+
+        let data = [1, 2, 3];
+        let reversed = unsafe {
+            unwrap!(call_vec_u8(|ud, cb| mock_reverse(
+                data.as_ptr(),
+                data.len(),
+                ud,
+                cb
+            )))
+        };
+        assert_eq!(reversed, [3, 2, 1]);
+
+        let description = unwrap!(CString::new("requested"));
+        let result = unsafe { call_0(|ud, cb| mock_fail(-42, description.as_ptr(), ud, cb)) };
+        assert_eq!(result, Err(-42));
+
+        let counter: Handle =
+            unwrap!(unsafe { sync_call_1(|o_handle| mock_counter_new(40, o_handle)) });
+        let value: u64 = unsafe { unwrap!(call_1(|ud, cb| mock_counter_add(counter, 2, ud, cb))) };
+        assert_eq!(value, 42);
+        assert_eq!(
+            unsafe { sync_call_1(|o_out| mock_counter_value(counter, o_out)) },
+            Ok(42u64)
+        );
+        assert_eq!(
+            unsafe { mock_counter_value(counter, ptr::null_mut()) },
+            ERR_NULL_OUT_PARAM
+        );
+        assert_eq!(mock_counter_free(counter), 0);
+        assert_eq!(mock_counter_free(counter), handles::ERR_INVALID_HANDLE);
+
+        let values: Vec<u32> = unsafe {
+            unwrap!(call_stream(|ud, data, done| mock_count_up(
+                3, ud, data, done
+            )))
+        };
+        assert_eq!(values, [1, 2, 3]);
+
+        assert_eq!(calls("mock_echo"), 2);
+        assert_eq!(calls("mock_counter_free"), 2);
+        assert_eq!(calls("mock_count_up"), 1);
+        assert_eq!(calls("unknown"), 0);
+        mock_reset();
+        assert_eq!(calls("mock_echo"), 0);
+    }
+}
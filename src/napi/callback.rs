@@ -0,0 +1,180 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::{check, ffi_result_to_js_error, NapiResult, ToJs};
+use crate::{shield, FfiResult, NativeResult, ReprC};
+use log::error;
+use napi_sys::*;
+use std::fmt::Debug;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+type Complete = Box<dyn FnOnce(napi_env) -> NapiResult<napi_value> + Send>;
+type Completion = Result<Complete, NativeResult>;
+
+/// JS callback which can be invoked once from any thread, through an N-API thread-safe
+/// function.
+pub struct JsCallback {
+    tsfn: napi_threadsafe_function,
+}
+
+// Thread-safe functions are meant to be called from any thread.
+unsafe impl Send for JsCallback {}
+unsafe impl Sync for JsCallback {}
+
+impl JsCallback {
+    /// Wrap the JS function `func`. `name` identifies the call in async diagnostics.
+    ///
+    /// # Safety
+    ///
+    /// Must be called on the JS thread, with its `env`.
+    pub unsafe fn new(env: napi_env, func: napi_value, name: &str) -> NapiResult<Self> {
+        let mut resource_name = ptr::null_mut();
+        check(napi_create_string_utf8(
+            env,
+            name.as_ptr() as *const c_char,
+            name.len(),
+            &mut resource_name,
+        ))?;
+
+        let mut tsfn = ptr::null_mut();
+        check(napi_create_threadsafe_function(
+            env,
+            func,
+            ptr::null_mut(),
+            resource_name,
+            0,
+            1,
+            ptr::null_mut(),
+            None,
+            ptr::null_mut(),
+            Some(call_js),
+            &mut tsfn,
+        ))?;
+
+        Ok(Self { tsfn })
+    }
+
+    /// Transfer the callback into a `user_data` pointer for one of the `callback_*` functions.
+    pub fn into_user_data(self) -> *mut c_void {
+        Box::into_raw(Box::new(self)) as *mut c_void
+    }
+
+    // Queue the completion for the JS thread and release the thread-safe function.
+    fn complete(self, completion: Completion) {
+        let data = Box::into_raw(Box::new(completion));
+        unsafe {
+            if let Err(e) = check(napi_call_threadsafe_function(
+                self.tsfn,
+                data as *mut c_void,
+                ThreadsafeFunctionCallMode::blocking,
+            )) {
+                error!("Failed to queue JS callback: {}", e);
+                drop(Box::from_raw(data));
+            }
+            let _ =
+                napi_release_threadsafe_function(self.tsfn, ThreadsafeFunctionReleaseMode::release);
+        }
+    }
+}
+
+// Invoke the JS callback with the completion, on the JS thread.
+unsafe extern "C" fn call_js(
+    env: napi_env,
+    js_callback: napi_value,
+    _context: *mut c_void,
+    data: *mut c_void,
+) {
+    let completion = Box::from_raw(data as *mut Completion);
+    // `env` is null if the environment is being torn down.
+    if env.is_null() {
+        return;
+    }
+
+    let res = (|| -> NapiResult<()> {
+        let mut null = ptr::null_mut();
+        check(napi_get_null(env, &mut null))?;
+        let undefined = ().to_js(env)?;
+
+        let args = match *completion {
+            Ok(complete) => [null, complete(env)?],
+            Err(result) => [ffi_result_to_js_error(env, &result)?, undefined],
+        };
+
+        let mut ret = ptr::null_mut();
+        check(napi_call_function(
+            env,
+            undefined,
+            js_callback,
+            args.len(),
+            args.as_ptr(),
+            &mut ret,
+        ))
+    })();
+
+    if let Err(e) = res {
+        error!("Failed to invoke JS callback: {}", e);
+    }
+}
+
+unsafe fn complete<F>(user_data: *mut c_void, res: *const FfiResult, value: F)
+where
+    F: FnOnce() -> Completion,
+{
+    let callback = *Box::from_raw(user_data as *mut JsCallback);
+    let completion = if (*res).error_code == 0 {
+        value()
+    } else {
+        Err(
+            NativeResult::clone_from_repr_c(res).unwrap_or_else(|_| NativeResult {
+                error_code: (*res).error_code,
+                description: None,
+            }),
+        )
+    };
+    callback.complete(completion);
+}
+
+/// Callback taking no value, invoking the `JsCallback` behind `user_data` with `(err)`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `JsCallback::into_user_data`, and must not be used
+/// afterwards.
+pub unsafe extern "C" fn callback_0(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || {
+        complete(user_data, res, || -> Completion {
+            Ok(Box::new(|env| ().to_js(env)))
+        })
+    })
+}
+
+/// Callback taking a value, invoking the `JsCallback` behind `user_data` with `(err, value)`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `JsCallback::into_user_data`, and must not be used
+/// afterwards.
+pub unsafe extern "C" fn callback_1<T>(user_data: *mut c_void, res: *const FfiResult, value: T::C)
+where
+    T: ReprC + ToJs + Send + 'static,
+    T::Error: Debug,
+{
+    shield(move || {
+        complete(user_data, res, || -> Completion {
+            match T::clone_from_repr_c(value) {
+                Ok(value) => Ok(Box::new(move |env| value.to_js(env))),
+                Err(e) => Err(NativeResult {
+                    error_code: -1,
+                    description: Some(format!("Invalid callback argument: {:?}", e)),
+                }),
+            }
+        })
+    })
+}
@@ -0,0 +1,429 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::{check, NapiError, NapiResult};
+use crate::payload::{self, PayloadTooLarge, NAPI_MAX_STRING_LEN};
+use crate::NativeResult;
+use log::error;
+use napi_sys::*;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+/// Conversion of a native value into a JS value.
+pub trait ToJs {
+    /// Convert `self` into a JS value.
+    ///
+    /// # Safety
+    ///
+    /// `env` must be the environment of the current JS thread.
+    unsafe fn to_js(self, env: napi_env) -> NapiResult<napi_value>;
+}
+
+impl ToJs for () {
+    unsafe fn to_js(self, env: napi_env) -> NapiResult<napi_value> {
+        let mut value = ptr::null_mut();
+        check(napi_get_undefined(env, &mut value))?;
+        Ok(value)
+    }
+}
+
+impl ToJs for bool {
+    unsafe fn to_js(self, env: napi_env) -> NapiResult<napi_value> {
+        let mut value = ptr::null_mut();
+        check(napi_get_boolean(env, self, &mut value))?;
+        Ok(value)
+    }
+}
+
+macro_rules! impl_to_js_number {
+    ($($ty:ty => $create:ident as $js_ty:ty),*) => {
+        $(
+            impl ToJs for $ty {
+                #[allow(trivial_numeric_casts)]
+                unsafe fn to_js(self, env: napi_env) -> NapiResult<napi_value> {
+                    let mut value = ptr::null_mut();
+                    check($create(env, self as $js_ty, &mut value))?;
+                    Ok(value)
+                }
+            }
+        )*
+    };
+}
+
+// 64-bit integers become doubles, which are exact up to 2^53.
+impl_to_js_number!(
+    i32 => napi_create_int32 as i32,
+    u32 => napi_create_uint32 as u32,
+    i64 => napi_create_int64 as i64,
+    u64 => napi_create_double as f64,
+    usize => napi_create_double as f64
+);
+
+impl ToJs for String {
+    unsafe fn to_js(self, env: napi_env) -> NapiResult<napi_value> {
+        string_to_js(env, &self)
+    }
+}
+
+impl ToJs for Vec<u8> {
+    unsafe fn to_js(self, env: napi_env) -> NapiResult<napi_value> {
+        bytes_to_array_buffer(env, self)
+    }
+}
+
+// Payloads are fitted within the configured limits. Strings beyond the V8 limit would otherwise
+// fail with an opaque status.
+fn payload_error(error: PayloadTooLarge) -> NapiError {
+    error!("{}", error);
+    NapiError(Status::napi_invalid_arg)
+}
+
+unsafe fn string_to_js(env: napi_env, s: &str) -> NapiResult<napi_value> {
+    let s = payload::fit_value_str(s, NAPI_MAX_STRING_LEN).map_err(payload_error)?;
+
+    let mut value = ptr::null_mut();
+    check(napi_create_string_utf8(
+        env,
+        s.as_ptr() as *const c_char,
+        s.len(),
+        &mut value,
+    ))?;
+    Ok(value)
+}
+
+/// Create a JS `Error` for a failed call, with the description as its message, and the error
+/// code as its `code` (a string, as for Node's own errors) and `errorCode` (a number)
+/// properties.
+///
+/// # Safety
+///
+/// `env` must be the environment of the current JS thread.
+pub unsafe fn ffi_result_to_js_error(
+    env: napi_env,
+    result: &NativeResult,
+) -> NapiResult<napi_value> {
+    let code = string_to_js(env, &result.error_code.to_string())?;
+    let msg = string_to_js(env, result.description.as_deref().unwrap_or_default())?;
+
+    let mut error = ptr::null_mut();
+    check(napi_create_error(env, code, msg, &mut error))?;
+    check(napi_set_named_property(
+        env,
+        error,
+        b"errorCode\0".as_ptr() as *const c_char,
+        result.error_code.to_js(env)?,
+    ))?;
+    Ok(error)
+}
+
+/// Move `data` into a JS `ArrayBuffer`, fitted within the configured payload limits.
+///
+/// The buffer is handed over without copying, and freed when the `ArrayBuffer` is collected.
+/// Runtimes which don't allow external buffers (such as Electron) get a copy instead.
+///
+/// # Safety
+///
+/// `env` must be the environment of the current JS thread.
+pub unsafe fn bytes_to_array_buffer(env: napi_env, mut data: Vec<u8>) -> NapiResult<napi_value> {
+    let len = payload::fit_value_bytes(&data, usize::MAX)
+        .map_err(payload_error)?
+        .len();
+    data.truncate(len);
+
+    let mut value = ptr::null_mut();
+    let mut data = data.into_boxed_slice();
+    let len = data.len();
+    let ptr = data.as_mut_ptr();
+
+    if len > 0 {
+        let hint = Box::into_raw(Box::new(data));
+        match check(napi_create_external_arraybuffer(
+            env,
+            ptr as *mut c_void,
+            len,
+            Some(free_bytes),
+            hint as *mut c_void,
+            &mut value,
+        )) {
+            Ok(()) => return Ok(value),
+            Err(NapiError(Status::napi_no_external_buffers_allowed)) => data = *Box::from_raw(hint),
+            Err(error) => {
+                drop(Box::from_raw(hint));
+                return Err(error);
+            }
+        }
+    }
+
+    let mut copy = ptr::null_mut();
+    check(napi_create_arraybuffer(env, len, &mut copy, &mut value))?;
+    if len > 0 {
+        ptr::copy_nonoverlapping(data.as_ptr(), copy as *mut u8, len);
+    }
+    Ok(value)
+}
+
+unsafe extern "C" fn free_bytes(_env: napi_env, _data: *mut c_void, hint: *mut c_void) {
+    drop(Box::from_raw(hint as *mut Box<[u8]>));
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::slice;
+    use unwrap::unwrap;
+
+    // Fake N-API, exported from the test executable, whose values are leaked `RefCell<Js>`s.
+    #[derive(Debug, PartialEq)]
+    enum Js {
+        Undefined,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Error(Vec<(String, napi_value)>),
+        ArrayBuffer { data: Vec<u8>, external: bool },
+    }
+
+    thread_local! {
+        static EXTERNAL_BUFFERS_ALLOWED: Cell<bool> = const { Cell::new(true) };
+    }
+
+    unsafe fn create(result: *mut napi_value, value: Js) -> napi_status {
+        *result = Box::into_raw(Box::new(RefCell::new(value))) as napi_value;
+        Status::napi_ok
+    }
+
+    unsafe fn get<'a>(value: napi_value) -> &'a RefCell<Js> {
+        &*(value as *const RefCell<Js>)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_get_undefined(_: napi_env, result: *mut napi_value) -> napi_status {
+        create(result, Js::Undefined)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_get_boolean(
+        _: napi_env,
+        value: bool,
+        result: *mut napi_value,
+    ) -> napi_status {
+        create(result, Js::Bool(value))
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_int32(
+        _: napi_env,
+        value: i32,
+        result: *mut napi_value,
+    ) -> napi_status {
+        create(result, Js::Number(value.into()))
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_uint32(
+        _: napi_env,
+        value: u32,
+        result: *mut napi_value,
+    ) -> napi_status {
+        create(result, Js::Number(value.into()))
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_int64(
+        _: napi_env,
+        value: i64,
+        result: *mut napi_value,
+    ) -> napi_status {
+        create(result, Js::Number(value as f64))
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_double(
+        _: napi_env,
+        value: f64,
+        result: *mut napi_value,
+    ) -> napi_status {
+        create(result, Js::Number(value))
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_string_utf8(
+        _: napi_env,
+        s: *const c_char,
+        len: usize,
+        result: *mut napi_value,
+    ) -> napi_status {
+        let s = String::from_utf8_lossy(slice::from_raw_parts(s as *const u8, len));
+        create(result, Js::String(s.into_owned()))
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_error(
+        _: napi_env,
+        code: napi_value,
+        msg: napi_value,
+        result: *mut napi_value,
+    ) -> napi_status {
+        let props = vec![("code".to_owned(), code), ("message".to_owned(), msg)];
+        create(result, Js::Error(props))
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_set_named_property(
+        _: napi_env,
+        object: napi_value,
+        name: *const c_char,
+        value: napi_value,
+    ) -> napi_status {
+        match &mut *get(object).borrow_mut() {
+            Js::Error(props) => {
+                let name = std::ffi::CStr::from_ptr(name).to_string_lossy();
+                props.push((name.into_owned(), value));
+                Status::napi_ok
+            }
+            _ => Status::napi_object_expected,
+        }
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_external_arraybuffer(
+        env: napi_env,
+        data: *mut c_void,
+        len: usize,
+        finalize: napi_finalize,
+        hint: *mut c_void,
+        result: *mut napi_value,
+    ) -> napi_status {
+        if !EXTERNAL_BUFFERS_ALLOWED.with(Cell::get) {
+            return Status::napi_no_external_buffers_allowed;
+        }
+
+        let data = slice::from_raw_parts(data as *const u8, len).to_vec();
+        // Collect the buffer right away, as the garbage collector eventually would.
+        unwrap!(finalize)(env, ptr::null_mut(), hint);
+        create(
+            result,
+            Js::ArrayBuffer {
+                data,
+                external: true,
+            },
+        )
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn napi_create_arraybuffer(
+        _: napi_env,
+        len: usize,
+        data: *mut *mut c_void,
+        result: *mut napi_value,
+    ) -> napi_status {
+        let status = create(
+            result,
+            Js::ArrayBuffer {
+                data: vec![0; len],
+                external: false,
+            },
+        );
+        if let Js::ArrayBuffer { data: buffer, .. } = &mut *get(*result).borrow_mut() {
+            *data = buffer.as_mut_ptr() as *mut c_void;
+        }
+        status
+    }
+
+    fn to_js<T: ToJs>(value: T) -> Js {
+        unsafe {
+            crate::napi::init();
+            let value = unwrap!(value.to_js(ptr::null_mut()));
+            get(value).replace(Js::Undefined)
+        }
+    }
+
+    fn take(value: napi_value) -> Js {
+        unsafe { get(value).replace(Js::Undefined) }
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(to_js(()), Js::Undefined);
+        assert_eq!(to_js(true), Js::Bool(true));
+        assert_eq!(to_js(-7i32), Js::Number(-7.0));
+        assert_eq!(to_js(7u32), Js::Number(7.0));
+        assert_eq!(to_js(-(1i64 << 40)), Js::Number(-((1u64 << 40) as f64)));
+        assert_eq!(to_js(1u64 << 53), Js::Number((1u64 << 53) as f64));
+        assert_eq!(to_js(3usize), Js::Number(3.0));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(to_js(String::new()), Js::String(String::new()));
+        assert_eq!(
+            to_js("héllo wörld".to_owned()),
+            Js::String("héllo wörld".to_owned())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let result = NativeResult {
+            error_code: -5,
+            description: Some("Boom".to_owned()),
+        };
+        let error = unsafe {
+            crate::napi::init();
+            unwrap!(ffi_result_to_js_error(ptr::null_mut(), &result))
+        };
+
+        let props = match take(error) {
+            Js::Error(props) => props,
+            value => panic!("not an error: {:?}", value),
+        };
+        let props: Vec<_> = props
+            .into_iter()
+            .map(|(name, value)| (name, take(value)))
+            .collect();
+        assert_eq!(
+            props,
+            vec![
+                ("code".to_owned(), Js::String("-5".to_owned())),
+                ("message".to_owned(), Js::String("Boom".to_owned())),
+                ("errorCode".to_owned(), Js::Number(-5.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn array_buffers() {
+        assert_eq!(
+            to_js(vec![1u8, 2, 3]),
+            Js::ArrayBuffer {
+                data: vec![1, 2, 3],
+                external: true,
+            }
+        );
+
+        // Empty buffers and runtimes without external buffers get a copy.
+        assert_eq!(
+            to_js(Vec::<u8>::new()),
+            Js::ArrayBuffer {
+                data: Vec::new(),
+                external: false,
+            }
+        );
+        EXTERNAL_BUFFERS_ALLOWED.with(|allowed| allowed.set(false));
+        assert_eq!(
+            to_js(vec![4u8, 5]),
+            Js::ArrayBuffer {
+                data: vec![4, 5],
+                external: false,
+            }
+        );
+        EXTERNAL_BUFFERS_ALLOWED.with(|allowed| allowed.set(true));
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Node.js N-API utilities.
+//!
+//! A Node addon converts the JS callback of a call into a `JsCallback`, passes it as
+//! `user_data` together with one of the `callback_*` functions, and the result is delivered to
+//! the JS callback on the JS thread, Node-style (`cb(err, value)`), whichever thread the native
+//! callback is invoked on:
+//!
+//! ```ignore
+//! unsafe extern "C" fn fetch(env: napi_env, info: napi_callback_info) -> napi_value {
+//!     let (app, js_cb) = args(env, info);
+//!     match JsCallback::new(env, js_cb, "fetch") {
+//!         Ok(callback) => app_fetch(app, callback.into_user_data(), napi::callback_1::<String>),
+//!         Err(e) => log::error!("{}", e),
+//!     }
+//!     ptr::null_mut()
+//! }
+//! ```
+//!
+//! N-API symbols are resolved from the host process at runtime, so `init` must be called from
+//! the addon's registration function before anything else.
+
+mod callback;
+mod convert;
+
+pub use self::callback::{callback_0, callback_1, JsCallback};
+pub use self::convert::{bytes_to_array_buffer, ffi_result_to_js_error, ToJs};
+
+use napi_sys::{napi_status, Status};
+use std::fmt::{self, Display};
+use std::sync::Once;
+
+/// Result of N-API calls.
+pub type NapiResult<T> = Result<T, NapiError>;
+
+/// Failed N-API call.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NapiError(pub napi_status);
+
+impl Display for NapiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "N-API call failed with status {}", self.0)
+    }
+}
+
+/// Turn the status of an N-API call into a `NapiResult`.
+pub fn check(status: napi_status) -> NapiResult<()> {
+    if status == Status::napi_ok {
+        Ok(())
+    } else {
+        Err(NapiError(status))
+    }
+}
+
+/// Resolve the N-API symbols from the host process. Call it from the addon's registration
+/// function; subsequent calls do nothing.
+///
+/// # Safety
+///
+/// Must be called from within a Node.js process.
+pub unsafe fn init() {
+    static INIT: Once = Once::new();
+    // The host process is never unloaded, so its handle doesn't need to be kept.
+    INIT.call_once(|| std::mem::forget(napi_sys::setup()));
+}
@@ -0,0 +1,169 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+use std::os::raw::c_void;
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(debug_assertions)]
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+#[cfg(debug_assertions)]
+use std::thread;
+use std::thread::ThreadId;
+
+/// Opaque `user_data` pointer handed into FFI functions, which can be moved to the thread
+/// invoking the callback.
+///
+/// Rust never dereferences the pointer: it is only passed back to the host's callbacks, which
+/// are expected to accept it on any thread. This is what makes the type `Send`, and, being a
+/// `Copy` pointer value, `Sync` as well. Rust-owned state must not be smuggled through it:
+/// either box a `Send + Sync` value with `pinned`, or use `TypedCtx`.
+///
+/// The pointer remains accessible as the public field, but `as_ptr` is preferred: in debug
+/// builds, it asserts that contexts created with `thread_bound` are only used on the thread
+/// which created them.
+#[derive(Clone, Copy, Debug)]
+pub struct OpaqueCtx(pub *mut c_void);
+
+// The pointer is never dereferenced by Rust; see above.
+unsafe impl Send for OpaqueCtx {}
+unsafe impl Sync for OpaqueCtx {}
+
+impl OpaqueCtx {
+    /// Wrap a `user_data` pointer received from the host.
+    pub fn from_host_pointer(ptr: *mut c_void) -> Self {
+        OpaqueCtx(ptr)
+    }
+
+    /// Transfer ownership of a boxed value into a context, whose address stays stable until it
+    /// is reclaimed with `into_box`.
+    pub fn pinned<T: Send + Sync + 'static>(value: Box<T>) -> Self {
+        Self::from_host_pointer(Box::into_raw(value) as *mut c_void)
+    }
+
+    /// Wrap a pointer which may only be used on the current thread, until `unbind` is called. In
+    /// debug builds, using any context holding the pointer on another thread panics.
+    pub fn thread_bound(ptr: *mut c_void) -> Self {
+        #[cfg(debug_assertions)]
+        bind(ptr, Some(thread::current().id()));
+        OpaqueCtx(ptr)
+    }
+
+    /// Lift the restriction put on the pointer by `thread_bound`, e.g. before the value it
+    /// points to is released.
+    pub fn unbind(self) {
+        #[cfg(debug_assertions)]
+        bind(self.0, None);
+    }
+
+    /// Return the pointer, to pass it to a callback.
+    #[track_caller]
+    pub fn as_ptr(self) -> *mut c_void {
+        #[cfg(debug_assertions)]
+        if let Some(origin) = self.origin_thread() {
+            assert!(
+                origin == thread::current().id(),
+                "thread-bound context used on another thread than {:?}",
+                origin
+            );
+        }
+
+        self.0
+    }
+
+    /// Thread the pointer is bound to with `thread_bound`. Only recorded in debug builds; always
+    /// `None` in release builds.
+    pub fn origin_thread(self) -> Option<ThreadId> {
+        #[cfg(debug_assertions)]
+        {
+            if BOUND_COUNT.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+            bound().get(&(self.0 as usize)).copied()
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            None
+        }
+    }
+
+    /// Take back ownership of a value transferred with `pinned`.
+    ///
+    /// # Safety
+    ///
+    /// The context must have been created by `pinned` with a `Box<T>`, and neither it nor its
+    /// copies may be used afterwards.
+    pub unsafe fn into_box<T>(self) -> Box<T> {
+        Box::from_raw(self.as_ptr() as *mut T)
+    }
+}
+
+// Threads the pointers created with `thread_bound` are bound to, keyed by address. The count
+// lets `as_ptr` skip the lock while no pointer is bound.
+#[cfg(debug_assertions)]
+static BOUND: OnceLock<Mutex<HashMap<usize, ThreadId>>> = OnceLock::new();
+#[cfg(debug_assertions)]
+static BOUND_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(debug_assertions)]
+fn bound() -> MutexGuard<'static, HashMap<usize, ThreadId>> {
+    BOUND
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(debug_assertions)]
+fn bind(ptr: *mut c_void, thread: Option<ThreadId>) {
+    let mut bound = bound();
+    match thread {
+        Some(thread) => {
+            let _ = bound.insert(ptr as usize, thread);
+        }
+        None => {
+            let _ = bound.remove(&(ptr as usize));
+        }
+    }
+    BOUND_COUNT.store(bound.len(), Ordering::Release);
+}
+
+impl From<OpaqueCtx> for *mut c_void {
+    fn from(ctx: OpaqueCtx) -> Self {
+        ctx.as_ptr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use unwrap::unwrap;
+
+    #[test]
+    fn pinned_round_trip() {
+        let ctx = OpaqueCtx::pinned(Box::new(42u64));
+        let value = unwrap!(thread::spawn(move || *unsafe { ctx.into_box::<u64>() }).join());
+        assert_eq!(value, 42);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn thread_bound_assertion() {
+        let mut value = 0;
+        let ctx = OpaqueCtx::thread_bound(std::ptr::from_mut(&mut value) as *mut c_void);
+        assert_eq!(ctx.origin_thread(), Some(thread::current().id()));
+        let _ = ctx.as_ptr();
+
+        assert!(thread::spawn(move || ctx.as_ptr() as usize).join().is_err());
+        ctx.unbind();
+        assert_eq!(ctx.origin_thread(), None);
+        assert!(thread::spawn(move || ctx.as_ptr() as usize).join().is_ok());
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Optional values passed across the FFI as `FfiOption`, so that "no value" is never encoded as
+//! a null pointer or a special error code.
+//!
+//! `Option<T>` converts to and from `FfiOption<T::C>` for any `T` implementing `ReprC`, so
+//! optional values work with callbacks and out-parameters like any other:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_find_peer(
+//!     app: Handle,
+//!     name: *const c_char,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(
+//!         user_data: *mut c_void,
+//!         result: *const FfiResult,
+//!         peer: FfiOption<*const c_char>,
+//!     ),
+//! ) {
+//!     catch_unwind_cb(user_data, o_cb, || -> Result<_, AppError> {
+//!         let name = decode_arg::<String>("name", name)?;
+//!         let address: Option<String> = handles::get::<App>(app)?.find_peer(&name);
+//!         dispatcher::global().dispatch_cb(user_data, o_cb, Ok::<_, AppError>(address))?;
+//!         Ok(())
+//!     })
+//! }
+//! ```
+
+use crate::callback::CallbackArgs;
+use crate::repr_c::{IntoReprC, ReprC};
+
+/// Tag of an `FfiOption` holding no value.
+pub const FFI_OPTION_NONE: u32 = 0;
+/// Tag of an `FfiOption` holding a value.
+pub const FFI_OPTION_SOME: u32 = 1;
+
+/// Optional value in its FFI representation.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FfiOption<T> {
+    /// `FFI_OPTION_SOME` if `value` holds a value, `FFI_OPTION_NONE` otherwise. Any other tag is
+    /// read as `FFI_OPTION_SOME`.
+    pub tag: u32,
+    /// The value, or a default value (0, or a null pointer) which must be ignored if `tag` is
+    /// `FFI_OPTION_NONE`.
+    pub value: T,
+}
+
+impl<T> FfiOption<T> {
+    /// Option holding `value`.
+    pub fn some(value: T) -> Self {
+        Self {
+            tag: FFI_OPTION_SOME,
+            value,
+        }
+    }
+
+    /// Return `true` if the option holds a value.
+    pub fn is_some(&self) -> bool {
+        self.tag != FFI_OPTION_NONE
+    }
+
+    /// Convert into an `Option`, still in the FFI representation.
+    pub fn into_option(self) -> Option<T> {
+        if self.is_some() {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: CallbackArgs> FfiOption<T> {
+    /// Option holding no value.
+    pub fn none() -> Self {
+        Self {
+            tag: FFI_OPTION_NONE,
+            value: T::default(),
+        }
+    }
+}
+
+impl<T: CallbackArgs> CallbackArgs for FfiOption<T> {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl<T: ReprC> ReprC for Option<T> {
+    type C = FfiOption<T::C>;
+    type Error = T::Error;
+
+    unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
+        repr_c
+            .into_option()
+            .map(|value| T::clone_from_repr_c(value))
+            .transpose()
+    }
+}
+
+impl<T> IntoReprC for Option<T>
+where
+    T: IntoReprC,
+    T::C: CallbackArgs,
+{
+    type Storage = Option<T::Storage>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        match self {
+            Some(value) => {
+                let (repr_c, storage) = value.into_repr_c()?;
+                Ok((FfiOption::some(repr_c), Some(storage)))
+            }
+            None => Ok((FfiOption::none(), None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(target_arch = "wasm32"))]
+    use crate::dispatcher;
+    use crate::test_utils::{call_1, TestError};
+    use std::ptr;
+    use unwrap::unwrap;
+
+    #[test]
+    fn conversions() {
+        for value in [Some("peer".to_owned()), None] {
+            let (repr_c, _storage) = unwrap!(value.clone().into_repr_c());
+            assert_eq!(repr_c.is_some(), value.is_some());
+            assert_eq!(
+                unsafe { unwrap!(Option::<String>::clone_from_repr_c(repr_c)) },
+                value
+            );
+        }
+
+        let none = FfiOption::<*const u8>::none();
+        assert_eq!(none.tag, FFI_OPTION_NONE);
+        assert_eq!(none.value, ptr::null());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn optional_callback_values() {
+        let found = |value: Option<u64>| -> Result<Option<u64>, i32> {
+            unsafe {
+                call_1(|ud, cb| {
+                    unwrap!(dispatcher::global().dispatch_cb(ud, cb, Ok::<_, TestError>(value)))
+                })
+            }
+        };
+        assert_eq!(found(Some(0)), Ok(Some(0)));
+        assert_eq!(found(None), Ok(None));
+    }
+}
@@ -0,0 +1,391 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Synchronous calling convention, where the value is written to an out-parameter and the
+//! error code is returned:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_peer_count(app: Handle, o_count: *mut u32) -> i32 {
+//!     catch_unwind_out(o_count, || -> Result<_, AppError> {
+//!         Ok(handles::get::<App>(app)?.peer_count())
+//!     })
+//! }
+//! ```
+//!
+//! The description of the last error on the current thread is available through `last_error`.
+//!
+//! `ffi_getter!` generates the whole function for getters reading a value from an object in the
+//! global handle registry:
+//!
+//! ```ignore
+//! ffi_getter! {
+//!     /// Number of peers the app is connected to.
+//!     pub fn app_peer_count(app: App) -> Result<u32, AppError> {
+//!         Ok(app.peer_count())
+//!     }
+//! }
+//! ```
+
+use crate::catch_unwind::catch_unwind_result;
+use crate::handles::{self, Handle, HandleError};
+#[cfg(any(test, feature = "testing"))]
+use crate::test_utils::{fault, reentrancy};
+use crate::{ffi_error, ErrorCode, IntoReprC, NativeResult};
+use log::debug;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::panic::Location;
+
+/// Error code returned when the out-parameter is null.
+pub const ERR_NULL_OUT_PARAM: i32 = -9011;
+
+/// Error returned when writing to a null out-parameter.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NullOutParam;
+
+impl ErrorCode for NullOutParam {
+    fn error_code(&self) -> i32 {
+        ERR_NULL_OUT_PARAM
+    }
+}
+
+impl Display for NullOutParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Null out-parameter")
+    }
+}
+
+impl Error for NullOutParam {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<NativeResult>> = const { RefCell::new(None) };
+}
+
+/// Return the error of the last call through `catch_unwind_out` or `catch_unwind_status` on the
+/// current thread, or `None` if it succeeded.
+pub fn last_error() -> Option<NativeResult> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+fn set_last_error(error: Option<NativeResult>) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+/// Write `value` to `o_out`.
+///
+/// # Safety
+///
+/// `o_out` must be null or valid for writes. The previous value isn't dropped.
+pub unsafe fn write_out_param<T>(o_out: *mut T, value: T) -> Result<(), NullOutParam> {
+    if o_out.is_null() {
+        Err(NullOutParam)
+    } else {
+        o_out.write(value);
+        Ok(())
+    }
+}
+
+/// Catch panics and run `f`, writing its value to `o_out` on success. Returns 0 on success, or
+/// the error code, whose description is available through `last_error`. `o_out` is left
+/// untouched on error. Null out-parameters are reported as `ERR_NULL_OUT_PARAM`, without
+/// running `f`.
+///
+/// # Safety
+///
+/// `o_out` must be null or valid for writes. The previous value isn't dropped.
+#[track_caller]
+pub unsafe fn catch_unwind_out<'a, T, F, E>(o_out: *mut T, f: F) -> i32
+where
+    F: FnOnce() -> Result<T, E>,
+    E: Debug + Display + ErrorCode + From<&'a str>,
+{
+    if o_out.is_null() {
+        return fail(ERR_NULL_OUT_PARAM, NullOutParam.to_string());
+    }
+
+    run(Location::caller(), f, |value| o_out.write(value))
+}
+
+/// Catch panics and run `f`, for functions without out-parameters. Returns 0 on success, or
+/// the error code, whose description is available through `last_error`.
+#[track_caller]
+pub fn catch_unwind_status<'a, F, E>(f: F) -> i32
+where
+    F: FnOnce() -> Result<(), E>,
+    E: Debug + Display + ErrorCode + From<&'a str>,
+{
+    run(Location::caller(), f, |()| ())
+}
+
+/// Look up the object of type `O` behind `handle` in the global registry, run `f` with it, and
+/// write the FFI representation of its value to `o_out`, like `catch_unwind_out`. Used by the
+/// functions defined with `ffi_getter!`.
+///
+/// Only values whose FFI representation owns no data are supported, as nothing would keep that
+/// data alive once the getter returns. Write strings into a buffer provided by the caller
+/// instead, see `caller_buf`.
+///
+/// # Safety
+///
+/// `o_out` must be null or valid for writes. The previous value isn't dropped.
+#[track_caller]
+pub unsafe fn get_out<'a, O, T, E, F>(handle: Handle, o_out: *mut T::C, f: F) -> i32
+where
+    O: Send + Sync + 'static,
+    F: FnOnce(&O) -> Result<T, E>,
+    T: IntoReprC<Storage = ()>,
+    T::Error: Debug,
+    E: Debug + Display + ErrorCode + From<&'a str> + From<HandleError>,
+{
+    catch_unwind_out(o_out, || -> Result<_, E> {
+        let object = handles::get::<O>(handle)?;
+        let (repr_c, ()) = f(&object)?.into_repr_c().map_err(|e| {
+            debug!(
+                "Could not convert result into its FFI representation: {:?}",
+                e
+            );
+            E::from("Could not convert result into its FFI representation")
+        })?;
+        Ok(repr_c)
+    })
+}
+
+/// Define exported getters, each reading a value from the object behind a handle of the global
+/// registry:
+///
+/// ```ignore
+/// ffi_getter! {
+///     /// Number of peers the app is connected to.
+///     pub fn app_peer_count(app: App) -> Result<u32, AppError> {
+///         Ok(app.peer_count())
+///     }
+/// }
+/// ```
+///
+/// This defines `app_peer_count(handle: Handle, o_out: *mut u32) -> i32`, which looks up the
+/// `App` behind `handle`, runs the body with `app: &App`, converts its value with `IntoReprC` and
+/// writes it to `o_out`. It returns 0 on success, or the error code: `ERR_NULL_OUT_PARAM` for a
+/// null `o_out`, the code of the `HandleError` if the lookup fails, or the code of the error
+/// returned by the body. The error type must implement `From<HandleError>` and `From<&str>`, the
+/// latter for the values failing to convert.
+///
+/// See `get_out` for the types of values supported.
+#[macro_export]
+macro_rules! ffi_getter {
+    ($(
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($object:ident: $object_ty:ty) -> Result<$out:ty, $error:ty>
+        $body:block
+    )*) => {$(
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// `o_out` must be null or valid for writes.
+        #[no_mangle]
+        $vis unsafe extern "C" fn $name(
+            handle: $crate::handles::Handle,
+            o_out: *mut <$out as $crate::ReprC>::C,
+        ) -> i32 {
+            $crate::get_out(handle, o_out, |$object: &$object_ty| -> Result<$out, $error> {
+                $body
+            })
+        }
+    )*};
+}
+
+#[cfg_attr(
+    not(any(test, feature = "testing", feature = "tracing")),
+    allow(unused_variables)
+)]
+fn run<'a, T, F, E, W>(location: &'static Location<'static>, f: F, write: W) -> i32
+where
+    F: FnOnce() -> Result<T, E>,
+    E: Debug + Display + ErrorCode + From<&'a str>,
+    W: FnOnce(T),
+{
+    #[cfg(any(test, feature = "testing"))]
+    let _reentrancy = reentrancy::enter(location);
+    #[cfg(feature = "tracing")]
+    let _span = crate::trace::call_span::<F>(location).entered();
+
+    #[cfg(any(test, feature = "testing"))]
+    if let Some(error_code) = fault::take() {
+        return fail(error_code, fault::DESCRIPTION.to_owned());
+    }
+
+    match catch_unwind_result(f) {
+        Ok(value) => {
+            write(value);
+            set_last_error(None);
+            0
+        }
+        Err(error) => {
+            let (error_code, description) = ffi_error!(error);
+            fail(error_code, description)
+        }
+    }
+}
+
+fn fail(error_code: i32, description: String) -> i32 {
+    debug!("FFI call failed with {}: {}", error_code, description);
+    set_last_error(Some(NativeResult {
+        error_code,
+        description: Some(description),
+    }));
+    error_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{sync_call_0, sync_call_1, TestError};
+    use std::ptr;
+    use unwrap::unwrap;
+
+    extern "C" fn half(value: u32, o_half: *mut u32) -> i32 {
+        unsafe {
+            catch_unwind_out(o_half, || -> Result<_, TestError> {
+                if value % 2 == 1 {
+                    return Err(TestError::from("odd"));
+                }
+                Ok(value / 2)
+            })
+        }
+    }
+
+    extern "C" fn check(value: u32) -> i32 {
+        catch_unwind_status(|| -> Result<_, TestError> {
+            if value == 0 {
+                panic!("zero");
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn out_param_calls() {
+        assert_eq!(unsafe { sync_call_1(|o_out| half(42, o_out)) }, Ok(21u32));
+        assert!(last_error().is_none());
+
+        let code = unwrap!(unsafe { sync_call_1::<u32, _>(|o_out| half(3, o_out)) }.err());
+        let error = unwrap!(last_error());
+        assert_eq!(error.error_code, code);
+        assert_eq!(error.description.as_deref(), Some("odd"));
+
+        assert_eq!(half(2, ptr::null_mut()), ERR_NULL_OUT_PARAM);
+
+        assert_eq!(sync_call_0(|| check(1)), Ok(()));
+        assert!(sync_call_0(|| check(0)).is_err());
+    }
+
+    struct Peer {
+        name: String,
+        port: u16,
+    }
+
+    #[derive(Debug)]
+    enum PeerError {
+        Handle(HandleError),
+        Other(String),
+    }
+
+    impl From<HandleError> for PeerError {
+        fn from(e: HandleError) -> Self {
+            PeerError::Handle(e)
+        }
+    }
+
+    impl<'a> From<&'a str> for PeerError {
+        fn from(s: &'a str) -> Self {
+            PeerError::Other(s.to_owned())
+        }
+    }
+
+    impl ErrorCode for PeerError {
+        fn error_code(&self) -> i32 {
+            match self {
+                PeerError::Handle(e) => e.error_code(),
+                PeerError::Other(_) => -1,
+            }
+        }
+    }
+
+    impl Display for PeerError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                PeerError::Handle(e) => write!(f, "{}", e),
+                PeerError::Other(s) => write!(f, "{}", s),
+            }
+        }
+    }
+
+    ffi_getter! {
+        fn peer_is_named(peer: Peer) -> Result<bool, PeerError> {
+            Ok(!peer.name.is_empty())
+        }
+
+        fn peer_port(peer: Peer) -> Result<u32, PeerError> {
+            if peer.port == 0 {
+                return Err(PeerError::from("not listening"));
+            }
+            Ok(u32::from(peer.port))
+        }
+    }
+
+    #[test]
+    fn getters() {
+        let peer = handles::register(Peer {
+            name: "alice".to_owned(),
+            port: 5483,
+        });
+        let idle = handles::register(Peer {
+            name: String::new(),
+            port: 0,
+        });
+
+        assert_eq!(
+            unsafe { sync_call_1(|o_out| peer_is_named(peer, o_out)) },
+            Ok(true)
+        );
+        assert_eq!(
+            unsafe { sync_call_1(|o_out| peer_is_named(idle, o_out)) },
+            Ok(false)
+        );
+        assert_eq!(
+            unsafe { sync_call_1(|o_out| peer_port(peer, o_out)) },
+            Ok(5483u32)
+        );
+
+        assert_eq!(
+            unsafe { sync_call_1::<u32, _>(|o_out| peer_port(idle, o_out)) },
+            Err(-1)
+        );
+        assert_eq!(
+            unsafe { peer_port(peer, ptr::null_mut()) },
+            ERR_NULL_OUT_PARAM
+        );
+
+        let wrong_type = handles::register(TestError::Test);
+        let code =
+            unwrap!(unsafe { sync_call_1::<u32, _>(|o_out| peer_port(wrong_type, o_out)) }.err());
+        assert_eq!(code, handles::ERR_HANDLE_TYPE_MISMATCH);
+
+        for handle in [peer, idle] {
+            unwrap!(handles::free::<Peer>(handle));
+        }
+        unwrap!(handles::free::<TestError>(wrong_type));
+        assert_eq!(
+            unsafe { sync_call_1::<u32, _>(|o_out| peer_port(peer, o_out)) },
+            Err(handles::ERR_INVALID_HANDLE)
+        );
+    }
+}
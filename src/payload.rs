@@ -0,0 +1,507 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Size limits for the strings and byte buffers delivered through callbacks.
+//!
+//! Bindings have hard practical limits on the payloads they can hand over to the host: JNI
+//! strings and arrays are indexed with a 32-bit `jsize`, and V8 strings can't exceed
+//! `NAPI_MAX_STRING_LEN` bytes. Their converters fit payloads within the configured limits,
+//! capped at their own, with `fit_value_bytes` and `fit_value_str`, instead of failing deep inside
+//! the runtime.
+//!
+//! The limits configured with `set_limits` apply to the payloads delivered with `deliver_bytes`
+//! and `deliver_str`, and their `OverflowPolicy` decides what happens to larger ones: they are
+//! truncated and flagged, rejected with `ERR_PAYLOAD_TOO_LARGE`, or split into chunks delivered
+//! one after the other, as a stream:
+//!
+//! ```ignore
+//! payload::set_limits(PayloadLimits {
+//!     max_bytes_len: 1 << 20,
+//!     policy: OverflowPolicy::Chunk,
+//!     ..PayloadLimits::default()
+//! });
+//!
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_read(
+//!     user_data: *mut c_void,
+//!     o_data: extern "C" fn(user_data: *mut c_void, data: *const u8, len: usize, truncated: bool),
+//!     o_done: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+//! ) {
+//!     catch_unwind_cb(user_data, o_done, || -> Result<_, AppError> {
+//!         let data = read()?;
+//!         payload::deliver_bytes(user_data, &data, o_data, o_done);
+//!         Ok(())
+//!     })
+//! }
+//! ```
+
+use crate::catch_unwind::call_error_cb;
+use crate::{call_cb_with_error, ErrorCode, FfiResult, ERR_INVALID_ARG, FFI_RESULT_OK};
+use log::warn;
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt::{self, Display};
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::sync::{PoisonError, RwLock};
+
+/// Error code returned for payloads exceeding their size limit.
+pub const ERR_PAYLOAD_TOO_LARGE: i32 = -9021;
+
+/// Largest string or array, in bytes, which can be created through JNI.
+pub const JNI_MAX_LEN: usize = i32::MAX as usize;
+/// Largest string, in bytes, which can be created through N-API on 64-bit V8.
+pub const NAPI_MAX_STRING_LEN: usize = (1 << 29) - 24;
+
+/// What happens to payloads exceeding their size limit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// Deliver the largest prefix within the limit, flagged as truncated.
+    Truncate,
+    /// Fail with `ERR_PAYLOAD_TOO_LARGE`.
+    Error,
+    /// Deliver the payload in chunks within the limit.
+    Chunk,
+}
+
+/// Maximum sizes, in bytes, of the payloads delivered through callbacks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PayloadLimits {
+    /// Maximum length of strings, excluding the NUL terminator.
+    pub max_string_len: usize,
+    /// Maximum length of byte buffers.
+    pub max_bytes_len: usize,
+    /// Policy for the payloads exceeding their limit.
+    pub policy: OverflowPolicy,
+}
+
+impl PayloadLimits {
+    /// No limits.
+    pub const UNLIMITED: Self = Self {
+        max_string_len: usize::MAX,
+        max_bytes_len: usize::MAX,
+        policy: OverflowPolicy::Error,
+    };
+
+    /// Fit `data` within `max_bytes_len`.
+    pub fn fit_bytes<'a>(&self, data: &'a [u8]) -> Result<Payload<'a, [u8]>, PayloadTooLarge> {
+        fit(data, self.max_bytes_len, self.policy)
+    }
+
+    /// Fit `s` within `max_string_len`, splitting it on character boundaries.
+    pub fn fit_str<'a>(&self, s: &'a str) -> Result<Payload<'a, str>, PayloadTooLarge> {
+        fit(s, self.max_string_len, self.policy)
+    }
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self::UNLIMITED
+    }
+}
+
+static LIMITS: RwLock<PayloadLimits> = RwLock::new(PayloadLimits::UNLIMITED);
+
+/// Set the limits of the payloads delivered with `deliver_bytes` and `deliver_str`.
+pub fn set_limits(limits: PayloadLimits) {
+    *LIMITS.write().unwrap_or_else(PoisonError::into_inner) = limits;
+}
+
+/// Limits of the payloads delivered with `deliver_bytes` and `deliver_str`.
+pub fn limits() -> PayloadLimits {
+    *LIMITS.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Error returned for payloads exceeding their size limit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PayloadTooLarge {
+    /// Length of the payload, in bytes.
+    pub len: usize,
+    /// Limit it exceeds, in bytes.
+    pub limit: usize,
+}
+
+impl ErrorCode for PayloadTooLarge {
+    fn error_code(&self) -> i32 {
+        ERR_PAYLOAD_TOO_LARGE
+    }
+}
+
+impl Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Payload of {} bytes exceeds the limit of {} bytes",
+            self.len, self.limit
+        )
+    }
+}
+
+impl Error for PayloadTooLarge {}
+
+/// Return `Err(PayloadTooLarge)` if `len` exceeds `limit`.
+pub fn check_len(len: usize, limit: usize) -> Result<(), PayloadTooLarge> {
+    if len > limit {
+        Err(PayloadTooLarge { len, limit })
+    } else {
+        Ok(())
+    }
+}
+
+/// Payload fitted within its size limit.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Payload<'a, T: ?Sized> {
+    /// The payload is within the limit.
+    Whole(&'a T),
+    /// Prefix of a payload exceeding the limit.
+    Truncated(&'a T),
+    /// Consecutive chunks of a payload exceeding the limit.
+    Chunked(Vec<&'a T>),
+}
+
+trait Split {
+    fn byte_len(&self) -> usize;
+    // Split after `at` bytes at most, or fewer if that isn't a valid boundary.
+    fn split_at_most(&self, at: usize) -> (&Self, &Self);
+}
+
+impl Split for [u8] {
+    fn byte_len(&self) -> usize {
+        self.len()
+    }
+
+    fn split_at_most(&self, at: usize) -> (&Self, &Self) {
+        self.split_at(at.min(self.len()))
+    }
+}
+
+impl Split for str {
+    fn byte_len(&self) -> usize {
+        self.len()
+    }
+
+    fn split_at_most(&self, at: usize) -> (&Self, &Self) {
+        let mut at = at.min(self.len());
+        while !self.is_char_boundary(at) {
+            at -= 1;
+        }
+        self.split_at(at)
+    }
+}
+
+fn fit<T>(
+    payload: &T,
+    limit: usize,
+    policy: OverflowPolicy,
+) -> Result<Payload<'_, T>, PayloadTooLarge>
+where
+    T: Split + ?Sized,
+{
+    let len = payload.byte_len();
+    if len <= limit {
+        return Ok(Payload::Whole(payload));
+    }
+
+    match policy {
+        OverflowPolicy::Truncate => Ok(Payload::Truncated(payload.split_at_most(limit).0)),
+        OverflowPolicy::Error => Err(PayloadTooLarge { len, limit }),
+        OverflowPolicy::Chunk => {
+            let mut chunks = Vec::new();
+            let mut rest = payload;
+            while rest.byte_len() > 0 {
+                let (chunk, tail) = rest.split_at_most(limit);
+                // A character wider than the limit can't be delivered at all.
+                if chunk.byte_len() == 0 {
+                    return Err(PayloadTooLarge { len, limit });
+                }
+                chunks.push(chunk);
+                rest = tail;
+            }
+            Ok(Payload::Chunked(chunks))
+        }
+    }
+}
+
+/// Fit `data`, converted by a binding into a single value, within the global limit lowered to
+/// `max_len`, the largest payload the binding can create.
+///
+/// A value can't be flagged as truncated, so truncations are logged instead. Nor can it be
+/// split, so `OverflowPolicy::Chunk` fails like `Error`: stream large payloads with
+/// `deliver_bytes` instead.
+pub fn fit_value_bytes(data: &[u8], max_len: usize) -> Result<&[u8], PayloadTooLarge> {
+    let limits = limits();
+    fit_value(data, limits.max_bytes_len.min(max_len), limits.policy)
+}
+
+/// Fit `s` within the global limit lowered to `max_len`, like `fit_value_bytes`. Strings are
+/// truncated on character boundaries.
+pub fn fit_value_str(s: &str, max_len: usize) -> Result<&str, PayloadTooLarge> {
+    let limits = limits();
+    fit_value(s, limits.max_string_len.min(max_len), limits.policy)
+}
+
+fn fit_value<T>(payload: &T, limit: usize, policy: OverflowPolicy) -> Result<&T, PayloadTooLarge>
+where
+    T: Split + ?Sized,
+{
+    let policy = match policy {
+        OverflowPolicy::Chunk => OverflowPolicy::Error,
+        policy => policy,
+    };
+
+    match fit(payload, limit, policy)? {
+        Payload::Whole(payload) => Ok(payload),
+        Payload::Truncated(prefix) => {
+            warn!(
+                "Payload of {} bytes truncated to {} bytes",
+                payload.byte_len(),
+                prefix.byte_len()
+            );
+            Ok(prefix)
+        }
+        Payload::Chunked(_) => unreachable!(),
+    }
+}
+
+/// Deliver `data` to `o_data` according to the global limits, then call `o_done`:
+///
+/// + within the limit, `o_data` is called once with `truncated` set to `false`;
+/// + with `OverflowPolicy::Truncate`, `o_data` is called once with the largest prefix within
+///   the limit and `truncated` set to `true`;
+/// + with `OverflowPolicy::Error`, only `o_done` is called, with `ERR_PAYLOAD_TOO_LARGE`;
+/// + with `OverflowPolicy::Chunk`, `o_data` is called with each chunk in order.
+///
+/// The data pointer is only valid during the call to `o_data`.
+pub fn deliver_bytes(
+    user_data: *mut c_void,
+    data: &[u8],
+    o_data: extern "C" fn(user_data: *mut c_void, data: *const u8, len: usize, truncated: bool),
+    o_done: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+) {
+    let send = |chunk: &[u8], truncated| {
+        let ptr = if chunk.is_empty() {
+            ptr::null()
+        } else {
+            chunk.as_ptr()
+        };
+        o_data(user_data, ptr, chunk.len(), truncated)
+    };
+
+    match limits().fit_bytes(data) {
+        Ok(Payload::Whole(data)) => send(data, false),
+        Ok(Payload::Truncated(data)) => send(data, true),
+        Ok(Payload::Chunked(chunks)) => chunks.into_iter().for_each(|chunk| send(chunk, false)),
+        Err(error) => return call_cb_with_error(user_data, o_done, &error),
+    }
+    o_done(user_data, FFI_RESULT_OK)
+}
+
+/// Deliver `s` to `o_data` as NUL-terminated strings, then call `o_done`, like `deliver_bytes`.
+/// Chunks are split on character boundaries.
+///
+/// Strings containing a NUL byte fail with `ERR_INVALID_ARG`, without calling `o_data`.
+pub fn deliver_str(
+    user_data: *mut c_void,
+    s: &str,
+    o_data: extern "C" fn(user_data: *mut c_void, data: *const c_char, truncated: bool),
+    o_done: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+) {
+    let chunks = match limits().fit_str(s) {
+        Ok(Payload::Whole(s)) => vec![(s, false)],
+        Ok(Payload::Truncated(s)) => vec![(s, true)],
+        Ok(Payload::Chunked(chunks)) => chunks.into_iter().map(|s| (s, false)).collect(),
+        Err(error) => return call_cb_with_error(user_data, o_done, &error),
+    };
+
+    let converted: Result<Vec<_>, _> = chunks
+        .into_iter()
+        .map(|(s, truncated)| CString::new(s).map(|s| (s, truncated)))
+        .collect();
+    match converted {
+        Ok(chunks) => {
+            for (s, truncated) in chunks {
+                o_data(user_data, s.as_ptr(), truncated);
+            }
+            o_done(user_data, FFI_RESULT_OK)
+        }
+        Err(error) => call_error_cb(
+            user_data,
+            o_done,
+            ERR_INVALID_ARG,
+            format!("String payload can't be delivered: {}", error),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::slice;
+    use std::sync::Mutex;
+
+    fn limits(max_len: usize, policy: OverflowPolicy) -> PayloadLimits {
+        PayloadLimits {
+            max_string_len: max_len,
+            max_bytes_len: max_len,
+            policy,
+        }
+    }
+
+    #[test]
+    fn payloads_within_limit_are_whole() {
+        let limits = limits(4, OverflowPolicy::Error);
+        assert_eq!(limits.fit_bytes(b"abcd"), Ok(Payload::Whole(&b"abcd"[..])));
+        assert_eq!(limits.fit_str(""), Ok(Payload::Whole("")));
+    }
+
+    #[test]
+    fn oversized_payloads_follow_policy() {
+        let data = &b"abcdefghij"[..];
+
+        assert_eq!(
+            limits(4, OverflowPolicy::Error).fit_bytes(data),
+            Err(PayloadTooLarge { len: 10, limit: 4 })
+        );
+        assert_eq!(
+            limits(4, OverflowPolicy::Truncate).fit_bytes(data),
+            Ok(Payload::Truncated(&data[..4]))
+        );
+        assert_eq!(
+            limits(4, OverflowPolicy::Chunk).fit_bytes(data),
+            Ok(Payload::Chunked(vec![&data[..4], &data[4..8], &data[8..]]))
+        );
+    }
+
+    #[test]
+    fn strings_split_on_character_boundaries() {
+        // "é" takes two bytes.
+        let s = "aééb";
+
+        assert_eq!(
+            limits(4, OverflowPolicy::Truncate).fit_str(s),
+            Ok(Payload::Truncated("aé"))
+        );
+        assert_eq!(
+            limits(2, OverflowPolicy::Chunk).fit_str(s),
+            Ok(Payload::Chunked(vec!["a", "é", "é", "b"]))
+        );
+        assert_eq!(
+            limits(1, OverflowPolicy::Chunk).fit_str(s),
+            Err(PayloadTooLarge { len: 6, limit: 1 })
+        );
+    }
+
+    // Global limits are shared by the tests delivering payloads.
+    static GLOBAL: Mutex<()> = Mutex::new(());
+
+    #[derive(Default)]
+    struct Delivered {
+        chunks: Vec<(Vec<u8>, bool)>,
+        error_code: Option<i32>,
+    }
+
+    extern "C" fn on_bytes(user_data: *mut c_void, data: *const u8, len: usize, truncated: bool) {
+        let delivered = unsafe { &mut *(user_data as *mut Delivered) };
+        let data = if data.is_null() {
+            Vec::new()
+        } else {
+            unsafe { slice::from_raw_parts(data, len).to_vec() }
+        };
+        delivered.chunks.push((data, truncated));
+    }
+
+    extern "C" fn on_str(user_data: *mut c_void, data: *const c_char, truncated: bool) {
+        let data = unsafe { CStr::from_ptr(data).to_bytes() };
+        on_bytes(user_data, data.as_ptr(), data.len(), truncated)
+    }
+
+    extern "C" fn on_done(user_data: *mut c_void, result: *const FfiResult) {
+        let delivered = unsafe { &mut *(user_data as *mut Delivered) };
+        delivered.error_code = Some(unsafe { (*result).error_code });
+    }
+
+    fn deliver<F>(limits: PayloadLimits, f: F) -> Delivered
+    where
+        F: FnOnce(*mut c_void),
+    {
+        let _global = GLOBAL.lock().unwrap_or_else(PoisonError::into_inner);
+        set_limits(limits);
+        let mut delivered = Delivered::default();
+        f(ptr::from_mut(&mut delivered) as *mut c_void);
+        set_limits(PayloadLimits::UNLIMITED);
+        delivered
+    }
+
+    #[test]
+    fn deliver_bytes_applies_global_limits() {
+        let data = b"abcdef";
+        let run = |policy| {
+            deliver(limits(4, policy), |user_data| {
+                deliver_bytes(user_data, data, on_bytes, on_done)
+            })
+        };
+
+        let delivered = run(OverflowPolicy::Truncate);
+        assert_eq!(delivered.chunks, vec![(b"abcd".to_vec(), true)]);
+        assert_eq!(delivered.error_code, Some(0));
+
+        let delivered = run(OverflowPolicy::Chunk);
+        assert_eq!(
+            delivered.chunks,
+            vec![(b"abcd".to_vec(), false), (b"ef".to_vec(), false)]
+        );
+        assert_eq!(delivered.error_code, Some(0));
+
+        let delivered = run(OverflowPolicy::Error);
+        assert!(delivered.chunks.is_empty());
+        assert_eq!(delivered.error_code, Some(ERR_PAYLOAD_TOO_LARGE));
+    }
+
+    #[test]
+    fn values_fit_within_the_lower_limit() {
+        let _global = GLOBAL.lock().unwrap_or_else(PoisonError::into_inner);
+
+        set_limits(limits(4, OverflowPolicy::Truncate));
+        assert_eq!(fit_value_bytes(b"abcdef", 8), Ok(&b"abcd"[..]));
+        assert_eq!(fit_value_str("abcdef", 2), Ok("ab"));
+
+        // Values can't be chunked.
+        set_limits(limits(4, OverflowPolicy::Chunk));
+        assert_eq!(fit_value_str("abc", 8), Ok("abc"));
+        assert_eq!(
+            fit_value_str("abcdef", 8),
+            Err(PayloadTooLarge { len: 6, limit: 4 })
+        );
+
+        set_limits(PayloadLimits::UNLIMITED);
+        assert_eq!(
+            fit_value_bytes(b"abcdef", 2),
+            Err(PayloadTooLarge { len: 6, limit: 2 })
+        );
+    }
+
+    #[test]
+    fn deliver_str_applies_global_limits() {
+        let delivered = deliver(limits(3, OverflowPolicy::Chunk), |user_data| {
+            deliver_str(user_data, "hello", on_str, on_done)
+        });
+        assert_eq!(
+            delivered.chunks,
+            vec![(b"hel".to_vec(), false), (b"lo".to_vec(), false)]
+        );
+        assert_eq!(delivered.error_code, Some(0));
+
+        let delivered = deliver(PayloadLimits::UNLIMITED, |user_data| {
+            deliver_str(user_data, "nul\0", on_str, on_done)
+        });
+        assert!(delivered.chunks.is_empty());
+        assert_eq!(delivered.error_code, Some(ERR_INVALID_ARG));
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Audit of callbacks which have been registered but not completed yet.
+//!
+//! Host test suites can assert quiescence before teardown, and shutdown code can wait for the
+//! outstanding callbacks to be delivered:
+//!
+//! ```ignore
+//! assert!(pending::wait_for_drain(Duration::from_secs(5)), "{:?}", pending::names());
+//! ```
+//!
+//! The count covers the jobs queued on or running in the global dispatcher, the pending timers
+//! and the callbacks attached to futures which haven't completed. Contexts such as `CtxOwned`
+//! may legitimately live as long as the library, e.g. for subscriptions, so they aren't counted;
+//! debug builds list the ones which haven't been released with `outstanding_contexts`.
+
+use crate::{dispatcher, future, timers};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// Number of completions so far, which `wait_for_drain` waits to change.
+static COMPLETIONS: Mutex<u64> = Mutex::new(0);
+static COMPLETED: Condvar = Condvar::new();
+
+fn completions() -> MutexGuard<'static, u64> {
+    COMPLETIONS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Wake up `wait_for_drain`, after a callback counted by `count` has completed.
+pub(crate) fn notify_completed() {
+    *completions() += 1;
+    COMPLETED.notify_all();
+}
+
+/// Number of callbacks which have been registered but not completed yet.
+pub fn count() -> usize {
+    dispatcher::pending_global() + timers::pending() + future::attached_callbacks()
+}
+
+/// Names of the callbacks counted by `count`: their kind.
+///
+/// Only available in debug builds; always returns an empty list in release builds.
+pub fn names() -> Vec<String> {
+    #[cfg(debug_assertions)]
+    {
+        let mut names = Vec::new();
+        let mut push = |count: usize, name: &str| {
+            names.extend((0..count).map(|_| name.to_owned()));
+        };
+
+        push(dispatcher::pending_global(), "dispatched job");
+        push(timers::pending(), "timer");
+        push(future::attached_callbacks(), "future callback");
+        names
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        Vec::new()
+    }
+}
+
+/// Names of the contexts which haven't been released: the type of each `CtxOwned` and, with
+/// the `java` feature, `CallbackCtx`. Not counted by `count`.
+///
+/// Only available in debug builds; always returns an empty list in release builds.
+pub fn outstanding_contexts() -> Vec<String> {
+    #[cfg(debug_assertions)]
+    {
+        let names = crate::typed_ctx::owned_context_names()
+            .into_iter()
+            .map(|name| format!("CtxOwned<{}>", name));
+        #[cfg(feature = "java")]
+        let names =
+            names.chain((0..crate::java::outstanding_contexts()).map(|_| "CallbackCtx".to_owned()));
+        names.collect()
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        Vec::new()
+    }
+}
+
+/// Block until no callbacks are pending, for at most `timeout`. Returns `false` on timeout.
+/// A timeout too large to be represented as an `Instant` never expires.
+///
+/// Must not be called from a dispatched job, which would wait for itself.
+pub fn wait_for_drain(timeout: Duration) -> bool {
+    let deadline = Instant::now().checked_add(timeout);
+    loop {
+        // Read before counting, so that a completion in between isn't missed.
+        let seen = *completions();
+        if count() == 0 {
+            return true;
+        }
+
+        let mut completions = completions();
+        while *completions == seen {
+            completions = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    COMPLETED
+                        .wait_timeout(completions, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => COMPLETED
+                    .wait(completions)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+/// Export the pending callback audit of the library.
+///
+/// Defines two `#[no_mangle]` functions:
+///
+/// + `ffi_pending_callbacks() -> u64` returning `pending::count`;
+/// + `ffi_pending_callback_names(out: *mut u8, out_len: usize) -> usize` writing
+///   `pending::names`, one per line, as a NUL-terminated string to `out` if it is large enough,
+///   and returning the required length.
+#[macro_export]
+macro_rules! export_pending_callbacks {
+    () => {
+        /// Return the number of callbacks which have been registered but not completed yet.
+        #[no_mangle]
+        pub extern "C" fn ffi_pending_callbacks() -> u64 {
+            $crate::pending::count() as u64
+        }
+
+        /// Write the names of the pending callbacks to `out`, one per line, if `out_len` bytes
+        /// are enough, and return the required length. Names are only tracked in debug builds.
+        #[no_mangle]
+        pub unsafe extern "C" fn ffi_pending_callback_names(out: *mut u8, out_len: usize) -> usize {
+            let names = $crate::pending::names().join("\n");
+            match $crate::write_str_to_caller_buf(out, out_len, &names) {
+                Ok(required) => required,
+                Err($crate::WriteStrError::TooSmall(error)) => error.required,
+                // Type names never contain NUL bytes.
+                Err($crate::WriteStrError::InteriorNul(_)) => 0,
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CtxOwned;
+    use std::sync::mpsc;
+    use unwrap::unwrap;
+
+    struct Marker;
+
+    // Only debug builds track contexts.
+    #[cfg(debug_assertions)]
+    #[test]
+    fn owned_contexts_are_listed() {
+        let is_marker = |name: &String| name.ends_with("pending::tests::Marker>");
+
+        let user_data = CtxOwned::new(Marker).into_user_data();
+        assert!(outstanding_contexts().iter().any(is_marker));
+        assert!(!names().iter().any(is_marker));
+
+        unsafe { unwrap!(CtxOwned::<Marker>::finish(user_data, |_| ())) };
+        assert!(!outstanding_contexts().iter().any(is_marker));
+    }
+
+    #[test]
+    fn long_lived_contexts_dont_block_drain() {
+        let user_data = CtxOwned::new(Marker).into_user_data();
+
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        unwrap!(dispatcher::global().dispatch(move || {
+            let _ = release_rx.recv();
+        }));
+        assert!(count() > 0);
+        unwrap!(release_tx.send(()));
+
+        // Other tests may dispatch jobs concurrently, so allow for them to complete.
+        assert!(wait_for_drain(Duration::from_secs(30)));
+        unsafe { unwrap!(CtxOwned::<Marker>::finish(user_data, |_| ())) };
+    }
+}
@@ -0,0 +1,261 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Python interop utilities, based on PyO3.
+//!
+//! A Python extension wraps the callable passed by the caller in a `PyCallback`, passes it as
+//! `user_data` together with one of the `callback_*` functions, and the callable is invoked with
+//! `(error, value)` once the call completes, whichever thread that happens on. `error` is `None`
+//! on success, or an `FfiError` carrying the error code in its `error_code` attribute.
+//!
+//! Extension modules must enable PyO3's `extension-module` feature themselves, and register
+//! `FfiError` in their module so that it can be caught from Python.
+
+use crate::{shield, FfiResult, NativeResult, ReprC, StringError};
+use log::error;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+use pyo3::IntoPyObjectExt;
+use std::fmt::Debug;
+use std::os::raw::{c_char, c_void};
+use std::slice;
+
+create_exception!(
+    sn_ffi_utils,
+    FfiError,
+    PyException,
+    "Error returned by an FFI call."
+);
+
+/// Create the `FfiError` for a failed call, with the description and error code as its
+/// arguments and the error code as its `error_code` attribute.
+pub fn native_result_to_pyerr(py: Python, result: &NativeResult) -> PyErr {
+    let description = result.description.clone().unwrap_or_default();
+    let err = FfiError::new_err((description, result.error_code));
+    if let Err(e) = err.value(py).setattr("error_code", result.error_code) {
+        error!("Failed to set the error code of an FfiError: {}", e);
+    }
+    err
+}
+
+/// Convert a string conversion error into a Python `ValueError`.
+pub fn string_error_to_pyerr(error: StringError) -> PyErr {
+    PyValueError::new_err(format!("{:?}", error))
+}
+
+/// Convert a nul-terminated UTF-8 string into a Python `str`.
+///
+/// # Safety
+///
+/// See `ReprC for String`.
+pub unsafe fn str_from_c<'py>(
+    py: Python<'py>,
+    ptr: *const c_char,
+) -> PyResult<Bound<'py, PyString>> {
+    let s = String::clone_from_repr_c(ptr).map_err(string_error_to_pyerr)?;
+    Ok(PyString::new(py, &s))
+}
+
+/// Copy `len` bytes at `ptr` into a Python `bytes`. A null `ptr` yields empty bytes.
+///
+/// # Safety
+///
+/// `ptr` must be null or valid for reads of `len` bytes.
+pub unsafe fn bytes_from_raw_parts<'py>(
+    py: Python<'py>,
+    ptr: *const u8,
+    len: usize,
+) -> Bound<'py, PyBytes> {
+    if ptr.is_null() || len == 0 {
+        PyBytes::new(py, &[])
+    } else {
+        PyBytes::new(py, slice::from_raw_parts(ptr, len))
+    }
+}
+
+/// Conversion of a native value into a Python object. Strings become `str` and byte vectors
+/// `bytes`.
+pub trait ToPython {
+    /// Convert `self` into a Python object.
+    fn to_python(self, py: Python) -> PyResult<PyObject>;
+}
+
+macro_rules! impl_to_python {
+    ($($ty:ty),*) => {
+        $(
+            impl ToPython for $ty {
+                fn to_python(self, py: Python) -> PyResult<PyObject> {
+                    self.into_py_any(py)
+                }
+            }
+        )*
+    };
+}
+
+impl_to_python!((), bool, i32, u32, i64, u64, usize, String);
+
+impl ToPython for Vec<u8> {
+    fn to_python(self, py: Python) -> PyResult<PyObject> {
+        PyBytes::new(py, &self).into_py_any(py)
+    }
+}
+
+/// Python callable which is invoked once with the outcome of a call.
+pub struct PyCallback(PyObject);
+
+impl PyCallback {
+    /// Wrap `callable`, failing with `TypeError` if it isn't callable.
+    pub fn new(callable: &Bound<PyAny>) -> PyResult<Self> {
+        if callable.is_callable() {
+            Ok(PyCallback(callable.clone().unbind()))
+        } else {
+            Err(PyTypeError::new_err("callback is not callable"))
+        }
+    }
+
+    /// Transfer the callback into a `user_data` pointer for one of the `callback_*` functions.
+    pub fn into_user_data(self) -> *mut c_void {
+        Box::into_raw(Box::new(self)) as *mut c_void
+    }
+}
+
+unsafe fn complete<F>(user_data: *mut c_void, res: *const FfiResult, value: F)
+where
+    F: FnOnce(Python) -> PyResult<PyObject>,
+{
+    let callback = Box::from_raw(user_data as *mut PyCallback);
+
+    Python::with_gil(|py| {
+        let outcome = if (*res).error_code == 0 {
+            value(py)
+        } else {
+            let result = NativeResult::clone_from_repr_c(res).unwrap_or_else(|_| NativeResult {
+                error_code: (*res).error_code,
+                description: None,
+            });
+            Err(native_result_to_pyerr(py, &result))
+        };
+        let args = match outcome {
+            Ok(value) => (py.None(), value),
+            Err(error) => (error.into_value(py).into_any(), py.None()),
+        };
+
+        if let Err(e) = callback.0.call1(py, args) {
+            error!("Python callback failed: {}", e);
+        }
+        // Release the callable while holding the GIL.
+        drop(callback);
+    })
+}
+
+/// Callback taking no value, invoking the `PyCallback` behind `user_data` with
+/// `(error, None)`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `PyCallback::into_user_data`, and must not be used
+/// afterwards.
+pub unsafe extern "C" fn callback_0(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || complete(user_data, res, |py| Ok(py.None())))
+}
+
+/// Callback taking a value, invoking the `PyCallback` behind `user_data` with
+/// `(error, value)`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `PyCallback::into_user_data`, and must not be used
+/// afterwards.
+pub unsafe extern "C" fn callback_1<T>(user_data: *mut c_void, res: *const FfiResult, value: T::C)
+where
+    T: ReprC + ToPython,
+    T::Error: Debug,
+{
+    shield(move || {
+        complete(user_data, res, |py| match T::clone_from_repr_c(value) {
+            Ok(value) => value.to_python(py),
+            Err(e) => Err(PyValueError::new_err(format!(
+                "Invalid callback argument: {:?}",
+                e
+            ))),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FFI_RESULT_OK;
+    use pyo3::types::{PyDict, PyList};
+    use std::ffi::{CStr, CString};
+    use std::thread;
+    use unwrap::unwrap;
+
+    #[test]
+    fn callbacks_invoke_callable() {
+        pyo3::prepare_freethreaded_python();
+
+        let (results, ok, failed) = Python::with_gil(|py| {
+            let results = PyList::empty(py);
+            let globals = PyDict::new(py);
+            unwrap!(globals.set_item("results", &results));
+            let callable = unwrap!(py.eval(
+                unwrap!(CStr::from_bytes_with_nul(
+                    b"lambda error, value: results.append((error, value))\0"
+                )),
+                Some(&globals),
+                None,
+            ));
+            (
+                results.unbind(),
+                unwrap!(PyCallback::new(&callable)).into_user_data() as usize,
+                unwrap!(PyCallback::new(&callable)).into_user_data() as usize,
+            )
+        });
+
+        let value = unwrap!(CString::new("hello"));
+        let value = value.as_ptr() as usize;
+        unwrap!(thread::spawn(move || unsafe {
+            callback_1::<String>(ok as *mut c_void, FFI_RESULT_OK, value as *const c_char);
+
+            let (res, _storage) = unwrap!(crate::IntoReprC::into_repr_c(NativeResult {
+                error_code: -3,
+                description: Some("failed".to_owned()),
+            }));
+            callback_0(failed as *mut c_void, res);
+        })
+        .join());
+
+        Python::with_gil(|py| {
+            let results = results.bind(py);
+            assert_eq!(results.len(), 2);
+
+            let (error, value): (PyObject, String) =
+                unwrap!(unwrap!(results.get_item(0)).extract());
+            assert!(error.is_none(py));
+            assert_eq!(value, "hello");
+
+            let (error, value): (Bound<PyAny>, PyObject) =
+                unwrap!(unwrap!(results.get_item(1)).extract());
+            assert!(error.is_instance_of::<FfiError>());
+            assert_eq!(
+                unwrap!(unwrap!(error.getattr("error_code")).extract::<i32>()),
+                -3
+            );
+            assert!(value.is_none(py));
+        });
+
+        Python::with_gil(|py| {
+            let not_callable = unwrap!(1.into_py_any(py));
+            assert!(PyCallback::new(not_callable.bind(py)).is_err());
+        });
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Callbacks into the host which return a value, with a bound on how long the host may take to
+//! respond.
+//!
+//! Some operations need an answer from the host, e.g. a password prompt. `query` calls the
+//! host's `QueryCallback` with a request and a reply token, and blocks the native thread until
+//! the host answers with `ffi_query_reply` (exported with `export_query!`), from any thread. A
+//! host which doesn't answer in time, typically because the thread which would answer is busy or
+//! frozen, makes `query` fail with `ERR_QUERY_TIMED_OUT` rather than wedge the native thread, and
+//! its late reply is rejected:
+//!
+//! ```ignore
+//! let password = query::query(
+//!     user_data,
+//!     o_prompt,
+//!     b"Password for the vault",
+//!     Duration::from_secs(30),
+//! )?;
+//! ```
+
+use crate::affinity;
+use crate::ErrorCode;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::os::raw::c_void;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// Error code returned when the host doesn't answer a query in time.
+pub const ERR_QUERY_TIMED_OUT: i32 = -9022;
+/// Error code returned when replying to a query which isn't pending, because it timed out or was
+/// already answered.
+pub const ERR_QUERY_NOT_PENDING: i32 = -9023;
+
+/// Callback asking the host for a value. The host answers, now or later and from any thread, by
+/// passing `token` to `ffi_query_reply`. The request is only valid during the call.
+pub type QueryCallback =
+    extern "C" fn(user_data: *mut c_void, token: u64, request: *const u8, request_len: usize);
+
+/// Error of a query.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QueryError {
+    /// The host didn't answer in time.
+    TimedOut,
+    /// The host answered with an error code.
+    Host(i32),
+    /// The query isn't pending.
+    NotPending,
+}
+
+impl ErrorCode for QueryError {
+    fn error_code(&self) -> i32 {
+        match self {
+            QueryError::TimedOut => ERR_QUERY_TIMED_OUT,
+            QueryError::Host(error_code) => *error_code,
+            QueryError::NotPending => ERR_QUERY_NOT_PENDING,
+        }
+    }
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::TimedOut => write!(f, "Host didn't answer the query in time"),
+            QueryError::Host(error_code) => {
+                write!(f, "Host answered the query with error {}", error_code)
+            }
+            QueryError::NotPending => write!(f, "Query isn't pending"),
+        }
+    }
+}
+
+impl Error for QueryError {}
+
+type Reply = Result<Vec<u8>, QueryError>;
+
+static PENDING: Mutex<BTreeMap<u64, Sender<Reply>>> = Mutex::new(BTreeMap::new());
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+fn pending() -> MutexGuard<'static, BTreeMap<u64, Sender<Reply>>> {
+    PENDING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Ask the host for a value by calling `cb` with `request`, and block until the host replies or
+/// `timeout` elapses.
+///
+/// Must not be called on the thread which would answer, which would always time out.
+pub fn query(
+    user_data: *mut c_void,
+    cb: QueryCallback,
+    request: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, QueryError> {
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = mpsc::channel();
+    let _ = pending().insert(token, tx);
+
+    affinity::check();
+    cb(user_data, token, request.as_ptr(), request.len());
+
+    match rx.recv_timeout(timeout) {
+        Ok(reply) => reply,
+        Err(_) => {
+            // The host may have replied since.
+            let _ = pending().remove(&token);
+            rx.try_recv().unwrap_or(Err(QueryError::TimedOut))
+        }
+    }
+}
+
+/// Answer the query `token` with the `len` bytes at `data` if `error_code` is 0, or with
+/// `error_code` otherwise.
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes. It may be null if `len` is 0 or `error_code`
+/// isn't.
+pub unsafe fn reply(
+    token: u64,
+    error_code: i32,
+    data: *const u8,
+    len: usize,
+) -> Result<(), QueryError> {
+    let tx = pending().remove(&token).ok_or(QueryError::NotPending)?;
+    let reply = if error_code != 0 {
+        Err(QueryError::Host(error_code))
+    } else if len == 0 {
+        Ok(Vec::new())
+    } else {
+        Ok(slice::from_raw_parts(data, len).to_vec())
+    };
+    tx.send(reply).map_err(|_| QueryError::NotPending)
+}
+
+/// Export the function answering queries.
+///
+/// Defines `ffi_query_reply(token: u64, error_code: i32, data: *const u8, len: usize) -> i32`,
+/// which answers the query `token` with the `len` bytes at `data` if `error_code` is 0, or with
+/// `error_code` otherwise. It returns 0 on success, or `ERR_QUERY_NOT_PENDING` if the query timed
+/// out or was already answered.
+#[macro_export]
+macro_rules! export_query {
+    () => {
+        /// Answer a query from the native library.
+        ///
+        /// # Safety
+        ///
+        /// `data` must be valid for reads of `len` bytes.
+        #[no_mangle]
+        pub unsafe extern "C" fn ffi_query_reply(
+            token: u64,
+            error_code: i32,
+            data: *const u8,
+            len: usize,
+        ) -> i32 {
+            $crate::ffi_result_code!($crate::query::reply(token, error_code, data, len))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+    use std::thread;
+    use unwrap::unwrap;
+
+    // Host answering with the request reversed, from another thread.
+    extern "C" fn reverse(_user_data: *mut c_void, token: u64, request: *const u8, len: usize) {
+        let mut answer = unsafe { slice::from_raw_parts(request, len) }.to_vec();
+        answer.reverse();
+        let _ = thread::spawn(move || unsafe {
+            unwrap!(reply(token, 0, answer.as_ptr(), answer.len()))
+        });
+    }
+
+    extern "C" fn refuse(_user_data: *mut c_void, token: u64, _request: *const u8, _len: usize) {
+        unsafe { unwrap!(reply(token, -7, std::ptr::null(), 0)) }
+    }
+
+    // Frozen host, handing the token over to the test through `user_data`.
+    extern "C" fn frozen(user_data: *mut c_void, token: u64, _request: *const u8, _len: usize) {
+        let tokens = unsafe { &*(user_data as *const Mutex<Sender<u64>>) };
+        unwrap!(unwrap!(tokens.lock()).send(token));
+    }
+
+    #[test]
+    fn host_replies() {
+        let answer = query(
+            std::ptr::null_mut(),
+            reverse,
+            b"abc",
+            Duration::from_secs(30),
+        );
+        assert_eq!(answer, Ok(b"cba".to_vec()));
+
+        let answer = query(
+            std::ptr::null_mut(),
+            refuse,
+            b"abc",
+            Duration::from_secs(30),
+        );
+        assert_eq!(answer.map_err(|e| e.error_code()), Err(-7));
+    }
+
+    #[test]
+    fn slow_host_times_out() {
+        let (tx, rx): (_, Receiver<u64>) = mpsc::channel();
+        let tokens = Mutex::new(tx);
+        let user_data = std::ptr::from_ref(&tokens) as *mut c_void;
+
+        let answer = query(user_data, frozen, b"abc", Duration::from_millis(10));
+        assert_eq!(answer, Err(QueryError::TimedOut));
+
+        let token = unwrap!(rx.recv());
+        let late = unsafe { reply(token, 0, std::ptr::null(), 0) };
+        assert_eq!(late.map_err(|e| e.error_code()), Err(ERR_QUERY_NOT_PENDING));
+    }
+}
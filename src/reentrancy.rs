@@ -0,0 +1,282 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Detection of FFI entry points re-entered synchronously from a host callback.
+//!
+//! Entry points which hold a lock while invoking callbacks deadlock when a callback calls back
+//! into the library. Each library (identified by a name, usually the crate name) keeps a
+//! per-thread depth counter, and a `ReentrancyPolicy` decides what happens to nested calls:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_refresh(
+//!     app: Handle,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+//! ) {
+//!     let user_data = OpaqueCtx::from_host_pointer(user_data);
+//!     reentrancy::run_cb("safe_app", user_data, o_cb, move || {
+//!         catch_unwind_cb(user_data, o_cb, || -> Result<_, AppError> {
+//!             let app = handles::get::<App>(app)?;
+//!             app.refresh()?;
+//!             o_cb(user_data.as_ptr(), FFI_RESULT_OK);
+//!             Ok(())
+//!         })
+//!     })
+//! }
+//! ```
+//!
+//! `test_utils::reentrancy` builds on the same depth counters to detect reentrant calls in
+//! tests.
+
+use crate::callback::Callback;
+use crate::catch_unwind::call_error_cb;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::dispatcher::{self, DispatchError};
+use crate::{ErrorCode, OpaqueCtx};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::os::raw::c_void;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+/// Error code returned by calls rejected by the `ReentrancyPolicy::Error` policy, unless the
+/// policy specifies another one.
+pub const ERR_REENTRANT_CALL: i32 = -9007;
+
+/// What to do with a call entering a library which is already entered on the same thread.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReentrancyPolicy {
+    /// Run the call.
+    Allow,
+    /// Reject the call with the given error code.
+    Error(i32),
+    /// Queue the call on the global dispatcher, so that it runs once the host callback has
+    /// returned, on a dispatcher thread. The call is rejected with `ERR_REENTRANT_CALL` instead
+    /// if the queue is full, and on `wasm32`.
+    Defer,
+}
+
+impl Default for ReentrancyPolicy {
+    fn default() -> Self {
+        ReentrancyPolicy::Error(ERR_REENTRANT_CALL)
+    }
+}
+
+/// Error returned when a reentrant call is not run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReentrancyError {
+    /// The call was rejected with the given error code.
+    Rejected(i32),
+    /// The call couldn't be deferred.
+    #[cfg(not(target_arch = "wasm32"))]
+    Dispatch(DispatchError),
+}
+
+impl ErrorCode for ReentrancyError {
+    fn error_code(&self) -> i32 {
+        match self {
+            ReentrancyError::Rejected(code) => *code,
+            #[cfg(not(target_arch = "wasm32"))]
+            ReentrancyError::Dispatch(e) => e.error_code(),
+        }
+    }
+}
+
+impl Display for ReentrancyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReentrancyError::Rejected(_) => write!(f, "Reentrant call from a callback"),
+            #[cfg(not(target_arch = "wasm32"))]
+            ReentrancyError::Dispatch(e) => write!(f, "Could not defer reentrant call: {}", e),
+        }
+    }
+}
+
+impl Error for ReentrancyError {}
+
+thread_local! {
+    static DEPTHS: RefCell<HashMap<&'static str, usize>> = RefCell::new(HashMap::new());
+}
+
+fn policies() -> &'static RwLock<HashMap<&'static str, ReentrancyPolicy>> {
+    static POLICIES: OnceLock<RwLock<HashMap<&'static str, ReentrancyPolicy>>> = OnceLock::new();
+    POLICIES.get_or_init(Default::default)
+}
+
+/// Set the policy of `library`.
+pub fn set_policy(library: &'static str, policy: ReentrancyPolicy) {
+    let _ = policies()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(library, policy);
+}
+
+/// Return the policy of `library`, `ReentrancyPolicy::default()` unless set with `set_policy`.
+pub fn policy(library: &'static str) -> ReentrancyPolicy {
+    policies()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(library)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Return `true` if `library` is entered on the current thread.
+pub fn is_entered(library: &'static str) -> bool {
+    DEPTHS.with(|depths| depths.borrow().get(library).is_some_and(|depth| *depth > 0))
+}
+
+/// Marks `library` as entered on the current thread until dropped.
+pub struct ReentrancyGuard {
+    library: &'static str,
+    depth: usize,
+}
+
+impl ReentrancyGuard {
+    /// Enter `library` on the current thread, regardless of its policy.
+    pub fn enter(library: &'static str) -> Self {
+        let depth = DEPTHS.with(|depths| {
+            let mut depths = depths.borrow_mut();
+            let depth = depths.entry(library).or_insert(0);
+            *depth += 1;
+            *depth
+        });
+        Self { library, depth }
+    }
+
+    /// Number of nested entries of the library on the current thread, this one included.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Return `true` if the library was already entered when this guard was created.
+    pub fn is_reentrant(&self) -> bool {
+        self.depth > 1
+    }
+}
+
+impl Drop for ReentrancyGuard {
+    fn drop(&mut self) {
+        // The thread local may already be gone when the thread is exiting.
+        let _ = DEPTHS.try_with(|depths| {
+            if let Some(depth) = depths.borrow_mut().get_mut(self.library) {
+                *depth -= 1;
+            }
+        });
+    }
+}
+
+/// Run `f` with `library` entered, applying the policy of the library if it is already entered
+/// on the current thread.
+pub fn run<F>(library: &'static str, f: F) -> Result<(), ReentrancyError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    if is_entered(library) {
+        match policy(library) {
+            ReentrancyPolicy::Allow => (),
+            ReentrancyPolicy::Error(code) => return Err(ReentrancyError::Rejected(code)),
+            // Without a dispatcher, the call can't be deferred.
+            #[cfg(target_arch = "wasm32")]
+            ReentrancyPolicy::Defer => return Err(ReentrancyError::Rejected(ERR_REENTRANT_CALL)),
+            #[cfg(not(target_arch = "wasm32"))]
+            ReentrancyPolicy::Defer => {
+                // Never wait for room in the queue: the callback may be running on the only
+                // worker, which would then never free any.
+                return dispatcher::global()
+                    .try_dispatch(move || {
+                        let _guard = ReentrancyGuard::enter(library);
+                        f()
+                    })
+                    .map_err(|e| match e {
+                        DispatchError::Full => ReentrancyError::Rejected(ERR_REENTRANT_CALL),
+                        e => ReentrancyError::Dispatch(e),
+                    });
+            }
+        }
+    }
+
+    let _guard = ReentrancyGuard::enter(library);
+    f();
+    Ok(())
+}
+
+/// Same as `run`, but calls `cb` with the error if the call is not run.
+pub fn run_cb<U, C, F>(library: &'static str, user_data: U, cb: C, f: F)
+where
+    U: Into<*mut c_void>,
+    C: Callback,
+    F: FnOnce() + Send + 'static,
+{
+    let user_data = OpaqueCtx::from_host_pointer(user_data.into());
+    if let Err(e) = run(library, f) {
+        call_error_cb(user_data.as_ptr(), cb, e.error_code(), e.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use unwrap::unwrap;
+
+    #[test]
+    fn guard_depth() {
+        assert!(!is_entered("guard_depth"));
+        {
+            let outer = ReentrancyGuard::enter("guard_depth");
+            assert!(!outer.is_reentrant());
+            assert!(is_entered("guard_depth"));
+            assert!(!is_entered("other"));
+
+            let inner = ReentrancyGuard::enter("guard_depth");
+            assert_eq!(inner.depth(), 2);
+            assert!(inner.is_reentrant());
+        }
+        assert!(!is_entered("guard_depth"));
+    }
+
+    #[test]
+    fn policies() {
+        let (tx, rx) = mpsc::channel();
+
+        let outer = tx.clone();
+        unwrap!(run("policy_error", move || {
+            let res = run("policy_error", || ());
+            unwrap!(outer.send(res));
+        }));
+        assert_eq!(
+            unwrap!(rx.try_recv()),
+            Err(ReentrancyError::Rejected(ERR_REENTRANT_CALL))
+        );
+
+        set_policy("policy_allow", ReentrancyPolicy::Allow);
+        let outer = tx.clone();
+        unwrap!(run("policy_allow", move || {
+            let inner = outer.clone();
+            let res = run("policy_allow", move || unwrap!(inner.send(Ok(()))));
+            unwrap!(outer.send(res));
+        }));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![Ok(()), Ok(())]);
+
+        set_policy("policy_defer", ReentrancyPolicy::Defer);
+        let (deferred_tx, deferred_rx) = mpsc::channel();
+        unwrap!(run("policy_defer", move || {
+            let res = run("policy_defer", move || {
+                unwrap!(deferred_tx.send(is_entered("policy_defer")));
+            });
+            unwrap!(tx.send(res));
+        }));
+        assert_eq!(unwrap!(rx.try_recv()), Ok(()));
+        assert!(unwrap!(deferred_rx.recv_timeout(Duration::from_secs(5))));
+    }
+}
@@ -18,6 +18,14 @@
 //! for better ABI stability.
 //! + `i128` and `u128`: do not have a stable ABI, so they cannot be returned across the FFI.
 
+use crate::ErrorCode;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use core::convert::Infallible;
+use core::error::Error;
+use core::fmt::{self, Debug, Display};
+
 /// Trait to convert between FFI and Rust representations of types.
 pub trait ReprC {
     /// C representation of the type.
@@ -36,122 +44,301 @@ pub trait ReprC {
         Self: Sized;
 }
 
+/// Trait to convert Rust types into their FFI representation, the inverse of `ReprC`.
+pub trait IntoReprC: ReprC + Sized {
+    /// Owner of the data the FFI representation points to, if any.
+    type Storage;
+
+    /// Convert into the FFI representation, which remains valid for as long as the returned
+    /// storage is alive.
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error>;
+}
+
+/// Error code returned when an argument of an FFI function fails to convert.
+pub const ERR_INVALID_ARG: i32 = -9017;
+
+/// Error converting an argument of an FFI function with `decode_args!`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidArg {
+    /// Name of the offending argument.
+    pub name: &'static str,
+    /// Description of the conversion error.
+    pub description: String,
+}
+
+impl ErrorCode for InvalidArg {
+    fn error_code(&self) -> i32 {
+        ERR_INVALID_ARG
+    }
+}
+
+impl Display for InvalidArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid argument `{}`: {}", self.name, self.description)
+    }
+}
+
+impl Error for InvalidArg {}
+
+/// Convert the argument `name` from its FFI representation, naming it in the error on failure.
+/// Used by `decode_args!`.
+///
+/// # Safety
+///
+/// See `ReprC::clone_from_repr_c`.
+pub unsafe fn decode_arg<T>(name: &'static str, repr_c: T::C) -> Result<T, InvalidArg>
+where
+    T: ReprC,
+    T::Error: Display,
+{
+    T::clone_from_repr_c(repr_c).map_err(|error| InvalidArg {
+        name,
+        description: format!("{}", error),
+    })
+}
+
+impl ReprC for () {
+    type C = ();
+    type Error = Infallible;
+
+    unsafe fn clone_from_repr_c(_repr_c: Self::C) -> Result<Self, Self::Error> {
+        Ok(())
+    }
+}
+
+impl IntoReprC for () {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok(((), ()))
+    }
+}
+
 impl ReprC for i32 {
     type C = i32;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c)
     }
 }
 
+impl IntoReprC for i32 {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((self, ()))
+    }
+}
+
 impl ReprC for i64 {
     type C = i64;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c)
     }
 }
 
+impl IntoReprC for i64 {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((self, ()))
+    }
+}
+
 impl ReprC for u32 {
     type C = u32;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c)
     }
 }
 
+impl IntoReprC for u32 {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((self, ()))
+    }
+}
+
 impl ReprC for u64 {
     type C = u64;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c)
     }
 }
 
+impl IntoReprC for u64 {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((self, ()))
+    }
+}
+
 impl ReprC for usize {
     type C = usize;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c)
     }
 }
 
+impl IntoReprC for usize {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((self, ()))
+    }
+}
+
 impl<T> ReprC for *const T {
     type C = *const T;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c)
     }
 }
 
+impl<T> IntoReprC for *const T {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((self, ()))
+    }
+}
+
 impl<T> ReprC for *mut T {
     type C = *mut T;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c)
     }
 }
 
+impl<T> IntoReprC for *mut T {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((self, ()))
+    }
+}
+
 // TODO: Replace these with a const generic implementation once it is stable.
 // https://github.com/rust-lang/rust/issues/44580
 
 impl ReprC for [u8; 24] {
     type C = *const [u8; 24];
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(*repr_c)
     }
 }
 
+impl IntoReprC for [u8; 24] {
+    type Storage = Box<[u8; 24]>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = Box::new(self);
+        Ok((&*storage, storage))
+    }
+}
+
 impl ReprC for [u8; 32] {
     type C = *const [u8; 32];
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(*repr_c)
     }
 }
 
+impl IntoReprC for [u8; 32] {
+    type Storage = Box<[u8; 32]>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = Box::new(self);
+        Ok((&*storage, storage))
+    }
+}
+
 impl ReprC for [u8; 48] {
     type C = *const [u8; 48];
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(*repr_c)
     }
 }
 
+impl IntoReprC for [u8; 48] {
+    type Storage = Box<[u8; 48]>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = Box::new(self);
+        Ok((&*storage, storage))
+    }
+}
+
 impl ReprC for [u8; 64] {
     type C = *const [u8; 64];
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(*repr_c)
     }
 }
 
+impl IntoReprC for [u8; 64] {
+    type Storage = Box<[u8; 64]>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = Box::new(self);
+        Ok((&*storage, storage))
+    }
+}
+
 impl ReprC for [u8; 96] {
     type C = *const [u8; 96];
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(*repr_c)
     }
 }
 
+impl IntoReprC for [u8; 96] {
+    type Storage = Box<[u8; 96]>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = Box::new(self);
+        Ok((&*storage, storage))
+    }
+}
+
 impl ReprC for bool {
     type C = u32;
-    type Error = ();
+    type Error = Infallible;
 
     unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
         Ok(repr_c != 0)
     }
 }
+
+impl IntoReprC for bool {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        Ok((u32::from(self), ()))
+    }
+}
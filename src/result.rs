@@ -10,19 +10,22 @@
 //! Utilities for handling results and errors across the FFI boundary.
 
 use crate::string::StringError;
-use crate::ReprC;
-use std::ffi::CString;
-use std::os::raw::c_char;
-use std::ptr;
+use crate::{IntoReprC, ReprC};
+use alloc::boxed::Box;
+use alloc::ffi::CString;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ffi::c_char;
+use core::ptr;
 
-/// Constant value to be used for OK result.
+/// Constant value to be used for OK result. Its description is an empty string.
 pub const FFI_RESULT_OK: &FfiResult = &FfiResult {
     error_code: 0,
-    description: ptr::null(),
+    description: b"\0" as *const u8 as *const c_char,
 };
 
 /// A native Rust version of the `FfiResult` struct.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct NativeResult {
     /// Unique error code.
     pub error_code: i32,
@@ -36,15 +39,37 @@ impl NativeResult {
         Ok(FfiResult {
             error_code: self.error_code,
             description: match self.description {
-                Some(description) => CString::new(description)
-                    .map_err(StringError::from)?
-                    .into_raw(),
+                Some(description) => {
+                    let description =
+                        CString::new(with_nul_capacity(description)).map_err(StringError::from)?;
+                    #[cfg(feature = "memory-report")]
+                    crate::memory::track(
+                        crate::memory::Category::CString,
+                        description.as_ptr(),
+                        description.as_bytes_with_nul().len(),
+                    );
+                    description.into_raw()
+                }
                 None => ptr::null(),
             },
         })
     }
 }
 
+// Return the bytes of `s` in a buffer without spare capacity beyond the nul terminator. Such a
+// buffer is kept, as `CString::new` at most grows it by that one byte. Others are copied, as
+// `CString::into_raw` would otherwise shrink them with a second reallocation.
+fn with_nul_capacity(s: String) -> Vec<u8> {
+    let bytes = s.into_bytes();
+    if bytes.capacity() <= bytes.len() + 1 {
+        bytes
+    } else {
+        let mut exact = Vec::with_capacity(bytes.len() + 1);
+        exact.extend_from_slice(&bytes);
+        exact
+    }
+}
+
 impl ReprC for NativeResult {
     type C = *const FfiResult;
     type Error = StringError;
@@ -66,6 +91,15 @@ impl ReprC for NativeResult {
     }
 }
 
+impl IntoReprC for NativeResult {
+    type Storage = Box<FfiResult>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = Box::new(NativeResult::into_repr_c(self)?);
+        Ok((&*storage, storage))
+    }
+}
+
 /// FFI result wrapper.
 #[repr(C)]
 #[derive(Debug)]
@@ -80,6 +114,8 @@ impl Drop for FfiResult {
     fn drop(&mut self) {
         unsafe {
             if !self.description.is_null() {
+                #[cfg(feature = "memory-report")]
+                crate::memory::untrack(self.description);
                 let _ = CString::from_raw(self.description as *mut _);
             }
         }
@@ -0,0 +1,356 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Passing serde types across the FFI as serialised byte blobs.
+//!
+//! For complex nested structures, maintaining a `#[repr(C)]` twin of every type isn't worth it.
+//! Instead, the value is serialised with a `Format` (`Bincode`, `Cbor` or `Json`, behind the
+//! features of the same names, or any other implementation) and passed as an `FfiByteSlice`.
+//!
+//! `Serialized<F, T>` implements `ReprC` and `IntoReprC`, so results can be delivered as blobs
+//! by `async_ffi::spawn_cb`, `Dispatcher::dispatch_cb` or `#[ffi_fn]`:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_status(
+//!     app: Handle,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, status: FfiByteSlice),
+//! ) {
+//!     async_ffi::spawn_cb(user_data, o_cb, async move {
+//!         let status = handles::get::<App>(app)?.status().await?;
+//!         Ok(Serialized::<Json, _>::new(status))
+//!     })
+//! }
+//! ```
+
+use crate::callback::CallbackArgs;
+use crate::{vec_from_raw_parts, vec_into_raw_parts, ErrorCode, IntoReprC, ReprC};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::ptr;
+use std::slice;
+
+/// Error code of values which can't be serialised or deserialised.
+pub const ERR_SERDE: i32 = -9010;
+
+/// Error serialising or deserialising a value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SerdeError(pub String);
+
+impl ErrorCode for SerdeError {
+    fn error_code(&self) -> i32 {
+        ERR_SERDE
+    }
+}
+
+impl Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Serialisation error: {}", self.0)
+    }
+}
+
+impl Error for SerdeError {}
+
+/// Serialisation format of the blobs.
+pub trait Format {
+    /// Serialise `value`.
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, SerdeError>;
+
+    /// Deserialise a value from `bytes`.
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SerdeError>;
+}
+
+/// The bincode format.
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Format for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, SerdeError> {
+        bincode::serialize(value).map_err(|e| SerdeError(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SerdeError> {
+        bincode::deserialize(bytes).map_err(|e| SerdeError(e.to_string()))
+    }
+}
+
+/// The CBOR format.
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Format for Cbor {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, SerdeError> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).map_err(|e| SerdeError(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SerdeError> {
+        ciborium::de::from_reader(bytes).map_err(|e| SerdeError(e.to_string()))
+    }
+}
+
+/// The JSON format.
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Format for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, SerdeError> {
+        serde_json::to_vec(value).map_err(|e| SerdeError(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SerdeError> {
+        serde_json::from_slice(bytes).map_err(|e| SerdeError(e.to_string()))
+    }
+}
+
+/// Byte blob passed across the FFI.
+///
+/// Blobs passed to callbacks are only valid during the call. Blobs returned by `encode_to_ffi`
+/// are owned by the caller and must be released with `free_byte_slice`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FfiByteSlice {
+    /// Pointer to the first byte, or null if the blob is empty.
+    pub data: *const u8,
+    /// Number of bytes.
+    pub len: usize,
+}
+
+impl FfiByteSlice {
+    /// Borrow the bytes of the blob.
+    ///
+    /// # Safety
+    ///
+    /// `data` must be null or valid for reads of `len` bytes for the lifetime `'a`.
+    pub unsafe fn as_slice<'a>(&self) -> &'a [u8] {
+        if self.data.is_null() || self.len == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(self.data, self.len)
+        }
+    }
+}
+
+impl CallbackArgs for FfiByteSlice {
+    fn default() -> Self {
+        FfiByteSlice {
+            data: ptr::null(),
+            len: 0,
+        }
+    }
+}
+
+/// Serialise `value` into a blob owned by the caller, to be released with `free_byte_slice`.
+///
+/// An empty blob has a null `data` pointer.
+pub fn encode_to_ffi<F: Format, T: Serialize + ?Sized>(
+    value: &T,
+) -> Result<FfiByteSlice, SerdeError> {
+    let bytes = F::encode(value)?;
+    if bytes.is_empty() {
+        return Ok(CallbackArgs::default());
+    }
+
+    let (data, len) = vec_into_raw_parts(bytes);
+    Ok(FfiByteSlice { data, len })
+}
+
+/// Deserialise a value from the `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be null or valid for reads of `len` bytes.
+pub unsafe fn decode_from_ffi<F: Format, T: DeserializeOwned>(
+    ptr: *const u8,
+    len: usize,
+) -> Result<T, SerdeError> {
+    F::decode(FfiByteSlice { data: ptr, len }.as_slice())
+}
+
+/// Release a blob returned by `encode_to_ffi`.
+///
+/// # Safety
+///
+/// `slice` must have been returned by `encode_to_ffi`, and must not be used afterwards.
+pub unsafe fn free_byte_slice(slice: FfiByteSlice) {
+    if !slice.data.is_null() {
+        drop(vec_from_raw_parts(slice.data as *mut u8, slice.len))
+    }
+}
+
+/// Value passed across the FFI serialised with the format `F`.
+pub struct Serialized<F, T> {
+    /// The value.
+    pub value: T,
+    _format: PhantomData<fn() -> F>,
+}
+
+impl<F, T> Serialized<F, T> {
+    /// Wrap `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            _format: PhantomData,
+        }
+    }
+
+    /// Unwrap the value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<F: Format, T: DeserializeOwned> ReprC for Serialized<F, T> {
+    type C = FfiByteSlice;
+    type Error = SerdeError;
+
+    unsafe fn clone_from_repr_c(repr_c: Self::C) -> Result<Self, Self::Error> {
+        F::decode(repr_c.as_slice()).map(Self::new)
+    }
+}
+
+impl<F: Format, T: Serialize + DeserializeOwned> IntoReprC for Serialized<F, T> {
+    type Storage = Vec<u8>;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let bytes = F::encode(&self.value)?;
+        let slice = FfiByteSlice {
+            data: if bytes.is_empty() {
+                ptr::null()
+            } else {
+                bytes.as_ptr()
+            },
+            len: bytes.len(),
+        };
+        Ok((slice, bytes))
+    }
+}
+
+/// Generate the exported function releasing the blobs returned by `encode_to_ffi`:
+///
+/// ```ignore
+/// export_serde_bridge!();
+/// ```
+///
+/// This defines `ffi_byte_slice_free(slice: FfiByteSlice)`.
+#[macro_export]
+macro_rules! export_serde_bridge {
+    () => {
+        /// Release a blob returned by the library.
+        ///
+        /// # Safety
+        ///
+        /// `slice` must have been returned by the library, and must not be used afterwards.
+        #[no_mangle]
+        pub unsafe extern "C" fn ffi_byte_slice_free(slice: $crate::serde_bridge::FfiByteSlice) {
+            $crate::serde_bridge::free_byte_slice(slice)
+        }
+    };
+}
+
+#[cfg(all(test, any(feature = "json", feature = "bincode", feature = "cbor")))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "json")]
+    use crate::test_utils::{call_1, TestError};
+    #[cfg(feature = "json")]
+    use crate::{FfiResult, OpaqueCtx, FFI_RESULT_OK};
+    use serde_derive::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    #[cfg(feature = "json")]
+    use std::os::raw::c_void;
+    use unwrap::unwrap;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Status {
+        name: String,
+        peers: Vec<u32>,
+        labels: BTreeMap<String, Option<bool>>,
+    }
+
+    fn status() -> Status {
+        let mut labels = BTreeMap::new();
+        let _ = labels.insert("online".to_owned(), Some(true));
+        let _ = labels.insert("synced".to_owned(), None);
+        Status {
+            name: "node".to_owned(),
+            peers: vec![1, 2, 3],
+            labels,
+        }
+    }
+
+    fn round_trip<F: Format>() {
+        let blob = unwrap!(encode_to_ffi::<F, _>(&status()));
+        let decoded: Status = unsafe { unwrap!(decode_from_ffi::<F, _>(blob.data, blob.len)) };
+        assert_eq!(decoded, status());
+        unsafe { free_byte_slice(blob) };
+
+        let res: Result<Status, _> = unsafe { decode_from_ffi::<F, _>(b"\xff".as_ptr(), 1) };
+        assert_eq!(res.map_err(|e| e.error_code()), Err(ERR_SERDE));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode() {
+        round_trip::<Bincode>();
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor() {
+        round_trip::<Cbor>();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        round_trip::<Json>();
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn empty_blob_is_null() {
+        // Bincode serialises `()` to no bytes at all.
+        let blob = unwrap!(encode_to_ffi::<Bincode, _>(&()));
+        assert!(blob.data.is_null());
+        assert_eq!(blob.len, 0);
+        unsafe {
+            unwrap!(decode_from_ffi::<Bincode, ()>(blob.data, blob.len));
+            free_byte_slice(blob);
+        }
+    }
+
+    #[cfg(feature = "json")]
+    extern "C" fn get_status(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, status: FfiByteSlice),
+    ) {
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        crate::catch_unwind_cb(user_data, o_cb, || -> Result<_, TestError> {
+            let (status, _storage) = unwrap!(Serialized::<Json, _>::new(status()).into_repr_c());
+            o_cb(user_data.as_ptr(), FFI_RESULT_OK, status);
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn serialized_callback_value() {
+        let res: Serialized<Json, Status> = unsafe { unwrap!(call_1(|ud, cb| get_status(ud, cb))) };
+        assert_eq!(res.into_inner(), status());
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! POSIX shared-memory regions, for handing large buffers to the host without copying them
+//! through `Vec<u8>` transfers.
+//!
+//! A region is created and filled on the Rust side, then handed across the FFI as an
+//! `FfiSharedMemory` descriptor. The host either maps the file descriptor itself (possibly in
+//! another process, after passing it over a socket) or opens the region by name, and releases
+//! the Rust side with `ffi_shmem_free` (exported with `export_shmem!`):
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn app_read_file(
+//!     app: Handle,
+//!     o_region: *mut FfiSharedMemory,
+//! ) -> i32 {
+//!     catch_unwind_out(o_region, || -> Result<_, AppError> {
+//!         let content = handles::get::<App>(app)?.read_file()?;
+//!         let mut region = SharedMemory::anonymous(content.len())?;
+//!         region.as_mut_slice().copy_from_slice(&content);
+//!         Ok(region.into_ffi())
+//!     })
+//! }
+//! ```
+//!
+//! Only available on Unix.
+
+use crate::handles::{self, Handle, HandleError};
+use crate::ErrorCode;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt::{self, Display};
+use std::io;
+use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Error code returned when a shared-memory region can't be created or mapped.
+pub const ERR_SHMEM: i32 = -9014;
+
+/// Error creating or mapping a shared-memory region.
+#[derive(Debug)]
+pub struct ShmemError(pub io::Error);
+
+impl ErrorCode for ShmemError {
+    fn error_code(&self) -> i32 {
+        ERR_SHMEM
+    }
+}
+
+impl Display for ShmemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Shared memory error: {}", self.0)
+    }
+}
+
+impl Error for ShmemError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl From<io::Error> for ShmemError {
+    fn from(error: io::Error) -> Self {
+        ShmemError(error)
+    }
+}
+
+/// Descriptor of a shared-memory region handed across the FFI.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FfiSharedMemory {
+    /// Handle keeping the region alive, released with `ffi_shmem_free`.
+    pub handle: Handle,
+    /// File descriptor of the region, valid until the handle is released.
+    pub fd: c_int,
+    /// Address of the region in this process.
+    pub ptr: *mut u8,
+    /// Length of the region in bytes.
+    pub len: usize,
+}
+
+/// Mapped shared-memory region, unmapped and closed on drop. Regions created with `create` are
+/// also unlinked.
+#[derive(Debug)]
+pub struct SharedMemory {
+    fd: RawFd,
+    ptr: *mut u8,
+    len: usize,
+    // Name to unlink on drop, for regions created by this process.
+    owned_name: Option<CString>,
+}
+
+// The region is only mutated through `&mut self`.
+unsafe impl Send for SharedMemory {}
+unsafe impl Sync for SharedMemory {}
+
+impl SharedMemory {
+    /// Create a region of `len` bytes named `name`, which must start with a `/` and contain no
+    /// other slash. Fails if the name is already taken.
+    pub fn create(name: &str, len: usize) -> Result<Self, ShmemError> {
+        let name = c_name(name)?;
+        let fd = shm_open(&name, libc::O_CREAT | libc::O_EXCL | libc::O_RDWR)?;
+        let mut region = match unsafe { Self::map_new(fd, len) } {
+            Ok(region) => region,
+            Err(error) => {
+                let _ = unsafe { libc::shm_unlink(name.as_ptr()) };
+                return Err(error);
+            }
+        };
+        region.owned_name = Some(name);
+        Ok(region)
+    }
+
+    /// Create an unnamed region of `len` bytes, which can only be shared through its descriptor.
+    pub fn anonymous(len: usize) -> Result<Self, ShmemError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!(
+            "/sn_ffi_utils-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let name = c_name(&name)?;
+        let fd = shm_open(&name, libc::O_CREAT | libc::O_EXCL | libc::O_RDWR)?;
+        let _ = unsafe { libc::shm_unlink(name.as_ptr()) };
+        unsafe { Self::map_new(fd, len) }
+    }
+
+    /// Open and map the first `len` bytes of the existing region named `name`. Fails if the
+    /// region is shorter than `len` bytes, as accessing the missing pages would raise `SIGBUS`.
+    pub fn open(name: &str, len: usize) -> Result<Self, ShmemError> {
+        let fd = shm_open(&c_name(name)?, libc::O_RDWR)?;
+        if let Err(error) = check_size(fd, len) {
+            let _ = unsafe { libc::close(fd) };
+            return Err(error);
+        }
+        unsafe { Self::from_fd(fd, len) }
+    }
+
+    /// Map the first `len` bytes of the region behind `fd`, taking ownership of the descriptor.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open descriptor of a region of at least `len` bytes, not owned by anything
+    /// else.
+    pub unsafe fn from_fd(fd: RawFd, len: usize) -> Result<Self, ShmemError> {
+        let ptr = if len == 0 {
+            ptr::NonNull::dangling().as_ptr()
+        } else {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                let error = io::Error::last_os_error();
+                let _ = libc::close(fd);
+                return Err(error.into());
+            }
+            ptr as *mut u8
+        };
+
+        Ok(Self {
+            fd,
+            ptr,
+            len,
+            owned_name: None,
+        })
+    }
+
+    unsafe fn map_new(fd: RawFd, len: usize) -> Result<Self, ShmemError> {
+        let size = libc::off_t::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "region too large"))?;
+        if libc::ftruncate(fd, size) != 0 {
+            let error = io::Error::last_os_error();
+            let _ = libc::close(fd);
+            return Err(error.into());
+        }
+        Self::from_fd(fd, len)
+    }
+
+    /// File descriptor of the region.
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Length of the region in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return `true` if the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Content of the region.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Mutable content of the region.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+
+    /// Register the region in the global handle registry and return its descriptor.
+    pub fn into_ffi(self) -> FfiSharedMemory {
+        let (fd, ptr, len) = (self.fd, self.ptr, self.len);
+        FfiSharedMemory {
+            handle: handles::register(self),
+            fd,
+            ptr,
+            len,
+        }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            if self.len > 0 {
+                let _ = libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            }
+            let _ = libc::close(self.fd);
+            if let Some(name) = &self.owned_name {
+                let _ = libc::shm_unlink(name.as_ptr());
+            }
+        }
+    }
+}
+
+/// Release the region behind `handle`, returned in an `FfiSharedMemory`.
+pub fn free(handle: Handle) -> Result<(), HandleError> {
+    handles::free::<SharedMemory>(handle)
+}
+
+fn c_name(name: &str) -> Result<CString, ShmemError> {
+    CString::new(name)
+        .map_err(|error| ShmemError(io::Error::new(io::ErrorKind::InvalidInput, error)))
+}
+
+fn check_size(fd: RawFd, len: usize) -> Result<(), ShmemError> {
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    if u64::try_from(stat.st_size).map_or(true, |size| size < len as u64) {
+        return Err(ShmemError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("region has {} bytes, {} requested", stat.st_size, len),
+        )));
+    }
+
+    Ok(())
+}
+
+fn shm_open(name: &CString, flags: c_int) -> Result<RawFd, ShmemError> {
+    let fd = unsafe { libc::shm_open(name.as_ptr(), flags, 0o600) };
+    if fd < 0 {
+        Err(io::Error::last_os_error().into())
+    } else {
+        Ok(fd)
+    }
+}
+
+/// Export the shared-memory functions of the library.
+///
+/// Defines `ffi_shmem_free(handle: u64) -> i32`, releasing a region returned in an
+/// `FfiSharedMemory`.
+#[macro_export]
+macro_rules! export_shmem {
+    () => {
+        /// Release a shared-memory region.
+        #[no_mangle]
+        pub extern "C" fn ffi_shmem_free(handle: u64) -> i32 {
+            $crate::ffi_result_code!($crate::shmem::free(handle))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unwrap::unwrap;
+
+    #[test]
+    fn share_region() {
+        let name = format!("/sn_ffi_utils-test-{}", std::process::id());
+        let mut region = unwrap!(SharedMemory::create(&name, 4));
+        region.as_mut_slice().copy_from_slice(&[1, 2, 3, 4]);
+
+        let other = unwrap!(SharedMemory::open(&name, 4));
+        assert_eq!(other.as_slice(), [1, 2, 3, 4]);
+        assert!(SharedMemory::open(&name, 5).is_err());
+        assert!(SharedMemory::create(&name, 4).is_err());
+
+        drop(region);
+        assert!(SharedMemory::open(&name, 4).is_err());
+    }
+
+    #[test]
+    fn anonymous_region_through_ffi() {
+        let mut region = unwrap!(SharedMemory::anonymous(3));
+        region.as_mut_slice().copy_from_slice(&[5, 6, 7]);
+        let ffi = region.into_ffi();
+
+        let fd = unsafe { libc::dup(ffi.fd) };
+        let mapped = unsafe { unwrap!(SharedMemory::from_fd(fd, ffi.len)) };
+        assert_eq!(mapped.as_slice(), [5, 6, 7]);
+
+        unwrap!(free(ffi.handle));
+        assert_eq!(mapped.as_slice(), [5, 6, 7]);
+    }
+}
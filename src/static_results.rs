@@ -0,0 +1,207 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Pre-allocated `FfiResult`s for errors with fixed descriptions.
+//!
+//! Reporting an error normally allocates its description as a fresh `CString`. Error codes
+//! whose description never changes can be registered once at startup; `catch_unwind_cb`,
+//! `call_result_cb!` and the other helpers invoking callbacks with errors then pass the
+//! registered `FfiResult` instead, allocating only for dynamic descriptions:
+//!
+//! ```ignore
+//! static_results::register(ERR_NOT_FOUND, "Entry not found")?;
+//! // Or register the descriptions of a whole `error_codes!` table.
+//! static_results::register_error_codes::<AppError>()?;
+//! ```
+//!
+//! A registered result is only used when the error's `Display` output matches the registered
+//! description exactly, which is checked without allocating.
+
+use crate::error_codes::ErrorCodes;
+use crate::{FfiResult, StringError};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt::{self, Display, Write};
+use std::sync::{PoisonError, RwLock};
+
+#[derive(Clone, Copy)]
+struct Registered {
+    description: &'static str,
+    result: &'static FfiResult,
+}
+
+// The registered results are never modified or released.
+unsafe impl Send for Registered {}
+unsafe impl Sync for Registered {}
+
+static REGISTERED: RwLock<Option<HashMap<i32, Registered>>> = RwLock::new(None);
+
+/// Register the fixed `description` of `error_code`, replacing any previous registration.
+/// Registered results are never released, so this is meant to be called once per code.
+pub fn register(error_code: i32, description: &'static str) -> Result<(), StringError> {
+    let c_description = CString::new(description)?;
+    let result = Box::leak(Box::new(FfiResult {
+        error_code,
+        description: c_description.into_raw(),
+    }));
+
+    let _ = REGISTERED
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(HashMap::new)
+        .insert(
+            error_code,
+            Registered {
+                description,
+                result,
+            },
+        );
+    Ok(())
+}
+
+/// Register the description of every code in the table of `E`.
+pub fn register_error_codes<E: ErrorCodes>() -> Result<(), StringError> {
+    E::ERROR_CODES
+        .iter()
+        .try_for_each(|desc| register(desc.code, desc.description))
+}
+
+/// Return the registered result of `error_code`, if its description is the `Display` output of
+/// `error`.
+pub fn lookup<E: Display + ?Sized>(error_code: i32, error: &E) -> Option<&'static FfiResult> {
+    let registered = *REGISTERED
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()?
+        .get(&error_code)?;
+
+    let mut matcher = Matcher {
+        rest: registered.description,
+    };
+    if write!(matcher, "{}", error).is_ok() && matcher.rest.is_empty() {
+        Some(registered.result)
+    } else {
+        None
+    }
+}
+
+// Consumes the expected string as it is written, failing on the first difference.
+struct Matcher {
+    rest: &'static str,
+}
+
+impl Write for Matcher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.rest.strip_prefix(s) {
+            Some(rest) => {
+                self.rest = rest;
+                Ok(())
+            }
+            None => Err(fmt::Error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{call_result_cb, catch_unwind_cb, ErrorCode};
+    use std::ffi::CStr;
+    use std::os::raw::c_void;
+    use std::ptr;
+    use unwrap::unwrap;
+
+    const ERR_STATIC: i32 = -9900;
+
+    #[derive(Debug)]
+    enum Error {
+        Static,
+        Dynamic(u32),
+        Panic,
+    }
+
+    impl ErrorCode for Error {
+        fn error_code(&self) -> i32 {
+            ERR_STATIC
+        }
+    }
+
+    impl Error {
+        const WHAT: &'static str = "failure";
+    }
+
+    impl Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                // Written in several chunks.
+                Error::Static => write!(f, "Static {}", Self::WHAT),
+                Error::Dynamic(n) => write!(f, "Static failure {}", n),
+                Error::Panic => write!(f, "panic"),
+            }
+        }
+    }
+
+    impl<'a> From<&'a str> for Error {
+        fn from(_: &'a str) -> Self {
+            Error::Panic
+        }
+    }
+
+    // Records the result pointer and description passed to the callback.
+    extern "C" fn cb(user_data: *mut c_void, result: *const FfiResult) {
+        unsafe {
+            let out = &mut *(user_data as *mut (*const FfiResult, Option<String>));
+            out.0 = result;
+            out.1 = if (*result).description.is_null() {
+                None
+            } else {
+                Some(
+                    CStr::from_ptr((*result).description)
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+            };
+        }
+    }
+
+    fn call(error: Error) -> (*const FfiResult, Option<String>) {
+        let mut out = (ptr::null(), None);
+        let user_data = ptr::from_mut(&mut out).cast::<c_void>();
+        let cb: extern "C" fn(_, _) = cb;
+        catch_unwind_cb(user_data, cb, || Err(error));
+        out
+    }
+
+    #[test]
+    fn registered_results() {
+        unwrap!(register(ERR_STATIC, "Static failure"));
+        let registered = unwrap!(lookup(ERR_STATIC, &Error::Static));
+
+        let (result, description) = call(Error::Static);
+        assert_eq!(result, ptr::from_ref(registered));
+        assert_eq!(description.as_deref(), Some("Static failure"));
+
+        let (result, description) = call(Error::Dynamic(2));
+        assert_ne!(result, ptr::from_ref(registered));
+        assert_eq!(description.as_deref(), Some("Static failure 2"));
+
+        assert!(lookup(ERR_STATIC, "Static").is_none());
+        assert!(lookup(ERR_STATIC - 1, "Static failure").is_none());
+    }
+
+    #[test]
+    fn success_does_not_allocate() {
+        let mut out: (*const FfiResult, Option<String>) = (ptr::null(), None);
+        let user_data = ptr::from_mut(&mut out).cast::<c_void>();
+        let cb: extern "C" fn(_, _) = cb;
+        call_result_cb!(Ok::<_, Error>(()), user_data, cb);
+        assert_eq!(out.0, ptr::from_ref(crate::FFI_RESULT_OK));
+        assert_eq!(out.1.as_deref(), Some(""));
+    }
+}
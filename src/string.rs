@@ -9,11 +9,16 @@
 
 //! Utilities for passing strings across FFI boundaries.
 
-use crate::repr_c::ReprC;
+use crate::repr_c::{IntoReprC, ReprC};
+use alloc::borrow::ToOwned;
+use alloc::ffi::{CString, IntoStringError, NulError};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ffi::{c_char, CStr};
+use core::fmt::{self, Display};
+use core::slice;
+use core::str::{self, Utf8Error};
 use serde_derive::{Deserialize, Serialize};
-use std::ffi::{CStr, IntoStringError, NulError};
-use std::os::raw::c_char;
-use std::str::Utf8Error;
 
 impl ReprC for String {
     type C = *const c_char;
@@ -27,7 +32,17 @@ impl ReprC for String {
                 "String could not be constructed from C null pointer".to_owned(),
             ));
         }
-        Ok(CStr::from_ptr(c_repr).to_str()?.to_owned())
+        let bytes = CStr::from_ptr(c_repr).to_bytes();
+        Ok(validate_utf8(bytes.as_ptr(), bytes.len())?.to_owned())
+    }
+}
+
+impl IntoReprC for String {
+    type Storage = CString;
+
+    fn into_repr_c(self) -> Result<(Self::C, Self::Storage), Self::Error> {
+        let storage = CString::new(self)?;
+        Ok((storage.as_ptr(), storage))
     }
 }
 
@@ -42,12 +57,30 @@ pub enum StringError {
     IntoString(String),
 }
 
+impl Display for StringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StringError::Utf8(e) => write!(f, "Invalid UTF-8: {}", e),
+            StringError::Null(e) => write!(f, "Null error: {}", e),
+            StringError::IntoString(e) => write!(f, "Invalid C string: {}", e),
+        }
+    }
+}
+
+impl core::error::Error for StringError {}
+
 impl From<Utf8Error> for StringError {
     fn from(e: Utf8Error) -> Self {
         StringError::Utf8(e.to_string())
     }
 }
 
+impl From<InvalidUtf8> for StringError {
+    fn from(e: InvalidUtf8) -> Self {
+        StringError::Utf8(e.to_string())
+    }
+}
+
 impl From<NulError> for StringError {
     fn from(e: NulError) -> Self {
         StringError::Null(e.to_string())
@@ -59,3 +92,108 @@ impl From<IntoStringError> for StringError {
         StringError::IntoString(e.to_string())
     }
 }
+
+/// First invalid UTF-8 sequence of a string, as found by `validate_utf8`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidUtf8 {
+    /// Offset of the sequence from the start of the string, in bytes.
+    pub offset: usize,
+    /// Bytes of the sequence.
+    pub sequence: Vec<u8>,
+    /// `true` if the string ends in the middle of the sequence, rather than the sequence being
+    /// invalid.
+    pub incomplete: bool,
+}
+
+impl InvalidUtf8 {
+    fn new(bytes: &[u8], error: Utf8Error) -> Self {
+        let offset = error.valid_up_to();
+        let len = error.error_len().unwrap_or(bytes.len() - offset);
+        Self {
+            offset,
+            sequence: bytes[offset..offset + len].to_vec(),
+            incomplete: error.error_len().is_none(),
+        }
+    }
+}
+
+impl Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.incomplete {
+            "Incomplete"
+        } else {
+            "Invalid"
+        };
+        write!(f, "{} UTF-8 sequence [", kind)?;
+        for (index, byte) in self.sequence.iter().enumerate() {
+            let separator = if index == 0 { "" } else { " " };
+            write!(f, "{}{:02x}", separator, byte)?;
+        }
+        write!(f, "] at byte {}", self.offset)
+    }
+}
+
+/// Check that the `len` bytes at `ptr` are valid UTF-8, returning them as a `str`, or the
+/// position and contents of the first invalid sequence.
+///
+/// # Safety
+///
+/// `ptr` must be valid for reads of `len` bytes for the lifetime `'a`. It may be null if `len`
+/// is 0.
+pub unsafe fn validate_utf8<'a>(ptr: *const u8, len: usize) -> Result<&'a str, InvalidUtf8> {
+    let bytes = if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    };
+    str::from_utf8(bytes).map_err(|error| InvalidUtf8::new(bytes, error))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    fn validate(bytes: &[u8]) -> Result<&str, InvalidUtf8> {
+        unsafe { validate_utf8(bytes.as_ptr(), bytes.len()) }
+    }
+
+    #[test]
+    fn valid_strings() {
+        assert_eq!(
+            validate("ascii and ünïcödé".as_bytes()),
+            Ok("ascii and ünïcödé")
+        );
+        assert_eq!(unsafe { validate_utf8(ptr::null(), 0) }, Ok(""));
+    }
+
+    #[test]
+    fn invalid_sequences_are_located() {
+        let error = unwrap::unwrap!(validate(b"abc\xffdef").err());
+        assert_eq!(error.offset, 3);
+        assert_eq!(error.sequence, [0xff]);
+        assert!(!error.incomplete);
+        assert_eq!(error.to_string(), "Invalid UTF-8 sequence [ff] at byte 3");
+
+        // "€" is e2 82 ac.
+        let error = unwrap::unwrap!(validate(b"price: \xe2\x82").err());
+        assert_eq!(error.offset, 7);
+        assert!(error.incomplete);
+        assert_eq!(
+            error.to_string(),
+            "Incomplete UTF-8 sequence [e2 82] at byte 7"
+        );
+    }
+
+    #[test]
+    fn string_conversion_reports_position() {
+        let c_string = b"ok\xc3(\0";
+        let error = unsafe { String::clone_from_repr_c(c_string.as_ptr() as *const c_char) };
+        assert_eq!(
+            error,
+            Err(StringError::Utf8(
+                "Invalid UTF-8 sequence [c3] at byte 2".to_owned()
+            ))
+        );
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Async counterparts of the `call_*` helpers, for testing FFI functions from async code.
+//!
+//! The FFI function is invoked straight away and the returned future resolves once its callback
+//! has been called, without blocking the runtime in the meantime:
+//!
+//! ```ignore
+//! let value: i32 = unsafe { call_1_async(|ud, cb| foreign_function(1, ud, cb)) }.await?;
+//! ```
+
+use super::{
+    callback_result, convert_arg, shielded_result, take_checked, CheckedSendWrapper, UserData,
+};
+use crate::repr_c::ReprC;
+use crate::shield;
+use crate::FfiResult;
+use std::fmt::Debug;
+use std::future::Future;
+use std::os::raw::c_void;
+use std::{ptr, slice};
+use tokio::sync::oneshot;
+
+// The `UserData` and the sender it points to are boxed and released by the callback, so the
+// returned future only holds the receiving end of the channel.
+fn oneshot_as_user_data<T>(tx: oneshot::Sender<T>) -> *mut c_void {
+    let ud = UserData {
+        common: Box::into_raw(Box::new(tx)) as *mut c_void,
+        custom: ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(ud)) as *mut c_void
+}
+
+unsafe fn send_via_oneshot<T>(user_data: *mut c_void, value: T) {
+    let ud = Box::from_raw(user_data as *mut UserData);
+    let tx = Box::from_raw(ud.common as *mut oneshot::Sender<T>);
+    // The receiver is gone if the future was dropped, in which case nobody wants the value.
+    let _ = tx.send(value);
+}
+
+async fn recv_oneshot<T>(rx: oneshot::Receiver<CheckedSendWrapper<T>>) -> T {
+    match rx.await {
+        Ok(value) => take_checked(value),
+        Err(_) => panic!("callback never invoked"),
+    }
+}
+
+// Generates `call_N_async` and its callback, for callbacks accepting N arguments in addition to
+// `user_data` and `error_code`. See `gen_call!` for the `unused_parens` allows.
+macro_rules! gen_call_async {
+    (
+        $count:literal,
+        [$($qual:tt)*] $call:ident,
+        $callback:ident
+        $(, ($arg:ident: $t:ident, $e:ident))*
+    ) => {
+        #[doc = concat!(
+            "Call an FFI function and return a future resolving to the arguments which were\n",
+            "passed to its callback.\n",
+            "Use this if the callback accepts ", $count, " in addition to `user_data`\n",
+            "and `error_code`."
+        )]
+        #[allow(unused_parens)]
+        pub $($qual)* fn $call<F, $($e,)* $($t),*>(
+            f: F,
+        ) -> impl Future<Output = Result<($($t),*), i32>>
+        where
+            F: FnOnce(
+                *mut c_void,
+                extern "C" fn(user_data: *mut c_void, result: *const FfiResult $(, $t::C)*),
+            ),
+            $($e: Debug, $t: ReprC<Error = $e>,)*
+        {
+            let (tx, rx) = oneshot::channel::<CheckedSendWrapper<Result<($($t),*), i32>>>();
+            f(oneshot_as_user_data(tx), $callback::<$($e,)* $($t),*>);
+            recv_oneshot(rx)
+        }
+
+        #[allow(unused_parens, clippy::needless_question_mark)]
+        extern "C" fn $callback<$($e,)* $($t),*>(
+            user_data: *mut c_void,
+            res: *const FfiResult
+            $(, $arg: $t::C)*
+        ) where
+            $($e: Debug, $t: ReprC<Error = $e>,)*
+        {
+            shield(move || unsafe {
+                let result: Result<($($t),*), i32> = shielded_result(|| {
+                    callback_result((*res).error_code, || Ok(($(convert_arg::<$t>($arg)?),*)))
+                });
+                send_via_oneshot(user_data, CheckedSendWrapper::new(result))
+            })
+        }
+    };
+}
+
+gen_call_async!("no arguments", [] call_0_async, callback_0_async);
+gen_call_async!(
+    "one argument",
+    [unsafe] call_1_async,
+    callback_1_async,
+    (arg: T, E)
+);
+gen_call_async!(
+    "two arguments",
+    [unsafe] call_2_async,
+    callback_2_async,
+    (arg0: T0, E0),
+    (arg1: T1, E1)
+);
+gen_call_async!(
+    "three arguments",
+    [unsafe] call_3_async,
+    callback_3_async,
+    (arg0: T0, E0),
+    (arg1: T1, E1),
+    (arg2: T2, E2)
+);
+gen_call_async!(
+    "four arguments",
+    [unsafe] call_4_async,
+    callback_4_async,
+    (arg0: T0, E0),
+    (arg1: T1, E1),
+    (arg2: T2, E2),
+    (arg3: T3, E3)
+);
+
+/// Call a FFI function and return a future resolving to the array argument which was passed to
+/// its callback, copied into a `Vec<T>`.
+/// Use this if the callback accepts `*const T` and `usize` (length) arguments in addition
+/// to `user_data` and `error_code`.
+pub unsafe fn call_vec_async<F, E, T, U>(f: F) -> impl Future<Output = Result<Vec<T>, i32>>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, T::C, usize),
+    ),
+    E: Debug,
+    T: ReprC<C = *const U, Error = E>,
+{
+    let (tx, rx) = oneshot::channel::<CheckedSendWrapper<Result<Vec<T>, i32>>>();
+    f(oneshot_as_user_data(tx), callback_vec_async::<E, T, U>);
+    recv_oneshot(rx)
+}
+
+/// Call a FFI function and return a future resolving to the byte array argument which was
+/// passed to its callback, copied into a `Vec<u8>`.
+pub unsafe fn call_vec_u8_async<F>(f: F) -> impl Future<Output = Result<Vec<u8>, i32>>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, *const u8, usize),
+    ),
+{
+    let (tx, rx) = oneshot::channel::<CheckedSendWrapper<Result<Vec<u8>, i32>>>();
+    f(oneshot_as_user_data(tx), callback_vec_u8_async);
+    recv_oneshot(rx)
+}
+
+extern "C" fn callback_vec_async<E, T, U>(
+    user_data: *mut c_void,
+    res: *const FfiResult,
+    array: *const U,
+    size: usize,
+) where
+    E: Debug,
+    T: ReprC<C = *const U, Error = E>,
+{
+    shield(move || unsafe {
+        let result: Result<Vec<T>, i32> = shielded_result(|| {
+            callback_result((*res).error_code, || {
+                slice::from_raw_parts(array, size)
+                    .iter()
+                    .map(|elt| convert_arg::<T>(elt))
+                    .collect()
+            })
+        });
+
+        send_via_oneshot(user_data, CheckedSendWrapper::new(result))
+    })
+}
+
+extern "C" fn callback_vec_u8_async(
+    user_data: *mut c_void,
+    res: *const FfiResult,
+    ptr: *const u8,
+    len: usize,
+) {
+    shield(move || unsafe {
+        let result = if (*res).error_code == 0 {
+            Ok(slice::from_raw_parts(ptr, len).to_vec())
+        } else {
+            Err((*res).error_code)
+        };
+
+        send_via_oneshot(user_data, CheckedSendWrapper::new(result))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FFI_RESULT_OK;
+    use std::thread;
+    use unwrap::unwrap;
+
+    extern "C" fn answer_from_thread(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, u32),
+    ) {
+        let user_data = user_data as usize;
+        let _ = thread::spawn(move || o_cb(user_data as *mut c_void, FFI_RESULT_OK, 42));
+    }
+
+    #[tokio::test]
+    async fn call_1_async_from_another_thread() {
+        let value: u32 =
+            unwrap!(unsafe { call_1_async(|ud, cb| answer_from_thread(ud, cb)) }.await);
+        assert_eq!(value, 42);
+    }
+}
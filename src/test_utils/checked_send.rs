@@ -0,0 +1,128 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::thread::{self, ThreadId};
+
+/// Wrapper for passing non-`Send` types through channels, which panics if the value is accessed
+/// or dropped on a thread other than its owner.
+///
+/// The owner is the thread which created the wrapper, until another thread takes over with
+/// `transfer`. Unlike `SendWrapper`, accidental use of the value on two threads is detected.
+/// A wrapper dropped on another thread while it is already panicking leaks the value instead.
+pub struct CheckedSendWrapper<T> {
+    value: ManuallyDrop<T>,
+    owner: ThreadId,
+}
+
+// The value is only ever used or dropped on its owner thread, whatever `T` is: any other thread
+// panics or leaks it. Moving it to another thread requires the unsafe `transfer`, whose caller
+// vouches for `T` being usable there.
+unsafe impl<T> Send for CheckedSendWrapper<T> {}
+
+impl<T> CheckedSendWrapper<T> {
+    /// Wrap `value`, owned by the current thread.
+    pub fn new(value: T) -> Self {
+        Self {
+            value: ManuallyDrop::new(value),
+            owner: thread::current().id(),
+        }
+    }
+
+    /// Make the current thread the owner of the value.
+    ///
+    /// # Safety
+    ///
+    /// The value must be safe to use from the current thread, e.g. it must not share any state
+    /// with values left on the previous owner.
+    pub unsafe fn transfer(mut self) -> Self {
+        self.owner = thread::current().id();
+        self
+    }
+
+    /// Unwrap the value.
+    pub fn into_inner(self) -> T {
+        self.check();
+        let mut this = ManuallyDrop::new(self);
+        unsafe { ManuallyDrop::take(&mut this.value) }
+    }
+
+    /// Return `true` if the current thread owns the value.
+    pub fn is_owner(&self) -> bool {
+        self.owner == thread::current().id()
+    }
+
+    fn check(&self) {
+        if !self.is_owner() {
+            panic!(
+                "value owned by {:?} accessed from {:?}",
+                self.owner,
+                thread::current().id()
+            );
+        }
+    }
+}
+
+impl<T> Deref for CheckedSendWrapper<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.check();
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CheckedSendWrapper<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.check();
+        &mut self.value
+    }
+}
+
+impl<T> Drop for CheckedSendWrapper<T> {
+    fn drop(&mut self) {
+        if self.is_owner() {
+            unsafe { ManuallyDrop::drop(&mut self.value) }
+        } else if !thread::panicking() {
+            self.check();
+        }
+        // A value not dropped above is leaked rather than dropped on the wrong thread. Checking
+        // during an ongoing panic would turn it into an abort.
+    }
+}
+
+impl<T> fmt::Debug for CheckedSendWrapper<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CheckedSendWrapper")
+            .field("owner", &self.owner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unwrap::unwrap;
+
+    #[test]
+    fn transferred_value_is_usable() {
+        let wrapper = unwrap!(thread::spawn(|| CheckedSendWrapper::new(vec![1, 2])).join());
+        assert!(!wrapper.is_owner());
+        assert_eq!(unsafe { wrapper.transfer() }.into_inner(), vec![1, 2]);
+    }
+
+    #[test]
+    fn access_from_other_thread_panics() {
+        let wrapper = CheckedSendWrapper::new(1);
+        let res = thread::spawn(move || *wrapper).join();
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Error injection, for testing the error paths of FFI bindings.
+//!
+//! `catch_unwind_cb` and `call_result_cb!` consult the hook registered with `fail_next` and, while
+//! it is active, report the chosen error code to the callback instead of the actual outcome.
+//! `catch_unwind_cb` does not even run its closure in that case.
+//!
+//! Faults are injected per thread: only the calls made on the thread which registered the hook
+//! are affected, so tests running in parallel don't interfere with each other.
+//!
+//! Requires the `testing` feature, without which the hooks are compiled out.
+//!
+//! ```ignore
+//! let _fault = fault::fail_next(1, -100);
+//! assert_eq!(unsafe { call_0(|ud, cb| app_reconnect(app, ud, cb)) }, Err(-100));
+//! ```
+
+use crate::callback::Callback;
+use crate::catch_unwind::call_error_cb;
+use std::cell::Cell;
+use std::os::raw::c_void;
+
+/// Description reported along with injected error codes.
+pub const DESCRIPTION: &str = "Injected fault";
+
+thread_local! {
+    // Number of calls left to fail, and the error code to fail them with.
+    static FAULT: Cell<(usize, i32)> = const { Cell::new((0, 0)) };
+}
+
+/// Make the next `calls` calls to `catch_unwind_cb` or `call_result_cb!` on the current thread
+/// fail with `error_code`, replacing any fault registered before.
+///
+/// The hook is removed when the returned guard is dropped, even if some of the calls are left.
+#[must_use = "the fault is cleared when the guard is dropped"]
+pub fn fail_next(calls: usize, error_code: i32) -> FaultGuard {
+    FAULT.with(|fault| fault.set((calls, error_code)));
+    FaultGuard(())
+}
+
+/// Number of calls on the current thread which are still going to fail.
+pub fn remaining() -> usize {
+    FAULT.with(|fault| fault.get().0)
+}
+
+/// Remove the hook registered on the current thread, if any.
+pub fn clear() {
+    FAULT.with(|fault| fault.set((0, 0)));
+}
+
+/// Consume one injected fault, returning its error code.
+#[doc(hidden)]
+pub fn take() -> Option<i32> {
+    FAULT.with(|fault| match fault.get() {
+        (0, _) => None,
+        (calls, error_code) => {
+            fault.set((calls - 1, error_code));
+            Some(error_code)
+        }
+    })
+}
+
+/// Call `cb` with the injected `error_code`.
+#[doc(hidden)]
+pub fn report<C: Callback>(user_data: *mut c_void, cb: C, error_code: i32) {
+    call_error_cb(user_data, cb, error_code, DESCRIPTION.to_owned())
+}
+
+/// Guard returned by `fail_next`, removing the hook when dropped.
+#[derive(Debug)]
+pub struct FaultGuard(());
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) {
+        clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_1, TestError};
+    use crate::{catch_unwind_cb, FfiResult, OpaqueCtx, FFI_RESULT_OK};
+    use std::os::raw::c_void;
+
+    unsafe extern "C" fn answer(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, u32),
+    ) {
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        catch_unwind_cb(user_data, o_cb, || -> Result<_, TestError> {
+            o_cb(user_data.as_ptr(), FFI_RESULT_OK, 42);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn next_calls_fail() {
+        let fault = fail_next(2, -100);
+        for _ in 0..2 {
+            assert_eq!(
+                unsafe { call_1::<_, _, u32>(|ud, cb| answer(ud, cb)) },
+                Err(-100)
+            );
+        }
+        assert_eq!(remaining(), 0);
+        assert_eq!(
+            unsafe { call_1::<_, _, u32>(|ud, cb| answer(ud, cb)) },
+            Ok(42)
+        );
+
+        let _ = fail_next(1, -100);
+        drop(fault);
+        assert_eq!(
+            unsafe { call_1::<_, _, u32>(|ud, cb| answer(ud, cb)) },
+            Ok(42)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::call_1;
+use crate::FfiResult;
+use std::fmt;
+use std::os::raw::c_void;
+
+/// Opaque handle returned by an FFI function, released with the corresponding `*_free` function
+/// when the guard is dropped.
+pub struct HandleGuard<H> {
+    handle: *mut H,
+    free: unsafe extern "C" fn(*mut H),
+}
+
+impl<H> HandleGuard<H> {
+    /// Take ownership of `handle`, to be released with `free`.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be valid to pass to `free`, and must not be released by anything else.
+    pub unsafe fn new(handle: *mut H, free: unsafe extern "C" fn(*mut H)) -> Self {
+        Self { handle, free }
+    }
+
+    /// Return the handle, which remains owned by the guard.
+    pub fn get(&self) -> *mut H {
+        self.handle
+    }
+
+    /// Give up ownership of the handle without releasing it.
+    pub fn into_raw(self) -> *mut H {
+        let handle = self.handle;
+        std::mem::forget(self);
+        handle
+    }
+}
+
+impl<H> Drop for HandleGuard<H> {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { (self.free)(self.handle) }
+        }
+    }
+}
+
+impl<H> fmt::Debug for HandleGuard<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("HandleGuard").field(&self.handle).finish()
+    }
+}
+
+/// Call a FFI function and block until its callback gets called, then return a guard owning the
+/// opaque handle which was passed to that callback. The handle is released with `free` when the
+/// guard is dropped.
+/// Use this if the callback accepts a `*mut H` argument in addition to `user_data` and
+/// `error_code`.
+pub unsafe fn call_handle<F, H>(
+    free: unsafe extern "C" fn(*mut H),
+    f: F,
+) -> Result<HandleGuard<H>, i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, result: *const FfiResult, *mut H)),
+{
+    let handle = call_1::<_, _, *mut H>(f)?;
+    Ok(HandleGuard::new(handle, free))
+}
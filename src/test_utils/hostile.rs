@@ -0,0 +1,234 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Inputs of a misbehaving host, to check that FFI functions fail with an error code instead of
+//! crashing.
+//!
+//! Every binding is hardened the same way: each FFI function taking a string or a buffer is run
+//! through `check_string_arg` or `check_buffer_arg`, and functions taking callbacks are called
+//! with callbacks which re-enter the library or panic:
+//!
+//! ```ignore
+//! hostile::check_string_arg(|name| call_0(|ud, cb| app_login(app, name, ud, cb)));
+//! hostile::check_buffer_arg(|ptr, len| call_0(|ud, cb| app_put(app, ptr, len, ud, cb)));
+//! ```
+
+use super::{send_via_channel, shielded_result, CallbackChannel, UserData, DEFAULT_CALL_TIMEOUT};
+use crate::{shield, FfiResult};
+use std::ffi::CString;
+use std::fmt::Debug;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Lengths which no buffer can have, as they exceed `isize::MAX`, the size limit of any
+/// allocation. Functions must reject them before building a slice.
+pub const ABSURD_LENGTHS: [usize; 2] = [isize::MAX as usize + 1, usize::MAX];
+
+/// A C string which is not valid UTF-8.
+pub fn non_utf8_c_string() -> CString {
+    // A lone continuation byte and bytes which never appear in UTF-8, but no nul byte.
+    unsafe { CString::from_vec_unchecked(vec![b'a', 0x80, 0xfe, 0xff]) }
+}
+
+/// Run `call`, made with the hostile input described by `case`, and return its error code.
+///
+/// Panics if the call succeeded, failed with a zero error code, or panicked.
+#[track_caller]
+pub fn expect_error<T, F>(case: &str, call: F) -> i32
+where
+    T: Debug,
+    F: FnOnce() -> Result<T, i32>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Err(0)) => panic!("{}: failed with a zero error code", case),
+        Ok(Err(error_code)) => error_code,
+        Ok(Ok(value)) => panic!("{}: succeeded with {:?}", case, value),
+        Err(_) => panic!("{}: panicked instead of returning an error", case),
+    }
+}
+
+/// Same as `expect_error`, for functions returning a status code, 0 meaning success.
+#[track_caller]
+pub fn expect_status<F>(case: &str, call: F) -> i32
+where
+    F: FnOnce() -> i32,
+{
+    expect_error(case, || match call() {
+        0 => Ok(()),
+        error_code => Err(error_code),
+    })
+}
+
+/// Check that `call` fails when given a null string or one which isn't valid UTF-8.
+#[track_caller]
+pub fn check_string_arg<T, F>(mut call: F)
+where
+    T: Debug,
+    F: FnMut(*const c_char) -> Result<T, i32>,
+{
+    let _ = expect_error("null string", || call(ptr::null()));
+
+    let invalid = non_utf8_c_string();
+    let _ = expect_error("non-UTF-8 string", || call(invalid.as_ptr()));
+}
+
+/// Check that `call` fails when given a null buffer with a non-zero length, or any of the
+/// `ABSURD_LENGTHS`. The pointer passed along with the absurd lengths is dangling, so a function
+/// which reads the buffer without validating its length crashes.
+#[track_caller]
+pub fn check_buffer_arg<T, F>(mut call: F)
+where
+    T: Debug,
+    F: FnMut(*const u8, usize) -> Result<T, i32>,
+{
+    let _ = expect_error("null buffer", || call(ptr::null(), 1));
+
+    for len in &ABSURD_LENGTHS {
+        let case = format!("buffer of length {:#x}", len);
+        let _ = expect_error(&case, || call(NonNull::dangling().as_ptr(), *len));
+    }
+}
+
+/// Same as `call_0`, but the callback runs `reenter` before returning the result, to check that
+/// an FFI function copes with its callback calling back into the library.
+///
+/// As `reenter` may borrow from the caller, the callback must not be invoked after this function
+/// has returned or panicked.
+pub fn call_0_reentrant<F, R>(f: F, reenter: R) -> Result<(), i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, result: *const FfiResult)),
+    R: FnOnce() + Send,
+{
+    let reenter = Mutex::new(Some(reenter));
+    let run: &(dyn Fn() + Sync) = &|| {
+        let reenter = reenter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(reenter) = reenter {
+            reenter()
+        }
+    };
+
+    let channel = CallbackChannel::<i32>::new(ptr::from_ref(&run) as *mut c_void);
+    f(channel.user_data(), callback_0_reentrant);
+
+    match channel.recv(DEFAULT_CALL_TIMEOUT) {
+        0 => Ok(()),
+        error_code => Err(error_code),
+    }
+}
+
+extern "C" fn callback_0_reentrant(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || unsafe {
+        let ud = &*(user_data as *const UserData);
+        let run = &*(ud.custom as *const &(dyn Fn() + Sync));
+        let error_code = match shielded_result(|| {
+            run();
+            Ok(())
+        }) {
+            Ok(()) => (*res).error_code,
+            Err(error_code) => error_code,
+        };
+        send_via_channel(user_data, error_code)
+    })
+}
+
+/// Call an FFI function with a callback which panics, and return whether the callback was
+/// invoked by the time the function returned.
+///
+/// Unwinding out of an `extern "C"` function aborts the process, so the panic is contained in
+/// the callback like a well-behaved host contains its own exceptions. This checks that the
+/// function doesn't rely on the callback completing, e.g. by leaving a lock held. The callback
+/// must be invoked before the function returns.
+pub unsafe fn call_0_panicking<F>(f: F) -> bool
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, result: *const FfiResult)),
+{
+    let invoked = AtomicBool::new(false);
+    f(ptr::from_ref(&invoked) as *mut c_void, callback_0_panicking);
+    invoked.load(Ordering::SeqCst)
+}
+
+extern "C" fn callback_0_panicking(user_data: *mut c_void, _res: *const FfiResult) {
+    shield(move || {
+        let invoked = unsafe { &*(user_data as *const AtomicBool) };
+        invoked.store(true, Ordering::SeqCst);
+        panic!("simulated panic in a host callback");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_0, TestError};
+    use crate::{catch_unwind_cb, decode_arg, FFI_RESULT_OK};
+    use unwrap::unwrap;
+
+    extern "C" fn greet(
+        name: *const c_char,
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult),
+    ) {
+        catch_unwind_cb(user_data, o_cb, || -> Result<(), TestError> {
+            let _ = unsafe { decode_arg::<String>("name", name) }
+                .map_err(|error| TestError::FromStr(error.to_string()))?;
+            o_cb(user_data, FFI_RESULT_OK);
+            Ok(())
+        })
+    }
+
+    extern "C" fn checksum(
+        data: *const u8,
+        len: usize,
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult),
+    ) {
+        catch_unwind_cb(user_data, o_cb, || -> Result<(), TestError> {
+            if data.is_null() || len > isize::MAX as usize {
+                return Err(TestError::Test);
+            }
+            let _ = unsafe { std::slice::from_raw_parts(data, len) }
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            o_cb(user_data, FFI_RESULT_OK);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn hostile_arguments_are_rejected() {
+        check_string_arg(|name| call_0(|ud, cb| greet(name, ud, cb)));
+        check_buffer_arg(|data, len| call_0(|ud, cb| checksum(data, len, ud, cb)));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty name: succeeded")]
+    fn accepted_argument_fails_the_check() {
+        let empty = CString::default();
+        let _ = expect_error("empty name", || {
+            call_0(|ud, cb| greet(empty.as_ptr(), ud, cb))
+        });
+    }
+
+    #[test]
+    fn misbehaving_callbacks() {
+        let name = unwrap!(CString::new("name"));
+        let res = call_0_reentrant(
+            |ud, cb| greet(name.as_ptr(), ud, cb),
+            || assert_eq!(call_0(|ud, cb| greet(ptr::null(), ud, cb)), Err(-2)),
+        );
+        assert_eq!(res, Ok(()));
+
+        assert!(unsafe { call_0_panicking(|ud, cb| greet(name.as_ptr(), ud, cb)) });
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Leak checking through a counting global allocator.
+//!
+//! `assert_no_leaks` only works once `CountingAllocator` is the global allocator, which the test
+//! binary has to declare itself, so that the library never replaces the allocator of the
+//! binaries it is linked into:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: CountingAllocator = CountingAllocator;
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Whether allocations made on the current thread are tracked.
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Tracked allocations which have not been freed yet, and their total size.
+static LIVE_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// Only one `assert_no_leaks` may be tracking at a time.
+static SESSION: Mutex<()> = Mutex::new(());
+
+const TRACKED: usize = 1;
+
+/// Global allocator which tags allocations made while `assert_no_leaks` is running, so that they
+/// can be counted until they are freed, on whichever thread that happens.
+///
+/// Every allocation is prefixed with a header holding the tag.
+pub struct CountingAllocator;
+
+fn header_size(layout: Layout) -> usize {
+    layout.align().max(size_of::<usize>())
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let header = header_size(layout);
+        let outer = match Layout::from_size_align(layout.size() + header, layout.align()) {
+            Ok(outer) => outer,
+            Err(_) => return std::ptr::null_mut(),
+        };
+
+        let base = System.alloc(outer);
+        if base.is_null() {
+            return base;
+        }
+
+        let ptr = base.add(header);
+        let tracked = TRACKING.with(Cell::get);
+        (ptr as *mut usize)
+            .sub(1)
+            .write_unaligned(if tracked { TRACKED } else { 0 });
+
+        if tracked {
+            let _ = LIVE_ALLOCS.fetch_add(1, Ordering::SeqCst);
+            let _ = LIVE_BYTES.fetch_add(layout.size(), Ordering::SeqCst);
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = header_size(layout);
+        if (ptr as *mut usize).sub(1).read_unaligned() == TRACKED {
+            let _ = LIVE_ALLOCS.fetch_sub(1, Ordering::SeqCst);
+            let _ = LIVE_BYTES.fetch_sub(layout.size(), Ordering::SeqCst);
+        }
+
+        let outer = Layout::from_size_align_unchecked(layout.size() + header, layout.align());
+        System.dealloc(ptr.sub(header), outer)
+    }
+}
+
+struct TrackingGuard;
+
+impl Drop for TrackingGuard {
+    fn drop(&mut self) {
+        TRACKING.with(|tracking| tracking.set(false));
+    }
+}
+
+/// Run `f`, panicking if any of the allocations it made on the current thread have not been
+/// freed, on any thread, by the time it returns.
+///
+/// Allocations which are meant to outlive `f` (e.g. lazily initialised statics) are reported
+/// as leaks too, so initialise them beforehand. Allocations made by threads spawned from `f`
+/// are not tracked.
+///
+/// Panics straight away if `CountingAllocator` is not the global allocator.
+pub fn assert_no_leaks<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _session = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+    assert!(
+        is_installed(),
+        "assert_no_leaks requires CountingAllocator as the global allocator"
+    );
+    let (allocs_before, bytes_before) = live();
+
+    let result = {
+        let _guard = TrackingGuard;
+        TRACKING.with(|tracking| tracking.set(true));
+        f()
+    };
+
+    let (allocs, bytes) = live();
+    if allocs != allocs_before {
+        panic!(
+            "{} allocation(s) of {} byte(s) in total were leaked",
+            allocs.wrapping_sub(allocs_before),
+            bytes.wrapping_sub(bytes_before)
+        );
+    }
+
+    result
+}
+
+/// Run `f` without tracking the allocations it makes on the current thread, e.g. for
+/// bookkeeping which is meant to outlive the call being checked.
+#[cfg(feature = "memory-report")]
+pub(crate) fn untracked<F: FnOnce() -> R, R>(f: F) -> R {
+    let tracking = TRACKING.with(|tracking| tracking.replace(false));
+    let _guard = RestoreGuard(tracking);
+    f()
+}
+
+#[cfg(feature = "memory-report")]
+struct RestoreGuard(bool);
+
+#[cfg(feature = "memory-report")]
+impl Drop for RestoreGuard {
+    fn drop(&mut self) {
+        let _ = TRACKING.try_with(|tracking| tracking.set(self.0));
+    }
+}
+
+// Whether allocations are counted, i.e. `CountingAllocator` is the global allocator. Must be
+// called within a session.
+fn is_installed() -> bool {
+    let before = live().0;
+    let _guard = TrackingGuard;
+    TRACKING.with(|tracking| tracking.set(true));
+    let probe = std::hint::black_box(Box::new(0u8));
+    let installed = live().0 != before;
+    drop(probe);
+    installed
+}
+
+fn live() -> (usize, usize) {
+    (
+        LIVE_ALLOCS.load(Ordering::SeqCst),
+        LIVE_BYTES.load(Ordering::SeqCst),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::call_vec_u8;
+    use crate::{vec_into_raw_parts, FfiResult, FFI_RESULT_OK};
+    use std::os::raw::c_void;
+
+    extern "C" fn bytes(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, *const u8, usize),
+    ) {
+        let data = [1u8, 2, 3];
+        o_cb(user_data, FFI_RESULT_OK, data.as_ptr(), data.len());
+    }
+
+    #[test]
+    fn balanced_allocations_pass() {
+        assert_no_leaks(|| {
+            let data = unsafe { unwrap::unwrap!(call_vec_u8(|ud, cb| bytes(ud, cb))) };
+            assert_eq!(data, vec![1, 2, 3]);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "1 allocation(s) of 4 byte(s)")]
+    fn leaked_allocation_fails() {
+        assert_no_leaks(|| {
+            let _ = vec_into_raw_parts(vec![0u8; 4]);
+        });
+    }
+
+    #[cfg(feature = "memory-report")]
+    #[test]
+    fn memory_report_bookkeeping_is_not_leaked() {
+        assert_no_leaks(|| {
+            let (ptr, len) = vec_into_raw_parts(vec![0u8; 4]);
+            let _ = unsafe { crate::vec_from_raw_parts(ptr, len) };
+        });
+    }
+}
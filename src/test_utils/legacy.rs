@@ -0,0 +1,203 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Counterparts of the `call_*` helpers for the legacy callback shape, which passes a bare
+//! `error_code: i32` instead of `result: *const FfiResult`:
+//!
+//! ```ignore
+//! extern "C" fn o_cb(user_data: *mut c_void, error_code: i32, value: u64);
+//!
+//! let value: u64 = unsafe { unwrap!(legacy::call_1(|ud, cb| app_get_value(app, ud, cb))) };
+//! ```
+
+use super::{
+    callback_result, convert_arg, error_code_to_result, send_via_channel, shielded_result,
+    CallbackChannel, CheckedSendWrapper, DEFAULT_CALL_TIMEOUT,
+};
+use crate::repr_c::ReprC;
+use crate::{shield, StringError};
+use std::fmt::Debug;
+use std::os::raw::{c_char, c_void};
+use std::{ptr, slice};
+
+/// Call a FFI function and block until its callback gets called.
+/// Use this if the callback accepts no arguments in addition to `user_data` and `error_code`.
+pub fn call_0<F>(f: F) -> Result<(), i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, error_code: i32)),
+{
+    let channel = CallbackChannel::<i32>::new(ptr::null_mut());
+    f(channel.user_data(), callback_0);
+    error_code_to_result(channel.recv(DEFAULT_CALL_TIMEOUT))
+}
+
+// Generates `call_N` and the callback it passes to the FFI function, for callbacks accepting N
+// arguments in addition to `user_data` and `error_code`.
+macro_rules! gen_legacy_call {
+    ($count:literal, $call:ident, $callback:ident, $(($arg:ident: $t:ident, $e:ident)),+) => {
+        #[doc = concat!(
+            "Call an FFI function and block until its callback gets called, then return\n",
+            "the arguments which were passed to that callback.\n",
+            "Use this if the callback accepts ", $count, " in addition to `user_data`\n",
+            "and `error_code`."
+        )]
+        #[allow(unused_parens)]
+        pub unsafe fn $call<F, $($e,)+ $($t),+>(f: F) -> Result<($($t),+), i32>
+        where
+            F: FnOnce(
+                *mut c_void,
+                extern "C" fn(user_data: *mut c_void, error_code: i32, $($t::C),+),
+            ),
+            $($e: Debug, $t: ReprC<Error = $e>,)+
+        {
+            let channel = CallbackChannel::<CheckedSendWrapper<Result<($($t),+), i32>>>::new(
+                ptr::null_mut(),
+            );
+            f(channel.user_data(), $callback::<$($e,)+ $($t),+>);
+            channel.recv_checked(DEFAULT_CALL_TIMEOUT)
+        }
+
+        #[allow(unused_parens, clippy::needless_question_mark)]
+        extern "C" fn $callback<$($e,)+ $($t),+>(
+            user_data: *mut c_void,
+            error_code: i32,
+            $($arg: $t::C),+
+        ) where
+            $($e: Debug, $t: ReprC<Error = $e>,)+
+        {
+            shield(move || unsafe {
+                let result: Result<($($t),+), i32> = shielded_result(|| {
+                    callback_result(error_code, || Ok(($(convert_arg::<$t>($arg)?),+)))
+                });
+                send_via_channel(user_data, CheckedSendWrapper::new(result))
+            })
+        }
+    };
+}
+
+gen_legacy_call!("one argument", call_1, callback_1, (arg: T, E));
+gen_legacy_call!(
+    "two arguments",
+    call_2,
+    callback_2,
+    (arg0: T0, E0),
+    (arg1: T1, E1)
+);
+gen_legacy_call!(
+    "three arguments",
+    call_3,
+    callback_3,
+    (arg0: T0, E0),
+    (arg1: T1, E1),
+    (arg2: T2, E2)
+);
+
+/// Call a FFI function and block until its callback gets called, then return the string which
+/// was passed to that callback.
+/// Use this if the callback accepts a `*const c_char` argument in addition to `user_data` and
+/// `error_code`. The pointer is not read if the callback reports an error, so it may be null.
+pub unsafe fn call_string<F>(f: F) -> Result<String, i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, error_code: i32, *const c_char)),
+{
+    call_1::<_, StringError, String>(f)
+}
+
+/// Call a FFI function and block until its callback gets called, then copy
+/// the byte array argument which was passed to `Vec<u8>` and return the result.
+pub unsafe fn call_vec_u8<F>(f: F) -> Result<Vec<u8>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, error_code: i32, *const u8, usize),
+    ),
+{
+    let channel = CallbackChannel::<Result<Vec<u8>, i32>>::new(ptr::null_mut());
+    f(channel.user_data(), callback_vec_u8);
+    channel.recv(DEFAULT_CALL_TIMEOUT)
+}
+
+extern "C" fn callback_0(user_data: *mut c_void, error_code: i32) {
+    shield(move || unsafe { send_via_channel(user_data, error_code) })
+}
+
+extern "C" fn callback_vec_u8(user_data: *mut c_void, error_code: i32, ptr: *const u8, len: usize) {
+    shield(move || unsafe {
+        let result = if error_code == 0 {
+            Ok(slice::from_raw_parts(ptr, len).to_vec())
+        } else {
+            Err(error_code)
+        };
+
+        send_via_channel(user_data, result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::ptr;
+    use std::thread;
+    use unwrap::unwrap;
+
+    const ERR: i32 = -42;
+
+    extern "C" fn get_value(
+        fail: bool,
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(user_data: *mut c_void, error_code: i32, value: u64),
+    ) {
+        let user_data = crate::OpaqueCtx::from_host_pointer(user_data);
+        let _ = thread::spawn(move || {
+            if fail {
+                o_cb(user_data.as_ptr(), ERR, 0)
+            } else {
+                o_cb(user_data.as_ptr(), 0, 42)
+            }
+        })
+        .join();
+    }
+
+    #[test]
+    fn legacy_callbacks() {
+        assert_eq!(call_0(|ud, cb| cb(ud, 0)), Ok(()));
+        assert_eq!(call_0(|ud, cb| cb(ud, ERR)), Err(ERR));
+
+        assert_eq!(
+            unsafe { call_1(|ud, cb| get_value(false, ud, cb)) },
+            Ok(42u64)
+        );
+        assert_eq!(
+            unsafe { call_1::<_, _, u64>(|ud, cb| get_value(true, ud, cb)) },
+            Err(ERR)
+        );
+
+        assert_eq!(
+            unsafe { call_2(|ud, cb| cb(ud, 0, 1, 2)) },
+            Ok((1u32, 2u64))
+        );
+
+        let name = unwrap!(CString::new("name"));
+        assert_eq!(
+            unsafe { call_string(|ud, cb| cb(ud, 0, name.as_ptr())) },
+            Ok("name".to_owned())
+        );
+        assert_eq!(
+            unsafe { call_string(|ud, cb| cb(ud, ERR, ptr::null())) },
+            Err(ERR)
+        );
+
+        let data = [1u8, 2, 3];
+        assert_eq!(
+            unsafe { call_vec_u8(|ud, cb| cb(ud, 0, data.as_ptr(), data.len())) },
+            Ok(data.to_vec())
+        );
+    }
+}
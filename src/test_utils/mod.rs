@@ -0,0 +1,1139 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Test utilities.
+//!
+//! The callbacks given to FFI functions panic when they can't convert their arguments or deliver
+//! their result. They run under `shield`, so the panic is logged instead of unwinding out of the
+//! `extern "C"` function, and the waiting call fails right away with `ERR_CALLBACK_PANICKED`
+//! (or once its timeout elapses, if the result couldn't be delivered at all). With the
+//! `panic-free` feature, such failures don't panic at all, and unconvertible arguments are
+//! reported as `ERR_INVALID_ARG` errors.
+
+// These functions specifically used for FFI are missing safety documentation.
+// It is probably not necessary for us to provide this for every single function
+// as that would be repetitive and verbose.
+#![allow(clippy::missing_safety_doc)]
+
+#[cfg(any(test, feature = "testing"))]
+pub mod fault;
+pub mod hostile;
+pub mod legacy;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+#[cfg(any(test, feature = "testing"))]
+pub mod reentrancy;
+
+#[cfg(feature = "tokio")]
+mod async_call;
+mod checked_send;
+mod handle;
+#[cfg(feature = "leak-check")]
+pub(crate) mod leak;
+mod multi;
+mod probe;
+mod recorder;
+mod sync_call;
+
+#[cfg(feature = "tokio")]
+pub use self::async_call::{
+    call_0_async, call_1_async, call_2_async, call_3_async, call_4_async, call_vec_async,
+    call_vec_u8_async,
+};
+pub use self::checked_send::CheckedSendWrapper;
+pub use self::handle::{call_handle, HandleGuard};
+#[cfg(feature = "leak-check")]
+pub use self::leak::{assert_no_leaks, CountingAllocator};
+pub use self::multi::{CallbackHandle, MultiCall};
+pub use self::probe::{CallbackProbe, Expectation};
+pub use self::recorder::{CallEvent, CallRecorder};
+pub use self::sync_call::{sync_call_0, sync_call_1};
+
+use crate::repr_c::{ReprC, ERR_INVALID_ARG};
+use crate::{shield, ErrorCode, FfiResult, StringError};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_void};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+use std::{fmt, ptr, slice};
+#[cfg(test)]
+use unwrap::unwrap;
+
+/// User data wrapper.
+pub struct UserData {
+    /// Common field, used by standard callbacks.
+    pub common: *mut c_void,
+    /// Custom field, used by additional callbacks.
+    pub custom: *mut c_void,
+}
+
+impl Default for UserData {
+    fn default() -> Self {
+        let common: *const c_void = ptr::null();
+        let custom: *const c_void = ptr::null();
+
+        UserData {
+            common: common as *mut c_void,
+            custom: custom as *mut c_void,
+        }
+    }
+}
+
+/// Typed senders registered under names, for tests needing more channels than the two pointers
+/// of `UserData`. Point the custom field of the user data at them with `as_user_data`, and send
+/// through them from callbacks with `send_via_slot`.
+#[derive(Default)]
+pub struct Slots(HashMap<&'static str, Box<dyn Any + Send>>);
+
+impl Slots {
+    /// Create an empty set of slots.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `sender` in the slot named `key`, replacing any sender already registered there.
+    pub fn insert<T: Send + 'static>(&mut self, key: &'static str, sender: Sender<T>) {
+        let _ = self.0.insert(key, Box::new(sender));
+    }
+
+    /// Return the sender registered in the slot named `key`, if it sends values of type `T`.
+    pub fn slot<T: Send + 'static>(&self, key: &str) -> Option<&Sender<T>> {
+        self.0.get(key)?.downcast_ref()
+    }
+
+    /// Return a pointer to the slots, to store in the custom field of a `UserData`.
+    pub fn as_user_data(&self) -> *mut c_void {
+        ptr::from_ref(self) as *mut c_void
+    }
+}
+
+/// Convert a `UserData` to a void pointer which can be passed to ffi functions.
+pub fn user_data_as_void(ud: &UserData) -> *mut c_void {
+    let ptr: *const _ = ud;
+    ptr as *mut c_void
+}
+
+/// Convert a `mpsc::Sender<T>` to a void ptr which is then stored in the `UserData` struct and
+/// passed to ffi functions.
+pub fn sender_as_user_data<T>(tx: &Sender<T>, ud: &mut UserData) -> *mut c_void {
+    let ptr: *const _ = tx;
+    ud.common = ptr as *mut c_void;
+    user_data_as_void(ud)
+}
+
+/// Send through a `mpsc::Sender` pointed to by the user data's common pointer.
+///
+/// Panics if the receiver is gone, or logs the failure with the `panic-free` feature.
+pub unsafe fn send_via_user_data<T>(user_data: *mut c_void, value: T)
+where
+    T: Send,
+{
+    let ud = user_data as *mut UserData;
+    // Send through a clone, as the receiver may release the original as soon as it gets the value.
+    let tx = (*((*ud).common as *mut Sender<T>)).clone();
+    if let Err(error) = tx.send(value) {
+        callback_failure(format_args!("{}", error));
+    }
+}
+
+/// Send through a `mpsc::Sender` pointed to by the user data's custom pointer.
+///
+/// Panics if the receiver is gone, or logs the failure with the `panic-free` feature.
+pub unsafe fn send_via_user_data_custom<T>(user_data: *mut c_void, value: T)
+where
+    T: Send,
+{
+    let ud = user_data as *mut UserData;
+    // Send through a clone, as the receiver may release the original as soon as it gets the value.
+    let tx = (*((*ud).custom as *mut Sender<T>)).clone();
+    if let Err(error) = tx.send(value) {
+        callback_failure(format_args!("{}", error));
+    }
+}
+
+/// Error code returned by the calls whose callback panicked, e.g. because it couldn't convert its
+/// arguments.
+pub const ERR_CALLBACK_PANICKED: i32 = -9027;
+
+// Compute the result delivered by a callback, turning a panic into `Err(ERR_CALLBACK_PANICKED)`
+// so that the waiting call gets it right away instead of timing out.
+fn shielded_result<T, F>(f: F) -> Result<T, i32>
+where
+    F: FnOnce() -> Result<T, i32>,
+{
+    shield(move || Some(f())).unwrap_or(Err(ERR_CALLBACK_PANICKED))
+}
+
+/// Report a failure inside a callback. Panics by default; with the `panic-free` feature the
+/// failure is only logged, as a panic unwinding out of an `extern "C"` callback aborts the
+/// process.
+#[track_caller]
+fn callback_failure(message: fmt::Arguments) {
+    #[cfg(feature = "panic-free")]
+    log::error!("{}", message);
+    #[cfg(not(feature = "panic-free"))]
+    panic!("{}", message);
+}
+
+/// Convert an argument received by a callback. A conversion failure is reported through
+/// `callback_failure`, and turns into an `ERR_INVALID_ARG` error with the `panic-free` feature.
+unsafe fn convert_arg<T>(arg: T::C) -> Result<T, i32>
+where
+    T: ReprC,
+    T::Error: Debug,
+{
+    T::clone_from_repr_c(arg).map_err(|error| {
+        callback_failure(format_args!("Invalid callback argument: {:?}", error));
+        ERR_INVALID_ARG
+    })
+}
+
+/// The result delivered by a callback: `Err(error_code)` on failure, or the outcome of
+/// converting its arguments otherwise.
+fn callback_result<T, F>(error_code: i32, convert: F) -> Result<T, i32>
+where
+    F: FnOnce() -> Result<T, i32>,
+{
+    if error_code == 0 {
+        convert()
+    } else {
+        Err(error_code)
+    }
+}
+
+/// How long the `call_*` helpers without an explicit timeout wait for the callback.
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Wait for the value sent by a callback, panicking if it does not arrive within `timeout`.
+fn recv_callback<T>(rx: &Receiver<T>, timeout: Duration) -> T {
+    // Callers keep a sender alive until the value arrives, so the channel can't disconnect.
+    rx.recv_timeout(timeout)
+        .unwrap_or_else(|_| panic!("callback never invoked within {:?}", timeout))
+}
+
+/// Same as `recv_callback`, taking the value sent by a callback out of its wrapper.
+fn recv_checked<T>(rx: &Receiver<CheckedSendWrapper<T>>, timeout: Duration) -> T {
+    take_checked(recv_callback(rx, timeout))
+}
+
+/// Take a value sent by a callback out of its wrapper. A value sent from another thread is
+/// transferred to the current one first.
+fn take_checked<T>(value: CheckedSendWrapper<T>) -> T {
+    if value.is_owner() {
+        value.into_inner()
+    } else {
+        // The callback converted the value from its C representation and moved it into the
+        // channel, so nothing sharing its state is left on the callback thread.
+        unsafe { value.transfer() }.into_inner()
+    }
+}
+
+/// Channel through which a callback delivers its value, along with the `UserData` passed to the
+/// FFI function as `user_data`, whose common pointer is the sender.
+///
+/// The channel is registered as live until dropped, which releases the sender even if the
+/// callback never arrived. Callbacks send through `send_via_channel`, so one arriving after its
+/// call timed out finds the channel gone and drops its value instead of using a dangling sender.
+struct CallbackChannel<'a, T> {
+    user_data: *mut UserData,
+    // The original common pointer of a `UserData` passed in by the caller, restored on drop, or
+    // `None` if the `UserData` belongs to the channel.
+    caller_common: Option<*mut c_void>,
+    rx: Receiver<T>,
+    _caller_ud: PhantomData<&'a mut UserData>,
+}
+
+// Addresses of the `UserData` of the live `CallbackChannel`s.
+static LIVE_CHANNELS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+fn live_channels() -> MutexGuard<'static, Vec<usize>> {
+    LIVE_CHANNELS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<T> CallbackChannel<'static, T> {
+    /// Create a channel with its own `UserData`, carrying the given custom pointer.
+    fn new(custom: *mut c_void) -> Self {
+        let user_data = Box::into_raw(Box::new(UserData {
+            common: ptr::null_mut(),
+            custom,
+        }));
+        Self::register(user_data, None)
+    }
+}
+
+impl<'a, T> CallbackChannel<'a, T> {
+    /// Create a channel passing the caller's `UserData`, whose common pointer is the sender
+    /// until the channel is dropped.
+    fn with_user_data(ud: &'a mut UserData) -> Self {
+        let common = ud.common;
+        Self::register(ud, Some(common))
+    }
+
+    fn register(user_data: *mut UserData, caller_common: Option<*mut c_void>) -> Self {
+        let (tx, rx) = mpsc::channel::<T>();
+        unsafe { (*user_data).common = Box::into_raw(Box::new(tx)) as *mut c_void };
+        live_channels().push(user_data as usize);
+
+        Self {
+            user_data,
+            caller_common,
+            rx,
+            _caller_ud: PhantomData,
+        }
+    }
+
+    /// Return the `user_data` pointer to pass to the FFI function.
+    fn user_data(&self) -> *mut c_void {
+        self.user_data as *mut c_void
+    }
+
+    /// Wait for the next value sent by the callback, panicking if it does not arrive within
+    /// `timeout`.
+    fn recv(&self, timeout: Duration) -> T {
+        recv_callback(&self.rx, timeout)
+    }
+}
+
+impl<'a, T> CallbackChannel<'a, CheckedSendWrapper<T>> {
+    /// Same as `recv`, taking the value sent by the callback out of its wrapper.
+    fn recv_checked(&self, timeout: Duration) -> T {
+        take_checked(self.recv(timeout))
+    }
+}
+
+impl<'a, T> Drop for CallbackChannel<'a, T> {
+    fn drop(&mut self) {
+        // Hold the lock while releasing, so that no callback is sending in the meantime.
+        let mut live = live_channels();
+        if let Some(index) = live.iter().position(|&ud| ud == self.user_data as usize) {
+            let _ = live.swap_remove(index);
+        }
+
+        unsafe {
+            drop(Box::from_raw((*self.user_data).common as *mut Sender<T>));
+            match self.caller_common {
+                Some(common) => (*self.user_data).common = common,
+                None => drop(Box::from_raw(self.user_data)),
+            }
+        }
+    }
+}
+
+/// Send through the sender of the `CallbackChannel` whose `UserData` is `user_data`. The value
+/// is dropped if the channel is gone, i.e. its call timed out before the callback arrived.
+unsafe fn send_via_channel<T>(user_data: *mut c_void, value: T)
+where
+    T: Send,
+{
+    let live = live_channels();
+    if live.contains(&(user_data as usize)) {
+        send_via_user_data(user_data, value)
+    } else {
+        drop(live);
+        log::warn!("Callback invoked after its call timed out");
+    }
+}
+
+fn error_code_to_result(error: i32) -> Result<(), i32> {
+    if error == 0 {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// Send through the sender registered in the slot named `key` of the `Slots` pointed to by the
+/// user data's custom pointer.
+///
+/// Panics if there is no such slot, if its sender does not send values of type `T`, or if its
+/// receiver is gone. With the `panic-free` feature, these failures are logged instead.
+pub unsafe fn send_via_slot<T>(user_data: *mut c_void, key: &str, value: T)
+where
+    T: Send + 'static,
+{
+    let ud = &*(user_data as *const UserData);
+    if ud.custom.is_null() {
+        return callback_failure(format_args!("no slots in user data"));
+    }
+
+    let slots = &*(ud.custom as *const Slots);
+    match slots.slot::<T>(key) {
+        Some(tx) => {
+            if let Err(error) = tx.clone().send(value) {
+                callback_failure(format_args!("{}", error));
+            }
+        }
+        None if slots.0.contains_key(key) => callback_failure(format_args!(
+            "slot {:?} does not send values of type {}",
+            key,
+            std::any::type_name::<T>()
+        )),
+        None => callback_failure(format_args!("no slot {:?} in user data", key)),
+    }
+}
+
+/// Call a FFI function and block until its callback gets called.
+/// Use this if the callback accepts no arguments in addition to `user_data`
+/// and `error_code`.
+pub fn call_0<F>(f: F) -> Result<(), i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, result: *const FfiResult)),
+{
+    let mut ud = Default::default();
+    call_0_with_custom(&mut ud, f)
+}
+
+/// Call a FFI function and block until its callback gets called.
+/// Use this if the callback accepts no arguments in addition to `user_data`
+/// and `error_code`.
+/// This version of the function takes a `UserData` with custom inner data.
+pub fn call_0_with_custom<F>(ud: &mut UserData, f: F) -> Result<(), i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, result: *const FfiResult)),
+{
+    let channel = CallbackChannel::<i32>::with_user_data(ud);
+    f(channel.user_data(), callback_0);
+    error_code_to_result(channel.recv(DEFAULT_CALL_TIMEOUT))
+}
+
+/// Same as `call_0`, but panics if the callback is not invoked within `timeout`.
+pub fn call_0_timeout<F>(timeout: Duration, f: F) -> Result<(), i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, result: *const FfiResult)),
+{
+    let channel = CallbackChannel::<i32>::new(ptr::null_mut());
+    f(channel.user_data(), callback_0);
+    error_code_to_result(channel.recv(timeout))
+}
+
+// Generates `call_N`, `call_N_with_custom` and the callback they pass to the FFI function, for
+// callbacks accepting N arguments in addition to `user_data` and `error_code`. With a single
+// argument the parenthesised "tuple" is just that argument, hence the `unused_parens` allows.
+macro_rules! gen_call {
+    (
+        $count:literal,
+        $call:ident,
+        $call_timeout:ident,
+        [$($qual:tt)*] $call_with_custom:ident,
+        $callback:ident,
+        $(($arg:ident: $t:ident, $e:ident)),+
+    ) => {
+        #[doc = concat!(
+            "Call an FFI function and block until its callback gets called, then return\n",
+            "the arguments which were passed to that callback.\n",
+            "Use this if the callback accepts ", $count, " in addition to `user_data`\n",
+            "and `error_code`."
+        )]
+        #[allow(unused_parens)]
+        pub unsafe fn $call<F, $($e,)+ $($t),+>(f: F) -> Result<($($t),+), i32>
+        where
+            F: FnOnce(
+                *mut c_void,
+                extern "C" fn(user_data: *mut c_void, result: *const FfiResult, $($t::C),+),
+            ),
+            $($e: Debug, $t: ReprC<Error = $e>,)+
+        {
+            let mut ud = Default::default();
+            $call_with_custom(&mut ud, f)
+        }
+
+        #[doc = concat!(
+            "Call an FFI function and block until its callback gets called, then return\n",
+            "the arguments which were passed to that callback.\n",
+            "Use this if the callback accepts ", $count, " in addition to `user_data`\n",
+            "and `error_code`.\n",
+            "This version of the function takes a `UserData` with custom inner data."
+        )]
+        #[allow(unused_parens)]
+        pub $($qual)* fn $call_with_custom<F, $($e,)+ $($t),+>(
+            ud: &mut UserData,
+            f: F,
+        ) -> Result<($($t),+), i32>
+        where
+            F: FnOnce(
+                *mut c_void,
+                extern "C" fn(user_data: *mut c_void, result: *const FfiResult, $($t::C),+),
+            ),
+            $($e: Debug, $t: ReprC<Error = $e>,)+
+        {
+            let channel =
+                CallbackChannel::<CheckedSendWrapper<Result<($($t),+), i32>>>::with_user_data(ud);
+            f(channel.user_data(), $callback::<$($e,)+ $($t),+>);
+            channel.recv_checked(DEFAULT_CALL_TIMEOUT)
+        }
+
+        #[doc = concat!(
+            "Same as `", stringify!($call), "`, but panics if the callback is not invoked within\n",
+            "`timeout`."
+        )]
+        #[allow(unused_parens)]
+        pub unsafe fn $call_timeout<F, $($e,)+ $($t),+>(
+            timeout: Duration,
+            f: F,
+        ) -> Result<($($t),+), i32>
+        where
+            F: FnOnce(
+                *mut c_void,
+                extern "C" fn(user_data: *mut c_void, result: *const FfiResult, $($t::C),+),
+            ),
+            $($e: Debug, $t: ReprC<Error = $e>,)+
+        {
+            let channel = CallbackChannel::<CheckedSendWrapper<Result<($($t),+), i32>>>::new(
+                ptr::null_mut(),
+            );
+            f(channel.user_data(), $callback::<$($e,)+ $($t),+>);
+            channel.recv_checked(timeout)
+        }
+
+        #[allow(unused_parens, clippy::needless_question_mark)]
+        extern "C" fn $callback<$($e,)+ $($t),+>(
+            user_data: *mut c_void,
+            res: *const FfiResult,
+            $($arg: $t::C),+
+        ) where
+            $($e: Debug, $t: ReprC<Error = $e>,)+
+        {
+            shield(move || unsafe {
+                let result: Result<($($t),+), i32> = shielded_result(|| {
+                    callback_result((*res).error_code, || Ok(($(convert_arg::<$t>($arg)?),+)))
+                });
+                send_via_channel(user_data, CheckedSendWrapper::new(result))
+            })
+        }
+    };
+}
+
+gen_call!("one argument", call_1, call_1_timeout, [] call_1_with_custom, callback_1, (arg: T, E));
+gen_call!(
+    "two arguments",
+    call_2,
+    call_2_timeout,
+    [unsafe] call_2_with_custom,
+    callback_2,
+    (arg0: T0, E0),
+    (arg1: T1, E1)
+);
+gen_call!(
+    "three arguments",
+    call_3,
+    call_3_timeout,
+    [unsafe] call_3_with_custom,
+    callback_3,
+    (arg0: T0, E0),
+    (arg1: T1, E1),
+    (arg2: T2, E2)
+);
+gen_call!(
+    "four arguments",
+    call_4,
+    call_4_timeout,
+    [unsafe] call_4_with_custom,
+    callback_4,
+    (arg0: T0, E0),
+    (arg1: T1, E1),
+    (arg2: T2, E2),
+    (arg3: T3, E3)
+);
+
+/// Same as `call_1`, but also asserts that the callback was invoked from a thread other than
+/// the calling one, to exercise the code paths of FFI functions completing asynchronously.
+pub unsafe fn call_1_cross_thread<F, E, T>(f: F) -> Result<T, i32>
+where
+    F: FnOnce(*mut c_void, extern "C" fn(user_data: *mut c_void, result: *const FfiResult, T::C)),
+    E: Debug,
+    T: ReprC<Error = E>,
+{
+    let channel =
+        CallbackChannel::<CheckedSendWrapper<(ThreadId, Result<T, i32>)>>::new(ptr::null_mut());
+    f(channel.user_data(), callback_1_cross_thread::<E, T>);
+
+    let (thread, result) = channel.recv_checked(DEFAULT_CALL_TIMEOUT);
+    assert_ne!(
+        thread,
+        thread::current().id(),
+        "callback was invoked on the calling thread"
+    );
+    result
+}
+
+extern "C" fn callback_1_cross_thread<E, T>(
+    user_data: *mut c_void,
+    res: *const FfiResult,
+    arg: T::C,
+) where
+    E: Debug,
+    T: ReprC<Error = E>,
+{
+    shield(move || unsafe {
+        let result =
+            shielded_result(|| callback_result((*res).error_code, || convert_arg::<T>(arg)));
+        send_via_channel(
+            user_data,
+            CheckedSendWrapper::new((thread::current().id(), result)),
+        )
+    })
+}
+
+/// Call a FFI function and block until its callback gets called, then return the string which
+/// was passed to that callback.
+/// Use this if the callback accepts a `*const c_char` argument in addition to `user_data` and
+/// `error_code`. The pointer is not read if the callback reports an error, so it may be null.
+pub unsafe fn call_string<F>(f: F) -> Result<String, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, *const c_char),
+    ),
+{
+    call_1::<_, StringError, String>(f)
+}
+
+/// Call a FFI function and block until its callback gets called, then return the string which
+/// was passed to that callback.
+/// Use this if the callback accepts a `*const c_char` argument in addition to `user_data` and
+/// `error_code`. The pointer is not read if the callback reports an error, so it may be null.
+/// This version of the function takes a `UserData` with custom inner data.
+pub fn call_string_with_custom<F>(ud: &mut UserData, f: F) -> Result<String, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, *const c_char),
+    ),
+{
+    call_1_with_custom::<_, StringError, String>(ud, f)
+}
+
+/// Call a FFI function and block until its callback gets called, then copy
+/// the array argument which was passed to `Vec<T>` and return the result.
+/// Use this if the callback accepts `*const T` and `usize` (length) arguments in addition
+/// to `user_data` and `error_code`.
+pub unsafe fn call_vec<F, E, T, U>(f: F) -> Result<Vec<T>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, T::C, usize),
+    ),
+    E: Debug,
+    T: ReprC<C = *const U, Error = E>,
+{
+    let mut ud = Default::default();
+    call_vec_with_custom(&mut ud, f)
+}
+
+/// Call a FFI function and block until its callback gets called, then copy
+/// the array argument which was passed to `Vec<T>` and return the result.
+/// Use this if the callback accepts `*const T` and `usize` (length) arguments in addition
+/// to `user_data` and `error_code`.
+/// This version of the function takes a `UserData` with custom inner data.
+pub unsafe fn call_vec_with_custom<F, E, T, U>(ud: &mut UserData, f: F) -> Result<Vec<T>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, T::C, usize),
+    ),
+    E: Debug,
+    T: ReprC<C = *const U, Error = E>,
+{
+    let channel = CallbackChannel::<CheckedSendWrapper<Result<Vec<T>, i32>>>::with_user_data(ud);
+    f(channel.user_data(), callback_vec::<E, T, U>);
+    channel.recv_checked(DEFAULT_CALL_TIMEOUT)
+}
+
+/// Same as `call_vec`, but panics if the callback is not invoked within `timeout`.
+pub unsafe fn call_vec_timeout<F, E, T, U>(timeout: Duration, f: F) -> Result<Vec<T>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, T::C, usize),
+    ),
+    E: Debug,
+    T: ReprC<C = *const U, Error = E>,
+{
+    let channel = CallbackChannel::<CheckedSendWrapper<Result<Vec<T>, i32>>>::new(ptr::null_mut());
+    f(channel.user_data(), callback_vec::<E, T, U>);
+    channel.recv_checked(timeout)
+}
+
+/// Call a FFI function and block until its callback gets called, then copy
+/// the byte array argument which was passed to `Vec<u8>` and return the result.
+pub unsafe fn call_vec_u8<F>(f: F) -> Result<Vec<u8>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, *const u8, usize),
+    ),
+{
+    let mut ud = Default::default();
+    call_vec_u8_with_custom(&mut ud, f)
+}
+
+/// Call a FFI function and block until its callback gets called, then copy
+/// the byte array argument which was passed to `Vec<u8>` and return the result.
+/// This version of the function takes a `UserData` with custom inner data.
+/// This version of the function takes a `UserData` with custom inner data.
+pub unsafe fn call_vec_u8_with_custom<F>(ud: &mut UserData, f: F) -> Result<Vec<u8>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, *const u8, usize),
+    ),
+{
+    let channel = CallbackChannel::<Result<Vec<u8>, i32>>::with_user_data(ud);
+    f(channel.user_data(), callback_vec_u8);
+    channel.recv(DEFAULT_CALL_TIMEOUT)
+}
+
+/// Same as `call_vec_u8`, but panics if the callback is not invoked within `timeout`.
+pub unsafe fn call_vec_u8_timeout<F>(timeout: Duration, f: F) -> Result<Vec<u8>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult, *const u8, usize),
+    ),
+{
+    let channel = CallbackChannel::<Result<Vec<u8>, i32>>::new(ptr::null_mut());
+    f(channel.user_data(), callback_vec_u8);
+    channel.recv(timeout)
+}
+
+/// Call a FFI function whose data callback fires repeatedly and whose completion callback
+/// fires once, and block until the latter gets called. Returns the values passed to the data
+/// callback, in order.
+/// Use this if the data callback accepts a single argument in addition to `user_data`, and the
+/// completion callback accepts no arguments in addition to `user_data` and `error_code`.
+pub unsafe fn call_stream<F, E, T>(f: F) -> Result<Vec<T>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, T::C),
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+    ),
+    E: Debug,
+    T: ReprC<Error = E>,
+{
+    let mut ud = Default::default();
+    call_stream_with_custom(&mut ud, f)
+}
+
+/// Call a FFI function whose data callback fires repeatedly and whose completion callback
+/// fires once, and block until the latter gets called. Returns the values passed to the data
+/// callback, in order.
+/// This version of the function takes a `UserData` with custom inner data.
+pub unsafe fn call_stream_with_custom<F, E, T>(ud: &mut UserData, f: F) -> Result<Vec<T>, i32>
+where
+    F: FnOnce(
+        *mut c_void,
+        extern "C" fn(user_data: *mut c_void, T::C),
+        extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+    ),
+    E: Debug,
+    T: ReprC<Error = E>,
+{
+    let channel = CallbackChannel::<CheckedSendWrapper<StreamEvent<T>>>::with_user_data(ud);
+    f(
+        channel.user_data(),
+        callback_stream_data::<E, T>,
+        callback_stream_done::<T>,
+    );
+
+    let mut chunks = Vec::new();
+    loop {
+        match channel.recv_checked(DEFAULT_CALL_TIMEOUT) {
+            StreamEvent::Data(chunk) => chunks.push(chunk),
+            StreamEvent::Done(0) => return Ok(chunks),
+            StreamEvent::Done(error) => return Err(error),
+        }
+    }
+}
+
+extern "C" fn callback_0(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || unsafe { send_via_channel(user_data, (*res).error_code) })
+}
+
+extern "C" fn callback_vec<E, T, U>(
+    user_data: *mut c_void,
+    res: *const FfiResult,
+    array: *const U,
+    size: usize,
+) where
+    E: Debug,
+    T: ReprC<C = *const U, Error = E>,
+{
+    shield(move || unsafe {
+        let result: Result<Vec<T>, i32> = shielded_result(|| {
+            callback_result((*res).error_code, || {
+                let slice_ffi = slice::from_raw_parts(array, size);
+                let mut vec = Vec::with_capacity(slice_ffi.len());
+                for elt in slice_ffi {
+                    vec.push(convert_arg::<T>(elt)?);
+                }
+                Ok(vec)
+            })
+        });
+
+        send_via_channel(user_data, CheckedSendWrapper::new(result))
+    })
+}
+
+extern "C" fn callback_vec_u8(
+    user_data: *mut c_void,
+    res: *const FfiResult,
+    ptr: *const u8,
+    len: usize,
+) {
+    shield(move || unsafe {
+        let result = if (*res).error_code == 0 {
+            Ok(slice::from_raw_parts(ptr, len).to_vec())
+        } else {
+            Err((*res).error_code)
+        };
+
+        send_via_channel(user_data, result)
+    })
+}
+
+enum StreamEvent<T> {
+    Data(T),
+    Done(i32),
+}
+
+extern "C" fn callback_stream_data<E, T>(user_data: *mut c_void, arg: T::C)
+where
+    E: Debug,
+    T: ReprC<Error = E>,
+{
+    shield(move || unsafe {
+        // A chunk which can't be converted ends the stream with its error.
+        let event = match shielded_result(|| convert_arg::<T>(arg)) {
+            Ok(chunk) => StreamEvent::Data(chunk),
+            Err(error) => StreamEvent::Done(error),
+        };
+        send_via_channel(user_data, CheckedSendWrapper::new(event))
+    })
+}
+
+extern "C" fn callback_stream_done<T>(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || unsafe {
+        send_via_channel(
+            user_data,
+            CheckedSendWrapper::new(StreamEvent::<T>::Done((*res).error_code)),
+        )
+    })
+}
+
+/// Unsafe wrapper for passing non-Send types through mpsc channels.
+/// Use with caution! Prefer `CheckedSendWrapper`, which detects use from the wrong thread.
+pub struct SendWrapper<T>(pub T);
+unsafe impl<T> Send for SendWrapper<T> {}
+
+/// Dummy error type for testing that implements ErrorCode.
+#[derive(Debug)]
+pub enum TestError {
+    /// Error from a string.
+    FromStr(String),
+    /// Simple test error.
+    Test,
+}
+
+impl<'a> From<&'a str> for TestError {
+    fn from(s: &'a str) -> Self {
+        TestError::FromStr(s.into())
+    }
+}
+
+impl ErrorCode for TestError {
+    fn error_code(&self) -> i32 {
+        use TestError::*;
+
+        match *self {
+            Test => -1,
+            FromStr(_) => -2,
+        }
+    }
+}
+
+impl Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use TestError::*;
+
+        match self {
+            Test => write!(f, "Test Error"),
+            FromStr(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OpaqueCtx, FFI_RESULT_OK};
+    use std::panic;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    extern "C" fn four_values(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, *const u8, usize, u32, u64),
+    ) {
+        let data = [1u8, 2, 3];
+        o_cb(
+            user_data,
+            FFI_RESULT_OK,
+            data.as_ptr(),
+            data.len(),
+            7,
+            0b101,
+        );
+    }
+
+    #[test]
+    fn call_4_returns_all_values() {
+        let (ptr, len, version, flags): (*const u8, usize, u32, u64) =
+            unsafe { unwrap!(call_4(|ud, cb| four_values(ud, cb))) };
+        assert!(!ptr.is_null());
+        assert_eq!(len, 3);
+        assert_eq!(version, 7);
+        assert_eq!(flags, 0b101);
+    }
+
+    extern "C" fn three_chunks(
+        user_data: *mut c_void,
+        o_data: extern "C" fn(*mut c_void, u32),
+        o_done: extern "C" fn(*mut c_void, *const FfiResult),
+    ) {
+        for chunk in 1..=3 {
+            o_data(user_data, chunk);
+        }
+        o_done(user_data, FFI_RESULT_OK);
+    }
+
+    #[test]
+    fn call_stream_collects_chunks_in_order() {
+        let chunks: Vec<u32> =
+            unsafe { unwrap!(call_stream(|ud, data, done| three_chunks(ud, data, done))) };
+        assert_eq!(chunks, vec![1, 2, 3]);
+    }
+
+    extern "C" fn greet(
+        fail: bool,
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, *const c_char),
+    ) {
+        if fail {
+            let res = FfiResult {
+                error_code: -1,
+                description: ptr::null(),
+            };
+            o_cb(user_data, &res, ptr::null());
+        } else {
+            o_cb(
+                user_data,
+                FFI_RESULT_OK,
+                b"hello\0".as_ptr() as *const c_char,
+            );
+        }
+    }
+
+    extern "C" fn answer(
+        spawn: bool,
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, u32),
+    ) {
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        if spawn {
+            let _ = thread::spawn(move || o_cb(user_data.as_ptr(), FFI_RESULT_OK, 42));
+        } else {
+            o_cb(user_data.as_ptr(), FFI_RESULT_OK, 42);
+        }
+    }
+
+    #[test]
+    fn call_1_cross_thread_from_other_thread() {
+        let value: u32 = unsafe { unwrap!(call_1_cross_thread(|ud, cb| answer(true, ud, cb))) };
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "callback was invoked on the calling thread")]
+    fn call_1_cross_thread_from_calling_thread() {
+        let _: Result<u32, _> = unsafe { call_1_cross_thread(|ud, cb| answer(false, ud, cb)) };
+    }
+
+    #[test]
+    fn call_string_converts_or_reports_error() {
+        assert_eq!(
+            unsafe { call_string(|ud, cb| greet(false, ud, cb)) },
+            Ok("hello".to_string())
+        );
+        assert_eq!(
+            unsafe { call_string(|ud, cb| greet(true, ud, cb)) },
+            Err(-1)
+        );
+    }
+
+    struct Session(u32);
+
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn session_open(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, *mut Session),
+    ) {
+        o_cb(
+            user_data,
+            FFI_RESULT_OK,
+            Box::into_raw(Box::new(Session(7))),
+        );
+    }
+
+    unsafe extern "C" fn session_free(session: *mut Session) {
+        drop(Box::from_raw(session));
+        let _ = FREED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn call_handle_frees_on_drop() {
+        let session = unsafe { unwrap!(call_handle(session_free, |ud, cb| session_open(ud, cb))) };
+        assert_eq!(unsafe { (*session.get()).0 }, 7);
+        drop(session);
+        assert_eq!(FREED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn send_via_slots() {
+        let (tx_a, rx_a) = mpsc::channel::<u32>();
+        let (tx_b, rx_b) = mpsc::channel::<String>();
+        let mut slots = Slots::new();
+        slots.insert("a", tx_a);
+        slots.insert("b", tx_b);
+        let ud = UserData {
+            common: ptr::null_mut(),
+            custom: slots.as_user_data(),
+        };
+
+        let user_data = user_data_as_void(&ud);
+        unsafe {
+            send_via_slot(user_data, "a", 1u32);
+            send_via_slot(user_data, "b", "two".to_string());
+        }
+
+        assert_eq!(unwrap!(rx_a.recv()), 1);
+        assert_eq!(unwrap!(rx_b.recv()), "two");
+    }
+
+    #[test]
+    #[cfg(not(feature = "panic-free"))]
+    #[should_panic(expected = "does not send values of type")]
+    fn send_via_slot_of_wrong_type() {
+        let (tx, _rx) = mpsc::channel::<u32>();
+        let mut slots = Slots::new();
+        slots.insert("a", tx);
+        let ud = UserData {
+            common: ptr::null_mut(),
+            custom: slots.as_user_data(),
+        };
+
+        unsafe { send_via_slot(user_data_as_void(&ud), "a", 1u64) };
+    }
+
+    #[test]
+    #[cfg(feature = "panic-free")]
+    fn send_via_slot_of_wrong_type_is_logged() {
+        let (tx, rx) = mpsc::channel::<u32>();
+        let mut slots = Slots::new();
+        slots.insert("a", tx);
+        let ud = UserData {
+            common: ptr::null_mut(),
+            custom: slots.as_user_data(),
+        };
+
+        unsafe { send_via_slot(user_data_as_void(&ud), "a", 1u64) };
+        assert!(rx.try_recv().is_err());
+    }
+
+    extern "C" fn invalid_utf8(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult, *const c_char),
+    ) {
+        let bytes = [0xffu8, 0];
+        o_cb(user_data, FFI_RESULT_OK, bytes.as_ptr() as *const c_char);
+    }
+
+    #[test]
+    #[cfg(feature = "panic-free")]
+    fn unconvertible_argument_is_an_error() {
+        let res: Result<String, i32> = unsafe { call_1(|ud, cb| invalid_utf8(ud, cb)) };
+        assert_eq!(res, Err(ERR_INVALID_ARG));
+    }
+
+    #[test]
+    #[cfg(not(feature = "panic-free"))]
+    fn unconvertible_argument_fails_the_call() {
+        let start = std::time::Instant::now();
+        let res: Result<String, i32> = unsafe { call_1(|ud, cb| invalid_utf8(ud, cb)) };
+        assert_eq!(res, Err(ERR_CALLBACK_PANICKED));
+        assert!(start.elapsed() < DEFAULT_CALL_TIMEOUT);
+    }
+
+    #[test]
+    fn call_with_custom_passes_user_data_through() {
+        let mut custom = 7u32;
+        let mut ud = UserData {
+            common: ptr::null_mut(),
+            custom: ptr::from_mut(&mut custom) as *mut c_void,
+        };
+        let ud_ptr = ptr::from_mut(&mut ud) as *mut c_void;
+
+        unwrap!(call_0_with_custom(&mut ud, |user_data, cb| {
+            assert_eq!(user_data, ud_ptr);
+            cb(user_data, FFI_RESULT_OK)
+        }));
+
+        // The common pointer is only borrowed for the call.
+        assert!(ud.common.is_null());
+        assert_eq!(unsafe { *(ud.custom as *const u32) }, 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "callback never invoked")]
+    fn call_timeout_without_callback() {
+        let _ = call_0_timeout(Duration::from_millis(10), |_, _| ());
+    }
+
+    static LATE_CALLBACK_DONE: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn answer_late(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult),
+    ) {
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        let _ = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            o_cb(user_data.as_ptr(), FFI_RESULT_OK);
+            LATE_CALLBACK_DONE.store(true, Ordering::SeqCst);
+        });
+    }
+
+    #[test]
+    fn callback_after_timeout_is_harmless() {
+        let res = panic::catch_unwind(|| {
+            call_0_timeout(Duration::from_millis(1), |ud, cb| answer_late(ud, cb))
+        });
+        assert!(res.is_err());
+
+        // The callback finds the channel gone, which is logged rather than touching freed memory.
+        while !LATE_CALLBACK_DONE.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,255 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Test harness for FFI functions taking several distinct callbacks.
+//!
+//! Every callback gets its own slot, identified by a const index, and its own typed channel:
+//!
+//! ```ignore
+//! let mut multi = MultiCall::new();
+//! let connected = multi.callback_0::<0>();
+//! let data = multi.callback_1::<1, _, Vec<u8>>();
+//! let disconnected = multi.callback_0::<2>();
+//!
+//! unsafe {
+//!     connect(
+//!         multi.user_data(),
+//!         connected.callback(),
+//!         data.callback(),
+//!         disconnected.callback(),
+//!     )
+//! };
+//!
+//! unwrap!(connected.wait());
+//! let chunk = unwrap!(data.wait());
+//! ```
+
+use super::{
+    callback_failure, callback_result, convert_arg, recv_checked, shielded_result, take_checked,
+    CheckedSendWrapper, DEFAULT_CALL_TIMEOUT,
+};
+use crate::repr_c::ReprC;
+use crate::{shield, FfiResult};
+use std::any::Any;
+use std::fmt::Debug;
+use std::os::raw::c_void;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+
+type Slots = Vec<Option<Box<dyn Any + Send>>>;
+
+/// `user_data` context for FFI functions taking several callbacks, each of which delivers its
+/// results through its own channel.
+///
+/// The context must outlive every invocation of the registered callbacks.
+pub struct MultiCall {
+    // Boxed so that `user_data` stays valid when the `MultiCall` is moved.
+    slots: Box<Slots>,
+}
+
+impl MultiCall {
+    /// Create a context with no callbacks registered.
+    pub fn new() -> Self {
+        Self {
+            slots: Box::default(),
+        }
+    }
+
+    /// Register a callback accepting no arguments in addition to `user_data` and
+    /// `error_code` in slot `I`.
+    ///
+    /// Panics if slot `I` is already taken.
+    pub fn callback_0<const I: usize>(
+        &mut self,
+    ) -> CallbackHandle<(), extern "C" fn(*mut c_void, *const FfiResult)> {
+        let rx = self.register::<()>(I);
+        CallbackHandle {
+            rx,
+            callback: callback_0::<I>,
+        }
+    }
+
+    /// Register a callback accepting one argument in addition to `user_data` and `error_code`
+    /// in slot `I`.
+    ///
+    /// Panics if slot `I` is already taken.
+    #[allow(clippy::type_complexity)]
+    pub fn callback_1<const I: usize, E, T>(
+        &mut self,
+    ) -> CallbackHandle<T, extern "C" fn(*mut c_void, *const FfiResult, T::C)>
+    where
+        E: Debug,
+        T: ReprC<Error = E> + 'static,
+    {
+        let rx = self.register::<T>(I);
+        CallbackHandle {
+            rx,
+            callback: callback_1::<I, E, T>,
+        }
+    }
+
+    /// Return the `user_data` pointer to pass to the FFI function.
+    pub fn user_data(&mut self) -> *mut c_void {
+        let slots: *mut Slots = &mut *self.slots;
+        slots as *mut c_void
+    }
+
+    fn register<T: 'static>(
+        &mut self,
+        index: usize,
+    ) -> Receiver<CheckedSendWrapper<Result<T, i32>>> {
+        if self.slots.len() <= index {
+            self.slots.resize_with(index + 1, || None);
+        }
+        assert!(
+            self.slots[index].is_none(),
+            "callback slot {} is already taken",
+            index
+        );
+
+        let (tx, rx) = mpsc::channel::<CheckedSendWrapper<Result<T, i32>>>();
+        self.slots[index] = Some(Box::new(tx));
+        rx
+    }
+}
+
+impl Default for MultiCall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Receiving end of a callback registered with `MultiCall`.
+pub struct CallbackHandle<T, C> {
+    rx: Receiver<CheckedSendWrapper<Result<T, i32>>>,
+    callback: C,
+}
+
+impl<T, C: Copy> CallbackHandle<T, C> {
+    /// Return the callback to pass to the FFI function.
+    pub fn callback(&self) -> C {
+        self.callback
+    }
+
+    /// Block until the callback gets called, and return the result it was called with.
+    ///
+    /// Panics if the callback is not invoked within `DEFAULT_CALL_TIMEOUT`.
+    pub fn wait(&self) -> Result<T, i32> {
+        self.wait_timeout(DEFAULT_CALL_TIMEOUT)
+    }
+
+    /// Same as `wait`, but panics if the callback is not invoked within `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<T, i32> {
+        recv_checked(&self.rx, timeout)
+    }
+
+    /// Return the result of a pending invocation of the callback, if any, without blocking.
+    pub fn try_wait(&self) -> Option<Result<T, i32>> {
+        match self.rx.try_recv() {
+            Ok(value) => Some(take_checked(value)),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+}
+
+unsafe fn send_to_slot<T: 'static>(user_data: *mut c_void, index: usize, value: Result<T, i32>) {
+    let slots = &*(user_data as *const Slots);
+    let tx = slots
+        .get(index)
+        .and_then(Option::as_ref)
+        .and_then(|slot| slot.downcast_ref::<Sender<CheckedSendWrapper<Result<T, i32>>>>());
+
+    match tx {
+        // Send through a clone, as the `MultiCall` may be released as soon as the value is
+        // received.
+        Some(tx) => {
+            if let Err(error) = tx.clone().send(CheckedSendWrapper::new(value)) {
+                callback_failure(format_args!("{}", error));
+            }
+        }
+        None => callback_failure(format_args!(
+            "no callback of this type registered in slot {}",
+            index
+        )),
+    }
+}
+
+extern "C" fn callback_0<const I: usize>(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || unsafe {
+        let result = if (*res).error_code == 0 {
+            Ok(())
+        } else {
+            Err((*res).error_code)
+        };
+        send_to_slot(user_data, I, result)
+    })
+}
+
+extern "C" fn callback_1<const I: usize, E, T>(
+    user_data: *mut c_void,
+    res: *const FfiResult,
+    arg: T::C,
+) where
+    E: Debug,
+    T: ReprC<Error = E> + 'static,
+{
+    shield(move || unsafe {
+        let result =
+            shielded_result(|| callback_result((*res).error_code, || convert_arg::<T>(arg)));
+        send_to_slot(user_data, I, result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FFI_RESULT_OK;
+    use unwrap::unwrap;
+
+    extern "C" fn connect(
+        user_data: *mut c_void,
+        o_connected: extern "C" fn(*mut c_void, *const FfiResult),
+        o_data: extern "C" fn(*mut c_void, *const FfiResult, u32),
+        o_disconnected: extern "C" fn(*mut c_void, *const FfiResult),
+    ) {
+        o_connected(user_data, FFI_RESULT_OK);
+        o_data(user_data, FFI_RESULT_OK, 1);
+        o_data(user_data, FFI_RESULT_OK, 2);
+        o_disconnected(user_data, FFI_RESULT_OK);
+    }
+
+    #[test]
+    fn callbacks_deliver_to_their_own_handles() {
+        let mut multi = MultiCall::new();
+        let connected = multi.callback_0::<0>();
+        let data = multi.callback_1::<1, _, u32>();
+        let disconnected = multi.callback_0::<2>();
+
+        connect(
+            multi.user_data(),
+            connected.callback(),
+            data.callback(),
+            disconnected.callback(),
+        );
+
+        unwrap!(connected.wait());
+        assert_eq!(unwrap!(data.wait()), 1);
+        assert_eq!(unwrap!(data.wait()), 2);
+        assert!(data.try_wait().is_none());
+        unwrap!(disconnected.wait());
+    }
+
+    #[test]
+    #[should_panic(expected = "already taken")]
+    fn slot_taken_twice() {
+        let mut multi = MultiCall::new();
+        let _first = multi.callback_0::<0>();
+        let _second = multi.callback_0::<0>();
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use crate::{shield, FfiResult};
+use std::os::raw::c_void;
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+
+/// How many times a `CallbackProbe` expects to be hit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expectation {
+    /// The callback must be invoked exactly once.
+    ExactlyOnce,
+    /// The callback must be invoked at least once.
+    AtLeastOnce,
+    /// The callback must never be invoked.
+    Never,
+}
+
+impl Expectation {
+    fn is_met(self, count: usize) -> bool {
+        match self {
+            Expectation::ExactlyOnce => count == 1,
+            Expectation::AtLeastOnce => count >= 1,
+            Expectation::Never => count == 0,
+        }
+    }
+}
+
+/// Records how many times, and on which threads, a callback was invoked, and panics when
+/// dropped if that does not match its `Expectation`.
+///
+/// Pass `as_user_data()` as the `user_data` of the FFI function, and call `hit` from the
+/// callback (or pass `CallbackProbe::callback_0` directly):
+///
+/// ```ignore
+/// let probe = CallbackProbe::exactly_once();
+/// unsafe { ffi_close(handle, probe.as_user_data(), CallbackProbe::callback_0) };
+/// ```
+///
+/// The probe must outlive every invocation of the callback.
+#[derive(Debug)]
+pub struct CallbackProbe {
+    expectation: Expectation,
+    hits: Mutex<Vec<ThreadId>>,
+}
+
+impl CallbackProbe {
+    /// Create a probe with the given expectation.
+    pub fn new(expectation: Expectation) -> Self {
+        Self {
+            expectation,
+            hits: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Create a probe expecting exactly one invocation.
+    pub fn exactly_once() -> Self {
+        Self::new(Expectation::ExactlyOnce)
+    }
+
+    /// Create a probe expecting at least one invocation.
+    pub fn at_least_once() -> Self {
+        Self::new(Expectation::AtLeastOnce)
+    }
+
+    /// Create a probe expecting no invocation at all.
+    pub fn never() -> Self {
+        Self::new(Expectation::Never)
+    }
+
+    /// Record an invocation on the current thread.
+    pub fn hit(&self) {
+        self.lock().push(thread::current().id());
+    }
+
+    /// Number of invocations recorded so far.
+    pub fn count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Threads of the invocations recorded so far, in order.
+    pub fn threads(&self) -> Vec<ThreadId> {
+        self.lock().clone()
+    }
+
+    /// Convert the probe to a `user_data` pointer.
+    pub fn as_user_data(&self) -> *mut c_void {
+        let ptr: *const Self = self;
+        ptr as *mut c_void
+    }
+
+    /// Borrow the probe behind a `user_data` pointer.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` must have been returned by `as_user_data` of a probe which is still alive.
+    pub unsafe fn from_user_data<'a>(user_data: *mut c_void) -> &'a Self {
+        &*(user_data as *const Self)
+    }
+
+    /// Callback recording an invocation of the probe behind `user_data`.
+    // Callbacks can't be `unsafe fn`s; `user_data` is trusted like in every other callback.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub extern "C" fn callback_0(user_data: *mut c_void, _res: *const FfiResult) {
+        shield(move || unsafe { Self::from_user_data(user_data) }.hit())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ThreadId>> {
+        self.hits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for CallbackProbe {
+    fn drop(&mut self) {
+        // Don't turn an ongoing test failure into an abort.
+        if thread::panicking() {
+            return;
+        }
+
+        let hits = self.lock();
+        if !self.expectation.is_met(hits.len()) {
+            panic!(
+                "callback expected {:?} but was invoked {} time(s), on threads {:?}",
+                self.expectation,
+                hits.len(),
+                *hits
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FFI_RESULT_OK;
+
+    extern "C" fn close_twice(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult),
+    ) {
+        o_cb(user_data, FFI_RESULT_OK);
+        o_cb(user_data, FFI_RESULT_OK);
+    }
+
+    #[test]
+    fn at_least_once_is_met() {
+        let probe = CallbackProbe::at_least_once();
+        close_twice(probe.as_user_data(), CallbackProbe::callback_0);
+        assert_eq!(probe.count(), 2);
+        assert_eq!(probe.threads(), vec![thread::current().id(); 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "was invoked 2 time(s)")]
+    fn exactly_once_is_violated() {
+        let probe = CallbackProbe::exactly_once();
+        close_twice(probe.as_user_data(), CallbackProbe::callback_0);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Property-based testing of `ReprC` conversions.
+//!
+//! Every type implementing `ArbitraryReprC` can be checked for conversion symmetry with
+//! `roundtrip`:
+//!
+//! ```ignore
+//! #[test]
+//! fn app_info_roundtrip() {
+//!     test_utils::proptest::roundtrip::<AppInfo>();
+//! }
+//! ```
+
+use crate::{IntoReprC, NativeResult};
+use ::proptest::collection::vec;
+use ::proptest::prelude::*;
+use ::proptest::test_runner::{TestCaseError, TestRunner};
+use std::fmt::Debug;
+
+/// Types with a generator of values which can be represented across the FFI.
+pub trait ArbitraryReprC: IntoReprC + Clone + PartialEq + Debug {
+    /// Strategy generating FFI-representable values of the type.
+    fn arbitrary_repr_c() -> BoxedStrategy<Self>;
+}
+
+/// Strategy generating strings which can be passed as C strings, i.e. without NUL characters.
+pub fn c_string() -> BoxedStrategy<String> {
+    "[^\u{0}]*".boxed()
+}
+
+/// Strategy generating byte arrays of length `N`.
+pub fn byte_array<const N: usize>() -> BoxedStrategy<[u8; N]> {
+    vec(any::<u8>(), N)
+        .prop_map(|bytes| {
+            let mut array = [0; N];
+            array.copy_from_slice(&bytes);
+            array
+        })
+        .boxed()
+}
+
+/// Check that converting values generated by `T::arbitrary_repr_c` into their FFI
+/// representation and back yields the original values, panicking with a minimal failing input
+/// otherwise.
+pub fn roundtrip<T>()
+where
+    T: ArbitraryReprC,
+    T::Error: Debug,
+{
+    roundtrip_with(T::arbitrary_repr_c())
+}
+
+/// Same as `roundtrip`, but with values generated by `strategy`.
+pub fn roundtrip_with<T, S>(strategy: S)
+where
+    T: IntoReprC + Clone + PartialEq + Debug,
+    T::Error: Debug,
+    S: Strategy<Value = T>,
+{
+    let result = TestRunner::default().run(&strategy, |value| {
+        let (repr_c, _storage) = value
+            .clone()
+            .into_repr_c()
+            .map_err(|e| TestCaseError::fail(format!("into_repr_c failed: {:?}", e)))?;
+        let cloned = unsafe { T::clone_from_repr_c(repr_c) }
+            .map_err(|e| TestCaseError::fail(format!("clone_from_repr_c failed: {:?}", e)))?;
+        prop_assert_eq!(cloned, value);
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+}
+
+macro_rules! impl_arbitrary_repr_c {
+    ($($ty:ty => $strategy:expr),+ $(,)*) => {
+        $(
+            impl ArbitraryReprC for $ty {
+                fn arbitrary_repr_c() -> BoxedStrategy<Self> {
+                    $strategy
+                }
+            }
+        )+
+    };
+}
+
+impl_arbitrary_repr_c!(
+    i32 => any::<i32>().boxed(),
+    i64 => any::<i64>().boxed(),
+    u32 => any::<u32>().boxed(),
+    u64 => any::<u64>().boxed(),
+    usize => any::<usize>().boxed(),
+    bool => any::<bool>().boxed(),
+    [u8; 24] => byte_array::<24>(),
+    [u8; 32] => byte_array::<32>(),
+    [u8; 48] => byte_array::<48>(),
+    [u8; 64] => byte_array::<64>(),
+    [u8; 96] => byte_array::<96>(),
+    String => c_string(),
+    NativeResult => (any::<i32>(), ::proptest::option::of(c_string()))
+        .prop_map(|(error_code, description)| NativeResult {
+            error_code,
+            description,
+        })
+        .boxed(),
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_types_roundtrip() {
+        roundtrip::<i32>();
+        roundtrip::<u64>();
+        roundtrip::<bool>();
+        roundtrip::<[u8; 96]>();
+        roundtrip::<String>();
+        roundtrip::<NativeResult>();
+    }
+}
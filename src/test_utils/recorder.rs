@@ -0,0 +1,112 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use std::fmt::{Debug, Write};
+use std::os::raw::c_void;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+
+/// Invocation of a callback recorded by `CallRecorder`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallEvent {
+    /// Name given to the callback.
+    pub name: &'static str,
+    /// Summary of the arguments the callback was invoked with.
+    pub args: String,
+    /// Thread the callback was invoked on.
+    pub thread: ThreadId,
+}
+
+/// Records the invocations of several callbacks sharing it as `user_data`, in order, so that
+/// tests can check the sequence of events rather than only the final state:
+///
+/// ```ignore
+/// extern "C" fn on_data(user_data: *mut c_void, len: usize) {
+///     unsafe { CallRecorder::from_user_data(user_data) }.record("data", len);
+/// }
+///
+/// let recorder = CallRecorder::new();
+/// unsafe { connect(recorder.as_user_data(), on_connect, on_data, on_done) };
+/// recorder.assert_order(&["connect", "data", "done"]);
+/// ```
+///
+/// The recorder must outlive every invocation of the callbacks.
+#[derive(Debug, Default)]
+pub struct CallRecorder {
+    events: Mutex<Vec<CallEvent>>,
+}
+
+impl CallRecorder {
+    /// Create an empty recorder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert the recorder to a `user_data` pointer.
+    pub fn as_user_data(&self) -> *mut c_void {
+        let ptr: *const Self = self;
+        ptr as *mut c_void
+    }
+
+    /// Borrow the recorder behind a `user_data` pointer.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` must have been returned by `as_user_data` of a recorder which is still alive.
+    pub unsafe fn from_user_data<'a>(user_data: *mut c_void) -> &'a Self {
+        &*(user_data as *const Self)
+    }
+
+    /// Record an invocation of the callback `name` on the current thread, summarising its
+    /// arguments with their `Debug` representation.
+    pub fn record<A: Debug>(&self, name: &'static str, args: A) {
+        self.lock().push(CallEvent {
+            name,
+            args: format!("{:?}", args),
+            thread: thread::current().id(),
+        });
+    }
+
+    /// Events recorded so far, in order.
+    pub fn events(&self) -> Vec<CallEvent> {
+        self.lock().clone()
+    }
+
+    /// Names of the callbacks invoked so far, in order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.lock().iter().map(|event| event.name).collect()
+    }
+
+    /// Panic with a report of all recorded events unless the callbacks invoked so far are
+    /// exactly `expected`, in that order.
+    pub fn assert_order(&self, expected: &[&str]) {
+        let events = self.lock();
+        if events
+            .iter()
+            .map(|event| event.name)
+            .eq(expected.iter().copied())
+        {
+            return;
+        }
+
+        let mut report = format!("expected callbacks {:?}, recorded:\n", expected);
+        for (index, event) in events.iter().enumerate() {
+            let _ = writeln!(
+                report,
+                "  {}. {}({}) on {:?}",
+                index, event.name, event.args, event.thread
+            );
+        }
+        panic!("{}", report);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<CallEvent>> {
+        self.events.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Detection of FFI functions re-entered synchronously through their own callbacks.
+//!
+//! Frontends which serialise their calls into the native library deadlock when a callback
+//! calls another FFI function before the function which invoked it has returned. Within
+//! `detect`, every FFI function entered through `catch_unwind_cb` is tracked on the current
+//! thread, and the test fails once `detect` returns if any of them was entered while another
+//! one was still running. Requires the `testing` feature:
+//!
+//! ```ignore
+//! reentrancy::detect(|| unsafe { call_0(|ud, cb| app_reconnect(app, ud, cb)) });
+//! ```
+
+use crate::reentrancy::ReentrancyGuard;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::panic::Location;
+
+#[derive(Default)]
+struct State {
+    enabled: bool,
+    // FFI functions currently running on the thread, outermost first.
+    stack: Vec<&'static Location<'static>>,
+    // Stacks at the time of every reentrant call.
+    reentered: Vec<Vec<&'static Location<'static>>>,
+}
+
+// Depth counter of the FFI functions entered through `catch_unwind_cb`, shared with the
+// `reentrancy` module.
+const LIBRARY: &str = "sn_ffi_utils::test_utils::reentrancy";
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Run `f`, panicking with a report of the call stacks once it returns if any FFI function was
+/// re-entered synchronously on the current thread in the meantime.
+pub fn detect<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let was_enabled = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.stack.clear();
+        state.reentered.clear();
+        std::mem::replace(&mut state.enabled, true)
+    });
+
+    // Restores `enabled` even if `f` panics.
+    let guard = DetectGuard { was_enabled };
+    let result = f();
+    drop(guard);
+
+    let reentered = STATE.with(|state| std::mem::take(&mut state.borrow_mut().reentered));
+
+    if !reentered.is_empty() {
+        let mut report = String::from("FFI functions re-entered through their callbacks:\n");
+        for stack in reentered {
+            let calls: Vec<_> = stack.iter().map(ToString::to_string).collect();
+            let _ = writeln!(report, "  {}", calls.join(" -> "));
+        }
+        panic!("{}", report);
+    }
+
+    result
+}
+
+struct DetectGuard {
+    was_enabled: bool,
+}
+
+impl Drop for DetectGuard {
+    fn drop(&mut self) {
+        let _ = STATE.try_with(|state| state.borrow_mut().enabled = self.was_enabled);
+    }
+}
+
+/// Record that the FFI function at `location` is entered, until the returned guard is dropped.
+#[doc(hidden)]
+pub fn enter(location: &'static Location<'static>) -> Option<EnterGuard> {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.enabled {
+            return None;
+        }
+
+        let depth = ReentrancyGuard::enter(LIBRARY);
+        state.stack.push(location);
+        if depth.is_reentrant() {
+            let stack = state.stack.clone();
+            state.reentered.push(stack);
+        }
+        Some(EnterGuard { _depth: depth })
+    })
+}
+
+/// Guard returned by `enter`.
+#[doc(hidden)]
+pub struct EnterGuard {
+    // Only held for its `Drop`.
+    _depth: ReentrancyGuard,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        // The thread local may already be gone when the thread is exiting.
+        let _ = STATE.try_with(|state| state.borrow_mut().stack.pop());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{call_0, TestError};
+    use crate::{catch_unwind_cb, FfiResult, OpaqueCtx, FFI_RESULT_OK};
+    use std::os::raw::c_void;
+    use std::ptr;
+
+    extern "C" fn ffi_fn(
+        user_data: *mut c_void,
+        o_cb: extern "C" fn(*mut c_void, *const FfiResult),
+    ) {
+        let user_data = OpaqueCtx::from_host_pointer(user_data);
+        catch_unwind_cb(user_data, o_cb, || -> Result<_, TestError> {
+            o_cb(user_data.as_ptr(), FFI_RESULT_OK);
+            Ok(())
+        })
+    }
+
+    extern "C" fn reenter(_user_data: *mut c_void, _res: *const FfiResult) {
+        ffi_fn(ptr::null_mut(), ignore);
+    }
+
+    extern "C" fn ignore(_user_data: *mut c_void, _res: *const FfiResult) {}
+
+    #[test]
+    fn sequential_calls_pass() {
+        detect(|| {
+            assert_eq!(call_0(|ud, cb| ffi_fn(ud, cb)), Ok(()));
+            assert_eq!(call_0(|ud, cb| ffi_fn(ud, cb)), Ok(()));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "re-entered through their callbacks")]
+    fn reentrant_call_fails() {
+        detect(|| ffi_fn(ptr::null_mut(), reenter));
+    }
+
+    #[test]
+    fn panic_disables_detection() {
+        let res = std::panic::catch_unwind(|| detect(|| panic!("simulated panic")));
+        assert!(res.is_err());
+
+        // Reentrant calls outside `detect` are no longer tracked.
+        ffi_fn(ptr::null_mut(), reenter);
+        assert!(STATE.with(|state| state.borrow().reentered.is_empty()));
+    }
+}
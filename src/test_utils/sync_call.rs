@@ -0,0 +1,41 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use crate::ReprC;
+use std::fmt::Debug;
+use std::mem::MaybeUninit;
+
+/// Call a synchronous FFI function returning only an error code.
+pub fn sync_call_0<F>(f: F) -> Result<(), i32>
+where
+    F: FnOnce() -> i32,
+{
+    match f() {
+        0 => Ok(()),
+        error_code => Err(error_code),
+    }
+}
+
+/// Call a synchronous FFI function writing its value to an out-parameter and returning an error
+/// code, and convert the value.
+///
+/// # Safety
+///
+/// On success, `f` must have written a valid `T::C` to the out-parameter, which must be safe to
+/// pass to `T::clone_from_repr_c`.
+pub unsafe fn sync_call_1<T, F>(f: F) -> Result<T, i32>
+where
+    F: FnOnce(*mut T::C) -> i32,
+    T: ReprC,
+    T::Error: Debug,
+{
+    let mut out = MaybeUninit::<T::C>::uninit();
+    sync_call_0(|| f(out.as_mut_ptr()))?;
+    Ok(unwrap::unwrap!(T::clone_from_repr_c(out.assume_init())))
+}
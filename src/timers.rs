@@ -0,0 +1,283 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Delayed callbacks.
+//!
+//! Timers are run by a single background thread, started on first use, and their callbacks are
+//! invoked on the global dispatcher like any other callback, rather than each timer spawning its
+//! own thread:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn app_retry_later(
+//!     delay_ms: u64,
+//!     user_data: *mut c_void,
+//!     o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+//! ) -> TimerHandle {
+//!     timers::schedule(delay_ms, user_data, o_cb)
+//! }
+//! ```
+//!
+//! Every scheduled callback is invoked exactly once: with `FFI_RESULT_OK` when the timer fires,
+//! or with `ERR_CANCELLED` when it is cancelled first.
+
+use crate::callback::Callback;
+use crate::cancel::{Cancelled, ERR_CANCELLED};
+use crate::catch_unwind::call_error_cb;
+use crate::{dispatcher, pending, OpaqueCtx, FFI_RESULT_OK};
+use log::warn;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+use std::os::raw::c_void;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Identifier of a scheduled timer. 0 is never a valid handle.
+pub type TimerHandle = u64;
+
+/// Error code reported when the timer thread can't be started.
+pub const ERR_TIMERS_UNAVAILABLE: i32 = -9025;
+
+type Job = Box<dyn FnOnce(bool) + Send>;
+
+struct State {
+    next_id: TimerHandle,
+    // Deadlines of the timers, including cancelled ones which are skipped once due.
+    deadlines: BinaryHeap<Reverse<(Instant, TimerHandle)>>,
+    jobs: HashMap<TimerHandle, Job>,
+}
+
+struct Timers {
+    state: Mutex<State>,
+    condvar: Condvar,
+    // Whether the timer thread has been started.
+    worker: Mutex<bool>,
+}
+
+impl Timers {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                next_id: 1,
+                deadlines: BinaryHeap::new(),
+                jobs: HashMap::new(),
+            }),
+            condvar: Condvar::new(),
+            worker: Mutex::new(false),
+        }
+    }
+
+    // Start the timer thread unless it is already running. A failed start is retried on the next
+    // call, rather than leaving every later timer pending forever.
+    fn start(&'static self) -> io::Result<()> {
+        let mut started = self.worker.lock().unwrap_or_else(PoisonError::into_inner);
+        if !*started {
+            let _ = thread::Builder::new()
+                .name("ffi-timers".to_owned())
+                .spawn(move || self.run())?;
+            *started = true;
+        }
+        Ok(())
+    }
+
+    fn schedule(&self, delay: Duration, job: Job) -> TimerHandle {
+        let mut state = self.state();
+        let id = state.next_id;
+        state.next_id += 1;
+        // A deadline too far in the future to be represented never comes: the timer stays
+        // pending until cancelled.
+        if let Some(deadline) = Instant::now().checked_add(delay) {
+            state.deadlines.push(Reverse((deadline, id)));
+        }
+        let _ = state.jobs.insert(id, job);
+        self.condvar.notify_one();
+        id
+    }
+
+    fn cancel(&self, id: TimerHandle) -> bool {
+        let job = self.state().jobs.remove(&id);
+        let cancelled = job.map(|job| job(true)).is_some();
+        if cancelled {
+            pending::notify_completed();
+        }
+        cancelled
+    }
+
+    fn cancel_all(&self) -> usize {
+        let jobs: Vec<_> = {
+            let mut state = self.state();
+            state.deadlines.clear();
+            state.jobs.drain().map(|(_, job)| job).collect()
+        };
+        let count = jobs.len();
+        jobs.into_iter().for_each(|job| job(true));
+        pending::notify_completed();
+        count
+    }
+
+    fn pending(&self) -> usize {
+        self.state().jobs.len()
+    }
+
+    fn run(&self) {
+        let mut state = self.state();
+        loop {
+            let now = Instant::now();
+            match state.deadlines.peek() {
+                Some(Reverse((deadline, _))) if *deadline <= now => {
+                    let Reverse((_, id)) = state.deadlines.pop().unwrap_or_else(|| unreachable!());
+                    if let Some(job) = state.jobs.remove(&id) {
+                        drop(state);
+                        job(false);
+                        pending::notify_completed();
+                        state = self.state();
+                    }
+                }
+                Some(Reverse((deadline, _))) => {
+                    let timeout = *deadline - now;
+                    state = self
+                        .condvar
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+                None => {
+                    state = self
+                        .condvar
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+static TIMERS: OnceLock<Timers> = OnceLock::new();
+
+fn timers() -> &'static Timers {
+    TIMERS.get_or_init(Timers::new)
+}
+
+/// Invoke `cb` on the global dispatcher once `delay_ms` milliseconds have elapsed, unless the
+/// returned timer is cancelled first.
+///
+/// If the timer thread can't be started, `cb` is invoked right away with `ERR_TIMERS_UNAVAILABLE`
+/// and 0 is returned.
+pub fn schedule<U, C>(delay_ms: u64, user_data: U, cb: C) -> TimerHandle
+where
+    U: Into<*mut c_void>,
+    C: Callback<Args = ()> + Send + 'static,
+{
+    let user_data = OpaqueCtx::from_host_pointer(user_data.into());
+    if let Err(error) = timers().start() {
+        call_error_cb(
+            user_data.as_ptr(),
+            cb,
+            ERR_TIMERS_UNAVAILABLE,
+            format!("Failed to start the timer thread: {}", error),
+        );
+        return 0;
+    }
+
+    let job = move |cancelled: bool| {
+        let dispatched = dispatcher::global().dispatch(move || {
+            if cancelled {
+                call_error_cb(user_data.as_ptr(), cb, ERR_CANCELLED, Cancelled.to_string());
+            } else {
+                cb.call(user_data.as_ptr(), FFI_RESULT_OK, ());
+            }
+        });
+        if let Err(error) = dispatched {
+            warn!("Dropping timer callback: {}", error);
+        }
+    };
+
+    timers().schedule(Duration::from_millis(delay_ms), Box::new(job))
+}
+
+// Run `job` on the timer thread once `delay` has elapsed, with `false`, or on cancellation, with
+// `true`. The job must not block. Returns `None`, without running `job`, if the timer thread
+// can't be started.
+pub(crate) fn schedule_fn<F>(delay: Duration, job: F) -> Option<TimerHandle>
+where
+    F: FnOnce(bool) + Send + 'static,
+{
+    let timers = timers();
+    if let Err(error) = timers.start() {
+        warn!("Failed to start the timer thread: {}", error);
+        return None;
+    }
+    Some(timers.schedule(delay, Box::new(job)))
+}
+
+/// Cancel the timer behind `handle`, invoking its callback with `ERR_CANCELLED`. Returns `false`
+/// if the timer has already fired or been cancelled.
+pub fn cancel(handle: TimerHandle) -> bool {
+    TIMERS.get().is_some_and(|timers| timers.cancel(handle))
+}
+
+/// Cancel every pending timer, returning how many there were.
+pub fn cancel_all() -> usize {
+    TIMERS.get().map_or(0, Timers::cancel_all)
+}
+
+/// Number of timers which have neither fired nor been cancelled.
+pub fn pending() -> usize {
+    TIMERS.get().map_or(0, Timers::pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::call_0;
+    use unwrap::unwrap;
+
+    #[test]
+    fn fire_after_delay() {
+        let start = Instant::now();
+        assert_eq!(
+            call_0(|user_data, cb| {
+                let _ = schedule(50, user_data, cb);
+            }),
+            Ok(())
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn cancel_before_firing() {
+        assert_eq!(
+            call_0(|user_data, cb| {
+                let timer = schedule(60_000, user_data, cb);
+                assert!(cancel(timer));
+                assert!(!cancel(timer));
+            }),
+            Err(ERR_CANCELLED)
+        );
+        assert!(!cancel(0));
+    }
+
+    #[test]
+    fn unrepresentable_deadline_never_fires() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let timer = unwrap!(schedule_fn(Duration::MAX, move |cancelled| unwrap!(
+            tx.send(cancelled)
+        )));
+        thread::sleep(Duration::from_millis(20));
+        assert!(rx.try_recv().is_err());
+        assert!(cancel(timer));
+        assert!(unwrap!(rx.recv()));
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Tracing spans for FFI calls.
+//!
+//! With the `tracing` feature, `catch_unwind_cb` and `async_ffi::spawn_cb` run the FFI function
+//! in an `ffi_call` span recording the function name, the call site and a unique `call_id`. The
+//! eventual callback invocation, including by `call_result_cb!` and `call_cb_with_error`, is
+//! recorded as an "invoking callback" event in the same span carrying the `call_id`, so native
+//! work can be traced back to the frontend call which triggered it.
+//!
+//! When no `tracing` subscriber is installed, events are emitted as `log` records, which are
+//! forwarded to the frontend by `logging::set_logger`.
+
+pub use crate::catch_unwind::function_name;
+
+use std::cell::Cell;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::span::EnteredSpan;
+use tracing::Span;
+
+static NEXT_CALL_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    // `call_id` of the call whose callback would be invoked on this thread.
+    static CURRENT_CALL_ID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Create the span of a call to the FFI function enclosing the closure or future `F`.
+pub fn call_span<F>(location: &Location) -> CallSpan {
+    let call_id = NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed);
+    let span = tracing::info_span!(
+        "ffi_call",
+        function = function_name::<F>(),
+        %location,
+        call_id
+    );
+    CallSpan { span, call_id }
+}
+
+/// Span of a call to an FFI function, along with its `call_id`.
+pub struct CallSpan {
+    span: Span,
+    call_id: u64,
+}
+
+impl CallSpan {
+    /// Enter the span, and make the call the current one of the thread, until the returned guard
+    /// is dropped.
+    pub fn entered(self) -> EnteredCall {
+        EnteredCall {
+            _call: CurrentCall::set(self.call_id),
+            _span: self.span.entered(),
+        }
+    }
+
+    /// Split into the span, e.g. to instrument a future, and the `call_id`.
+    pub fn into_parts(self) -> (Span, u64) {
+        (self.span, self.call_id)
+    }
+}
+
+/// Guard of a call span entered with `CallSpan::entered`.
+pub struct EnteredCall {
+    _span: EnteredSpan,
+    _call: CurrentCall,
+}
+
+/// Makes a call the current one of the thread, whose `call_id` is recorded by
+/// `invoking_callback`, until dropped.
+pub struct CurrentCall(Option<u64>);
+
+impl CurrentCall {
+    /// Make the call `call_id` the current one.
+    pub fn set(call_id: u64) -> Self {
+        Self(CURRENT_CALL_ID.with(|current| current.replace(Some(call_id))))
+    }
+}
+
+impl Drop for CurrentCall {
+    fn drop(&mut self) {
+        let _ = CURRENT_CALL_ID.try_with(|current| current.set(self.0));
+    }
+}
+
+/// Record the invocation of a callback reporting `error_code`, along with the `call_id` of the
+/// current call, if any.
+pub fn invoking_callback(error_code: i32) {
+    let call_id = CURRENT_CALL_ID.with(Cell::get);
+    tracing::debug!(error_code, call_id, "invoking callback");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestError;
+    use crate::{call_cb_with_error, call_result_cb, catch_unwind_cb, FfiResult};
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::os::raw::c_void;
+    use std::ptr;
+    use std::sync::{Arc, Mutex, PoisonError};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Clone, Default)]
+    struct Fields(HashMap<&'static str, String>);
+
+    // Subscriber recording the fields of every event.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+    impl Recorder {
+        fn events(&self) -> Vec<Fields> {
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+        }
+    }
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            let _ = self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &Attributes) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(fields);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    extern "C" fn cb(_user_data: *mut c_void, _result: *const FfiResult) {}
+
+    // The `(error_code, call_id)` of the "invoking callback" events recorded while running `f`.
+    fn callback_events<F: FnOnce()>(f: F) -> Vec<(String, Option<String>)> {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), f);
+        recorder
+            .events()
+            .into_iter()
+            .map(|Fields(fields)| fields)
+            .filter(|fields| fields.get("message").map(String::as_str) == Some("invoking callback"))
+            .map(|mut fields| {
+                let error_code = fields.remove("error_code").unwrap_or_default();
+                (error_code, fields.remove("call_id"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn invoking_callback_carries_call_id() {
+        let cb: extern "C" fn(_, _) = cb;
+        let events = callback_events(|| {
+            catch_unwind_cb(
+                ptr::null_mut::<c_void>(),
+                cb,
+                || -> Result<(), TestError> {
+                    call_result_cb!(Ok::<_, TestError>(()), ptr::null_mut::<c_void>(), cb);
+                    call_cb_with_error(ptr::null_mut(), cb, &TestError::Test);
+                    Err(TestError::Test)
+                },
+            );
+        });
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].0, "0");
+        assert_eq!(events[1].0, "-1");
+        assert_eq!(events[2].0, "-1");
+        let call_id = events[0].1.clone();
+        assert!(call_id.is_some());
+        assert!(events.iter().all(|event| event.1 == call_id));
+
+        // Outside of a call, there's no `call_id` to record.
+        let events = callback_events(|| {
+            call_result_cb!(Ok::<_, TestError>(()), ptr::null_mut::<c_void>(), cb);
+        });
+        assert_eq!(events, vec![("0".to_owned(), None)]);
+    }
+
+    #[test]
+    fn closure_function_name() {
+        fn name_of<F>(_: &F) -> &'static str {
+            function_name::<F>()
+        }
+
+        let outer = || {
+            let inner = || ();
+            name_of(&inner)
+        };
+        assert_eq!(outer(), "sn_ffi_utils::trace::tests::closure_function_name");
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use crate::ErrorCode;
+use log::error;
+use std::any;
+#[cfg(debug_assertions)]
+use std::any::TypeId;
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(debug_assertions)]
+use std::sync::{Mutex, PoisonError};
+
+// `repr(C)` keeps the tag at the start of the allocation whatever `T` is, so that it can be read
+// before knowing whether the context really holds a `T`.
+#[repr(C)]
+struct Tagged<T> {
+    #[cfg(debug_assertions)]
+    tag: TypeId,
+    #[cfg(debug_assertions)]
+    type_name: &'static str,
+    value: T,
+}
+
+/// Rust-owned `user_data` context holding a value of type `T`.
+///
+/// The value is boxed and handed out as a `*mut c_void` with `into_raw`, then borrowed from
+/// callbacks with `borrow` and reclaimed with `into_inner`. In debug builds, the context is
+/// tagged with the type of its value and using it as another type panics instead of being
+/// undefined behaviour:
+///
+/// ```ignore
+/// let user_data = TypedCtx::new(Progress::default()).into_raw();
+/// // ...
+/// let progress = unsafe { TypedCtx::<Progress>::borrow(user_data) };
+/// ```
+pub struct TypedCtx<T: 'static> {
+    inner: Box<Tagged<T>>,
+}
+
+impl<T: Send + 'static> TypedCtx<T> {
+    /// Box `value` into a context.
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Box::new(Tagged {
+                #[cfg(debug_assertions)]
+                tag: TypeId::of::<T>(),
+                #[cfg(debug_assertions)]
+                type_name: any::type_name::<T>(),
+                value,
+            }),
+        }
+    }
+}
+
+impl<T: 'static> TypedCtx<T> {
+    /// Transfer the context into a `user_data` pointer.
+    pub fn into_raw(self) -> *mut c_void {
+        Box::into_raw(self.inner) as *mut c_void
+    }
+
+    /// Borrow the value behind a `user_data` pointer without releasing it.
+    ///
+    /// Panics if `ptr` is null or, in debug builds, if the context holds another type.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `TypedCtx::into_raw` and not yet passed to `into_inner`.
+    pub unsafe fn borrow<'a>(ptr: *mut c_void) -> &'a T {
+        &Self::check(ptr).value
+    }
+
+    /// Take back ownership of the value behind a `user_data` pointer.
+    ///
+    /// Panics if `ptr` is null or, in debug builds, if the context holds another type.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `TypedCtx::into_raw`, and must not be used afterwards.
+    pub unsafe fn into_inner(ptr: *mut c_void) -> T {
+        let _ = Self::check(ptr);
+        Box::from_raw(ptr as *mut Tagged<T>).value
+    }
+
+    unsafe fn check<'a>(ptr: *mut c_void) -> &'a Tagged<T> {
+        assert!(!ptr.is_null(), "null {} context", any::type_name::<T>());
+
+        #[cfg(debug_assertions)]
+        {
+            // Only read the header, which is laid out identically for every `T`.
+            let header = &*(ptr as *const Tagged<()>);
+            assert!(
+                header.tag == TypeId::of::<T>(),
+                "context holds a {}, not a {}",
+                header.type_name,
+                any::type_name::<T>()
+            );
+        }
+
+        &*(ptr as *const Tagged<T>)
+    }
+}
+
+impl<T: 'static> From<TypedCtx<T>> for *mut c_void {
+    fn from(ctx: TypedCtx<T>) -> Self {
+        ctx.into_raw()
+    }
+}
+
+/// Error code reported when a `CtxOwned` context is null, already released or of another type.
+pub const ERR_INVALID_CONTEXT: i32 = -9026;
+
+/// Invalid `CtxOwned` context. Only null contexts are detected in release builds.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ContextError {
+    /// The context is null.
+    Null(&'static str),
+    /// The context has already been released.
+    Released(&'static str),
+    /// The context holds a value of another type.
+    WrongType {
+        /// Type of the value held by the context.
+        actual: &'static str,
+        /// Type the context was used as.
+        expected: &'static str,
+    },
+}
+
+impl ErrorCode for ContextError {
+    fn error_code(&self) -> i32 {
+        ERR_INVALID_CONTEXT
+    }
+}
+
+impl Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContextError::Null(name) => write!(f, "null {} context", name),
+            ContextError::Released(name) => {
+                write!(f, "{} context used after being released", name)
+            }
+            ContextError::WrongType { actual, expected } => {
+                write!(f, "context holds a {}, not a {}", actual, expected)
+            }
+        }
+    }
+}
+
+impl Error for ContextError {}
+
+// Addresses, types and type names of the live `CtxOwned` contexts, to catch double releases in
+// debug builds.
+#[cfg(debug_assertions)]
+type Live = HashMap<usize, (TypeId, &'static str)>;
+
+#[cfg(debug_assertions)]
+static LIVE: Mutex<Option<Live>> = Mutex::new(None);
+
+#[cfg(debug_assertions)]
+fn live<R>(f: impl FnOnce(&mut Live) -> R) -> R {
+    f(LIVE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(HashMap::new))
+}
+
+/// `user_data` context allocated by Rust for callbacks implemented in Rust (e.g. a boxed
+/// sender), released exactly once by the terminal callback.
+///
+/// Intermediate callbacks use `with`, and the terminal callback `finish`, which takes the
+/// context back before running its body, so that it is released whether the operation
+/// succeeded, failed or panicked, and even if the body returns early or panics:
+///
+/// ```ignore
+/// extern "C" fn on_done(user_data: *mut c_void, result: *const FfiResult) {
+///     let finished = unsafe {
+///         CtxOwned::<Sender<i32>>::finish(user_data, |tx| {
+///             let _ = tx.send((*result).error_code);
+///         })
+///     };
+///     if let Err(error) = finished {
+///         error!("{}", error);
+///     }
+/// }
+///
+/// app_fetch(app, CtxOwned::new(tx).into_user_data(), on_done);
+/// ```
+///
+/// Neither `with` nor `finish` panics on an invalid context, as they run inside `extern "C"`
+/// functions: they return a `ContextError` instead, which a callback forwarding to the host can
+/// report with `call_cb_with_error`. Contexts are type-checked like `TypedCtx` in debug builds,
+/// which also detect contexts released twice and count live ones with
+/// `outstanding_owned_contexts`.
+pub struct CtxOwned<T: 'static> {
+    ctx: TypedCtx<T>,
+}
+
+impl<T: Send + 'static> CtxOwned<T> {
+    /// Box `value` into a context.
+    pub fn new(value: T) -> Self {
+        Self {
+            ctx: TypedCtx::new(value),
+        }
+    }
+}
+
+impl<T: 'static> CtxOwned<T> {
+    /// Transfer the context into a `user_data` pointer, to be released by `finish`.
+    pub fn into_user_data(self) -> *mut c_void {
+        let ptr = self.ctx.into_raw();
+        #[cfg(debug_assertions)]
+        let _ = live(|live| live.insert(ptr as usize, (TypeId::of::<T>(), any::type_name::<T>())));
+        ptr
+    }
+
+    /// Run `f` with the context from an intermediate callback, leaving it alive. Panics raised
+    /// by `f` are logged rather than unwinding into the caller.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` must have been returned by `into_user_data` and not yet passed to `finish`.
+    /// In debug builds, violations are detected and reported as a `ContextError`.
+    pub unsafe fn with<F>(user_data: *mut c_void, f: F) -> Result<(), ContextError>
+    where
+        F: FnOnce(&T),
+    {
+        Self::check_null(user_data)?;
+        #[cfg(debug_assertions)]
+        live(|live| Self::check_entry(live.get(&(user_data as usize)).copied()))?;
+
+        let value = TypedCtx::<T>::borrow(user_data);
+        if panic::catch_unwind(AssertUnwindSafe(|| f(value))).is_err() {
+            error!(
+                "Callback using a {} context panicked",
+                any::type_name::<T>()
+            );
+        }
+        Ok(())
+    }
+
+    /// Release the context from the terminal callback, running `f` with it first. The context
+    /// is dropped even if `f` panics; the panic is logged rather than unwinding into the caller.
+    ///
+    /// # Safety
+    ///
+    /// `user_data` must have been returned by `into_user_data`, and must not be used afterwards.
+    /// In debug builds, violations are detected and reported as a `ContextError`, without
+    /// releasing anything.
+    pub unsafe fn finish<F>(user_data: *mut c_void, f: F) -> Result<(), ContextError>
+    where
+        F: FnOnce(T),
+    {
+        Self::check_null(user_data)?;
+        // Claim the context under a single lock, so that only one of two concurrent releases
+        // gets it.
+        #[cfg(debug_assertions)]
+        live(|live| {
+            let entry = live.remove(&(user_data as usize));
+            let checked = Self::check_entry(entry);
+            if let (Err(ContextError::WrongType { .. }), Some(entry)) = (&checked, entry) {
+                // Not ours to release: leave it to its actual owner.
+                let _ = live.insert(user_data as usize, entry);
+            }
+            checked
+        })?;
+
+        let value = TypedCtx::<T>::into_inner(user_data);
+        if panic::catch_unwind(AssertUnwindSafe(|| f(value))).is_err() {
+            error!(
+                "Terminal callback of a {} context panicked",
+                any::type_name::<T>()
+            );
+        }
+        Ok(())
+    }
+
+    fn check_null(user_data: *mut c_void) -> Result<(), ContextError> {
+        if user_data.is_null() {
+            Err(ContextError::Null(any::type_name::<T>()))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn check_entry(entry: Option<(TypeId, &'static str)>) -> Result<(), ContextError> {
+        match entry {
+            None => Err(ContextError::Released(any::type_name::<T>())),
+            Some((tag, _)) if tag == TypeId::of::<T>() => Ok(()),
+            Some((_, actual)) => Err(ContextError::WrongType {
+                actual,
+                expected: any::type_name::<T>(),
+            }),
+        }
+    }
+}
+
+/// Number of `CtxOwned` contexts transferred with `into_user_data` which have not been
+/// released yet.
+///
+/// Only tracked in debug builds; always returns 0 in release builds.
+pub fn outstanding_owned_contexts() -> usize {
+    #[cfg(debug_assertions)]
+    {
+        live(|live| live.len())
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        0
+    }
+}
+
+// Type names of the live `CtxOwned` contexts.
+#[cfg(debug_assertions)]
+pub(crate) fn owned_context_names() -> Vec<&'static str> {
+    live(|live| live.values().map(|(_, name)| *name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FfiResult, FFI_RESULT_OK};
+    use std::ptr;
+    use std::sync::mpsc::{self, Sender};
+    use unwrap::unwrap;
+
+    #[test]
+    fn round_trip() {
+        let ptr = TypedCtx::new(vec![1, 2, 3]).into_raw();
+        assert_eq!(unsafe { TypedCtx::<Vec<i32>>::borrow(ptr) }, &[1, 2, 3]);
+        assert_eq!(unsafe { TypedCtx::<Vec<i32>>::into_inner(ptr) }, [1, 2, 3]);
+    }
+
+    #[test]
+    fn owned_context_released_once() {
+        extern "C" fn on_progress(user_data: *mut c_void, progress: u32) {
+            unsafe {
+                unwrap!(CtxOwned::<Sender<u32>>::with(user_data, |tx| unwrap!(
+                    tx.send(progress)
+                )))
+            }
+        }
+
+        extern "C" fn on_done(user_data: *mut c_void, result: *const FfiResult) {
+            unsafe {
+                unwrap!(CtxOwned::<Sender<u32>>::finish(user_data, |tx| {
+                    if (*result).error_code != 0 {
+                        panic!("failed");
+                    }
+                    unwrap!(tx.send(0));
+                }))
+            }
+        }
+
+        let (tx, rx) = mpsc::channel::<u32>();
+        let user_data = CtxOwned::new(tx.clone()).into_user_data();
+        on_progress(user_data, 50);
+        on_done(user_data, FFI_RESULT_OK);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [50, 0]);
+
+        let user_data = CtxOwned::new(tx).into_user_data();
+        let failed = FfiResult {
+            error_code: -1,
+            description: ptr::null(),
+        };
+        on_done(user_data, &failed);
+        // The sender was dropped despite the panic, disconnecting the channel.
+        assert!(rx.recv().is_err());
+
+        #[cfg(debug_assertions)]
+        unsafe {
+            assert_eq!(
+                CtxOwned::<Sender<u32>>::with(user_data, |_| ()).map_err(|e| e.error_code()),
+                Err(ERR_INVALID_CONTEXT)
+            );
+            assert!(matches!(
+                CtxOwned::<Sender<u32>>::finish(user_data, |_| ()),
+                Err(ContextError::Released(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_context_is_reported() {
+        unsafe {
+            assert!(matches!(
+                CtxOwned::<u32>::finish(ptr::null_mut(), |_| ()),
+                Err(ContextError::Null(_))
+            ));
+        }
+
+        #[cfg(debug_assertions)]
+        unsafe {
+            let user_data = CtxOwned::new(String::from("ctx")).into_user_data();
+            assert!(matches!(
+                CtxOwned::<u64>::finish(user_data, |_| ()),
+                Err(ContextError::WrongType { .. })
+            ));
+            // The context is still alive, and can be released as its actual type.
+            unwrap!(CtxOwned::<String>::finish(user_data, |value| assert_eq!(
+                value,
+                "ctx"
+            )));
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "context holds a alloc::string::String, not a u64")]
+    fn wrong_type() {
+        let ptr: *mut c_void = TypedCtx::new(String::from("ctx")).into();
+        let _ = unsafe { TypedCtx::<u64>::borrow(ptr) };
+    }
+}
@@ -7,9 +7,11 @@
 // specific language governing permissions and limitations relating to use of the SAFE Network
 // Software.
 
-use std::mem;
-use std::ptr;
-use std::slice;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem;
+use core::ptr;
+use core::slice;
 
 /// Provides FFI-safe pointers, as opposed to raw `as_ptr()` in `Vec` and `String` which can return
 /// values such as `0x01` that can cause segmentation faults with the automatic pointer
@@ -38,14 +40,26 @@ impl<T> SafePtr for Vec<T> {
 ///
 /// The pointer which this function returns must be returned to Rust and reconstituted using
 /// `vec_from_raw_parts` to be properly deallocated. Specifically, one should not use the standard C
-/// `free()` function to deallocate this data.
+/// `free()` function to deallocate this data, unless the host registered its own allocator with
+/// `allocator::set_allocator`, in which case the data may also be released with the host's free
+/// function. Once this has been called, no allocator can be registered anymore.
 ///
 /// Failure to call `vec_from_raw_parts` will lead to a memory leak.
 pub fn vec_into_raw_parts<T>(v: Vec<T>) -> (*mut T, usize) {
+    // Host-owned buffers aren't tracked, as the host may release them itself.
+    #[cfg(feature = "std")]
+    if let Some(allocator) = crate::allocator::allocator_for_transfer() {
+        return allocator.transfer(v);
+    }
+
     let mut b = v.into_boxed_slice();
     let ptr = b.as_mut_ptr();
     let len = b.len();
     mem::forget(b);
+
+    #[cfg(feature = "memory-report")]
+    crate::memory::track(crate::memory::Category::Vec, ptr, len * size_of::<T>());
+
     (ptr, len)
 }
 
@@ -55,6 +69,14 @@ pub fn vec_into_raw_parts<T>(v: Vec<T>) -> (*mut T, usize) {
 ///
 /// Unsafe. See documentation for `slice::from_raw_parts_mut` and `Box::from_raw`.
 pub unsafe fn vec_from_raw_parts<T>(ptr: *mut T, len: usize) -> Vec<T> {
+    #[cfg(feature = "std")]
+    if let Some(allocator) = crate::allocator::allocator() {
+        return allocator.reclaim(ptr, len);
+    }
+
+    #[cfg(feature = "memory-report")]
+    crate::memory::untrack(ptr);
+
     Box::from_raw(slice::from_raw_parts_mut(ptr, len)).into_vec()
 }
 
@@ -68,7 +90,7 @@ pub unsafe fn vec_clone_from_raw_parts<T: Clone>(ptr: *const T, len: usize) -> V
     slice::from_raw_parts(ptr, len).to_vec()
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
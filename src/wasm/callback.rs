@@ -0,0 +1,121 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use super::{native_result_to_js_error, ToJsValue};
+use crate::callback::{Callback, CallbackArgs};
+use crate::{shield, FfiResult, NativeResult, ReprC};
+use js_sys::Function;
+use log::error;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use wasm_bindgen::JsValue;
+
+/// JS function invoked with the outcome of a call, whose value is of type `T`.
+pub struct JsCallback<T> {
+    function: Function,
+    _value: PhantomData<fn(T)>,
+}
+
+impl<T> JsCallback<T> {
+    /// Wrap the JS function `function`.
+    pub fn new(function: Function) -> Self {
+        Self {
+            function,
+            _value: PhantomData,
+        }
+    }
+
+    /// Transfer the callback into a `user_data` pointer for one of the `callback_*` functions.
+    pub fn into_user_data(self) -> *mut c_void {
+        Box::into_raw(Box::new(self)) as *mut c_void
+    }
+
+    // Call the function with `(err, value)`.
+    unsafe fn complete<F>(&self, res: *const FfiResult, value: F)
+    where
+        F: FnOnce() -> Result<JsValue, JsValue>,
+    {
+        let outcome = if (*res).error_code == 0 {
+            value()
+        } else {
+            let result = NativeResult::clone_from_repr_c(res).unwrap_or_else(|_| NativeResult {
+                error_code: (*res).error_code,
+                description: None,
+            });
+            Err(native_result_to_js_error(&result).into())
+        };
+        let (err, value) = match outcome {
+            Ok(value) => (JsValue::NULL, value),
+            Err(err) => (err, JsValue::UNDEFINED),
+        };
+
+        if let Err(e) = self.function.call2(&JsValue::NULL, &err, &value) {
+            error!("JS callback failed: {:?}", e);
+        }
+    }
+}
+
+unsafe fn decode<T>(value: T::C) -> Result<JsValue, JsValue>
+where
+    T: ReprC + ToJsValue,
+    T::Error: Debug,
+{
+    T::clone_from_repr_c(value)
+        .map(ToJsValue::to_js_value)
+        .map_err(|e| js_sys::Error::new(&format!("Invalid callback argument: {:?}", e)).into())
+}
+
+/// Ignores `user_data`, so that a borrowed callback can be passed to `catch_unwind_cb` and
+/// invoked any number of times.
+impl<T> Callback for &JsCallback<T>
+where
+    T: ReprC + ToJsValue,
+    T::C: CallbackArgs,
+    T::Error: Debug,
+{
+    type Args = T::C;
+
+    // `error` is provided by the caller of the `Callback`, as for the extern callbacks.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn call(&self, _user_data: *mut c_void, error: *const FfiResult, args: Self::Args) {
+        unsafe { self.complete(error, || decode::<T>(args)) }
+    }
+}
+
+/// Callback taking no value, invoking the `JsCallback` behind `user_data` with
+/// `(err, undefined)`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `JsCallback::into_user_data`, and must not be used
+/// afterwards.
+pub unsafe extern "C" fn callback_0(user_data: *mut c_void, res: *const FfiResult) {
+    shield(move || {
+        let callback = Box::from_raw(user_data as *mut JsCallback<()>);
+        callback.complete(res, || Ok(JsValue::UNDEFINED))
+    })
+}
+
+/// Callback taking a value, invoking the `JsCallback` behind `user_data` with `(err, value)`.
+///
+/// # Safety
+///
+/// `user_data` must have been returned by `JsCallback::<T>::into_user_data`, and must not be
+/// used afterwards.
+pub unsafe extern "C" fn callback_1<T>(user_data: *mut c_void, res: *const FfiResult, value: T::C)
+where
+    T: ReprC + ToJsValue,
+    T::Error: Debug,
+{
+    shield(move || {
+        let callback = Box::from_raw(user_data as *mut JsCallback<T>);
+        callback.complete(res, || decode::<T>(value))
+    })
+}
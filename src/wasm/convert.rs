@@ -0,0 +1,93 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+use crate::{ErrorCode, NativeResult};
+use js_sys::{Error, Reflect, Uint8Array};
+use std::fmt::Display;
+use wasm_bindgen::JsValue;
+
+/// Conversion of a native value into a JS value.
+pub trait ToJsValue {
+    /// Convert `self` into a JS value.
+    fn to_js_value(self) -> JsValue;
+}
+
+impl ToJsValue for () {
+    fn to_js_value(self) -> JsValue {
+        JsValue::UNDEFINED
+    }
+}
+
+impl ToJsValue for bool {
+    fn to_js_value(self) -> JsValue {
+        JsValue::from_bool(self)
+    }
+}
+
+macro_rules! impl_to_js_value_number {
+    ($($ty:ty),*) => {
+        $(
+            impl ToJsValue for $ty {
+                #[allow(trivial_numeric_casts)]
+                fn to_js_value(self) -> JsValue {
+                    JsValue::from_f64(self as f64)
+                }
+            }
+        )*
+    };
+}
+
+// 64-bit integers become doubles, which are exact up to 2^53.
+impl_to_js_value_number!(i32, u32, i64, u64, usize, f64);
+
+impl ToJsValue for String {
+    fn to_js_value(self) -> JsValue {
+        JsValue::from_str(&self)
+    }
+}
+
+impl ToJsValue for Vec<u8> {
+    fn to_js_value(self) -> JsValue {
+        Uint8Array::from(&self[..]).into()
+    }
+}
+
+impl<T: ToJsValue> ToJsValue for Option<T> {
+    fn to_js_value(self) -> JsValue {
+        self.map_or(JsValue::NULL, ToJsValue::to_js_value)
+    }
+}
+
+/// Create a JS `Error` with the description of `result` as message and its error code as
+/// `code` property.
+pub fn native_result_to_js_error(result: &NativeResult) -> Error {
+    let error = Error::new(result.description.as_deref().unwrap_or_default());
+    let _ = Reflect::set(
+        &error,
+        &JsValue::from_str("code"),
+        &JsValue::from_f64(f64::from(result.error_code)),
+    );
+    error
+}
+
+/// Convert the result of a call into the return value of a `#[wasm_bindgen]` function, so that
+/// errors are thrown as JS exceptions (see `native_result_to_js_error`).
+pub fn result_to_js<T, E>(result: Result<T, E>) -> Result<JsValue, JsValue>
+where
+    T: ToJsValue,
+    E: ErrorCode + Display,
+{
+    result.map(ToJsValue::to_js_value).map_err(|err| {
+        native_result_to_js_error(&NativeResult {
+            error_code: err.error_code(),
+            description: Some(err.to_string()),
+        })
+        .into()
+    })
+}
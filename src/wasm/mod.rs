@@ -0,0 +1,35 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! WebAssembly (`wasm-bindgen`) utilities.
+//!
+//! Browser consumers call the same native functions as FFI consumers, with a `JsCallback`
+//! wrapping the JS function in place of the C callback. The function is invoked Node-style,
+//! `cb(err, value)`, where `err` is `null` on success or an `Error` carrying the error code in
+//! its `code` property:
+//!
+//! ```ignore
+//! #[wasm_bindgen]
+//! pub fn fetch(app: &App, cb: js_sys::Function) {
+//!     let cb = JsCallback::<String>::new(cb);
+//!     app_fetch(app, cb.into_user_data(), wasm::callback_1::<String>);
+//! }
+//! ```
+//!
+//! `&JsCallback` also implements `Callback`, so it can be passed to `catch_unwind_cb` directly.
+//! Synchronous functions can instead return `result_to_js`, which wasm-bindgen turns into a
+//! thrown exception on error.
+//!
+//! The target has no threads, so `events::emit_queued` delivers on the emitting thread.
+
+mod callback;
+mod convert;
+
+pub use self::callback::{callback_0, callback_1, JsCallback};
+pub use self::convert::{native_result_to_js_error, result_to_js, ToJsValue};
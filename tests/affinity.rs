@@ -0,0 +1,93 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Tests of the callback thread affinity policies, which are process-wide and so run in their
+//! own test binary.
+
+#![cfg(feature = "std")]
+#![warn(
+    missing_docs,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results
+)]
+#![allow(unsafe_code)]
+
+use sn_ffi_utils::affinity::{self, AffinityPolicy};
+use sn_ffi_utils::dispatcher;
+use sn_ffi_utils::test_utils::{call_0, TestError};
+use sn_ffi_utils::{export_callback_affinity, ERR_INVALID_ARG};
+use std::os::raw::c_void;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use unwrap::unwrap;
+
+export_callback_affinity!();
+
+// Report success to `o_cb` from a new thread, through `affinity::call_cb`.
+fn complete_on_thread(
+    user_data: *mut c_void,
+    o_cb: extern "C" fn(user_data: *mut c_void, result: *const sn_ffi_utils::FfiResult),
+) -> thread::JoinHandle<()> {
+    let user_data = user_data as usize;
+    thread::spawn(move || affinity::call_cb(user_data as *mut c_void, o_cb, Ok::<_, TestError>(())))
+}
+
+// Name of the thread a callback sending it through `invoke` runs on.
+fn invoked_on<F: FnOnce(Sender<Option<String>>)>(f: F) -> Option<String> {
+    let (tx, rx) = mpsc::channel();
+    f(tx);
+    unwrap!(rx.recv())
+}
+
+fn send_name(tx: Sender<Option<String>>) {
+    affinity::invoke(move || unwrap!(tx.send(thread::current().name().map(str::to_owned))))
+}
+
+#[test]
+fn callback_affinity() {
+    assert_eq!(ffi_set_callback_affinity(7), ERR_INVALID_ARG);
+
+    // Reroute: callbacks completed on other threads arrive on a dispatcher worker.
+    assert_eq!(ffi_set_callback_affinity(AffinityPolicy::Reroute as u32), 0);
+    ffi_register_callback_thread();
+    let result = call_0(|user_data, cb| {
+        unwrap!(complete_on_thread(user_data, cb).join());
+    });
+    assert_eq!(result, Ok(()));
+
+    assert_eq!(
+        invoked_on(send_name),
+        thread::current().name().map(str::to_owned)
+    );
+    assert_eq!(
+        invoked_on(|tx| unwrap!(thread::spawn(|| send_name(tx)).join())),
+        Some("ffi-dispatch-0".to_owned())
+    );
+
+    // Assert: callbacks on unregistered threads panic in debug builds.
+    assert_eq!(ffi_set_callback_affinity(AffinityPolicy::Assert as u32), 0);
+    affinity::check();
+    let checked = thread::spawn(affinity::check).join();
+    assert_eq!(checked.is_err(), cfg!(debug_assertions));
+
+    // Callbacks queued on the dispatcher are still delivered.
+    let result = call_0(|user_data, cb| {
+        unwrap!(dispatcher::global().dispatch_cb(user_data, cb, Ok::<_, TestError>(())));
+    });
+    assert_eq!(result, Ok(()));
+
+    ffi_unregister_callback_thread();
+    assert!(!affinity::is_callback_thread());
+    assert_eq!(ffi_set_callback_affinity(AffinityPolicy::Off as u32), 0);
+    assert!(affinity::is_callback_thread());
+}
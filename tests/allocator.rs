@@ -0,0 +1,64 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Tests of the host allocator, which is process-wide and so runs in its own test binary.
+
+#![cfg(feature = "std")]
+#![warn(
+    missing_docs,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results
+)]
+#![allow(unsafe_code)]
+
+use sn_ffi_utils::allocator::ERR_ALLOCATOR_ALREADY_SET;
+use sn_ffi_utils::{export_allocator, vec_from_raw_parts, vec_into_raw_parts};
+use std::alloc::{self, Layout};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+export_allocator!();
+
+// Number of bytes allocated by the host and not released yet.
+static OUTSTANDING: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn host_alloc(size: usize, align: usize) -> *mut c_void {
+    let _ = OUTSTANDING.fetch_add(size, Ordering::SeqCst);
+    unsafe { alloc::alloc(Layout::from_size_align_unchecked(size, align)) as *mut c_void }
+}
+
+extern "C" fn host_free(ptr: *mut c_void, size: usize, align: usize) {
+    let _ = OUTSTANDING.fetch_sub(size, Ordering::SeqCst);
+    unsafe {
+        alloc::dealloc(
+            ptr as *mut u8,
+            Layout::from_size_align_unchecked(size, align),
+        )
+    }
+}
+
+#[test]
+fn transfers_go_through_host_allocator() {
+    assert_eq!(ffi_set_allocator(host_alloc, host_free), 0);
+    assert_eq!(
+        ffi_set_allocator(host_alloc, host_free),
+        ERR_ALLOCATOR_ALREADY_SET
+    );
+
+    let (ptr, len) = vec_into_raw_parts(vec![1u32, 2, 3]);
+    assert_eq!(OUTSTANDING.load(Ordering::SeqCst), 12);
+
+    let v = unsafe { vec_from_raw_parts(ptr, len) };
+    assert_eq!(v, [1, 2, 3]);
+    assert_eq!(OUTSTANDING.load(Ordering::SeqCst), 0);
+}
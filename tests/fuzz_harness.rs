@@ -0,0 +1,163 @@
+// Copyright 2019 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Tests that the fuzz targets generated by `sn_ffi_utils::fuzz` compile and run. The target of
+//! `app_move` is checked in under `fuzz_harness/`, and included here with a stand-in for
+//! `libfuzzer_sys::fuzz_target!`.
+
+#![cfg(feature = "fuzz")]
+#![warn(
+    missing_docs,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results
+)]
+#![allow(unsafe_code)]
+
+use sn_ffi_utils::describe::{FfiArgDesc, FfiCallbackDesc, FfiFnDesc};
+use sn_ffi_utils::fuzz::{self, arbitrary, Arbitrary, Unstructured};
+use sn_ffi_utils::{FfiResult, IntoReprC, ReprC, FFI_RESULT_OK};
+use std::convert::Infallible;
+use std::os::raw::c_void;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Handle to an object of the library.
+pub type Handle = u64;
+
+/// C representation of `Point`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PointC {
+    x: i32,
+    y: i32,
+}
+
+/// Native argument of `app_move`, decoded by the target with `fuzz::repr_arg`.
+#[derive(Debug)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+impl<'a> Arbitrary<'a> for Point {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Point {
+            x: u.arbitrary()?,
+            y: u.arbitrary()?,
+        })
+    }
+}
+
+impl ReprC for Point {
+    type C = PointC;
+    type Error = Infallible;
+
+    unsafe fn clone_from_repr_c(repr_c: PointC) -> Result<Self, Infallible> {
+        Ok(Point {
+            x: repr_c.x,
+            y: repr_c.y,
+        })
+    }
+}
+
+impl IntoReprC for Point {
+    type Storage = ();
+
+    fn into_repr_c(self) -> Result<(PointC, ()), Infallible> {
+        Ok((
+            PointC {
+                x: self.x,
+                y: self.y,
+            },
+            (),
+        ))
+    }
+}
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// The fuzzed function.
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes.
+pub unsafe fn app_move(
+    _app: Handle,
+    to: PointC,
+    data: *const u8,
+    len: usize,
+    user_data: *mut c_void,
+    o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+) {
+    let _ = (to.x, to.y, slice::from_raw_parts(data, len));
+    let _ = CALLS.fetch_add(1, Ordering::SeqCst);
+    o_cb(user_data, FFI_RESULT_OK);
+}
+
+static DESC: FfiFnDesc = FfiFnDesc {
+    name: "app_move",
+    args: &[
+        FfiArgDesc {
+            name: "app",
+            ty: "Handle",
+        },
+        FfiArgDesc {
+            name: "to",
+            ty: "PointC",
+        },
+        FfiArgDesc {
+            name: "data",
+            ty: "*const u8",
+        },
+        FfiArgDesc {
+            name: "len",
+            ty: "usize",
+        },
+    ],
+    callbacks: &[FfiCallbackDesc {
+        name: "o_cb",
+        args: &[FfiArgDesc {
+            name: "result",
+            ty: "*const FfiResult",
+        }],
+    }],
+};
+
+const PRELUDE: &str = "use crate::{FfiResult, Handle, Point};";
+const NATIVE_TYPES: &[(&str, &str)] = &[("PointC", "Point")];
+
+mod target {
+    macro_rules! fuzz_target {
+        (|$input:ident: &[u8]| $body:block) => {
+            pub fn run($input: &[u8]) $body
+        };
+    }
+
+    include!("fuzz_harness/app_move.rs");
+}
+
+#[test]
+fn checked_in_target_is_current() {
+    assert_eq!(
+        fuzz::harness_body(&DESC, "crate", PRELUDE, NATIVE_TYPES),
+        include_str!("fuzz_harness/app_move.rs")
+    );
+}
+
+#[test]
+fn generated_target_runs() {
+    let input: Vec<u8> = (0..=255).collect();
+    target::run(&input);
+    target::run(&[]);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+}
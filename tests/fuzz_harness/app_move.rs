@@ -0,0 +1,16 @@
+use sn_ffi_utils::fuzz;
+use std::os::raw::c_void;
+use std::ptr;
+use crate::{FfiResult, Handle, Point};
+
+extern "C" fn o_cb(_user_data: *mut c_void, _result: *const FfiResult) {}
+
+fuzz_target!(|input: &[u8]| {
+    fuzz::run("app_move", input, |unstructured| {
+        let app = fuzz::arg::<Handle>(unstructured)?;
+        let to = fuzz::repr_arg::<Point>(unstructured)?;
+        let data: Vec<u8> = unstructured.arbitrary()?;
+        unsafe { crate::app_move(app.get(), to.get(), data.as_ptr(), data.len(), ptr::null_mut(), o_cb) };
+        Ok(())
+    });
+});
@@ -9,6 +9,7 @@
 
 //! Integration tests for FFI utilities.
 
+#![cfg(feature = "std")]
 #![doc(
     html_logo_url = "https://raw.githubusercontent.com/maidsafe/QA/master/Images/maidsafe_logo.png",
     html_favicon_url = "http://maidsafe.net/img/favicon.ico",
@@ -79,6 +80,62 @@ fn basic() {
     }
 }
 
+// Test FFI functions generated by `#[ffi_fn]`.
+#[test]
+fn ffi_fn_attribute() {
+    use sn_ffi_utils::ffi_fn;
+    use sn_ffi_utils::test_utils::{call_0, call_1, TestError};
+    use unwrap::unwrap;
+
+    /// Repeat `word` `count` times.
+    #[ffi_fn]
+    fn repeat_word(word: String, count: u32) -> Result<String, TestError> {
+        if count == 0 {
+            return Err(TestError::Test);
+        }
+        Ok(word.repeat(count as usize))
+    }
+
+    #[ffi_fn]
+    fn check_positive(value: i32) -> Result<(), TestError> {
+        if value > 0 {
+            Ok(())
+        } else {
+            Err(TestError::Test)
+        }
+    }
+
+    // The `cfg` applies to the exported function too, so this doesn't need to compile.
+    #[cfg(any())]
+    #[ffi_fn]
+    fn disabled(value: DoesNotExist) -> Result<(), TestError> {
+        Ok(())
+    }
+
+    #[deprecated]
+    #[ffi_fn(crate = sn_ffi_utils)]
+    fn negate(value: i32) -> Result<i32, TestError> {
+        Ok(-value)
+    }
+
+    let word = unwrap!(std::ffi::CString::new("ab"));
+    let res: String = unsafe { unwrap!(call_1(|ud, cb| repeat_word(word.as_ptr(), 2, ud, cb))) };
+    assert_eq!(res, "abab");
+
+    let res: Result<String, i32> =
+        unsafe { call_1(|ud, cb| repeat_word(word.as_ptr(), 0, ud, cb)) };
+    assert_eq!(res, Err(-1));
+
+    unsafe {
+        unwrap!(call_0(|ud, cb| check_positive(1, ud, cb)));
+        assert_eq!(call_0(|ud, cb| check_positive(0, ud, cb)), Err(-1));
+
+        #[allow(deprecated)]
+        let res: i32 = unwrap!(call_1(|ud, cb| negate(3, ud, cb)));
+        assert_eq!(res, -3);
+    }
+}
+
 // Test the utility functions as covered in "FFI calling conventions".
 #[test]
 fn utility_functions() {
@@ -144,6 +201,169 @@ fn utility_functions() {
     }
 }
 
+// Test the library lifecycle functions generated by `export_lifecycle!`.
+#[test]
+fn library_lifecycle() {
+    use sn_ffi_utils::export_lifecycle;
+    use sn_ffi_utils::handles;
+    use sn_ffi_utils::init::{
+        Library, LifecycleError, ERR_ALREADY_INITIALISED, ERR_INIT_PANICKED, ERR_NOT_INITIALISED,
+    };
+    use sn_ffi_utils::test_utils::TestError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use unwrap::unwrap;
+
+    struct App {
+        name: String,
+    }
+
+    #[derive(Debug)]
+    struct AppError(LifecycleError);
+
+    impl From<LifecycleError> for AppError {
+        fn from(e: LifecycleError) -> Self {
+            AppError(e)
+        }
+    }
+
+    impl std::fmt::Display for AppError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl sn_ffi_utils::ErrorCode for AppError {
+        fn error_code(&self) -> i32 {
+            self.0.error_code()
+        }
+    }
+
+    static APP: Library<App> = Library::new();
+    static PANIC: AtomicBool = AtomicBool::new(true);
+
+    fn init_app() -> Result<App, AppError> {
+        // The library may be used while it is being initialised.
+        assert!(!APP.is_initialised());
+        if PANIC.swap(false, Ordering::SeqCst) {
+            panic!("simulated panic");
+        }
+        Ok(App {
+            name: "app".to_owned(),
+        })
+    }
+
+    export_lifecycle!(APP, init_app);
+
+    assert_eq!(ffi_shutdown(), ERR_NOT_INITIALISED);
+    assert_eq!(ffi_init(), ERR_INIT_PANICKED);
+    assert_eq!(ffi_init(), 0);
+    assert_eq!(ffi_init(), ERR_ALREADY_INITIALISED);
+    assert_eq!(unwrap!(APP.with(|app| app.name.clone())), "app");
+
+    let handle = APP.handles().register(TestError::from("object"));
+    let global = handles::register(TestError::from("global"));
+    assert_eq!(ffi_shutdown(), 0);
+    assert!(APP.get().is_err());
+    assert!(APP.handles().get::<TestError>(handle).is_err());
+
+    // Handles registered elsewhere outlive the library.
+    unwrap!(handles::free::<TestError>(global));
+}
+
+// Test a graceful shutdown cancelling the operations in flight and waiting for them to drain.
+#[test]
+fn graceful_library_shutdown() {
+    use sn_ffi_utils::cancel::CancelHandle;
+    use sn_ffi_utils::dispatcher;
+    use sn_ffi_utils::init::{Library, LifecycleError};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use unwrap::unwrap;
+
+    static APP: Library<()> = Library::new();
+
+    unwrap!(APP.init(()));
+    let cancel = CancelHandle::new();
+    let (done_tx, done_rx) = mpsc::channel();
+    unwrap!(dispatcher::global().dispatch({
+        let cancel = cancel.clone();
+        move || assert!(cancel.wait_timeout(Duration::from_secs(60)))
+    }));
+
+    // Other tests may register callbacks concurrently, so allow for them to complete.
+    unwrap!(
+        APP.shutdown_graceful(Duration::from_secs(30), move |result| {
+            unwrap!(done_tx.send(result))
+        })
+    );
+    assert!(APP.get().is_err());
+    assert!(cancel.is_cancelled());
+    assert_eq!(unwrap!(done_rx.recv()), Ok(()));
+
+    // An operation which can't be cancelled outlives the timeout.
+    unwrap!(APP.init(()));
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let (done_tx, done_rx) = mpsc::channel();
+    unwrap!(dispatcher::global().dispatch(move || {
+        let _ = release_rx.recv();
+    }));
+
+    unwrap!(
+        APP.shutdown_graceful(Duration::from_millis(10), move |result| {
+            unwrap!(done_tx.send(result))
+        })
+    );
+    assert_eq!(
+        unwrap!(done_rx.recv()),
+        Err(LifecycleError::ShutdownTimedOut)
+    );
+    unwrap!(release_tx.send(()));
+
+    // A timeout too large to be represented waits for the operations to complete.
+    unwrap!(APP.init(()));
+    let (done_tx, done_rx) = mpsc::channel();
+    unwrap!(APP.shutdown_graceful(Duration::MAX, move |result| unwrap!(done_tx.send(result))));
+    assert_eq!(unwrap!(done_rx.recv()), Ok(()));
+
+    assert_eq!(
+        APP.shutdown_graceful(Duration::from_millis(10), |_| ()),
+        Err(LifecycleError::NotInitialised)
+    );
+}
+
+// Test the pending callback audit functions generated by `export_pending_callbacks!`.
+#[test]
+fn pending_callbacks() {
+    use sn_ffi_utils::{dispatcher, export_pending_callbacks};
+    use std::ptr;
+    use std::sync::mpsc;
+
+    export_pending_callbacks!();
+
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    unwrap::unwrap!(dispatcher::global().dispatch(move || {
+        let _ = release_rx.recv();
+    }));
+    assert!(ffi_pending_callbacks() > 0);
+
+    // Other tests may register callbacks between the two calls.
+    let mut names = loop {
+        let required = unsafe { ffi_pending_callback_names(ptr::null_mut(), 0) };
+        let mut names = vec![0u8; required];
+        if unsafe { ffi_pending_callback_names(names.as_mut_ptr(), names.len()) } == required {
+            break names;
+        }
+    };
+    assert_eq!(names.pop(), Some(0));
+    let names = unwrap::unwrap!(String::from_utf8(names));
+
+    if cfg!(debug_assertions) {
+        assert!(names.contains("dispatched job"));
+    }
+
+    unwrap::unwrap!(release_tx.send(()));
+}
+
 mod utils {
     use sn_ffi_utils::test_utils::{send_via_user_data, sender_as_user_data, SendWrapper};
     use sn_ffi_utils::{FfiResult, NativeResult, ReprC};